@@ -1,27 +1,48 @@
-use axum::{routing::post, Json, Router};
+use axum::{extract::State, routing::post, Json, Router};
    use serde::{Deserialize, Serialize};
-   use sha2::{Digest, Sha256};
-   use uuid::Uuid;
-   use chrono::Utc;
+   use sequence_code::{Action, SequenceCode};
    use std::fs;
    use std::net::SocketAddr;
+   use std::sync::Arc;
    use tracing::{info, error};
    use tracing_subscriber::{fmt, EnvFilter};
 
+   const EVENT_REPORT_URL_ENV: &str = "EVENT_REPORT_URL";
+   const EVENT_INGEST_TOKEN_ENV: &str = "EVENT_INGEST_TOKEN";
+
+   #[derive(Clone)]
+   struct AppState {
+       sequence_secret: Arc<[u8]>,
+       http: reqwest::Client,
+       /// The system service's `/report/events` endpoint.
+       event_report_url: Arc<str>,
+       event_ingest_token: Arc<str>,
+   }
+
    #[derive(Serialize, Deserialize)]
    struct BiometricData {
        face_scan: Vec<u8>,
        session_id: String,
    }
 
+   #[derive(Serialize)]
+   struct IssuedEvent<'a> {
+       sequence_code: &'a str,
+       action: &'a str,
+       service: &'a str,
+       human_hash_id: &'a str,
+   }
+
    #[derive(Serialize, Deserialize)]
    struct EnrollmentResult {
        human_hash_id: String,
        proof: String,
        sequence_code: String,
+       /// Liveness confidence in [0, 1]; relying-party policies can require a minimum.
+       liveness_score: f64,
    }
 
-   async fn enroll_biometric(Json(data): Json<BiometricData>) -> Json<EnrollmentResult> {
+   async fn enroll_biometric(State(state): State<AppState>, Json(data): Json<BiometricData>) -> Json<EnrollmentResult> {
        info!("Processing enrollment for session_id: {}", data.session_id);
        
        // Load Bitcoin wallet data
@@ -34,6 +55,7 @@ use axum::{routing::post, Json, Router};
        };
        
        // Process biometric data with FaceTec/MegaMatcher (simplified)
+       let liveness_score = score_liveness(&data.face_scan);
        let processed_data = process_biometric(data.face_scan.clone());
        
        // Placeholder for BitSNARK proof generation
@@ -43,7 +65,8 @@ use axum::{routing::post, Json, Router};
        let human_hash_id = commit_to_popchain(&proof);
        
        // Generate unique sequence code
-       let sequence_code = generate_sequence_code("ENR");
+       let sequence_code = SequenceCode::issue(Action::Enroll, &state.sequence_secret).to_string();
+       report_event(&state, &sequence_code, Action::Enroll, &human_hash_id).await;
        
        info!("Enrollment successful, human_hash_id: {}, sequence_code: {}", human_hash_id, sequence_code);
        
@@ -51,6 +74,7 @@ use axum::{routing::post, Json, Router};
            human_hash_id,
            proof,
            sequence_code,
+           liveness_score,
        })
    }
 
@@ -59,6 +83,11 @@ use axum::{routing::post, Json, Router};
        data
    }
 
+   fn score_liveness(face_scan: &[u8]) -> f64 {
+       // Placeholder for FaceTec liveness scoring
+       if face_scan.is_empty() { 0.0 } else { 1.0 }
+   }
+
    fn generate_mock_proof(data: &[u8]) -> String {
        // Mock zk-SNARK proof generation
        format!("mock_proof_{}", hex::encode(data))
@@ -69,13 +98,14 @@ use axum::{routing::post, Json, Router};
        format!("0x{}", hex::encode(proof))
    }
 
-   fn generate_sequence_code(action: &str) -> String {
-       let uuid = Uuid::new_v4();
-       let timestamp = Utc::now().timestamp();
-       let mut hasher = Sha256::new();
-       hasher.update(format!("{}{}", uuid, timestamp));
-       let hash = hex::encode(hasher.finalize());
-       format!("TX-{}-{}-{}-{}", action, uuid, timestamp, &hash[..8])
+   /// Records an issued sequence code in the system service's event store.
+   async fn report_event(state: &AppState, sequence_code: &str, action: Action, human_hash_id: &str) {
+       let event = IssuedEvent { sequence_code, action: action.as_str(), service: "biometric", human_hash_id };
+       match state.http.post(&*state.event_report_url).bearer_auth(&state.event_ingest_token).json(&event).send().await {
+           Ok(response) if response.status().is_success() => {}
+           Ok(response) => error!("Event store rejected {}: {}", sequence_code, response.status()),
+           Err(e) => error!("Failed to report {} to the event store: {}", sequence_code, e),
+       }
    }
 
    #[tokio::main]
@@ -85,8 +115,16 @@ use axum::{routing::post, Json, Router};
            .with_thread_ids(true)
            .init();
        
+       let env = |name: &str| std::env::var(name).unwrap_or_else(|_| panic!("{} must be set", name));
+       let state = AppState {
+           sequence_secret: sequence_code::secret_from_env().expect("Missing sequence code secret").into(),
+           http: reqwest::Client::new(),
+           event_report_url: env(EVENT_REPORT_URL_ENV).into(),
+           event_ingest_token: env(EVENT_INGEST_TOKEN_ENV).into(),
+       };
        let app = Router::new()
-           .route("/identity/enroll", post(enroll_biometric));
+           .route("/identity/enroll", post(enroll_biometric))
+           .with_state(state);
        
        let addr = SocketAddr::from(([0, 0, 0, 0], 8080));
        info!("Starting biometric service on {}", addr);
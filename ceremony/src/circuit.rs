@@ -0,0 +1,54 @@
+use ark_bn254::Fr;
+use ark_relations::lc;
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystemRef, SynthesisError};
+
+/// Names accepted by `ceremony init`.
+pub const CIRCUITS: &[&str] = &["biometric_verification"];
+
+/// Proves knowledge of a biometric template hash and salt opening the public
+/// commitment `(template_hash + salt)^5`. The verifier's challenge, verifier ID
+/// and expiry are public inputs too, so a proof only verifies for the
+/// presentation it was made for. Only the constraint shape matters for the
+/// setup, so the witness is left empty there.
+#[derive(Clone, Default)]
+pub struct BiometricVerificationCircuit {
+    pub commitment: Option<Fr>,
+    pub challenge: Option<Fr>,
+    pub verifier_id: Option<Fr>,
+    pub expires_at: Option<Fr>,
+    pub template_hash: Option<Fr>,
+    pub salt: Option<Fr>,
+}
+
+impl ConstraintSynthesizer<Fr> for BiometricVerificationCircuit {
+    fn generate_constraints(self, cs: ConstraintSystemRef<Fr>) -> Result<(), SynthesisError> {
+        let opening = self.template_hash.zip(self.salt).map(|(hash, salt)| hash + salt);
+
+        let commitment = cs.new_input_variable(|| self.commitment.ok_or(SynthesisError::AssignmentMissing))?;
+        let template_hash = cs.new_witness_variable(|| self.template_hash.ok_or(SynthesisError::AssignmentMissing))?;
+        let salt = cs.new_witness_variable(|| self.salt.ok_or(SynthesisError::AssignmentMissing))?;
+
+        let x2 = cs.new_witness_variable(|| opening.map(|x| x * x).ok_or(SynthesisError::AssignmentMissing))?;
+        let x4 = cs.new_witness_variable(|| opening.map(|x| x * x * x * x).ok_or(SynthesisError::AssignmentMissing))?;
+
+        cs.enforce_constraint(lc!() + template_hash + salt, lc!() + template_hash + salt, lc!() + x2)?;
+        cs.enforce_constraint(lc!() + x2, lc!() + x2, lc!() + x4)?;
+        cs.enforce_constraint(lc!() + x4, lc!() + template_hash + salt, lc!() + commitment)?;
+
+        // Binding inputs take no part in the relation; squaring each one keeps
+        // it in the constraint system so the proof cannot be re-targeted.
+        for value in [self.challenge, self.verifier_id, self.expires_at] {
+            let input = cs.new_input_variable(|| value.ok_or(SynthesisError::AssignmentMissing))?;
+            let square = cs.new_witness_variable(|| value.map(|v| v * v).ok_or(SynthesisError::AssignmentMissing))?;
+            cs.enforce_constraint(lc!() + input, lc!() + input, lc!() + square)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn circuit_by_name(name: &str) -> Option<BiometricVerificationCircuit> {
+    match name {
+        "biometric_verification" => Some(BiometricVerificationCircuit::default()),
+        _ => None,
+    }
+}
@@ -0,0 +1,235 @@
+use ark_bn254::{Bn254, G1Affine, G2Affine};
+use ark_groth16::ProvingKey;
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+use rand::rngs::{OsRng, StdRng};
+use rand::{RngCore, SeedableRng};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::error::Error;
+use std::fs;
+
+mod circuit;
+mod phase1;
+mod phase2;
+
+use phase1::PowersOfTau;
+use phase2::Contribution;
+
+const USAGE: &str = "usage:
+  ceremony init <circuit> <powers_of_tau> <params_out> <transcript>
+  ceremony contribute <params_in> <params_out> <transcript> <participant> [entropy]
+  ceremony verify <powers_of_tau> <final_params> <transcript>
+  ceremony finalize <powers_of_tau> <final_params> <transcript> <verifying_key_out> <registry>";
+
+#[derive(Serialize, Deserialize)]
+struct Transcript {
+    circuit: String,
+    /// SHA-256 of the phase-1 accumulator the initial parameters are built
+    /// from.
+    powers_of_tau_hash: String,
+    initial_params_hash: String,
+    contributions: Vec<ContributionRecord>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ContributionRecord {
+    index: usize,
+    participant: String,
+    contribution_hash: String,
+    delta_after: String,
+    s_g1: String,
+    s_delta_g1: String,
+    r_delta_g2: String,
+    timestamp: u64,
+}
+
+struct VerifiedCeremony {
+    transcript: Transcript,
+    final_params: ProvingKey<Bn254>,
+    transcript_hash: [u8; 32],
+}
+
+#[derive(Serialize, Deserialize)]
+struct RegistryEntry {
+    circuit: String,
+    verifying_key_hash: String,
+    transcript_hash: String,
+    contributions: usize,
+    registered_at: u64,
+}
+
+fn main() -> Result<(), Box<dyn Error>> {
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    match args.as_slice() {
+        ["init", circuit, powers_of_tau, params_out, transcript] => init(circuit, powers_of_tau, params_out, transcript),
+        ["contribute", params_in, params_out, transcript, participant] => contribute(params_in, params_out, transcript, participant, ""),
+        ["contribute", params_in, params_out, transcript, participant, entropy] => contribute(params_in, params_out, transcript, participant, entropy),
+        ["verify", powers_of_tau, last, transcript] => verify(powers_of_tau, last, transcript).map(|_| ()),
+        ["finalize", powers_of_tau, last, transcript, vk_out, registry] => finalize(powers_of_tau, last, transcript, vk_out, registry),
+        _ => {
+            eprintln!("{}", USAGE);
+            std::process::exit(2);
+        }
+    }
+}
+
+fn init(circuit_name: &str, powers_of_tau_path: &str, params_out: &str, transcript_path: &str) -> Result<(), Box<dyn Error>> {
+    let (powers, powers_of_tau_hash) = read_powers_of_tau(powers_of_tau_path)?;
+    let params = initial_params(circuit_name, &powers)?;
+    write_params(params_out, &params)?;
+
+    let transcript = Transcript {
+        circuit: circuit_name.to_string(),
+        powers_of_tau_hash: hex::encode(powers_of_tau_hash),
+        initial_params_hash: hex::encode(phase2::params_hash(&params)),
+        contributions: Vec::new(),
+    };
+    fs::write(transcript_path, serde_json::to_string_pretty(&transcript)?)?;
+    println!("Initialized {} parameters at {}", circuit_name, params_out);
+    println!("Initial parameters hash: {}", transcript.initial_params_hash);
+    Ok(())
+}
+
+fn contribute(params_in: &str, params_out: &str, transcript_path: &str, participant: &str, entropy: &str) -> Result<(), Box<dyn Error>> {
+    let mut params = read_params(params_in)?;
+    let mut transcript: Transcript = serde_json::from_str(&fs::read_to_string(transcript_path)?)?;
+    let previous_hash = match transcript.contributions.last() {
+        Some(last) => decode_hash(&last.contribution_hash)?,
+        None => decode_hash(&transcript.initial_params_hash)?,
+    };
+    if transcript.contributions.is_empty() && phase2::params_hash(&params) != previous_hash {
+        return Err("input parameters do not match the transcript's initial parameters".into());
+    }
+    if let Some(last) = transcript.contributions.last() {
+        if decode_point::<G1Affine>(&last.delta_after)? != params.delta_g1 {
+            return Err("input parameters are not the output of the last contribution".into());
+        }
+    }
+
+    // Mix user-supplied entropy into the OS randomness so a compromised RNG
+    // alone cannot reproduce the secret.
+    let mut seed = [0u8; 32];
+    OsRng.fill_bytes(&mut seed);
+    let mut rng = StdRng::from_seed(Sha256::new().chain_update(seed).chain_update(entropy).finalize().into());
+
+    let contribution = phase2::contribute(&mut params, &previous_hash, participant, &mut rng);
+    let contribution_hash = phase2::contribution_hash(&previous_hash, &contribution);
+    write_params(params_out, &params)?;
+
+    transcript.contributions.push(ContributionRecord {
+        index: transcript.contributions.len() + 1,
+        participant: participant.to_string(),
+        contribution_hash: hex::encode(contribution_hash),
+        delta_after: encode_point(&contribution.delta_after),
+        s_g1: encode_point(&contribution.s_g1),
+        s_delta_g1: encode_point(&contribution.s_delta_g1),
+        r_delta_g2: encode_point(&contribution.r_delta_g2),
+        timestamp: std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs(),
+    });
+    fs::write(transcript_path, serde_json::to_string_pretty(&transcript)?)?;
+    println!("Contribution {} by {} written to {}", transcript.contributions.len(), participant, params_out);
+    println!("Contribution hash (publish this): {}", hex::encode(contribution_hash));
+    Ok(())
+}
+
+/// Re-derives the initial parameters from the powers of tau rather than
+/// trusting a file, so a verified ceremony carries no toxic waste from `init`.
+fn verify(powers_of_tau_path: &str, final_path: &str, transcript_path: &str) -> Result<VerifiedCeremony, Box<dyn Error>> {
+    let last = read_params(final_path)?;
+    let transcript: Transcript = serde_json::from_str(&fs::read_to_string(transcript_path)?)?;
+    let (powers, powers_of_tau_hash) = read_powers_of_tau(powers_of_tau_path)?;
+    if hex::encode(powers_of_tau_hash) != transcript.powers_of_tau_hash {
+        return Err("powers of tau do not match the transcript".into());
+    }
+    let initial = initial_params(&transcript.circuit, &powers)?;
+    if hex::encode(phase2::params_hash(&initial)) != transcript.initial_params_hash {
+        return Err("initial parameters do not match the transcript".into());
+    }
+
+    let mut contributions = Vec::with_capacity(transcript.contributions.len());
+    let mut hashes = Vec::with_capacity(transcript.contributions.len());
+    for record in &transcript.contributions {
+        contributions.push(Contribution {
+            participant: record.participant.clone(),
+            delta_after: decode_point(&record.delta_after)?,
+            s_g1: decode_point(&record.s_g1)?,
+            s_delta_g1: decode_point(&record.s_delta_g1)?,
+            r_delta_g2: decode_point::<G2Affine>(&record.r_delta_g2)?,
+        });
+        hashes.push(decode_hash(&record.contribution_hash)?);
+    }
+
+    let transcript_hash = phase2::verify_transcript(&initial, &last, &contributions, &hashes, &mut OsRng)?;
+    for record in &transcript.contributions {
+        println!("  #{} {} {}", record.index, record.participant, record.contribution_hash);
+    }
+    println!("Transcript verified: {} contributions, final hash {}", contributions.len(), hex::encode(transcript_hash));
+    Ok(VerifiedCeremony { transcript, final_params: last, transcript_hash })
+}
+
+fn finalize(powers_of_tau_path: &str, final_path: &str, transcript_path: &str, vk_out: &str, registry_path: &str) -> Result<(), Box<dyn Error>> {
+    let VerifiedCeremony { transcript, final_params, transcript_hash } = verify(powers_of_tau_path, final_path, transcript_path)?;
+    if transcript.contributions.is_empty() {
+        return Err("refusing to finalize parameters without any contribution".into());
+    }
+
+    let mut vk_bytes = Vec::new();
+    final_params.vk.serialize_compressed(&mut vk_bytes)?;
+    fs::write(vk_out, &vk_bytes)?;
+    let verifying_key_hash = hex::encode(Sha256::digest(&vk_bytes));
+
+    let mut registry: Vec<RegistryEntry> = match fs::read_to_string(registry_path) {
+        Ok(contents) => serde_json::from_str(&contents)?,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+        Err(e) => return Err(e.into()),
+    };
+    registry.push(RegistryEntry {
+        circuit: transcript.circuit,
+        verifying_key_hash: verifying_key_hash.clone(),
+        transcript_hash: hex::encode(transcript_hash),
+        contributions: transcript.contributions.len(),
+        registered_at: std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs(),
+    });
+    fs::write(registry_path, serde_json::to_string_pretty(&registry)?)?;
+    println!("Verifying key written to {}", vk_out);
+    println!("Verifying key hash {} registered in {}", verifying_key_hash, registry_path);
+    Ok(())
+}
+
+fn read_powers_of_tau(path: &str) -> Result<(PowersOfTau, [u8; 32]), Box<dyn Error>> {
+    let bytes = fs::read(path)?;
+    let powers = PowersOfTau::read(&bytes, &mut OsRng)?;
+    Ok((powers, Sha256::digest(&bytes).into()))
+}
+
+fn initial_params(circuit_name: &str, powers: &PowersOfTau) -> Result<ProvingKey<Bn254>, Box<dyn Error>> {
+    let circuit = circuit::circuit_by_name(circuit_name)
+        .ok_or_else(|| format!("unknown circuit {}, expected one of {:?}", circuit_name, circuit::CIRCUITS))?;
+    Ok(phase2::initialize(circuit, powers)?)
+}
+
+fn read_params(path: &str) -> Result<ProvingKey<Bn254>, Box<dyn Error>> {
+    Ok(ProvingKey::deserialize_compressed(fs::read(path)?.as_slice())?)
+}
+
+fn write_params(path: &str, params: &ProvingKey<Bn254>) -> Result<(), Box<dyn Error>> {
+    let mut bytes = Vec::new();
+    params.serialize_compressed(&mut bytes)?;
+    fs::write(path, bytes)?;
+    Ok(())
+}
+
+fn encode_point<P: CanonicalSerialize>(point: &P) -> String {
+    let mut bytes = Vec::new();
+    point.serialize_compressed(&mut bytes).expect("serializing to a Vec cannot fail");
+    hex::encode(bytes)
+}
+
+fn decode_point<P: CanonicalDeserialize>(value: &str) -> Result<P, Box<dyn Error>> {
+    Ok(P::deserialize_compressed(hex::decode(value)?.as_slice())?)
+}
+
+fn decode_hash(value: &str) -> Result<[u8; 32], Box<dyn Error>> {
+    hex::decode(value)?.try_into().map_err(|_| "transcript hashes must be 32 bytes".into())
+}
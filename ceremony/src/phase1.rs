@@ -0,0 +1,158 @@
+//! Phase-1 input: the final accumulator of a public, circuit-independent
+//! powers-of-tau ceremony. Phase 2 starts from these powers, so `tau`,
+//! `alpha` and `beta` are never known to whoever runs `ceremony init`.
+
+use ark_bn254::{Fr, G1Affine, G1Projective, G2Affine, G2Projective};
+use ark_ec::{AffineRepr, CurveGroup, VariableBaseMSM};
+use ark_ff::UniformRand;
+use ark_serialize::{CanonicalDeserialize, CanonicalSerialize};
+use rand::Rng;
+
+use crate::phase2::same_ratio;
+
+/// An accumulator for circuits whose QAP domain has at most `n` points,
+/// serialized as compressed points in this field order (the layout of the
+/// powersoftau accumulator).
+#[derive(Clone, Debug, PartialEq, CanonicalSerialize, CanonicalDeserialize)]
+pub struct PowersOfTau {
+    /// `[tau^i]_1` for `i < 2n - 1`.
+    pub tau_g1: Vec<G1Affine>,
+    /// `[tau^i]_2` for `i < n`.
+    pub tau_g2: Vec<G2Affine>,
+    /// `[alpha * tau^i]_1` for `i < n`.
+    pub alpha_tau_g1: Vec<G1Affine>,
+    /// `[beta * tau^i]_1` for `i < n`.
+    pub beta_tau_g1: Vec<G1Affine>,
+    pub beta_g2: G2Affine,
+}
+
+#[derive(Debug)]
+pub enum Phase1Error {
+    Malformed(String),
+    Inconsistent(&'static str),
+}
+
+impl std::fmt::Display for Phase1Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Phase1Error::Malformed(e) => write!(f, "powers of tau could not be read: {}", e),
+            Phase1Error::Inconsistent(field) => write!(f, "powers of tau are inconsistent: {}", field),
+        }
+    }
+}
+
+impl std::error::Error for Phase1Error {}
+
+impl PowersOfTau {
+    /// Reads an accumulator and checks that it is well formed.
+    pub fn read(bytes: &[u8], rng: &mut impl Rng) -> Result<Self, Phase1Error> {
+        let powers = PowersOfTau::deserialize_compressed(bytes).map_err(|e| Phase1Error::Malformed(e.to_string()))?;
+        powers.verify(rng)?;
+        Ok(powers)
+    }
+
+    /// Largest QAP domain the accumulator supports.
+    pub fn size(&self) -> usize {
+        self.tau_g2.len()
+    }
+
+    /// Checks that every vector holds successive powers of the same `tau`
+    /// and that `beta` agrees between the groups. The ratios are checked on
+    /// random linear combinations, so each costs two pairings.
+    pub fn verify(&self, rng: &mut impl Rng) -> Result<(), Phase1Error> {
+        let n = self.tau_g2.len();
+        if n < 2 || self.tau_g1.len() != 2 * n - 1 || self.alpha_tau_g1.len() != n || self.beta_tau_g1.len() != n {
+            return Err(Phase1Error::Inconsistent("vector lengths"));
+        }
+        if self.tau_g1[0] != G1Affine::generator() || self.tau_g2[0] != G2Affine::generator() {
+            return Err(Phase1Error::Inconsistent("generators"));
+        }
+
+        let g1 = G1Affine::generator();
+        let g2 = G2Affine::generator();
+        let tau_g1 = (g1, self.tau_g1[1]);
+        let tau_g2 = (g2, self.tau_g2[1]);
+        if !same_ratio(successive::<G1Projective>(&self.tau_g1, rng), tau_g2) {
+            return Err(Phase1Error::Inconsistent("tau_g1"));
+        }
+        if !same_ratio(tau_g1, successive::<G2Projective>(&self.tau_g2, rng)) {
+            return Err(Phase1Error::Inconsistent("tau_g2"));
+        }
+        if !same_ratio(successive::<G1Projective>(&self.alpha_tau_g1, rng), tau_g2) {
+            return Err(Phase1Error::Inconsistent("alpha_tau_g1"));
+        }
+        if !same_ratio(successive::<G1Projective>(&self.beta_tau_g1, rng), tau_g2) {
+            return Err(Phase1Error::Inconsistent("beta_tau_g1"));
+        }
+        if !same_ratio((g1, self.beta_tau_g1[0]), (g2, self.beta_g2)) {
+            return Err(Phase1Error::Inconsistent("beta_g2"));
+        }
+        Ok(())
+    }
+
+    /// The accumulator a powers-of-tau ceremony with these secrets would
+    /// produce; only for tests, where the secrets are known anyway.
+    #[cfg(test)]
+    pub fn from_secrets(n: usize, tau: Fr, alpha: Fr, beta: Fr) -> Self {
+        let powers: Vec<Fr> = std::iter::successors(Some(Fr::from(1u64)), |power| Some(*power * tau)).take(2 * n - 1).collect();
+        let g1 = |scalars: &mut dyn Iterator<Item = Fr>| {
+            G1Projective::normalize_batch(&scalars.map(|s| G1Affine::generator() * s).collect::<Vec<_>>())
+        };
+        PowersOfTau {
+            tau_g1: g1(&mut powers.iter().copied()),
+            tau_g2: G2Projective::normalize_batch(&powers[..n].iter().map(|s| G2Affine::generator() * s).collect::<Vec<_>>()),
+            alpha_tau_g1: g1(&mut powers[..n].iter().map(|s| alpha * s)),
+            beta_tau_g1: g1(&mut powers[..n].iter().map(|s| beta * s)),
+            beta_g2: (G2Affine::generator() * beta).into_affine(),
+        }
+    }
+}
+
+/// `(sum r_i * P_i, sum r_i * P_{i+1})` for random `r_i`: the two sides
+/// share a ratio exactly when every consecutive pair does.
+fn successive<G: CurveGroup<ScalarField = Fr> + VariableBaseMSM<MulBase = G::Affine>>(points: &[G::Affine], rng: &mut impl Rng) -> (G::Affine, G::Affine) {
+    let coefficients: Vec<Fr> = (1..points.len()).map(|_| Fr::rand(rng)).collect();
+    let lower = G::msm(&points[..points.len() - 1], &coefficients).expect("equal lengths");
+    let upper = G::msm(&points[1..], &coefficients).expect("equal lengths");
+    (lower.into_affine(), upper.into_affine())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    #[test]
+    fn reads_well_formed_powers() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let powers = PowersOfTau::from_secrets(8, Fr::rand(&mut rng), Fr::rand(&mut rng), Fr::rand(&mut rng));
+        let mut bytes = Vec::new();
+        powers.serialize_compressed(&mut bytes).unwrap();
+        assert_eq!(PowersOfTau::read(&bytes, &mut rng).unwrap(), powers);
+        assert!(matches!(PowersOfTau::read(&bytes[..bytes.len() - 1], &mut rng), Err(Phase1Error::Malformed(_))));
+    }
+
+    #[test]
+    fn rejects_inconsistent_powers() {
+        let mut rng = StdRng::seed_from_u64(2);
+        let (tau, alpha, beta) = (Fr::rand(&mut rng), Fr::rand(&mut rng), Fr::rand(&mut rng));
+        let powers = PowersOfTau::from_secrets(8, tau, alpha, beta);
+
+        let mut altered = powers.clone();
+        altered.tau_g1[5] = altered.tau_g1[4];
+        assert!(matches!(altered.verify(&mut rng), Err(Phase1Error::Inconsistent("tau_g1"))));
+
+        let mut altered = powers.clone();
+        altered.tau_g2[3] = altered.tau_g2[2];
+        assert!(matches!(altered.verify(&mut rng), Err(Phase1Error::Inconsistent("tau_g2"))));
+
+        let mut altered = powers.clone();
+        altered.beta_g2 = (G2Affine::generator() * alpha).into_affine();
+        assert!(matches!(altered.verify(&mut rng), Err(Phase1Error::Inconsistent("beta_g2"))));
+
+        let mut altered = powers.clone();
+        altered.alpha_tau_g1.pop();
+        assert!(matches!(altered.verify(&mut rng), Err(Phase1Error::Inconsistent("vector lengths"))));
+    }
+}
@@ -0,0 +1,428 @@
+//! Groth16 phase-2 (circuit-specific) contributions after Bowe, Gabizon and
+//! Miers. Each participant multiplies `delta` by a secret `s` and divides the
+//! `l_query`/`h_query` elements by it; as long as one participant discards `s`,
+//! nobody knows the final `delta`.
+
+use ark_bn254::{Bn254, Fq, Fq2, Fr, G1Affine, G1Projective, G2Affine, G2Projective};
+use ark_ec::pairing::Pairing;
+use ark_ec::{AffineRepr, CurveGroup, VariableBaseMSM};
+use ark_ff::{Field, PrimeField, UniformRand, Zero};
+use ark_groth16::{ProvingKey, VerifyingKey};
+use ark_poly::{EvaluationDomain, GeneralEvaluationDomain};
+use ark_relations::r1cs::{ConstraintSynthesizer, ConstraintSystem, OptimizationGoal, SynthesisMode};
+use ark_serialize::CanonicalSerialize;
+use rand::Rng;
+use sha2::{Digest, Sha256};
+
+use crate::phase1::PowersOfTau;
+
+/// Public record of one contribution: the new `delta` and a proof of
+/// knowledge of the secret that produced it.
+#[derive(Clone, Debug)]
+pub struct Contribution {
+    pub participant: String,
+    pub delta_after: G1Affine,
+    pub s_g1: G1Affine,
+    pub s_delta_g1: G1Affine,
+    pub r_delta_g2: G2Affine,
+}
+
+#[derive(Debug)]
+pub enum Phase2Error {
+    Synthesis(String),
+    InvalidProofOfKnowledge(usize),
+    DeltaMismatch,
+    QueryMismatch(&'static str),
+    HashMismatch(usize),
+    NotEnoughPowers { needed: usize, available: usize },
+}
+
+impl std::fmt::Display for Phase2Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Phase2Error::Synthesis(e) => write!(f, "circuit synthesis failed: {}", e),
+            Phase2Error::InvalidProofOfKnowledge(i) => write!(f, "contribution {} has an invalid proof of knowledge", i),
+            Phase2Error::DeltaMismatch => write!(f, "final delta does not match the transcript"),
+            Phase2Error::QueryMismatch(field) => write!(f, "{} was modified outside of the delta update", field),
+            Phase2Error::HashMismatch(i) => write!(f, "contribution {} hash does not match the transcript", i),
+            Phase2Error::NotEnoughPowers { needed, available } => {
+                write!(f, "circuit needs {} powers of tau but only {} are available", needed, available)
+            }
+        }
+    }
+}
+
+impl std::error::Error for Phase2Error {}
+
+/// Builds the starting parameters from public phase-1 powers, with
+/// `gamma = delta = 1` so all of the final `delta` comes from phase-2
+/// contributions. Deterministic, so anyone holding the powers of tau can
+/// re-derive the initial parameters and check that nothing else went in.
+///
+/// Mirrors `Groth16::generate_parameters_with_qap` and its libsnark
+/// reduction, with each evaluation at `tau` replaced by the same combination
+/// of the published powers.
+pub fn initialize<C: ConstraintSynthesizer<Fr>>(circuit: C, powers: &PowersOfTau) -> Result<ProvingKey<Bn254>, Phase2Error> {
+    let cs = ConstraintSystem::new_ref();
+    cs.set_optimization_goal(OptimizationGoal::Constraints);
+    cs.set_mode(SynthesisMode::Setup);
+    circuit.generate_constraints(cs.clone()).map_err(|e| Phase2Error::Synthesis(e.to_string()))?;
+    cs.finalize();
+    let matrices = cs.to_matrices().ok_or_else(|| Phase2Error::Synthesis("constraint system is not available".to_string()))?;
+
+    let num_constraints = cs.num_constraints();
+    let num_instance_variables = cs.num_instance_variables();
+    let num_variables = num_instance_variables + cs.num_witness_variables();
+    let domain = GeneralEvaluationDomain::<Fr>::new(num_constraints + num_instance_variables)
+        .ok_or_else(|| Phase2Error::Synthesis("circuit is too large for the evaluation domain".to_string()))?;
+    let n = domain.size();
+    if powers.size() < n {
+        return Err(Phase2Error::NotEnoughPowers { needed: n, available: powers.size() });
+    }
+
+    // [L_j(tau)], [alpha L_j(tau)] and [beta L_j(tau)] for the Lagrange basis
+    // of the domain: the inverse FFT of the powers, taken in the group.
+    let lagrange_g1 = domain.ifft(&projective(&powers.tau_g1[..n]));
+    let lagrange_g2 = domain.ifft(&powers.tau_g2[..n].iter().map(|p| G2Projective::from(*p)).collect::<Vec<_>>());
+    let alpha_lagrange_g1 = domain.ifft(&projective(&powers.alpha_tau_g1[..n]));
+    let beta_lagrange_g1 = domain.ifft(&projective(&powers.beta_tau_g1[..n]));
+
+    let mut a = vec![G1Projective::zero(); num_variables];
+    let mut b_g1 = vec![G1Projective::zero(); num_variables];
+    let mut b_g2 = vec![G2Projective::zero(); num_variables];
+    // beta * u_i(tau) + alpha * v_i(tau) + w_i(tau)
+    let mut abc = vec![G1Projective::zero(); num_variables];
+
+    // The reduction adds `input * 1 = 0` after the constraints for every
+    // instance variable, keeping the input polynomials independent.
+    for i in 0..num_instance_variables {
+        a[i] += lagrange_g1[num_constraints + i];
+        abc[i] += beta_lagrange_g1[num_constraints + i];
+    }
+    for (row, constraint) in matrices.a.iter().enumerate() {
+        for (coeff, index) in constraint {
+            a[*index] += lagrange_g1[row] * coeff;
+            abc[*index] += beta_lagrange_g1[row] * coeff;
+        }
+    }
+    for (row, constraint) in matrices.b.iter().enumerate() {
+        for (coeff, index) in constraint {
+            b_g1[*index] += lagrange_g1[row] * coeff;
+            b_g2[*index] += lagrange_g2[row] * coeff;
+            abc[*index] += alpha_lagrange_g1[row] * coeff;
+        }
+    }
+    for (row, constraint) in matrices.c.iter().enumerate() {
+        for (coeff, index) in constraint {
+            abc[*index] += lagrange_g1[row] * coeff;
+        }
+    }
+
+    // [tau^i Z(tau)] = [tau^(n+i)] - [tau^i] with Z(x) = x^n - 1.
+    let h_query: Vec<G1Projective> = (0..n - 1).map(|i| powers.tau_g1[n + i] - powers.tau_g1[i]).collect();
+
+    let abc = G1Projective::normalize_batch(&abc);
+    Ok(ProvingKey {
+        vk: VerifyingKey {
+            alpha_g1: powers.alpha_tau_g1[0],
+            beta_g2: powers.beta_g2,
+            gamma_g2: G2Affine::generator(),
+            delta_g2: G2Affine::generator(),
+            gamma_abc_g1: abc[..num_instance_variables].to_vec(),
+        },
+        beta_g1: powers.beta_tau_g1[0],
+        delta_g1: G1Affine::generator(),
+        a_query: G1Projective::normalize_batch(&a),
+        b_g1_query: G1Projective::normalize_batch(&b_g1),
+        b_g2_query: G2Projective::normalize_batch(&b_g2),
+        h_query: G1Projective::normalize_batch(&h_query),
+        l_query: abc[num_instance_variables..].to_vec(),
+    })
+}
+
+pub fn params_hash(params: &ProvingKey<Bn254>) -> [u8; 32] {
+    let mut bytes = Vec::new();
+    params.serialize_compressed(&mut bytes).expect("serializing to a Vec cannot fail");
+    Sha256::digest(&bytes).into()
+}
+
+/// Applies a fresh contribution to `params` in place. `previous_hash` is the
+/// transcript hash the contribution builds on.
+pub fn contribute(params: &mut ProvingKey<Bn254>, previous_hash: &[u8; 32], participant: &str, rng: &mut impl Rng) -> Contribution {
+    let s = loop {
+        let s = Fr::rand(rng);
+        if s != Fr::ZERO {
+            break s;
+        }
+    };
+    let s_inv = s.inverse().expect("s is non-zero");
+
+    let s_g1 = G1Projective::rand(rng).into_affine();
+    let s_delta_g1 = (s_g1 * s).into_affine();
+    let r = hash_to_g2(previous_hash, &s_g1, &s_delta_g1);
+    let r_delta_g2 = (r * s).into_affine();
+
+    params.delta_g1 = (params.delta_g1 * s).into_affine();
+    params.vk.delta_g2 = (params.vk.delta_g2 * s).into_affine();
+    params.l_query = scale_all(&params.l_query, s_inv);
+    params.h_query = scale_all(&params.h_query, s_inv);
+
+    Contribution {
+        participant: participant.to_string(),
+        delta_after: params.delta_g1,
+        s_g1,
+        s_delta_g1,
+        r_delta_g2,
+    }
+}
+
+/// Hash chaining a contribution onto the transcript; the value participants
+/// publish to show their randomness was included.
+pub fn contribution_hash(previous_hash: &[u8; 32], contribution: &Contribution) -> [u8; 32] {
+    let mut hasher = Sha256::new();
+    hasher.update(previous_hash);
+    hasher.update(contribution.participant.as_bytes());
+    for point in [&contribution.delta_after, &contribution.s_g1, &contribution.s_delta_g1] {
+        hasher.update(compressed(point));
+    }
+    hasher.update(compressed(&contribution.r_delta_g2));
+    hasher.finalize().into()
+}
+
+/// Checks that `final_params` is `initial_params` with exactly the recorded
+/// contributions applied, and returns the final transcript hash.
+pub fn verify_transcript(
+    initial_params: &ProvingKey<Bn254>,
+    final_params: &ProvingKey<Bn254>,
+    contributions: &[Contribution],
+    recorded_hashes: &[[u8; 32]],
+    rng: &mut impl Rng,
+) -> Result<[u8; 32], Phase2Error> {
+    let mut previous_hash = params_hash(initial_params);
+    let mut previous_delta = initial_params.delta_g1;
+    for (i, contribution) in contributions.iter().enumerate() {
+        let r = hash_to_g2(&previous_hash, &contribution.s_g1, &contribution.s_delta_g1);
+        let knows_s = same_ratio((contribution.s_g1, contribution.s_delta_g1), (r, contribution.r_delta_g2));
+        let updates_delta = same_ratio((previous_delta, contribution.delta_after), (r, contribution.r_delta_g2));
+        if contribution.s_g1.is_zero() || !knows_s || !updates_delta {
+            return Err(Phase2Error::InvalidProofOfKnowledge(i + 1));
+        }
+        previous_hash = contribution_hash(&previous_hash, contribution);
+        if recorded_hashes.get(i) != Some(&previous_hash) {
+            return Err(Phase2Error::HashMismatch(i + 1));
+        }
+        previous_delta = contribution.delta_after;
+    }
+
+    if final_params.delta_g1 != previous_delta
+        || !same_ratio((G1Affine::generator(), final_params.delta_g1), (G2Affine::generator(), final_params.vk.delta_g2))
+    {
+        return Err(Phase2Error::DeltaMismatch);
+    }
+
+    let unchanged = [
+        ("alpha_g1", initial_params.vk.alpha_g1 == final_params.vk.alpha_g1),
+        ("beta_g2", initial_params.vk.beta_g2 == final_params.vk.beta_g2),
+        ("gamma_g2", initial_params.vk.gamma_g2 == final_params.vk.gamma_g2),
+        ("gamma_abc_g1", initial_params.vk.gamma_abc_g1 == final_params.vk.gamma_abc_g1),
+        ("beta_g1", initial_params.beta_g1 == final_params.beta_g1),
+        ("a_query", initial_params.a_query == final_params.a_query),
+        ("b_g1_query", initial_params.b_g1_query == final_params.b_g1_query),
+        ("b_g2_query", initial_params.b_g2_query == final_params.b_g2_query),
+    ];
+    if let Some((field, _)) = unchanged.iter().find(|(_, same)| !same) {
+        return Err(Phase2Error::QueryMismatch(field));
+    }
+
+    // query_final * delta_final must equal query_initial * delta_initial;
+    // checked on a random linear combination so it costs two pairings.
+    for (field, initial, last) in [
+        ("l_query", &initial_params.l_query, &final_params.l_query),
+        ("h_query", &initial_params.h_query, &final_params.h_query),
+    ] {
+        if initial.len() != last.len() {
+            return Err(Phase2Error::QueryMismatch(field));
+        }
+        let coefficients: Vec<Fr> = (0..initial.len()).map(|_| Fr::rand(rng)).collect();
+        let initial_sum = G1Projective::msm(initial, &coefficients).expect("equal lengths").into_affine();
+        let final_sum = G1Projective::msm(last, &coefficients).expect("equal lengths").into_affine();
+        if !same_ratio((final_sum, initial_sum), (initial_params.vk.delta_g2, final_params.vk.delta_g2)) {
+            return Err(Phase2Error::QueryMismatch(field));
+        }
+    }
+
+    Ok(previous_hash)
+}
+
+/// `e(a, d) == e(b, c)`, i.e. `b/a` and `d/c` share the same discrete log.
+pub(crate) fn same_ratio(g1: (G1Affine, G1Affine), g2: (G2Affine, G2Affine)) -> bool {
+    Bn254::pairing(g1.0, g2.1) == Bn254::pairing(g1.1, g2.0)
+}
+
+fn projective(points: &[G1Affine]) -> Vec<G1Projective> {
+    points.iter().map(|p| G1Projective::from(*p)).collect()
+}
+
+fn scale_all(points: &[G1Affine], scalar: Fr) -> Vec<G1Affine> {
+    let scaled: Vec<G1Projective> = points.iter().map(|p| *p * scalar).collect();
+    G1Projective::normalize_batch(&scaled)
+}
+
+/// Try-and-increment hash onto G2, so nobody knows the discrete log of the
+/// point the proof of knowledge is taken against.
+fn hash_to_g2(previous_hash: &[u8; 32], s_g1: &G1Affine, s_delta_g1: &G1Affine) -> G2Affine {
+    let mut seed = Sha256::new();
+    seed.update(previous_hash);
+    seed.update(compressed(s_g1));
+    seed.update(compressed(s_delta_g1));
+    let seed = seed.finalize();
+
+    for counter in 0u32.. {
+        let coordinate = |part: u8| {
+            let mut hasher = Sha256::new();
+            hasher.update(seed);
+            hasher.update(counter.to_be_bytes());
+            hasher.update([part]);
+            Fq::from_be_bytes_mod_order(&hasher.finalize())
+        };
+        let x = Fq2::new(coordinate(0), coordinate(1));
+        if let Some(point) = G2Affine::get_point_from_x_unchecked(x, counter & 1 == 0) {
+            let point = point.clear_cofactor();
+            if !point.is_zero() {
+                return point;
+            }
+        }
+    }
+    unreachable!("ran out of hash-to-curve attempts")
+}
+
+fn compressed<P: CanonicalSerialize>(point: &P) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    point.serialize_compressed(&mut bytes).expect("serializing to a Vec cannot fail");
+    bytes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::circuit::BiometricVerificationCircuit;
+    use ark_groth16::{prepare_verifying_key, Groth16};
+    use rand::rngs::StdRng;
+    use rand::SeedableRng;
+
+    fn powers(rng: &mut StdRng) -> PowersOfTau {
+        PowersOfTau::from_secrets(32, Fr::rand(rng), Fr::rand(rng), Fr::rand(rng))
+    }
+
+    /// Initial parameters and a transcript of two contributions on top.
+    fn ceremony(rng: &mut StdRng) -> (ProvingKey<Bn254>, ProvingKey<Bn254>, Vec<Contribution>, Vec<[u8; 32]>) {
+        let initial = initialize(BiometricVerificationCircuit::default(), &powers(rng)).unwrap();
+        let mut params = initial.clone();
+        let mut previous_hash = params_hash(&initial);
+        let (mut contributions, mut hashes) = (Vec::new(), Vec::new());
+        for participant in ["alice", "bob"] {
+            let contribution = contribute(&mut params, &previous_hash, participant, rng);
+            previous_hash = contribution_hash(&previous_hash, &contribution);
+            contributions.push(contribution);
+            hashes.push(previous_hash);
+        }
+        (initial, params, contributions, hashes)
+    }
+
+    #[test]
+    fn initialize_matches_groth16_setup() {
+        let mut rng = StdRng::seed_from_u64(1);
+        let (alpha, beta) = (Fr::rand(&mut rng), Fr::rand(&mut rng));
+        // The generator draws tau first, so a copy of its RNG yields the same tau.
+        let tau = Fr::rand(&mut rng.clone());
+        let expected = Groth16::<Bn254>::generate_parameters_with_qap(
+            BiometricVerificationCircuit::default(),
+            alpha,
+            beta,
+            Fr::ONE,
+            Fr::ONE,
+            G1Projective::from(G1Affine::generator()),
+            G2Projective::from(G2Affine::generator()),
+            &mut rng,
+        )
+        .unwrap();
+
+        let powers = PowersOfTau::from_secrets(32, tau, alpha, beta);
+        assert_eq!(initialize(BiometricVerificationCircuit::default(), &powers).unwrap(), expected);
+        assert!(matches!(
+            initialize(BiometricVerificationCircuit::default(), &PowersOfTau::from_secrets(8, tau, alpha, beta)),
+            Err(Phase2Error::NotEnoughPowers { needed: 16, available: 8 })
+        ));
+    }
+
+    #[test]
+    fn contributed_parameters_prove_and_verify() {
+        let mut rng = StdRng::seed_from_u64(2);
+        let (initial, params, contributions, hashes) = ceremony(&mut rng);
+        let transcript_hash = verify_transcript(&initial, &params, &contributions, &hashes, &mut rng).unwrap();
+        assert_eq!(transcript_hash, hashes[1]);
+        assert_ne!(params.delta_g1, G1Affine::generator());
+
+        let (template_hash, salt) = (Fr::from(11u64), Fr::from(31u64));
+        let circuit = BiometricVerificationCircuit {
+            commitment: Some((template_hash + salt).pow([5])),
+            challenge: Some(Fr::from(3u64)),
+            verifier_id: Some(Fr::from(5u64)),
+            expires_at: Some(Fr::from(7u64)),
+            template_hash: Some(template_hash),
+            salt: Some(salt),
+        };
+        let inputs = [circuit.commitment.unwrap(), Fr::from(3u64), Fr::from(5u64), Fr::from(7u64)];
+        let proof = Groth16::<Bn254>::create_random_proof_with_reduction(circuit, &params, &mut rng).unwrap();
+        let vk = prepare_verifying_key(&params.vk);
+        assert!(Groth16::<Bn254>::verify_proof(&vk, &proof, &inputs).unwrap());
+        assert!(!Groth16::<Bn254>::verify_proof(&vk, &proof, &[inputs[0], inputs[1], inputs[2], Fr::from(8u64)]).unwrap());
+    }
+
+    #[test]
+    fn tampered_transcripts_are_rejected() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let (initial, params, contributions, hashes) = ceremony(&mut rng);
+
+        let mut forged = contributions.clone();
+        forged[1].s_delta_g1 = forged[0].s_delta_g1;
+        assert!(matches!(
+            verify_transcript(&initial, &params, &forged, &hashes, &mut rng),
+            Err(Phase2Error::InvalidProofOfKnowledge(2))
+        ));
+
+        let mut wrong_hashes = hashes.clone();
+        wrong_hashes[0][0] ^= 1;
+        assert!(matches!(
+            verify_transcript(&initial, &params, &contributions, &wrong_hashes, &mut rng),
+            Err(Phase2Error::HashMismatch(1))
+        ));
+
+        // Dropping the last contribution leaves its delta unaccounted for.
+        assert!(matches!(
+            verify_transcript(&initial, &params, &contributions[..1], &hashes[..1], &mut rng),
+            Err(Phase2Error::DeltaMismatch)
+        ));
+
+        let mut altered = params.clone();
+        altered.a_query[1] = altered.a_query[2];
+        assert!(matches!(
+            verify_transcript(&initial, &altered, &contributions, &hashes, &mut rng),
+            Err(Phase2Error::QueryMismatch("a_query"))
+        ));
+
+        let mut altered = params.clone();
+        altered.l_query[0] = (altered.l_query[0] * Fr::from(2u64)).into_affine();
+        assert!(matches!(
+            verify_transcript(&initial, &altered, &contributions, &hashes, &mut rng),
+            Err(Phase2Error::QueryMismatch("l_query"))
+        ));
+
+        let mut altered = params.clone();
+        altered.h_query.pop();
+        assert!(matches!(
+            verify_transcript(&initial, &altered, &contributions, &hashes, &mut rng),
+            Err(Phase2Error::QueryMismatch("h_query"))
+        ));
+    }
+}
@@ -0,0 +1,446 @@
+//! Oracle-signed attribute credentials and the zero-knowledge predicates a
+//! holder derives from them. The oracle commits to each KYC attribute with a
+//! Pedersen commitment and signs the commitments once; holders then prove
+//! statements such as "over 18" or "resident in one of these countries"
+//! without revealing the attribute itself. Each credential names the key of
+//! the holder it was issued to, and only a presentation signed with that key
+//! is accepted, so a leaked credential cannot be presented by anyone else.
+
+use curve25519_dalek_ng::ristretto::{CompressedRistretto, RistrettoPoint};
+use curve25519_dalek_ng::scalar::Scalar;
+use rand::rngs::OsRng;
+use secp256k1::{Keypair, Message, Secp256k1, Signing, Verification, XOnlyPublicKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+mod membership;
+mod range;
+
+#[derive(Debug)]
+pub enum DisclosureError {
+    InvalidDate(String),
+    InvalidCountry(String),
+    InvalidEncoding(&'static str),
+    InvalidSignature,
+    InvalidHolderSignature,
+    PredicateNotSatisfied(String),
+}
+
+impl std::fmt::Display for DisclosureError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DisclosureError::InvalidDate(date) => write!(f, "invalid date {}, expected YYYY-MM-DD", date),
+            DisclosureError::InvalidCountry(country) => write!(f, "invalid country code {}, expected ISO 3166-1 alpha-2", country),
+            DisclosureError::InvalidEncoding(field) => write!(f, "invalid encoding for {}", field),
+            DisclosureError::InvalidSignature => write!(f, "credential signature does not verify"),
+            DisclosureError::InvalidHolderSignature => write!(f, "presentation is not signed by the credential's holder"),
+            DisclosureError::PredicateNotSatisfied(predicate) => write!(f, "attributes do not satisfy {}", predicate),
+        }
+    }
+}
+
+impl std::error::Error for DisclosureError {}
+
+/// Attribute commitments signed by the oracle. Safe to show to any verifier.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AttributeCredential {
+    pub human_hash_id: String,
+    /// X-only key of the holder the credential was issued to, hex encoded.
+    pub holder_key: String,
+    pub issuer: String,
+    pub birth_date_commitment: String,
+    pub country_commitment: String,
+    pub issued_at: u64,
+    pub signature: String,
+}
+
+/// Commitment openings; stay with the holder.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AttributeOpenings {
+    /// Birth date as `YYYYMMDD`.
+    pub birth_date: u32,
+    pub birth_date_blinding: String,
+    pub country: String,
+    pub country_blinding: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PredicateRequest {
+    /// Holder was at least `min_age` years old on `as_of` (`YYYYMMDD`).
+    AgeOver { min_age: u32, as_of: u32 },
+    /// Holder's country is one of `countries`.
+    CountryIn { countries: Vec<String> },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum PredicateProof {
+    AgeOver { min_age: u32, as_of: u32, range_proof: String },
+    CountryIn { countries: Vec<String>, challenges: Vec<String>, responses: Vec<String> },
+}
+
+impl PredicateProof {
+    pub fn describe(&self) -> String {
+        match self {
+            PredicateProof::AgeOver { min_age, as_of, .. } => format!("age_over_{}@{}", min_age, as_of),
+            PredicateProof::CountryIn { countries, .. } => format!("country_in_{}", countries.join("_")),
+        }
+    }
+}
+
+/// What a holder hands to a verifier. `context` is verifier-chosen (e.g. a
+/// nonce) and bound into every proof, so a presentation cannot be reused
+/// under a different context.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Presentation {
+    pub credential: AttributeCredential,
+    pub context: String,
+    pub predicates: Vec<PredicateProof>,
+    /// Schnorr signature by the credential's holder key over the credential
+    /// and `context`.
+    pub holder_signature: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PredicateOutcome {
+    pub predicate: String,
+    pub verified: bool,
+}
+
+impl AttributeCredential {
+    fn signing_digest(&self) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(b"humanhash-attribute-credential");
+        for field in [&self.human_hash_id, &self.holder_key, &self.issuer, &self.birth_date_commitment, &self.country_commitment] {
+            hasher.update((field.len() as u64).to_be_bytes());
+            hasher.update(field.as_bytes());
+        }
+        hasher.update(self.issued_at.to_be_bytes());
+        hasher.finalize().into()
+    }
+
+    pub fn verify_signature<C: Verification>(&self, secp: &Secp256k1<C>, issuer_pubkey: &XOnlyPublicKey) -> bool {
+        let signature = match hex::decode(&self.signature).ok().and_then(|bytes| secp256k1::schnorr::Signature::from_slice(&bytes).ok()) {
+            Some(signature) => signature,
+            None => return false,
+        };
+        let message = Message::from_digest(self.signing_digest());
+        secp.verify_schnorr(&signature, &message, issuer_pubkey).is_ok()
+    }
+
+    /// Digest the holder signs to present the credential under `context`.
+    fn presentation_digest(&self, context: &str) -> [u8; 32] {
+        let mut hasher = Sha256::new();
+        hasher.update(b"humanhash-attribute-presentation");
+        for field in [&self.signature, context] {
+            hasher.update((field.len() as u64).to_be_bytes());
+            hasher.update(field.as_bytes());
+        }
+        hasher.finalize().into()
+    }
+}
+
+impl Presentation {
+    pub fn verify_holder_signature<C: Verification>(&self, secp: &Secp256k1<C>) -> bool {
+        let holder_key = hex::decode(&self.credential.holder_key).ok().and_then(|bytes| XOnlyPublicKey::from_slice(&bytes).ok());
+        let signature = hex::decode(&self.holder_signature).ok().and_then(|bytes| secp256k1::schnorr::Signature::from_slice(&bytes).ok());
+        let (Some(holder_key), Some(signature)) = (holder_key, signature) else {
+            return false;
+        };
+        let message = Message::from_digest(self.credential.presentation_digest(&self.context));
+        secp.verify_schnorr(&signature, &message, &holder_key).is_ok()
+    }
+}
+
+/// Commits to the KYC attributes and signs the commitments, and the key of
+/// the holder they are issued to, with the oracle key.
+#[allow(clippy::too_many_arguments)]
+pub fn issue_credential(
+    secp: &Secp256k1<secp256k1::All>,
+    keypair: &Keypair,
+    issuer: &str,
+    human_hash_id: &str,
+    holder_key: &XOnlyPublicKey,
+    birth_date: &str,
+    country: &str,
+    issued_at: u64,
+) -> Result<(AttributeCredential, AttributeOpenings), DisclosureError> {
+    let birth_date = parse_date(birth_date)?;
+    let country_value = country_scalar(country)?;
+    let gens = bulletproofs::PedersenGens::default();
+    let birth_date_blinding = Scalar::random(&mut OsRng);
+    let country_blinding = Scalar::random(&mut OsRng);
+
+    let mut credential = AttributeCredential {
+        human_hash_id: human_hash_id.to_string(),
+        holder_key: hex::encode(holder_key.serialize()),
+        issuer: issuer.to_string(),
+        birth_date_commitment: encode_point(&gens.commit(Scalar::from(birth_date as u64), birth_date_blinding)),
+        country_commitment: encode_point(&gens.commit(country_value, country_blinding)),
+        issued_at,
+        signature: String::new(),
+    };
+    let message = Message::from_digest(credential.signing_digest());
+    credential.signature = hex::encode(secp.sign_schnorr(&message, keypair).serialize());
+
+    Ok((credential, AttributeOpenings {
+        birth_date,
+        birth_date_blinding: hex::encode(birth_date_blinding.to_bytes()),
+        country: country.to_ascii_uppercase(),
+        country_blinding: hex::encode(country_blinding.to_bytes()),
+    }))
+}
+
+/// Holder side: proves each requested predicate about the credential and
+/// signs the presentation with the holder key.
+pub fn prove<C: Signing>(
+    secp: &Secp256k1<C>,
+    holder: &Keypair,
+    credential: &AttributeCredential,
+    openings: &AttributeOpenings,
+    requests: &[PredicateRequest],
+    context: &str,
+) -> Result<Presentation, DisclosureError> {
+    let predicates = requests
+        .iter()
+        .map(|request| match request {
+            PredicateRequest::AgeOver { min_age, as_of } => range::prove_age_over(credential, openings, *min_age, *as_of, context),
+            PredicateRequest::CountryIn { countries } => membership::prove_country_in(credential, openings, countries, context),
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+    let message = Message::from_digest(credential.presentation_digest(context));
+    let holder_signature = hex::encode(secp.sign_schnorr(&message, holder).serialize());
+    Ok(Presentation { credential: credential.clone(), context: context.to_string(), predicates, holder_signature })
+}
+
+/// Verifier side: checks the oracle signature and that the holder presented
+/// the credential, then every predicate proof.
+pub fn verify_presentation<C: Verification>(
+    secp: &Secp256k1<C>,
+    issuer_pubkey: &XOnlyPublicKey,
+    presentation: &Presentation,
+) -> Result<Vec<PredicateOutcome>, DisclosureError> {
+    if !presentation.credential.verify_signature(secp, issuer_pubkey) {
+        return Err(DisclosureError::InvalidSignature);
+    }
+    if !presentation.verify_holder_signature(secp) {
+        return Err(DisclosureError::InvalidHolderSignature);
+    }
+    Ok(presentation
+        .predicates
+        .iter()
+        .map(|predicate| {
+            let verified = match predicate {
+                PredicateProof::AgeOver { min_age, as_of, range_proof } => {
+                    range::verify_age_over(&presentation.credential, *min_age, *as_of, range_proof, &presentation.context)
+                }
+                PredicateProof::CountryIn { countries, challenges, responses } => {
+                    membership::verify_country_in(&presentation.credential, countries, challenges, responses, &presentation.context)
+                }
+            };
+            PredicateOutcome { predicate: predicate.describe(), verified }
+        })
+        .collect())
+}
+
+/// Parses `YYYY-MM-DD` into `YYYYMMDD`, whose integer order matches date order.
+pub fn parse_date(date: &str) -> Result<u32, DisclosureError> {
+    let invalid = || DisclosureError::InvalidDate(date.to_string());
+    let parts: Vec<&str> = date.split('-').collect();
+    if parts.len() != 3 || parts[0].len() != 4 || parts[1].len() != 2 || parts[2].len() != 2 {
+        return Err(invalid());
+    }
+    let year: u32 = parts[0].parse().map_err(|_| invalid())?;
+    let month: u32 = parts[1].parse().map_err(|_| invalid())?;
+    let day: u32 = parts[2].parse().map_err(|_| invalid())?;
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return Err(invalid());
+    }
+    Ok(year * 10000 + month * 100 + day)
+}
+
+fn country_scalar(country: &str) -> Result<Scalar, DisclosureError> {
+    let bytes = country.as_bytes();
+    if bytes.len() != 2 || !bytes.iter().all(u8::is_ascii_alphabetic) {
+        return Err(DisclosureError::InvalidCountry(country.to_string()));
+    }
+    let upper = country.to_ascii_uppercase();
+    let upper = upper.as_bytes();
+    Ok(Scalar::from(((upper[0] as u64) << 8) | upper[1] as u64))
+}
+
+fn encode_point(point: &RistrettoPoint) -> String {
+    hex::encode(point.compress().as_bytes())
+}
+
+fn decode_point(value: &str, field: &'static str) -> Result<RistrettoPoint, DisclosureError> {
+    let bytes = hex::decode(value).map_err(|_| DisclosureError::InvalidEncoding(field))?;
+    if bytes.len() != 32 {
+        return Err(DisclosureError::InvalidEncoding(field));
+    }
+    CompressedRistretto::from_slice(&bytes).decompress().ok_or(DisclosureError::InvalidEncoding(field))
+}
+
+fn decode_scalar(value: &str, field: &'static str) -> Result<Scalar, DisclosureError> {
+    let bytes: [u8; 32] = hex::decode(value)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or(DisclosureError::InvalidEncoding(field))?;
+    Scalar::from_canonical_bytes(bytes).ok_or(DisclosureError::InvalidEncoding(field))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AS_OF: u32 = 20250101;
+
+    fn holder() -> Keypair {
+        Keypair::from_seckey_slice(&Secp256k1::new(), &[9; 32]).unwrap()
+    }
+
+    fn issued() -> (Keypair, AttributeCredential, AttributeOpenings) {
+        let secp = Secp256k1::new();
+        let keypair = Keypair::from_seckey_slice(&secp, &[7; 32]).unwrap();
+        let holder_key = holder().x_only_public_key().0;
+        let (credential, openings) = issue_credential(&secp, &keypair, "oracle", "hh-1", &holder_key, "2000-06-15", "nl", 1_800_000_000).unwrap();
+        (keypair, credential, openings)
+    }
+
+    fn present(credential: &AttributeCredential, openings: &AttributeOpenings, requests: &[PredicateRequest], context: &str) -> Result<Presentation, DisclosureError> {
+        prove(&Secp256k1::new(), &holder(), credential, openings, requests, context)
+    }
+
+    /// The holder signing an altered presentation, so only the proofs
+    /// themselves can reject it.
+    fn resign(presentation: &mut Presentation) {
+        let message = Message::from_digest(presentation.credential.presentation_digest(&presentation.context));
+        presentation.holder_signature = hex::encode(Secp256k1::new().sign_schnorr(&message, &holder()).serialize());
+    }
+
+    fn outcomes(keypair: &Keypair, presentation: &Presentation) -> Vec<bool> {
+        let outcomes = verify_presentation(&Secp256k1::verification_only(), &keypair.x_only_public_key().0, presentation).unwrap();
+        outcomes.iter().map(|outcome| outcome.verified).collect()
+    }
+
+    fn countries(list: &[&str]) -> Vec<String> {
+        list.iter().map(|country| country.to_string()).collect()
+    }
+
+    #[test]
+    fn age_over_round_trip() {
+        let (keypair, credential, openings) = issued();
+        let requests = [PredicateRequest::AgeOver { min_age: 18, as_of: AS_OF }, PredicateRequest::AgeOver { min_age: 18, as_of: 20180615 }];
+        let presentation = present(&credential, &openings, &requests, "nonce-1").unwrap();
+        assert_eq!(outcomes(&keypair, &presentation), vec![true, true]);
+
+        // The day before the eighteenth birthday.
+        let request = [PredicateRequest::AgeOver { min_age: 18, as_of: 20180614 }];
+        assert!(matches!(present(&credential, &openings, &request, "nonce-1"), Err(DisclosureError::PredicateNotSatisfied(_))));
+    }
+
+    #[test]
+    fn tampered_age_proofs_fail() {
+        let (keypair, credential, openings) = issued();
+        let presentation = present(&credential, &openings, &[PredicateRequest::AgeOver { min_age: 18, as_of: AS_OF }], "nonce-1").unwrap();
+        let PredicateProof::AgeOver { range_proof, .. } = &presentation.predicates[0] else { unreachable!() };
+
+        let mut replayed = presentation.clone();
+        replayed.context = "nonce-2".to_string();
+        resign(&mut replayed);
+        assert_eq!(outcomes(&keypair, &replayed), vec![false]);
+
+        for (min_age, as_of) in [(21, AS_OF), (18, AS_OF + 10000)] {
+            let mut altered = presentation.clone();
+            altered.predicates[0] = PredicateProof::AgeOver { min_age, as_of, range_proof: range_proof.clone() };
+            assert_eq!(outcomes(&keypair, &altered), vec![false]);
+        }
+
+        let mut bytes = hex::decode(range_proof).unwrap();
+        bytes[40] ^= 1;
+        let mut altered = presentation.clone();
+        altered.predicates[0] = PredicateProof::AgeOver { min_age: 18, as_of: AS_OF, range_proof: hex::encode(bytes) };
+        assert_eq!(outcomes(&keypair, &altered), vec![false]);
+
+        // The proof does not carry over to another birth-date commitment.
+        let (_, other, _) = issued();
+        assert!(!range::verify_age_over(&other, 18, AS_OF, range_proof, "nonce-1"));
+    }
+
+    #[test]
+    fn country_in_round_trip() {
+        let (keypair, credential, openings) = issued();
+        let requests = [PredicateRequest::CountryIn { countries: countries(&["DE", "NL", "FR"]) }, PredicateRequest::CountryIn { countries: countries(&["nl"]) }];
+        let presentation = present(&credential, &openings, &requests, "nonce-1").unwrap();
+        assert_eq!(outcomes(&keypair, &presentation), vec![true, true]);
+
+        let request = [PredicateRequest::CountryIn { countries: countries(&["DE", "FR"]) }];
+        assert!(matches!(present(&credential, &openings, &request, "nonce-1"), Err(DisclosureError::PredicateNotSatisfied(_))));
+    }
+
+    #[test]
+    fn tampered_country_proofs_fail() {
+        let (keypair, credential, openings) = issued();
+        let listed = countries(&["DE", "NL", "FR"]);
+        let presentation = present(&credential, &openings, &[PredicateRequest::CountryIn { countries: listed.clone() }], "nonce-1").unwrap();
+        let PredicateProof::CountryIn { challenges, responses, .. } = presentation.predicates[0].clone() else { unreachable!() };
+
+        let mut replayed = presentation.clone();
+        replayed.context = "nonce-2".to_string();
+        resign(&mut replayed);
+        assert_eq!(outcomes(&keypair, &replayed), vec![false]);
+
+        let mut swapped_challenges = challenges.clone();
+        swapped_challenges.swap(0, 1);
+        let mut swapped_responses = responses.clone();
+        swapped_responses.swap(1, 2);
+        for (countries, challenges, responses) in [
+            (countries(&["DE", "BE", "FR"]), challenges.clone(), responses.clone()),
+            (listed.clone(), swapped_challenges, responses.clone()),
+            (listed.clone(), challenges.clone(), swapped_responses),
+            (listed[..2].to_vec(), challenges[..2].to_vec(), responses[..2].to_vec()),
+            (listed.clone(), challenges.clone(), responses[..2].to_vec()),
+        ] {
+            let mut altered = presentation.clone();
+            altered.predicates[0] = PredicateProof::CountryIn { countries, challenges, responses };
+            assert_eq!(outcomes(&keypair, &altered), vec![false]);
+        }
+    }
+
+    #[test]
+    fn credentials_need_the_issuer_signature() {
+        let (keypair, credential, openings) = issued();
+        let presentation = present(&credential, &openings, &[PredicateRequest::AgeOver { min_age: 18, as_of: AS_OF }], "nonce-1").unwrap();
+        let secp = Secp256k1::new();
+
+        let other = Keypair::from_seckey_slice(&secp, &[8; 32]).unwrap();
+        assert!(matches!(verify_presentation(&secp, &other.x_only_public_key().0, &presentation), Err(DisclosureError::InvalidSignature)));
+
+        let mut altered = presentation.clone();
+        altered.credential.human_hash_id = "hh-2".to_string();
+        assert!(matches!(verify_presentation(&secp, &keypair.x_only_public_key().0, &altered), Err(DisclosureError::InvalidSignature)));
+    }
+
+    #[test]
+    fn presentations_need_the_holder_signature() {
+        let (keypair, credential, openings) = issued();
+        let presentation = present(&credential, &openings, &[PredicateRequest::AgeOver { min_age: 18, as_of: AS_OF }], "nonce-1").unwrap();
+        let secp = Secp256k1::new();
+        let issuer = keypair.x_only_public_key().0;
+        assert!(verify_presentation(&secp, &issuer, &presentation).is_ok());
+
+        // Someone else holding the credential and its openings.
+        let other = Keypair::from_seckey_slice(&secp, &[10; 32]).unwrap();
+        let stolen = prove(&secp, &other, &credential, &openings, &[PredicateRequest::AgeOver { min_age: 18, as_of: AS_OF }], "nonce-1").unwrap();
+        assert!(matches!(verify_presentation(&secp, &issuer, &stolen), Err(DisclosureError::InvalidHolderSignature)));
+
+        let mut replayed = presentation.clone();
+        replayed.context = "nonce-2".to_string();
+        assert!(matches!(verify_presentation(&secp, &issuer, &replayed), Err(DisclosureError::InvalidHolderSignature)));
+
+        let mut rebound = stolen.clone();
+        rebound.credential.holder_key = hex::encode(other.x_only_public_key().0.serialize());
+        assert!(matches!(verify_presentation(&secp, &issuer, &rebound), Err(DisclosureError::InvalidSignature)));
+    }
+}
@@ -0,0 +1,114 @@
+use bulletproofs::PedersenGens;
+use curve25519_dalek_ng::ristretto::RistrettoPoint;
+use curve25519_dalek_ng::scalar::Scalar;
+use rand::rngs::OsRng;
+use sha2::{Digest, Sha512};
+
+use crate::{country_scalar, decode_point, decode_scalar, AttributeCredential, AttributeOpenings, DisclosureError, PredicateProof};
+
+/// One-out-of-many proof (Cramer–Damgård–Schoenmakers OR composition of
+/// Schnorr proofs) that the country commitment opens to one of `countries`:
+/// for the true entry `C - c_i·B` is `r·H` for a known `r`, the others are
+/// simulated, and the verifier cannot tell which branch was real.
+pub(crate) fn prove_country_in(
+    credential: &AttributeCredential,
+    openings: &AttributeOpenings,
+    countries: &[String],
+    context: &str,
+) -> Result<PredicateProof, DisclosureError> {
+    let predicate = format!("country_in_{}", countries.join("_"));
+    let real = countries
+        .iter()
+        .position(|country| country.eq_ignore_ascii_case(&openings.country))
+        .ok_or(DisclosureError::PredicateNotSatisfied(predicate))?;
+    let blinding = decode_scalar(&openings.country_blinding, "country_blinding")?;
+    let statements = statements(credential, countries)?;
+    let h = PedersenGens::default().B_blinding;
+
+    let mut challenges = vec![Scalar::zero(); countries.len()];
+    let mut responses = vec![Scalar::zero(); countries.len()];
+    let mut nonces = Vec::with_capacity(countries.len());
+    let witness_nonce = Scalar::random(&mut OsRng);
+    for (i, statement) in statements.iter().enumerate() {
+        if i == real {
+            nonces.push(h * witness_nonce);
+        } else {
+            challenges[i] = Scalar::random(&mut OsRng);
+            responses[i] = Scalar::random(&mut OsRng);
+            nonces.push(h * responses[i] - statement * challenges[i]);
+        }
+    }
+
+    let total = challenge(credential, countries, &statements, &nonces, context);
+    let simulated: Scalar = challenges.iter().sum();
+    challenges[real] = total - simulated;
+    responses[real] = witness_nonce + challenges[real] * blinding;
+
+    Ok(PredicateProof::CountryIn {
+        countries: countries.to_vec(),
+        challenges: challenges.iter().map(|c| hex::encode(c.to_bytes())).collect(),
+        responses: responses.iter().map(|z| hex::encode(z.to_bytes())).collect(),
+    })
+}
+
+pub(crate) fn verify_country_in(
+    credential: &AttributeCredential,
+    countries: &[String],
+    challenges: &[String],
+    responses: &[String],
+    context: &str,
+) -> bool {
+    if countries.is_empty() || challenges.len() != countries.len() || responses.len() != countries.len() {
+        return false;
+    }
+    let statements = match statements(credential, countries) {
+        Ok(statements) => statements,
+        Err(_) => return false,
+    };
+    let decoded = challenges
+        .iter()
+        .zip(responses)
+        .map(|(c, z)| Ok((decode_scalar(c, "challenge")?, decode_scalar(z, "response")?)))
+        .collect::<Result<Vec<_>, DisclosureError>>();
+    let decoded = match decoded {
+        Ok(decoded) => decoded,
+        Err(_) => return false,
+    };
+
+    let h = PedersenGens::default().B_blinding;
+    let nonces: Vec<RistrettoPoint> = statements
+        .iter()
+        .zip(&decoded)
+        .map(|(statement, (c, z))| h * z - statement * c)
+        .collect();
+    let sum: Scalar = decoded.iter().map(|(c, _)| c).sum();
+    sum == challenge(credential, countries, &statements, &nonces, context)
+}
+
+/// `C - c_i·B` for every candidate country `c_i`.
+fn statements(credential: &AttributeCredential, countries: &[String]) -> Result<Vec<RistrettoPoint>, DisclosureError> {
+    let commitment = decode_point(&credential.country_commitment, "country_commitment")?;
+    let b = PedersenGens::default().B;
+    countries.iter().map(|country| Ok(commitment - b * country_scalar(country)?)).collect()
+}
+
+fn challenge(
+    credential: &AttributeCredential,
+    countries: &[String],
+    statements: &[RistrettoPoint],
+    nonces: &[RistrettoPoint],
+    context: &str,
+) -> Scalar {
+    let mut hasher = Sha512::new();
+    hasher.update(b"humanhash-country-in");
+    hasher.update(credential.country_commitment.as_bytes());
+    for country in countries {
+        hasher.update(country.to_ascii_uppercase().as_bytes());
+    }
+    for point in statements.iter().chain(nonces) {
+        hasher.update(point.compress().as_bytes());
+    }
+    hasher.update((context.len() as u64).to_be_bytes());
+    hasher.update(context.as_bytes());
+    Scalar::from_bytes_mod_order_wide(&hasher.finalize().into())
+}
@@ -0,0 +1,78 @@
+use bulletproofs::{BulletproofGens, PedersenGens, RangeProof};
+use curve25519_dalek_ng::scalar::Scalar;
+use merlin::Transcript;
+
+use crate::{decode_point, decode_scalar, AttributeCredential, AttributeOpenings, DisclosureError, PredicateProof};
+
+/// Bit width of the range proof; `YYYYMMDD` differences fit comfortably.
+const RANGE_BITS: usize = 32;
+
+/// `birth_date <= as_of - min_age years` holds iff `cutoff - birth_date` is
+/// non-negative. The verifier derives a commitment to that difference from the
+/// signed birth-date commitment, and the holder proves it lies in `[0, 2^32)`.
+pub(crate) fn prove_age_over(
+    credential: &AttributeCredential,
+    openings: &AttributeOpenings,
+    min_age: u32,
+    as_of: u32,
+    context: &str,
+) -> Result<PredicateProof, DisclosureError> {
+    let predicate = format!("age_over_{}", min_age);
+    let cutoff = cutoff(min_age, as_of).ok_or_else(|| DisclosureError::PredicateNotSatisfied(predicate.clone()))?;
+    let difference = cutoff.checked_sub(openings.birth_date).ok_or(DisclosureError::PredicateNotSatisfied(predicate))?;
+    let blinding = decode_scalar(&openings.birth_date_blinding, "birth_date_blinding")?;
+
+    let mut transcript = age_transcript(credential, min_age, as_of, context);
+    let (proof, _) = RangeProof::prove_single(
+        &BulletproofGens::new(RANGE_BITS, 1),
+        &PedersenGens::default(),
+        &mut transcript,
+        difference as u64,
+        &-blinding,
+        RANGE_BITS,
+    )
+    .map_err(|_| DisclosureError::InvalidEncoding("birth_date"))?;
+
+    Ok(PredicateProof::AgeOver { min_age, as_of, range_proof: hex::encode(proof.to_bytes()) })
+}
+
+pub(crate) fn verify_age_over(credential: &AttributeCredential, min_age: u32, as_of: u32, range_proof: &str, context: &str) -> bool {
+    let cutoff = match cutoff(min_age, as_of) {
+        Some(cutoff) => cutoff,
+        None => return false,
+    };
+    let birth_date_commitment = match decode_point(&credential.birth_date_commitment, "birth_date_commitment") {
+        Ok(point) => point,
+        Err(_) => return false,
+    };
+    let proof = match hex::decode(range_proof).ok().and_then(|bytes| RangeProof::from_bytes(&bytes).ok()) {
+        Some(proof) => proof,
+        None => return false,
+    };
+
+    let gens = PedersenGens::default();
+    let difference_commitment = gens.B * Scalar::from(cutoff as u64) - birth_date_commitment;
+    let mut transcript = age_transcript(credential, min_age, as_of, context);
+    proof
+        .verify_single(
+            &BulletproofGens::new(RANGE_BITS, 1),
+            &gens,
+            &mut transcript,
+            &difference_commitment.compress(),
+            RANGE_BITS,
+        )
+        .is_ok()
+}
+
+fn cutoff(min_age: u32, as_of: u32) -> Option<u32> {
+    as_of.checked_sub(min_age.checked_mul(10000)?)
+}
+
+fn age_transcript(credential: &AttributeCredential, min_age: u32, as_of: u32, context: &str) -> Transcript {
+    let mut transcript = Transcript::new(b"humanhash-age-over");
+    transcript.append_message(b"commitment", credential.birth_date_commitment.as_bytes());
+    transcript.append_u64(b"min_age", min_age as u64);
+    transcript.append_u64(b"as_of", as_of as u64);
+    transcript.append_message(b"context", context.as_bytes());
+    transcript
+}
@@ -0,0 +1,281 @@
+//! Client for LND's REST API, shared by the services that talk to the node.
+//! One pooled HTTP client is kept for the life of the process; the macaroon
+//! and TLS certificate are read again only when their files change, so
+//! rotating either needs no restart.
+
+use reqwest::{Client, Method};
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::RwLock;
+use std::time::{Duration, SystemTime};
+
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
+mod types;
+
+pub use types::{
+    AddInvoice, AddedInvoice, Amount, Channel, ChannelBalance, Channels, FeeLimit, Hash, Invoice, InvoiceState, NodeInfo, PayReq, Payment,
+    SendPayment, WalletBalance,
+};
+use types::{ErrorBody, SendPaymentResponse};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+
+#[derive(Debug)]
+pub enum LndError {
+    /// The macaroon or TLS certificate could not be read.
+    Credentials(String),
+    Transport(String),
+    /// LND answered with an error status.
+    Node { status: u16, message: String },
+    InvalidResponse(String),
+    PaymentFailed(String),
+}
+
+impl std::fmt::Display for LndError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LndError::Credentials(e) => write!(f, "LND credentials: {}", e),
+            LndError::Transport(e) => write!(f, "LND unreachable: {}", e),
+            LndError::Node { status, message } => write!(f, "LND returned {}: {}", status, message),
+            LndError::InvalidResponse(e) => write!(f, "invalid LND response: {}", e),
+            LndError::PaymentFailed(e) => write!(f, "payment failed: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for LndError {}
+
+/// Size and modification time, enough to notice a file was replaced.
+type Fingerprint = (u64, SystemTime);
+
+struct Credentials {
+    http: Client,
+    macaroon: String,
+    macaroon_fingerprint: Fingerprint,
+    tls_cert_fingerprint: Option<Fingerprint>,
+}
+
+pub struct LndClient {
+    host: String,
+    macaroon_path: PathBuf,
+    /// Without one, the system's roots are trusted (or the host is plain
+    /// HTTP, as with the mock).
+    tls_cert_path: Option<PathBuf>,
+    credentials: RwLock<Credentials>,
+}
+
+impl LndClient {
+    /// Reads the credentials once up front, so a misconfigured path fails at
+    /// startup rather than on the first request.
+    pub fn new(host: &str, macaroon_path: impl Into<PathBuf>, tls_cert_path: Option<PathBuf>) -> Result<Self, LndError> {
+        let macaroon_path = macaroon_path.into();
+        let credentials = load_credentials(&macaroon_path, tls_cert_path.as_deref(), None)?;
+        Ok(LndClient { host: host.trim_end_matches('/').to_string(), macaroon_path, tls_cert_path, credentials: RwLock::new(credentials) })
+    }
+
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    pub async fn get_info(&self) -> Result<NodeInfo, LndError> {
+        self.call(Method::GET, "/v1/getinfo", None::<&()>).await
+    }
+
+    pub async fn add_invoice(&self, invoice: &AddInvoice) -> Result<AddedInvoice, LndError> {
+        self.call(Method::POST, "/v1/invoices", Some(invoice)).await
+    }
+
+    pub async fn lookup_invoice(&self, payment_hash: &Hash) -> Result<Invoice, LndError> {
+        self.call(Method::GET, &format!("/v1/invoice/{}", hex::encode(payment_hash)), None::<&()>).await
+    }
+
+    /// Decodes a BOLT11 invoice, so its amount can be checked before paying.
+    pub async fn decode_payment_request(&self, payment_request: &str) -> Result<PayReq, LndError> {
+        self.call(Method::GET, &format!("/v1/payreq/{}", payment_request), None::<&()>).await
+    }
+
+    /// Pays a BOLT11 invoice and waits for the outcome. A payment LND
+    /// reports as failed, or whose preimage does not match its hash, is an
+    /// error.
+    pub async fn pay(&self, payment: &SendPayment) -> Result<Payment, LndError> {
+        let response: SendPaymentResponse = self.call(Method::POST, "/v1/channels/transactions", Some(payment)).await?;
+        if !response.payment_error.is_empty() {
+            return Err(LndError::PaymentFailed(response.payment_error));
+        }
+        let (Some(payment_hash), Some(payment_preimage)) = (response.payment_hash, response.payment_preimage) else {
+            return Err(LndError::InvalidResponse("payment without hash or preimage".into()));
+        };
+        if <[u8; 32]>::from(Sha256::digest(payment_preimage)) != payment_hash {
+            return Err(LndError::InvalidResponse("preimage does not match the payment hash".into()));
+        }
+        Ok(Payment { payment_hash, payment_preimage })
+    }
+
+    pub async fn channels(&self) -> Result<Vec<Channel>, LndError> {
+        let channels: Channels = self.call(Method::GET, "/v1/channels", None::<&()>).await?;
+        Ok(channels.channels)
+    }
+
+    pub async fn channel_balance(&self) -> Result<ChannelBalance, LndError> {
+        self.call(Method::GET, "/v1/balance/channels", None::<&()>).await
+    }
+
+    pub async fn wallet_balance(&self) -> Result<WalletBalance, LndError> {
+        self.call(Method::GET, "/v1/balance/blockchain", None::<&()>).await
+    }
+
+    async fn call<B: Serialize, T: DeserializeOwned>(&self, method: Method, path: &str, body: Option<&B>) -> Result<T, LndError> {
+        let (http, macaroon) = self.current_credentials()?;
+        let mut request = http.request(method, format!("{}{}", self.host, path)).header("Grpc-Metadata-macaroon", macaroon);
+        if let Some(body) = body {
+            request = request.json(body);
+        }
+        let response = request.send().await.map_err(|e| LndError::Transport(e.to_string()))?;
+        let status = response.status();
+        if !status.is_success() {
+            let text = response.text().await.unwrap_or_default();
+            let message = serde_json::from_str::<ErrorBody>(&text).map(|body| body.message).unwrap_or(text);
+            return Err(LndError::Node { status: status.as_u16(), message });
+        }
+        response.json().await.map_err(|e| LndError::InvalidResponse(e.to_string()))
+    }
+
+    /// The HTTP client and hex macaroon to use, reloaded first if either
+    /// file changed. The connection pool is kept unless the certificate
+    /// changed.
+    fn current_credentials(&self) -> Result<(Client, String), LndError> {
+        let macaroon_fingerprint = fingerprint(&self.macaroon_path)?;
+        let tls_cert_fingerprint = self.tls_cert_path.as_deref().map(fingerprint).transpose()?;
+        {
+            let credentials = self.credentials.read().unwrap();
+            if credentials.macaroon_fingerprint == macaroon_fingerprint && credentials.tls_cert_fingerprint == tls_cert_fingerprint {
+                return Ok((credentials.http.clone(), credentials.macaroon.clone()));
+            }
+        }
+        let mut credentials = self.credentials.write().unwrap();
+        let http = (credentials.tls_cert_fingerprint == tls_cert_fingerprint).then(|| credentials.http.clone());
+        *credentials = load_credentials(&self.macaroon_path, self.tls_cert_path.as_deref(), http)?;
+        Ok((credentials.http.clone(), credentials.macaroon.clone()))
+    }
+}
+
+fn load_credentials(macaroon_path: &Path, tls_cert_path: Option<&Path>, http: Option<Client>) -> Result<Credentials, LndError> {
+    let macaroon_fingerprint = fingerprint(macaroon_path)?;
+    let macaroon = fs::read(macaroon_path).map_err(|e| LndError::Credentials(format!("{}: {}", macaroon_path.display(), e)))?;
+    let tls_cert_fingerprint = tls_cert_path.map(fingerprint).transpose()?;
+    let http = match http {
+        Some(http) => http,
+        None => {
+            let mut builder = Client::builder().timeout(REQUEST_TIMEOUT);
+            if let Some(path) = tls_cert_path {
+                let pem = fs::read(path).map_err(|e| LndError::Credentials(format!("{}: {}", path.display(), e)))?;
+                let certificate = reqwest::Certificate::from_pem(&pem).map_err(|e| LndError::Credentials(format!("{}: {}", path.display(), e)))?;
+                builder = builder.add_root_certificate(certificate);
+            }
+            builder.build().map_err(|e| LndError::Credentials(e.to_string()))?
+        }
+    };
+    Ok(Credentials { http, macaroon: hex::encode(macaroon), macaroon_fingerprint, tls_cert_fingerprint })
+}
+
+fn fingerprint(path: &Path) -> Result<Fingerprint, LndError> {
+    let metadata = fs::metadata(path).map_err(|e| LndError::Credentials(format!("{}: {}", path.display(), e)))?;
+    let modified = metadata.modified().map_err(|e| LndError::Credentials(format!("{}: {}", path.display(), e)))?;
+    Ok((metadata.len(), modified))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::mock::{MockLnd, IDENTITY_PUBKEY};
+    use super::*;
+
+    #[tokio::test]
+    async fn invoices_round_trip() {
+        let lnd = MockLnd::start().await;
+        let client = lnd.client();
+        assert_eq!(client.get_info().await.unwrap().identity_pubkey, IDENTITY_PUBKEY);
+
+        let description_hash: Hash = Sha256::digest("what is paid for").into();
+        let added = client.add_invoice(&AddInvoice { value: 1000, description_hash: Some(description_hash), expiry: 600, ..Default::default() }).await.unwrap();
+        let invoice = client.lookup_invoice(&added.payment_hash).await.unwrap();
+        assert_eq!(invoice.state, InvoiceState::Open);
+        assert_eq!(invoice.value, 1000);
+        assert_eq!(invoice.description_hash, Some(description_hash));
+        assert_eq!(invoice.payment_request, added.payment_request);
+        let decoded = client.decode_payment_request(&added.payment_request).await.unwrap();
+        assert_eq!((decoded.num_satoshis, decoded.payment_hash), (1000, hex::encode(added.payment_hash)));
+        assert_eq!(client.decode_payment_request("lnbc25u1elsewhere").await.unwrap().num_satoshis, 2500);
+        assert_eq!(client.decode_payment_request("lnbc1elsewhere").await.unwrap().num_satoshis, 0);
+
+        lnd.settle(&added.payment_hash, 1000);
+        let invoice = client.lookup_invoice(&added.payment_hash).await.unwrap();
+        assert_eq!((invoice.state, invoice.amt_paid_sat), (InvoiceState::Settled, 1000));
+
+        match client.lookup_invoice(&[0; 32]).await {
+            Err(LndError::Node { status: 404, message }) => assert_eq!(message, "unable to locate invoice"),
+            other => panic!("expected 404, got {:?}", other),
+        }
+    }
+
+    #[tokio::test]
+    async fn payments_settle_and_fail() {
+        let lnd = MockLnd::start().await;
+        let client = lnd.client();
+        let added = client.add_invoice(&AddInvoice { value: 250, expiry: 600, ..Default::default() }).await.unwrap();
+        let payment = client.pay(&SendPayment { payment_request: added.payment_request.clone(), fee_limit: Some(FeeLimit { fixed: 10 }) }).await.unwrap();
+        assert_eq!(payment.payment_hash, added.payment_hash);
+        assert_eq!(client.lookup_invoice(&added.payment_hash).await.unwrap().state, InvoiceState::Settled);
+        assert_eq!(lnd.payments(), vec![added.payment_request.clone()]);
+
+        match client.pay(&SendPayment { payment_request: added.payment_request, fee_limit: None }).await {
+            Err(LndError::PaymentFailed(error)) => assert_eq!(error, "invoice is already paid"),
+            other => panic!("expected a failed payment, got {:?}", other),
+        }
+        lnd.fail_payments(Some("no route"));
+        assert!(matches!(client.pay(&SendPayment { payment_request: "lnbc1elsewhere".into(), fee_limit: None }).await, Err(LndError::PaymentFailed(_))));
+    }
+
+    #[tokio::test]
+    async fn channels_and_balances() {
+        let lnd = MockLnd::start().await;
+        let channel = Channel { chan_id: 870_000_000_000_001, remote_pubkey: "03ab".into(), active: true, capacity: 1_000_000, local_balance: 600_000, remote_balance: 390_000 };
+        lnd.set_channels(vec![channel]);
+        lnd.set_balances(
+            ChannelBalance { local_balance: Amount { sat: 600_000 }, remote_balance: Amount { sat: 390_000 } },
+            WalletBalance { total_balance: 50_000, confirmed_balance: 40_000, unconfirmed_balance: 10_000 },
+        );
+        let client = lnd.client();
+        let channels = client.channels().await.unwrap();
+        assert_eq!((channels.len(), channels[0].chan_id, channels[0].local_balance), (1, 870_000_000_000_001, 600_000));
+        assert_eq!(client.get_info().await.unwrap().num_active_channels, 1);
+        assert_eq!(client.channel_balance().await.unwrap().remote_balance.sat, 390_000);
+        assert_eq!(client.wallet_balance().await.unwrap().unconfirmed_balance, 10_000);
+    }
+
+    #[tokio::test]
+    async fn rotated_macaroons_are_picked_up() {
+        let lnd = MockLnd::start().await;
+        let client = lnd.client();
+        client.get_info().await.unwrap();
+        lnd.rotate_macaroon();
+        client.get_info().await.unwrap();
+
+        let reloading = LndClient::new(lnd.host(), lnd.macaroon_path(), None).unwrap();
+        let copy = std::env::temp_dir().join(format!("lnd-stale-{}.macaroon", std::process::id()));
+        fs::copy(lnd.macaroon_path(), &copy).unwrap();
+        let pinned = LndClient::new(lnd.host(), &copy, None).unwrap();
+        lnd.rotate_macaroon();
+        reloading.get_info().await.unwrap();
+        assert!(matches!(pinned.get_info().await, Err(LndError::Node { status: 403, .. })));
+        fs::remove_file(copy).unwrap();
+    }
+
+    #[test]
+    fn missing_credentials_fail_up_front() {
+        assert!(matches!(LndClient::new("https://localhost:8080", "/nonexistent/admin.macaroon", None), Err(LndError::Credentials(_))));
+    }
+}
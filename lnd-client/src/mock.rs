@@ -0,0 +1,288 @@
+//! An in-process stand-in for LND's REST API, for testing services offline.
+//! It serves plain HTTP on a loopback port, checks the macaroon it wrote to
+//! disk, and keeps invoices, payments, channels and balances in memory for
+//! the test to inspect and drive.
+
+use axum::extract::{Path, State};
+use axum::http::{HeaderMap, StatusCode};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use tokio::task::JoinHandle;
+
+use crate::types::SendPaymentResponse;
+use crate::{AddInvoice, AddedInvoice, Channel, ChannelBalance, Channels, Hash, Invoice, InvoiceState, LndClient, NodeInfo, PayReq, SendPayment, WalletBalance};
+
+pub const IDENTITY_PUBKEY: &str = "02a5c3a7a4d4cbb7e4a1c6e3a1fa3f2f2a1f4b0b5b8c6e0c8a2f1d0b7c3e9a4d11";
+
+struct MockInvoice {
+    invoice: Invoice,
+    preimage: Hash,
+}
+
+#[derive(Default)]
+struct MockState {
+    macaroon: Vec<u8>,
+    invoices: HashMap<Hash, MockInvoice>,
+    payments: Vec<String>,
+    payment_error: Option<String>,
+    channels: Vec<Channel>,
+    channel_balance: ChannelBalance,
+    wallet_balance: WalletBalance,
+}
+
+pub struct MockLnd {
+    host: String,
+    dir: PathBuf,
+    state: Arc<Mutex<MockState>>,
+    server: JoinHandle<()>,
+}
+
+impl MockLnd {
+    /// Starts the mock on a free loopback port.
+    pub async fn start() -> MockLnd {
+        static STARTED: AtomicUsize = AtomicUsize::new(0);
+        let dir = std::env::temp_dir().join(format!("lnd-mock-{}-{}", std::process::id(), STARTED.fetch_add(1, Ordering::Relaxed)));
+        fs::create_dir_all(&dir).expect("create mock LND directory");
+        let state = Arc::new(Mutex::new(MockState::default()));
+        let app = Router::new()
+            .route("/v1/getinfo", get(get_info))
+            .route("/v1/invoices", post(add_invoice))
+            .route("/v1/invoice/:payment_hash", get(lookup_invoice))
+            .route("/v1/payreq/:payment_request", get(decode_payment_request))
+            .route("/v1/channels/transactions", post(send_payment))
+            .route("/v1/channels", get(channels))
+            .route("/v1/balance/channels", get(channel_balance))
+            .route("/v1/balance/blockchain", get(wallet_balance))
+            .with_state(state.clone());
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.expect("bind mock LND");
+        let host = format!("http://{}", listener.local_addr().unwrap());
+        let server = tokio::spawn(async move {
+            axum::serve(listener, app).await.expect("serve mock LND");
+        });
+        let mock = MockLnd { host, dir, state, server };
+        mock.rotate_macaroon();
+        mock
+    }
+
+    pub fn host(&self) -> &str {
+        &self.host
+    }
+
+    pub fn macaroon_path(&self) -> PathBuf {
+        self.dir.join("admin.macaroon")
+    }
+
+    /// A client for the mock, reading the mock's macaroon file.
+    pub fn client(&self) -> LndClient {
+        LndClient::new(&self.host, self.macaroon_path(), None).expect("mock LND credentials")
+    }
+
+    /// Bakes a new macaroon and writes it over the old one, which stops
+    /// being accepted.
+    pub fn rotate_macaroon(&self) {
+        let macaroon: [u8; 32] = rand::random();
+        self.state.lock().unwrap().macaroon = macaroon.to_vec();
+        fs::write(self.macaroon_path(), macaroon).expect("write mock macaroon");
+    }
+
+    pub fn invoice(&self, payment_hash: &Hash) -> Option<Invoice> {
+        self.state.lock().unwrap().invoices.get(payment_hash).map(|invoice| invoice.invoice.clone())
+    }
+
+    pub fn invoice_count(&self) -> usize {
+        self.state.lock().unwrap().invoices.len()
+    }
+
+    /// Settles an invoice as if `amount_sat` had been paid to it.
+    pub fn settle(&self, payment_hash: &Hash, amount_sat: u64) {
+        let mut state = self.state.lock().unwrap();
+        let invoice = &mut state.invoices.get_mut(payment_hash).expect("unknown invoice").invoice;
+        invoice.state = InvoiceState::Settled;
+        invoice.amt_paid_sat = amount_sat;
+        invoice.settle_date = unix_now();
+    }
+
+    pub fn cancel(&self, payment_hash: &Hash) {
+        self.state.lock().unwrap().invoices.get_mut(payment_hash).expect("unknown invoice").invoice.state = InvoiceState::Canceled;
+    }
+
+    /// Payment requests paid through the mock, in order.
+    pub fn payments(&self) -> Vec<String> {
+        self.state.lock().unwrap().payments.clone()
+    }
+
+    /// Makes every payment fail with `error` until called with `None`.
+    pub fn fail_payments(&self, error: Option<&str>) {
+        self.state.lock().unwrap().payment_error = error.map(str::to_string);
+    }
+
+    pub fn set_channels(&self, channels: Vec<Channel>) {
+        self.state.lock().unwrap().channels = channels;
+    }
+
+    pub fn set_balances(&self, channel_balance: ChannelBalance, wallet_balance: WalletBalance) {
+        let mut state = self.state.lock().unwrap();
+        state.channel_balance = channel_balance;
+        state.wallet_balance = wallet_balance;
+    }
+}
+
+impl Drop for MockLnd {
+    fn drop(&mut self) {
+        self.server.abort();
+        let _ = fs::remove_dir_all(&self.dir);
+    }
+}
+
+type Shared = State<Arc<Mutex<MockState>>>;
+
+type ErrorResponse = (StatusCode, Json<serde_json::Value>);
+
+/// Shaped like the REST proxy's errors.
+fn error(status: StatusCode, message: &str) -> ErrorResponse {
+    (status, Json(json!({ "code": 2, "message": message, "details": [] })))
+}
+
+fn authorize(state: &MockState, headers: &HeaderMap) -> Result<(), ErrorResponse> {
+    match headers.get("Grpc-Metadata-macaroon") {
+        Some(value) if value.as_bytes() == hex::encode(&state.macaroon).as_bytes() => Ok(()),
+        _ => Err(error(StatusCode::FORBIDDEN, "verification failed: signature mismatch after caveat verification")),
+    }
+}
+
+async fn get_info(State(state): Shared, headers: HeaderMap) -> Result<Json<NodeInfo>, ErrorResponse> {
+    let state = state.lock().unwrap();
+    authorize(&state, &headers)?;
+    Ok(Json(NodeInfo {
+        identity_pubkey: IDENTITY_PUBKEY.to_string(),
+        alias: "mock".to_string(),
+        block_height: 800_000,
+        synced_to_chain: true,
+        num_active_channels: state.channels.iter().filter(|channel| channel.active).count() as u32,
+    }))
+}
+
+async fn add_invoice(State(state): Shared, headers: HeaderMap, Json(request): Json<AddInvoice>) -> Result<Json<AddedInvoice>, ErrorResponse> {
+    let mut state = state.lock().unwrap();
+    authorize(&state, &headers)?;
+    let preimage: Hash = rand::random();
+    let payment_hash: Hash = Sha256::digest(preimage).into();
+    // Not a valid BOLT11 string, but unique and recognisable when paid back
+    // through the mock.
+    let payment_request = format!("lnbcrt{}n1mock{}", request.value * 10, hex::encode(payment_hash));
+    let invoice = Invoice {
+        state: InvoiceState::Open,
+        value: request.value,
+        amt_paid_sat: 0,
+        payment_request: payment_request.clone(),
+        description_hash: request.description_hash,
+        settle_date: 0,
+    };
+    state.invoices.insert(payment_hash, MockInvoice { invoice, preimage });
+    let add_index = state.invoices.len() as u64;
+    Ok(Json(AddedInvoice { payment_hash, payment_request, add_index }))
+}
+
+async fn lookup_invoice(State(state): Shared, headers: HeaderMap, Path(payment_hash): Path<String>) -> Result<Json<Invoice>, ErrorResponse> {
+    let state = state.lock().unwrap();
+    authorize(&state, &headers)?;
+    let payment_hash: Hash = hex::decode(&payment_hash)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| error(StatusCode::BAD_REQUEST, "invalid hash"))?;
+    let invoice = state.invoices.get(&payment_hash).ok_or_else(|| error(StatusCode::NOT_FOUND, "unable to locate invoice"))?;
+    Ok(Json(invoice.invoice.clone()))
+}
+
+/// Decodes the mock's own invoices from what it issued; anything else only
+/// for the amount in its human-readable part.
+async fn decode_payment_request(State(state): Shared, headers: HeaderMap, Path(payment_request): Path<String>) -> Result<Json<PayReq>, ErrorResponse> {
+    let state = state.lock().unwrap();
+    authorize(&state, &headers)?;
+    let own = state.invoices.iter().find(|(_, invoice)| invoice.invoice.payment_request == payment_request);
+    let (payment_hash, num_satoshis) = match own {
+        Some((payment_hash, invoice)) => (hex::encode(payment_hash), invoice.invoice.value),
+        None => (String::new(), hrp_amount_sat(&payment_request).ok_or_else(|| error(StatusCode::BAD_REQUEST, "invalid payment request"))?),
+    };
+    Ok(Json(PayReq { destination: IDENTITY_PUBKEY.to_string(), payment_hash, num_satoshis, expiry: 3600 }))
+}
+
+/// The amount of a BOLT11 human-readable part such as `lnbc25u`, in
+/// satoshis; zero when it has none.
+fn hrp_amount_sat(payment_request: &str) -> Option<u64> {
+    let rest = payment_request.strip_prefix("lnbcrt").or_else(|| payment_request.strip_prefix("lnbc"))?;
+    let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+    let (amount, rest) = rest.split_at(digits);
+    let (multiplier, rest) = rest.split_at(rest.chars().next().filter(|c| "munp".contains(*c)).map_or(0, |_| 1));
+    if amount.is_empty() || !rest.starts_with('1') || multiplier.is_empty() {
+        return Some(0);
+    }
+    let amount: u64 = amount.parse().ok()?;
+    Some(match multiplier {
+        "m" => amount * 100_000,
+        "u" => amount * 100,
+        "n" => amount / 10,
+        _ => amount / 10_000,
+    })
+}
+
+/// Pays an invoice of the mock's own by settling it; anything else is paid
+/// to an imaginary node.
+async fn send_payment(State(state): Shared, headers: HeaderMap, Json(request): Json<SendPayment>) -> Result<Json<SendPaymentResponse>, ErrorResponse> {
+    let mut state = state.lock().unwrap();
+    authorize(&state, &headers)?;
+    if request.payment_request.is_empty() {
+        return Err(error(StatusCode::BAD_REQUEST, "invalid payment request"));
+    }
+    if let Some(payment_error) = state.payment_error.clone() {
+        return Ok(Json(SendPaymentResponse { payment_error, payment_hash: None, payment_preimage: None }));
+    }
+    state.payments.push(request.payment_request.clone());
+    let own = state.invoices.values_mut().find(|invoice| invoice.invoice.payment_request == request.payment_request);
+    let preimage = match own {
+        Some(invoice) if invoice.invoice.state != InvoiceState::Open => {
+            return Ok(Json(SendPaymentResponse { payment_error: "invoice is already paid".to_string(), payment_hash: None, payment_preimage: None }));
+        }
+        Some(invoice) => {
+            invoice.invoice.state = InvoiceState::Settled;
+            invoice.invoice.amt_paid_sat = invoice.invoice.value;
+            invoice.invoice.settle_date = unix_now();
+            invoice.preimage
+        }
+        None => rand::random(),
+    };
+    Ok(Json(SendPaymentResponse {
+        payment_error: String::new(),
+        payment_hash: Some(Sha256::digest(preimage).into()),
+        payment_preimage: Some(preimage),
+    }))
+}
+
+async fn channels(State(state): Shared, headers: HeaderMap) -> Result<Json<Channels>, ErrorResponse> {
+    let state = state.lock().unwrap();
+    authorize(&state, &headers)?;
+    Ok(Json(Channels { channels: state.channels.clone() }))
+}
+
+async fn channel_balance(State(state): Shared, headers: HeaderMap) -> Result<Json<ChannelBalance>, ErrorResponse> {
+    let state = state.lock().unwrap();
+    authorize(&state, &headers)?;
+    Ok(Json(state.channel_balance.clone()))
+}
+
+async fn wallet_balance(State(state): Shared, headers: HeaderMap) -> Result<Json<WalletBalance>, ErrorResponse> {
+    let state = state.lock().unwrap();
+    authorize(&state, &headers)?;
+    Ok(Json(state.wallet_balance.clone()))
+}
+
+fn unix_now() -> u64 {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs()
+}
@@ -0,0 +1,241 @@
+//! Requests and responses of the LND REST calls the services make. The REST
+//! proxy encodes 64-bit integers as strings and bytes as base64; both are
+//! decoded here so callers see numbers and 32-byte hashes.
+
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+/// SHA-256 payment hash or preimage.
+pub type Hash = [u8; 32];
+
+/// `GET /v1/getinfo`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct NodeInfo {
+    pub identity_pubkey: String,
+    #[serde(default)]
+    pub alias: String,
+    #[serde(default)]
+    pub block_height: u32,
+    #[serde(default)]
+    pub synced_to_chain: bool,
+    #[serde(default)]
+    pub num_active_channels: u32,
+}
+
+/// `POST /v1/invoices`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct AddInvoice {
+    #[serde(with = "int64")]
+    pub value: u64,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub memo: String,
+    /// Committed to by the BOLT11 `h` tag instead of a plain memo.
+    #[serde(default, with = "optional_base64_hash", skip_serializing_if = "Option::is_none")]
+    pub description_hash: Option<Hash>,
+    #[serde(with = "int64")]
+    pub expiry: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AddedInvoice {
+    #[serde(rename = "r_hash", with = "base64_hash")]
+    pub payment_hash: Hash,
+    /// BOLT11 invoice.
+    pub payment_request: String,
+    #[serde(default, with = "int64")]
+    pub add_index: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "SCREAMING_SNAKE_CASE")]
+pub enum InvoiceState {
+    Open,
+    Settled,
+    Canceled,
+    /// HTLCs held on a hold invoice; not settled yet.
+    Accepted,
+}
+
+/// `GET /v1/invoice/{payment_hash}`.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Invoice {
+    pub state: InvoiceState,
+    #[serde(default, with = "int64")]
+    pub value: u64,
+    #[serde(default, with = "int64")]
+    pub amt_paid_sat: u64,
+    #[serde(default)]
+    pub payment_request: String,
+    #[serde(default, with = "optional_base64_hash", skip_serializing_if = "Option::is_none")]
+    pub description_hash: Option<Hash>,
+    /// Unix time; zero while unsettled.
+    #[serde(default, with = "int64")]
+    pub settle_date: u64,
+}
+
+/// `GET /v1/payreq/{payment_request}`: what a BOLT11 invoice asks for.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PayReq {
+    #[serde(default)]
+    pub destination: String,
+    /// Hex here, unlike the base64 hashes of the other calls.
+    #[serde(default)]
+    pub payment_hash: String,
+    /// Zero for an invoice that leaves the amount to the payer.
+    #[serde(default, with = "int64")]
+    pub num_satoshis: u64,
+    #[serde(default, with = "int64")]
+    pub expiry: u64,
+}
+
+/// `POST /v1/channels/transactions`, which waits for the payment to
+/// complete.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SendPayment {
+    pub payment_request: String,
+    /// Most the payment may cost in routing fees.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub fee_limit: Option<FeeLimit>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FeeLimit {
+    #[serde(with = "int64")]
+    pub fixed: u64,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Payment {
+    #[serde(with = "base64_hash")]
+    pub payment_hash: Hash,
+    #[serde(with = "base64_hash")]
+    pub payment_preimage: Hash,
+}
+
+/// What LND answers to a payment, failed or not; see
+/// [`LndClient::pay`](crate::LndClient::pay).
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct SendPaymentResponse {
+    #[serde(default)]
+    pub payment_error: String,
+    #[serde(default, with = "optional_base64_hash", skip_serializing_if = "Option::is_none")]
+    pub payment_hash: Option<Hash>,
+    #[serde(default, with = "optional_base64_hash", skip_serializing_if = "Option::is_none")]
+    pub payment_preimage: Option<Hash>,
+}
+
+/// `GET /v1/channels`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Channels {
+    #[serde(default)]
+    pub channels: Vec<Channel>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Channel {
+    #[serde(with = "int64")]
+    pub chan_id: u64,
+    pub remote_pubkey: String,
+    #[serde(default)]
+    pub active: bool,
+    #[serde(default, with = "int64")]
+    pub capacity: u64,
+    #[serde(default, with = "int64")]
+    pub local_balance: u64,
+    #[serde(default, with = "int64")]
+    pub remote_balance: u64,
+}
+
+/// `GET /v1/balance/channels`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct ChannelBalance {
+    #[serde(default)]
+    pub local_balance: Amount,
+    #[serde(default)]
+    pub remote_balance: Amount,
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Amount {
+    #[serde(default, with = "int64")]
+    pub sat: u64,
+}
+
+/// `GET /v1/balance/blockchain`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct WalletBalance {
+    #[serde(default, with = "int64")]
+    pub total_balance: u64,
+    #[serde(default, with = "int64")]
+    pub confirmed_balance: u64,
+    #[serde(default, with = "int64")]
+    pub unconfirmed_balance: u64,
+}
+
+/// Error body of the REST proxy.
+#[derive(Deserialize)]
+pub(crate) struct ErrorBody {
+    pub message: String,
+}
+
+/// int64 fields: written as strings, read from strings or numbers.
+mod int64 {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(value: &u64, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&value.to_string())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<u64, D::Error> {
+        #[derive(Deserialize)]
+        #[serde(untagged)]
+        enum Int64 {
+            Number(u64),
+            String(String),
+        }
+        match Int64::deserialize(deserializer)? {
+            Int64::Number(value) => Ok(value),
+            Int64::String(value) => value.parse().map_err(serde::de::Error::custom),
+        }
+    }
+}
+
+mod base64_hash {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(hash: &Hash, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&BASE64.encode(hash))
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Hash, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        BASE64
+            .decode(&value)
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or_else(|| serde::de::Error::custom("expected a base64 32-byte hash"))
+    }
+}
+
+/// Like `base64_hash`, with an empty string meaning none.
+mod optional_base64_hash {
+    use super::*;
+
+    pub fn serialize<S: Serializer>(hash: &Option<Hash>, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&hash.map(|hash| BASE64.encode(hash)).unwrap_or_default())
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Option<Hash>, D::Error> {
+        let value = String::deserialize(deserializer)?;
+        if value.is_empty() {
+            return Ok(None);
+        }
+        BASE64
+            .decode(&value)
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .map(Some)
+            .ok_or_else(|| serde::de::Error::custom("expected a base64 32-byte hash"))
+    }
+}
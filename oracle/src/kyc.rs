@@ -0,0 +1,70 @@
+//! Verified identity attributes from the KYC provider. Attribute credentials
+//! commit to what the provider recorded for a HumanHash ID, never to values
+//! supplied by the holder.
+
+use serde::Deserialize;
+
+/// What the provider holds for one identity.
+#[derive(Clone, Debug, Deserialize)]
+pub struct KycRecord {
+    /// Whether the provider completed identity verification.
+    pub verified: bool,
+    /// `YYYY-MM-DD`.
+    pub birth_date: String,
+    /// ISO 3166-1 alpha-2.
+    pub country: String,
+}
+
+#[derive(Debug)]
+pub enum KycError {
+    Http(reqwest::Error),
+    Status(u16),
+}
+
+impl std::fmt::Display for KycError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            KycError::Http(e) => write!(f, "KYC provider request failed: {}", e),
+            KycError::Status(status) => write!(f, "KYC provider answered {}", status),
+        }
+    }
+}
+
+impl std::error::Error for KycError {}
+
+impl From<reqwest::Error> for KycError {
+    fn from(e: reqwest::Error) -> Self {
+        KycError::Http(e)
+    }
+}
+
+pub struct KycProvider {
+    http: reqwest::Client,
+    endpoint: reqwest::Url,
+}
+
+impl KycProvider {
+    /// `None` when no provider is configured (`api_endpoint` of `"none"`).
+    pub fn new(endpoint: &str) -> Result<Option<Self>, String> {
+        if endpoint.is_empty() || endpoint == "none" {
+            return Ok(None);
+        }
+        let endpoint = reqwest::Url::parse(endpoint.trim_end_matches('/')).map_err(|e| format!("invalid KYC endpoint {}: {}", endpoint, e))?;
+        if endpoint.cannot_be_a_base() {
+            return Err(format!("invalid KYC endpoint {}", endpoint));
+        }
+        Ok(Some(KycProvider { http: reqwest::Client::new(), endpoint }))
+    }
+
+    /// The provider's record for `human_hash_id`, `None` if it has none.
+    pub async fn lookup(&self, human_hash_id: &str) -> Result<Option<KycRecord>, KycError> {
+        let mut url = self.endpoint.clone();
+        url.path_segments_mut().expect("checked in new").push(human_hash_id);
+        let response = self.http.get(url).send().await?;
+        match response.status() {
+            reqwest::StatusCode::NOT_FOUND => Ok(None),
+            status if status.is_success() => Ok(Some(response.json().await?)),
+            status => Err(KycError::Status(status.as_u16())),
+        }
+    }
+}
@@ -1,16 +1,31 @@
 use anyhow::Result;
-use axum::{routing::{get, post}, Router, Json, extract::State, http::StatusCode};
+use axum::{routing::{get, post}, Router, Json, extract::{FromRef, FromRequestParts, State}, http::{header, request::Parts, StatusCode}};
 use serde::{Deserialize, Serialize};
 use std::fs;
 use std::net::SocketAddr;
-use tracing::{info, Level};
-use tracing_subscriber;
-use reqwest::Client;
+use std::sync::Arc;
+use tracing::{error, info, warn, Level};
 use secp256k1::{Keypair, Message, Secp256k1, SecretKey, XOnlyPublicKey};
 use sha2::{Digest, Sha256};
+use disclosure::{AttributeCredential, AttributeOpenings};
+use lnd_client::{FeeLimit, LndClient, LndError, SendPayment};
 
+mod kyc;
 mod zkp;
 
+use kyc::KycProvider;
+
+const ORACLE_ID: &str = "humanhash-oracle-001";
+const ORACLE_SECRET_KEY: &str = "33d0fe452d329ae213c531dfda4582300742cfe7ec6a36b43e6eaa2c1564ea42";
+/// Environment variable holding the bearer token that authorizes payments.
+const ADMIN_TOKEN_ENV: &str = "ORACLE_ADMIN_TOKEN";
+/// Environment variable holding the bearer token of the system service,
+/// which requests attribute credentials for holders it has authenticated.
+const SERVICE_TOKEN_ENV: &str = "ORACLE_SERVICE_TOKEN";
+/// Environment variable holding the hex secp256k1 key attribute
+/// credentials are signed with; kept apart from the KYC attestation key.
+const ATTRIBUTE_KEY_ENV: &str = "ORACLE_ATTRIBUTE_KEY";
+
 #[derive(Clone, Deserialize)]
 struct Config {
     host: String,
@@ -19,19 +34,110 @@ struct Config {
     lnd_macaroon_path: String,
     lnd_tls_cert_path: String,
     oracle_provider: String,
+    /// KYC provider API, `{api_endpoint}/{humanHashId}`; `"none"` disables
+    /// attribute credentials.
     api_endpoint: String,
     oracle_pubkey: String,
     kyc_endpoint: String,
     payment_endpoint: String,
+    /// Largest invoice the payment endpoint pays.
+    max_payment_sat: u64,
+    /// Largest routing fee a payment may be allowed.
+    max_fee_sat: u64,
+}
+
+#[derive(Clone)]
+struct AppState {
+    config: Config,
+    /// `None` when the LND credentials could not be read at startup; KYC
+    /// works without it, payments do not.
+    lnd: Option<Arc<LndClient>>,
+    /// `None` when no KYC provider is configured; attribute credentials are
+    /// then unavailable.
+    kyc: Option<Arc<KycProvider>>,
+    /// SHA-256 of the admin token; `None` disables payments.
+    admin_token_hash: Option<Arc<[u8]>>,
+    /// SHA-256 of the service token; `None` disables attribute credentials.
+    service_token_hash: Option<Arc<[u8]>>,
+    attribute_keypair: Keypair,
+}
+
+impl FromRef<AppState> for Config {
+    fn from_ref(state: &AppState) -> Config {
+        state.config.clone()
+    }
+}
+
+/// A caller holding the admin token; payments spend the node's funds.
+struct Admin;
+
+#[axum::async_trait]
+impl FromRequestParts<AppState> for Admin {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        bearer_matches(parts, state.admin_token_hash.as_deref()).map(|_| Admin)
+    }
+}
+
+/// The system service, calling on behalf of a holder it has authenticated.
+struct Service;
+
+#[axum::async_trait]
+impl FromRequestParts<AppState> for Service {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        bearer_matches(parts, state.service_token_hash.as_deref()).map(|_| Service)
+    }
+}
+
+/// Checks the bearer token against `expected`, its SHA-256; no expected
+/// token means the endpoints behind it are off.
+fn bearer_matches(parts: &Parts, expected: Option<&[u8]>) -> Result<(), StatusCode> {
+    let expected = expected.ok_or(StatusCode::SERVICE_UNAVAILABLE)?;
+    let token = parts
+        .headers
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "));
+    match token {
+        Some(token) if Sha256::digest(token.as_bytes())[..] == expected[..] => Ok(()),
+        _ => Err(StatusCode::UNAUTHORIZED),
+    }
+}
+
+#[derive(Deserialize)]
+struct PaymentRequest {
+    /// BOLT11 invoice to pay.
+    payment_request: String,
+    /// Most to spend on routing fees; at most the configured `max_fee_sat`.
+    fee_limit_sat: u64,
+}
+
+#[derive(Serialize)]
+struct PaymentResponse {
+    payment_hash: String,
+    payment_preimage: String,
 }
 
 #[derive(Deserialize)]
 struct KycRequest {
     #[serde(rename = "humanHashId")]
     human_hash_id: String,
+    #[allow(dead_code)]
     proof: Option<String>,
 }
 
+#[derive(Deserialize)]
+struct CredentialRequest {
+    #[serde(rename = "humanHashId")]
+    human_hash_id: String,
+    /// X-only key the holder will sign presentations with, hex encoded.
+    #[serde(rename = "holderKey")]
+    holder_key: String,
+}
+
 #[derive(Deserialize)]
 struct VerifyZkpRequest {
     proof: String,
@@ -66,11 +172,17 @@ struct VerifyZkpResponse {
     valid: bool,
 }
 
+#[derive(Serialize)]
+struct CredentialResponse {
+    credential: AttributeCredential,
+    openings: AttributeOpenings,
+}
+
 async fn query_kyc(State(config): State<Config>, Json(payload): Json<KycRequest>) -> Result<(StatusCode, Json<OracleAttestation>), StatusCode> {
     info!("KYC query for HumanHash ID: {}", payload.human_hash_id);
 
     let secp = Secp256k1::new();
-    let secret_key = match SecretKey::from_slice(&hex::decode("33d0fe452d329ae213c531dfda4582300742cfe7ec6a36b43e6eaa2c1564ea42").unwrap()) {
+    let secret_key = match SecretKey::from_slice(&hex::decode(ORACLE_SECRET_KEY).unwrap()) {
         Ok(sk) => sk,
         Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
     };
@@ -98,7 +210,7 @@ async fn query_kyc(State(config): State<Config>, Json(payload): Json<KycRequest>
     }
 
     Ok((StatusCode::OK, Json(OracleAttestation {
-        oracle_id: "humanhash-oracle-001".to_string(),
+        oracle_id: ORACLE_ID.to_string(),
         provider: config.oracle_provider.clone(),
         data_source: "humanhash-verification".to_string(),
         verification_result: true,
@@ -110,7 +222,7 @@ async fn query_kyc(State(config): State<Config>, Json(payload): Json<KycRequest>
 
 async fn zkp(State(config): State<Config>, Json(payload): Json<KycRequest>) -> Result<(StatusCode, Json<ZkpResponse>), StatusCode> {
     let secp = Secp256k1::new();
-    let secret_key = match SecretKey::from_slice(&hex::decode("33d0fe452d329ae213c531dfda4582300742cfe7ec6a36b43e6eaa2c1564ea42").unwrap()) {
+    let secret_key = match SecretKey::from_slice(&hex::decode(ORACLE_SECRET_KEY).unwrap()) {
         Ok(sk) => sk,
         Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
     };
@@ -152,7 +264,7 @@ async fn verify_zkp(State(config): State<Config>, Json(payload): Json<VerifyZkpR
     let mut hasher = Sha256::new();
     hasher.update(outcome.as_bytes());
     let message = Message::from_digest_slice(&hasher.finalize()).expect("32 bytes hash required");
-    let signature = secp.sign_schnorr(&message, &Keypair::from_secret_key(&secp, &SecretKey::from_slice(&hex::decode("33d0fe452d329ae213c531dfda4582300742cfe7ec6a36b43e6eaa2c1564ea42").unwrap()).unwrap()));
+    let signature = secp.sign_schnorr(&message, &Keypair::from_secret_key(&secp, &SecretKey::from_slice(&hex::decode(ORACLE_SECRET_KEY).unwrap()).unwrap()));
     let serialized_sig = signature.serialize();
 
     let valid = match zkp::verify_zkp(&payload.proof, &payload.verifying_key, outcome.as_bytes(), &serialized_sig, &config_pubkey.serialize()) {
@@ -163,45 +275,85 @@ async fn verify_zkp(State(config): State<Config>, Json(payload): Json<VerifyZkpR
     Ok((StatusCode::OK, Json(VerifyZkpResponse { valid })))
 }
 
-async fn payment_handler(State(config): State<Config>, Json(_payload): Json<serde_json::Value>) -> Result<(StatusCode, Json<serde_json::Value>), StatusCode> {
-    let tls_cert = match fs::read(&config.lnd_tls_cert_path) {
-        Ok(data) => data,
-        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
-    };
-    let certificate = match reqwest::Certificate::from_pem(&tls_cert) {
-        Ok(cert) => cert,
-        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
-    };
-    let client = match Client::builder().add_root_certificate(certificate).build() {
-        Ok(client) => client,
-        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+/// Issues a credential to a holder the system service has authenticated as
+/// `humanHashId`; the response carries the openings, so it goes only to them.
+async fn issue_attribute_credential(_: Service, State(state): State<AppState>, Json(payload): Json<CredentialRequest>) -> Result<(StatusCode, Json<CredentialResponse>), StatusCode> {
+    info!("Attribute credential request for HumanHash ID: {}", payload.human_hash_id);
+    let kyc = state.kyc.as_ref().ok_or(StatusCode::SERVICE_UNAVAILABLE)?;
+    let holder_key = hex::decode(&payload.holder_key)
+        .ok()
+        .and_then(|bytes| XOnlyPublicKey::from_slice(&bytes).ok())
+        .ok_or(StatusCode::UNPROCESSABLE_ENTITY)?;
+    let record = match kyc.lookup(&payload.human_hash_id).await {
+        Ok(Some(record)) if record.verified => record,
+        Ok(_) => {
+            info!("No verified KYC record for HumanHash ID: {}", payload.human_hash_id);
+            return Err(StatusCode::NOT_FOUND);
+        }
+        Err(e) => {
+            error!("KYC lookup failed: {}", e);
+            return Err(StatusCode::BAD_GATEWAY);
+        }
     };
 
-    let macaroon = match fs::read(&config.lnd_macaroon_path) {
-        Ok(data) => hex::encode(data),
-        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
+    let secp = Secp256k1::new();
+    let issued_at = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
+    let (credential, openings) = match disclosure::issue_credential(
+        &secp,
+        &state.attribute_keypair,
+        ORACLE_ID,
+        &payload.human_hash_id,
+        &holder_key,
+        &record.birth_date,
+        &record.country,
+        issued_at,
+    ) {
+        Ok(issued) => issued,
+        Err(e) => {
+            error!("KYC record for {} is unusable: {}", payload.human_hash_id, e);
+            return Err(StatusCode::BAD_GATEWAY);
+        }
     };
 
-    let payment_request = serde_json::json!({ "payment_request": "temp-invoice" });
-    let response = match client
-        .post(format!("{}/v1/payments", config.lnd_host))
-        .header("Grpc-Metadata-macaroon", &macaroon)
-        .json(&payment_request)
-        .send()
-        .await
-    {
-        Ok(resp) => resp,
-        Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
-    };
+    Ok((StatusCode::OK, Json(CredentialResponse { credential, openings })))
+}
 
-    if response.status().is_success() {
-        let json = match response.json::<serde_json::Value>().await {
-            Ok(json) => json,
-            Err(_) => return Err(StatusCode::INTERNAL_SERVER_ERROR),
-        };
-        Ok((StatusCode::OK, Json(json)))
-    } else {
-        Err(StatusCode::INTERNAL_SERVER_ERROR)
+async fn payment_handler(_: Admin, State(state): State<AppState>, Json(payload): Json<PaymentRequest>) -> Result<(StatusCode, Json<PaymentResponse>), StatusCode> {
+    let lnd = state.lnd.as_ref().ok_or(StatusCode::SERVICE_UNAVAILABLE)?;
+    if payload.fee_limit_sat > state.config.max_fee_sat {
+        warn!("Refused a payment allowing {} sat in fees", payload.fee_limit_sat);
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+    // An invoice without an amount would let the payer pick one.
+    let amount_sat = match lnd.decode_payment_request(&payload.payment_request).await {
+        Ok(decoded) => decoded.num_satoshis,
+        Err(LndError::Node { status, message }) if status < 500 => {
+            warn!("Refused an undecodable invoice: {}", message);
+            return Err(StatusCode::UNPROCESSABLE_ENTITY);
+        }
+        Err(e) => {
+            error!("Failed to decode invoice: {}", e);
+            return Err(StatusCode::BAD_GATEWAY);
+        }
+    };
+    if amount_sat == 0 || amount_sat > state.config.max_payment_sat {
+        warn!("Refused an invoice for {} sat", amount_sat);
+        return Err(StatusCode::UNPROCESSABLE_ENTITY);
+    }
+    let payment = SendPayment { payment_request: payload.payment_request, fee_limit: Some(FeeLimit { fixed: payload.fee_limit_sat }) };
+    match lnd.pay(&payment).await {
+        Ok(payment) => {
+            info!("Paid invoice {}", hex::encode(payment.payment_hash));
+            Ok((StatusCode::OK, Json(PaymentResponse { payment_hash: hex::encode(payment.payment_hash), payment_preimage: hex::encode(payment.payment_preimage) })))
+        }
+        Err(LndError::PaymentFailed(e)) => {
+            warn!("Payment failed: {}", e);
+            Err(StatusCode::UNPROCESSABLE_ENTITY)
+        }
+        Err(e) => {
+            error!("Payment error: {}", e);
+            Err(StatusCode::BAD_GATEWAY)
+        }
     }
 }
 
@@ -217,14 +369,47 @@ async fn main() -> Result<()> {
     let config: Config = serde_json::from_str(&config_str)?;
     let addr: SocketAddr = format!("{}:{}", config.host, config.port).parse()?;
     info!("Starting Oracle server on {}", addr);
+    let lnd = match LndClient::new(&config.lnd_host, &config.lnd_macaroon_path, Some(config.lnd_tls_cert_path.clone().into())) {
+        Ok(lnd) => Some(Arc::new(lnd)),
+        Err(e) => {
+            warn!("Payments disabled: {}", e);
+            None
+        }
+    };
+    let admin_token_hash = match std::env::var(ADMIN_TOKEN_ENV) {
+        Ok(token) if !token.is_empty() => Some(Arc::from(&Sha256::digest(token.as_bytes())[..])),
+        _ => {
+            warn!("Payments disabled: {} is not set", ADMIN_TOKEN_ENV);
+            None
+        }
+    };
+    let service_token_hash = match std::env::var(SERVICE_TOKEN_ENV) {
+        Ok(token) if !token.is_empty() => Some(Arc::from(&Sha256::digest(token.as_bytes())[..])),
+        _ => {
+            warn!("Attribute credentials disabled: {} is not set", SERVICE_TOKEN_ENV);
+            None
+        }
+    };
+    let attribute_keypair = std::env::var(ATTRIBUTE_KEY_ENV)
+        .ok()
+        .and_then(|key| hex::decode(key.trim()).ok())
+        .and_then(|bytes| SecretKey::from_slice(&bytes).ok())
+        .map(|secret_key| Keypair::from_secret_key(&Secp256k1::new(), &secret_key))
+        .ok_or_else(|| anyhow::anyhow!("{} must be set to a hex secp256k1 secret key", ATTRIBUTE_KEY_ENV))?;
+    info!("Attribute credentials are signed by {}", hex::encode(attribute_keypair.x_only_public_key().0.serialize()));
+    let kyc = KycProvider::new(&config.api_endpoint).map_err(anyhow::Error::msg)?.map(Arc::new);
+    if kyc.is_none() {
+        warn!("Attribute credentials disabled: no KYC provider configured");
+    }
 
     let app = Router::new()
         .route(&config.kyc_endpoint, post(query_kyc))
         .route(&config.payment_endpoint, post(payment_handler))
+        .route("/oracle/credential", post(issue_attribute_credential))
         .route("/oracle/zkp", post(zkp))
         .route("/oracle/verify_zkp", post(verify_zkp))
         .route("/health", get(health))
-        .with_state(config);
+        .with_state(AppState { config, lnd, kyc, admin_token_hash, service_token_hash, attribute_keypair });
 
     axum::serve(
         tokio::net::TcpListener::bind(&addr).await?,
@@ -232,4 +417,129 @@ async fn main() -> Result<()> {
     ).await?;
 
     Ok(())
-}
\ No newline at end of file
+}
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lnd_client::mock::MockLnd;
+    use lnd_client::AddInvoice;
+
+    fn state(lnd: Option<LndClient>) -> AppState {
+        let config = serde_json::from_str(include_str!("../oracle_config.json")).unwrap();
+        AppState {
+            config,
+            lnd: lnd.map(Arc::new),
+            kyc: None,
+            admin_token_hash: Some(Arc::from(&Sha256::digest(b"admin-token")[..])),
+            service_token_hash: Some(Arc::from(&Sha256::digest(b"service-token")[..])),
+            attribute_keypair: Keypair::from_seckey_slice(&Secp256k1::new(), &[7; 32]).unwrap(),
+        }
+    }
+
+    fn pay(payment_request: &str, fee_limit_sat: u64) -> Json<PaymentRequest> {
+        Json(PaymentRequest { payment_request: payment_request.to_string(), fee_limit_sat })
+    }
+
+    #[tokio::test]
+    async fn payments_go_through_lnd() {
+        let mock = MockLnd::start().await;
+        let invoice = mock.client().add_invoice(&AddInvoice { value: 500, expiry: 600, ..Default::default() }).await.unwrap();
+        let (status, Json(response)) = payment_handler(Admin, State(state(Some(mock.client()))), pay(&invoice.payment_request, 5)).await.unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(response.payment_hash, hex::encode(invoice.payment_hash));
+        assert_eq!(mock.payments(), vec![invoice.payment_request]);
+
+        mock.fail_payments(Some("no route"));
+        assert_eq!(payment_handler(Admin, State(state(Some(mock.client()))), pay("lnbc5u1elsewhere", 5)).await.err(), Some(StatusCode::UNPROCESSABLE_ENTITY));
+    }
+
+    #[tokio::test]
+    async fn payments_are_capped() {
+        let mock = MockLnd::start().await;
+        let state = state(Some(mock.client()));
+        let too_large = format!("lnbc{}u1elsewhere", state.config.max_payment_sat / 100 + 1);
+        assert_eq!(payment_handler(Admin, State(state.clone()), pay(&too_large, 5)).await.err(), Some(StatusCode::UNPROCESSABLE_ENTITY));
+        assert_eq!(payment_handler(Admin, State(state.clone()), pay("lnbc1elsewhere", 5)).await.err(), Some(StatusCode::UNPROCESSABLE_ENTITY));
+        let fee = state.config.max_fee_sat + 1;
+        assert_eq!(payment_handler(Admin, State(state.clone()), pay("lnbc5u1elsewhere", fee)).await.err(), Some(StatusCode::UNPROCESSABLE_ENTITY));
+        assert!(mock.payments().is_empty());
+
+        assert!(payment_handler(Admin, State(state), pay("lnbc5u1elsewhere", 5)).await.is_ok());
+    }
+
+    fn bearer(token: &str) -> Parts {
+        let request = axum::http::Request::builder().header(header::AUTHORIZATION, format!("Bearer {}", token)).body(()).unwrap();
+        request.into_parts().0
+    }
+
+    #[tokio::test]
+    async fn payments_need_the_admin_token() {
+        let state = state(None);
+        assert!(Admin::from_request_parts(&mut bearer("admin-token"), &state).await.is_ok());
+        assert_eq!(Admin::from_request_parts(&mut bearer("guess"), &state).await.err(), Some(StatusCode::UNAUTHORIZED));
+        assert_eq!(Admin::from_request_parts(&mut bearer("service-token"), &state).await.err(), Some(StatusCode::UNAUTHORIZED));
+        let disabled = AppState { admin_token_hash: None, ..state };
+        assert_eq!(Admin::from_request_parts(&mut bearer("admin-token"), &disabled).await.err(), Some(StatusCode::SERVICE_UNAVAILABLE));
+    }
+
+    #[tokio::test]
+    async fn credentials_need_the_service_token() {
+        let state = state(None);
+        assert!(Service::from_request_parts(&mut bearer("service-token"), &state).await.is_ok());
+        assert_eq!(Service::from_request_parts(&mut bearer("admin-token"), &state).await.err(), Some(StatusCode::UNAUTHORIZED));
+        let mut anonymous = axum::http::Request::builder().body(()).unwrap().into_parts().0;
+        assert_eq!(Service::from_request_parts(&mut anonymous, &state).await.err(), Some(StatusCode::UNAUTHORIZED));
+        let disabled = AppState { service_token_hash: None, ..state };
+        assert_eq!(Service::from_request_parts(&mut bearer("service-token"), &disabled).await.err(), Some(StatusCode::SERVICE_UNAVAILABLE));
+    }
+
+    /// A KYC provider knowing one verified and one unverified identity.
+    async fn kyc_provider() -> KycProvider {
+        let app = Router::new().route(
+            "/kyc/:id",
+            get(|axum::extract::Path(id): axum::extract::Path<String>| async move {
+                match id.as_str() {
+                    "hh-verified" => Ok(Json(serde_json::json!({ "verified": true, "birth_date": "1990-01-02", "country": "nl" }))),
+                    "hh-pending" => Ok(Json(serde_json::json!({ "verified": false, "birth_date": "1990-01-02", "country": "NL" }))),
+                    _ => Err(StatusCode::NOT_FOUND),
+                }
+            }),
+        );
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        tokio::spawn(async move { axum::serve(listener, app).await });
+        KycProvider::new(&format!("http://{}/kyc", addr)).unwrap().unwrap()
+    }
+
+    #[tokio::test]
+    async fn credentials_commit_to_the_kyc_record() {
+        let mut state = state(None);
+        state.kyc = Some(Arc::new(kyc_provider().await));
+        let secp = Secp256k1::new();
+        let holder = Keypair::from_seckey_slice(&secp, &[9; 32]).unwrap();
+        let holder_key = hex::encode(holder.x_only_public_key().0.serialize());
+        let request = |id: &str, holder_key: &str| {
+            let request = serde_json::json!({ "humanHashId": id, "holderKey": holder_key, "birth_date": "2020-01-01" });
+            Json(serde_json::from_value::<CredentialRequest>(request).unwrap())
+        };
+
+        let (status, Json(response)) = issue_attribute_credential(Service, State(state.clone()), request("hh-verified", &holder_key)).await.unwrap();
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(response.openings.birth_date, 19900102);
+        assert_eq!(response.openings.country, "NL");
+        assert_eq!(response.credential.human_hash_id, "hh-verified");
+        assert_eq!(response.credential.holder_key, holder_key);
+        assert!(response.credential.verify_signature(&secp, &state.attribute_keypair.x_only_public_key().0));
+
+        assert_eq!(issue_attribute_credential(Service, State(state.clone()), request("hh-verified", "not-a-key")).await.err(), Some(StatusCode::UNPROCESSABLE_ENTITY));
+        assert_eq!(issue_attribute_credential(Service, State(state.clone()), request("hh-pending", &holder_key)).await.err(), Some(StatusCode::NOT_FOUND));
+        assert_eq!(issue_attribute_credential(Service, State(state.clone()), request("hh-unknown", &holder_key)).await.err(), Some(StatusCode::NOT_FOUND));
+        state.kyc = None;
+        assert_eq!(issue_attribute_credential(Service, State(state), request("hh-verified", &holder_key)).await.err(), Some(StatusCode::SERVICE_UNAVAILABLE));
+    }
+
+    #[tokio::test]
+    async fn payments_need_lnd() {
+        assert_eq!(payment_handler(Admin, State(state(None)), pay("lnbc5u1elsewhere", 5)).await.err(), Some(StatusCode::SERVICE_UNAVAILABLE));
+    }
+}
@@ -0,0 +1,414 @@
+//! Anchoring: the ledger root is periodically committed to Bitcoin in an
+//! `OP_RETURN` output, so the tree a root describes cannot later be denied
+//! or rewritten without contradicting the chain. Anchors are tracked until
+//! they are buried under `min_confirmations` blocks.
+
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex, RwLock};
+use std::time::Duration;
+
+use crate::bitcoind::{BitcoinRpc, RpcConfig};
+use crate::ledger::MerkleLog;
+use popchain::block::{display_hex, sha256d, Transaction};
+use popchain::merkle::Hash;
+use popchain::ots::Op;
+use popchain::spv::anchor_payload;
+
+#[derive(Clone, Deserialize)]
+pub struct AnchorConfig {
+    /// `mainnet`, `testnet`, `testnet4`, `signet` or `regtest`; checked
+    /// against the node before anything is broadcast.
+    pub network: String,
+    #[serde(flatten)]
+    pub rpc: RpcConfig,
+    pub interval_secs: u64,
+    /// Depth at which an anchor is considered final and no longer tracked.
+    pub min_confirmations: u64,
+    /// How long an anchor may stay unconfirmed before it is abandoned and
+    /// the root anchored again. Until then an anchor the mempool evicted is
+    /// rebroadcast.
+    pub drop_after_secs: u64,
+    pub store_path: String,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnchorStatus {
+    Pending,
+    Confirmed,
+    /// Conflicted, or unconfirmed for longer than `drop_after_secs`; the
+    /// root is anchored again.
+    Dropped,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Anchor {
+    pub tree_size: usize,
+    pub root_hash: String,
+    pub network: String,
+    pub txid: String,
+    pub broadcast_at: u64,
+    pub status: AnchorStatus,
+    pub confirmations: u64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block_hash: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub block_height: Option<u64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub inclusion: Option<TxInclusion>,
+}
+
+/// Where the anchor transaction sits in its block, captured on confirmation
+/// so the root can be proven against a block header without a node.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TxInclusion {
+    /// The transaction without witness data, which is what the txid hashes.
+    pub raw_tx: String,
+    pub tx_index: usize,
+    /// Sibling hashes, internal byte order, from the txid up to the block's
+    /// merkle root.
+    pub merkle_branch: Vec<String>,
+}
+
+impl Anchor {
+    /// OpenTimestamps operations from the anchored root to the merkle root of
+    /// the block the anchor confirmed in: into the `OP_RETURN` output, to the
+    /// txid, then up the block's merkle tree.
+    pub fn timestamp_ops(&self) -> Option<Vec<Op>> {
+        let inclusion = self.inclusion.as_ref()?;
+        let tx = hex::decode(&inclusion.raw_tx).ok()?;
+        let root: Hash = hex::decode(&self.root_hash).ok()?.try_into().ok()?;
+        let payload = anchor_payload(self.tree_size, &root);
+        let start = tx.windows(payload.len()).position(|window| window == payload)? + payload.len() - root.len();
+        let mut ops = vec![
+            Op::Prepend(tx[..start].to_vec()),
+            Op::Append(tx[start + root.len()..].to_vec()),
+            Op::Sha256,
+            Op::Sha256,
+        ];
+        let mut index = inclusion.tx_index;
+        for sibling in &inclusion.merkle_branch {
+            let sibling = hex::decode(sibling).ok()?;
+            ops.push(if index & 1 == 0 { Op::Append(sibling) } else { Op::Prepend(sibling) });
+            ops.extend([Op::Sha256, Op::Sha256]);
+            index >>= 1;
+        }
+        Some(ops)
+    }
+}
+
+/// Anchors in broadcast order, written through to a JSON file.
+pub struct AnchorStore {
+    path: PathBuf,
+    anchors: Vec<Anchor>,
+}
+
+impl AnchorStore {
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let anchors = match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(AnchorStore { path, anchors })
+    }
+
+    pub fn list(&self) -> &[Anchor] {
+        &self.anchors
+    }
+
+    /// The earliest confirmed anchor whose tree contains the leaf at
+    /// `leaf_index`.
+    pub fn covering(&self, leaf_index: usize) -> Option<&Anchor> {
+        self.anchors.iter().find(|anchor| anchor.status == AnchorStatus::Confirmed && anchor.tree_size > leaf_index)
+    }
+
+    /// Unconfirmed anchors with their positions in the store.
+    fn pending(&self) -> Vec<(usize, Anchor)> {
+        self.anchors
+            .iter()
+            .enumerate()
+            .filter(|(_, anchor)| anchor.status == AnchorStatus::Pending)
+            .map(|(index, anchor)| (index, anchor.clone()))
+            .collect()
+    }
+
+    fn last_anchored_root(&self) -> Option<&str> {
+        self.anchors.iter().rev().find(|anchor| anchor.status != AnchorStatus::Dropped).map(|anchor| anchor.root_hash.as_str())
+    }
+
+    fn push(&mut self, anchor: Anchor) -> io::Result<()> {
+        self.anchors.push(anchor);
+        self.save()
+    }
+
+    fn update(&mut self, index: usize, updated: Anchor) -> io::Result<()> {
+        if let Some(anchor) = self.anchors.get_mut(index) {
+            *anchor = updated;
+        }
+        self.save()
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let contents = serde_json::to_string_pretty(&self.anchors).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, contents)?;
+        fs::rename(&tmp, &self.path)
+    }
+}
+
+/// bitcoind's `getblockchaininfo` name for a configured network.
+pub fn chain_name(network: &str) -> Option<&'static str> {
+    match network {
+        "mainnet" => Some("main"),
+        "testnet" => Some("test"),
+        "testnet4" => Some("testnet4"),
+        "signet" => Some("signet"),
+        "regtest" => Some("regtest"),
+        _ => None,
+    }
+}
+
+pub async fn run(config: AnchorConfig, rpc: BitcoinRpc, log: Arc<Mutex<MerkleLog>>, anchors: Arc<RwLock<AnchorStore>>) {
+    let Some(expected_chain) = chain_name(&config.network) else {
+        eprintln!("Anchoring disabled: unknown network {}", config.network);
+        return;
+    };
+    let mut ticker = tokio::time::interval(Duration::from_secs(config.interval_secs.max(1)));
+    let mut chain_checked = false;
+    loop {
+        ticker.tick().await;
+        if !chain_checked {
+            match rpc.blockchain_info().await {
+                Ok(info) if info.chain == expected_chain => chain_checked = true,
+                Ok(info) => {
+                    eprintln!("Anchoring disabled: bitcoind is on {}, configured for {}", info.chain, config.network);
+                    return;
+                }
+                Err(e) => {
+                    eprintln!("Anchoring waiting for bitcoind: {}", e);
+                    continue;
+                }
+            }
+        }
+        if let Err(e) = track_pending(&config, &rpc, &anchors).await {
+            eprintln!("Failed to track anchors: {}", e);
+        }
+        if let Err(e) = anchor_root(&config, &rpc, &log, &anchors).await {
+            eprintln!("Failed to anchor root: {}", e);
+        }
+    }
+}
+
+/// Updates confirmations of unconfirmed anchors, noting the block they
+/// landed in (or left, after a reorg). Unconfirmed anchors missing from the
+/// mempool are rebroadcast, and given up on after `drop_after_secs`.
+async fn track_pending(config: &AnchorConfig, rpc: &BitcoinRpc, anchors: &RwLock<AnchorStore>) -> Result<(), Box<dyn std::error::Error>> {
+    let pending = anchors.read().unwrap().pending();
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?.as_secs();
+    for (index, mut anchor) in pending {
+        let tx = rpc.wallet_transaction(&anchor.txid).await?;
+        if tx.confirmations < 0 {
+            println!("Anchor {} for tree size {} was dropped", anchor.txid, anchor.tree_size);
+            drop_anchor(&mut anchor);
+        } else if tx.confirmations == 0 && unconfirmed_too_long(&anchor, config, now) {
+            // Abandoning fails while the transaction is still in the mempool;
+            // it is dropped regardless, since a later anchor covers its root.
+            if let Err(e) = rpc.abandon(&anchor.txid).await {
+                eprintln!("Failed to abandon anchor {}: {}", anchor.txid, e);
+            }
+            println!("Anchor {} for tree size {} unconfirmed after {}s, dropped", anchor.txid, anchor.tree_size, config.drop_after_secs);
+            drop_anchor(&mut anchor);
+        } else {
+            if tx.confirmations == 0 && !rpc.in_mempool(&anchor.txid).await? {
+                match rpc.rebroadcast(&tx.hex).await {
+                    Ok(_) => println!("Rebroadcast anchor {} for tree size {}", anchor.txid, anchor.tree_size),
+                    Err(e) => eprintln!("Failed to rebroadcast anchor {}: {}", anchor.txid, e),
+                }
+            }
+            anchor.confirmations = tx.confirmations as u64;
+            match tx.blockhash {
+                Some(block_hash) if anchor.block_hash.as_deref() != Some(&block_hash) => {
+                    let header = rpc.block_header(&block_hash).await?;
+                    anchor.block_hash = Some(header.hash);
+                    anchor.block_height = Some(header.height);
+                }
+                Some(_) => {}
+                None => {
+                    anchor.block_hash = None;
+                    anchor.block_height = None;
+                }
+            }
+            if anchor.confirmations >= config.min_confirmations.max(1) {
+                let block_hash = anchor.block_hash.clone().ok_or("confirmed anchor without a block")?;
+                anchor.inclusion = Some(tx_inclusion(rpc, &tx.hex, &anchor.txid, &block_hash).await?);
+                println!(
+                    "Anchor {} for tree size {} confirmed at height {}",
+                    anchor.txid,
+                    anchor.tree_size,
+                    anchor.block_height.unwrap_or_default()
+                );
+                anchor.status = AnchorStatus::Confirmed;
+            }
+        }
+        anchors.write().unwrap().update(index, anchor)?;
+    }
+    Ok(())
+}
+
+fn unconfirmed_too_long(anchor: &Anchor, config: &AnchorConfig, now: u64) -> bool {
+    now.saturating_sub(anchor.broadcast_at) >= config.drop_after_secs
+}
+
+fn drop_anchor(anchor: &mut Anchor) {
+    anchor.status = AnchorStatus::Dropped;
+    anchor.confirmations = 0;
+    anchor.block_hash = None;
+    anchor.block_height = None;
+}
+
+/// Broadcasts an anchor for the current root if it differs from the last
+/// anchored one. Only one anchor is in flight at a time, so a slow block
+/// interval costs one transaction rather than one per tick.
+async fn anchor_root(config: &AnchorConfig, rpc: &BitcoinRpc, log: &Mutex<MerkleLog>, anchors: &RwLock<AnchorStore>) -> Result<(), Box<dyn std::error::Error>> {
+    let (tree_size, root) = {
+        let log = log.lock().unwrap();
+        (log.len(), log.root())
+    };
+    let root_hash = hex::encode(root);
+    {
+        let anchors = anchors.read().unwrap();
+        if tree_size == 0 || !anchors.pending().is_empty() || anchors.last_anchored_root() == Some(root_hash.as_str()) {
+            return Ok(());
+        }
+    }
+    let txid = rpc.send_op_return(&anchor_payload(tree_size, &root)).await?;
+    println!("Anchored tree size {} in {} on {}", tree_size, txid, config.network);
+    anchors.write().unwrap().push(Anchor {
+        tree_size,
+        root_hash,
+        network: config.network.clone(),
+        txid,
+        broadcast_at: std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?.as_secs(),
+        status: AnchorStatus::Pending,
+        confirmations: 0,
+        block_hash: None,
+        block_height: None,
+        inclusion: None,
+    })?;
+    Ok(())
+}
+
+/// Locates the anchor transaction in its block, checking the branch against
+/// the block's merkle root before it is stored.
+async fn tx_inclusion(rpc: &BitcoinRpc, tx_hex: &str, txid: &str, block_hash: &str) -> Result<TxInclusion, Box<dyn std::error::Error>> {
+    let raw_tx = Transaction::parse(&hex::decode(tx_hex)?)?.legacy;
+    if display_hex(&sha256d(&raw_tx)) != txid {
+        return Err("anchor transaction does not hash to its txid".into());
+    }
+    let block = rpc.block(block_hash).await?;
+    let tx_index = block.tx.iter().position(|id| id == txid).ok_or("anchor transaction not in its block")?;
+    let txids = block
+        .tx
+        .iter()
+        .map(|id| {
+            let mut hash: Hash = hex::decode(id).ok()?.try_into().ok()?;
+            hash.reverse();
+            Some(hash)
+        })
+        .collect::<Option<Vec<Hash>>>()
+        .ok_or("malformed txid in block")?;
+    let (merkle_root, merkle_branch) = merkle_branch(txids, tx_index);
+    if display_hex(&merkle_root) != block.merkleroot {
+        return Err("block transactions do not hash to its merkle root".into());
+    }
+    Ok(TxInclusion {
+        raw_tx: hex::encode(raw_tx),
+        tx_index,
+        merkle_branch: merkle_branch.iter().map(hex::encode).collect(),
+    })
+}
+
+/// Bitcoin's block merkle root over `txids` and the branch for the one at
+/// `index`. Odd levels pair their last hash with itself.
+fn merkle_branch(mut level: Vec<Hash>, mut index: usize) -> (Hash, Vec<Hash>) {
+    let mut branch = Vec::new();
+    while level.len() > 1 {
+        if level.len() % 2 == 1 {
+            level.push(level[level.len() - 1]);
+        }
+        branch.push(level[index ^ 1]);
+        level = level.chunks(2).map(|pair| sha256d(&[pair[0], pair[1]].concat())).collect();
+        index >>= 1;
+    }
+    (level[0], branch)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn txids(count: u8) -> Vec<Hash> {
+        (0..count).map(|i| sha256d(&[i])).collect()
+    }
+
+    fn fold_branch(txid: Hash, mut index: usize, branch: &[Hash]) -> Hash {
+        branch.iter().fold(txid, |hash, sibling| {
+            let pair = if index & 1 == 0 { [hash, *sibling].concat() } else { [*sibling, hash].concat() };
+            index >>= 1;
+            sha256d(&pair)
+        })
+    }
+
+    #[test]
+    fn merkle_branches_lead_to_the_block_root() {
+        let [a, b, c] = txids(3).try_into().unwrap();
+        let (root, branch) = merkle_branch(vec![a, b, c], 2);
+        // The odd last transaction is paired with itself.
+        assert_eq!(root, sha256d(&[sha256d(&[a, b].concat()), sha256d(&[c, c].concat())].concat()));
+        assert_eq!(branch, vec![c, sha256d(&[a, b].concat())]);
+        assert_eq!(merkle_branch(vec![a], 0), (a, Vec::new()));
+
+        for count in 1..=9 {
+            let txids = txids(count);
+            for index in 0..txids.len() {
+                let (root, branch) = merkle_branch(txids.clone(), index);
+                assert_eq!(fold_branch(txids[index], index, &branch), root, "tx {} of {}", index, count);
+            }
+        }
+    }
+
+    #[test]
+    fn timestamp_ops_commit_the_root_to_the_block() {
+        let root = [5u8; 32];
+        let tx = [&b"version and inputs"[..], &anchor_payload(7, &root), b"change output and locktime"].concat();
+        let mut txids = txids(5);
+        txids[3] = sha256d(&tx);
+        let (block_root, branch) = merkle_branch(txids, 3);
+        let mut anchor = Anchor {
+            tree_size: 7,
+            root_hash: hex::encode(root),
+            network: "regtest".to_string(),
+            txid: display_hex(&sha256d(&tx)),
+            broadcast_at: 0,
+            status: AnchorStatus::Confirmed,
+            confirmations: 6,
+            block_hash: None,
+            block_height: None,
+            inclusion: None,
+        };
+        assert!(anchor.timestamp_ops().is_none());
+
+        anchor.inclusion = Some(TxInclusion { raw_tx: hex::encode(&tx), tx_index: 3, merkle_branch: branch.iter().map(hex::encode).collect() });
+        let ops = anchor.timestamp_ops().unwrap();
+        assert_eq!(ops.iter().fold(root.to_vec(), |msg, op| op.apply(&msg)), block_root.to_vec());
+
+        // A transaction that does not carry the anchored root proves nothing.
+        anchor.root_hash = hex::encode([6u8; 32]);
+        assert!(anchor.timestamp_ops().is_none());
+    }
+}
@@ -0,0 +1,198 @@
+use reqwest::Client;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::fs;
+use std::time::Duration;
+
+/// Environment variable holding the RPC password when no cookie file is set.
+pub const RPC_PASSWORD_ENV: &str = "BITCOIND_RPC_PASSWORD";
+
+#[derive(Debug)]
+pub enum RpcError {
+    Transport(String),
+    /// A JSON-RPC error returned by bitcoind.
+    Node { code: i64, message: String },
+    InvalidResponse(String),
+}
+
+impl std::fmt::Display for RpcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RpcError::Transport(e) => write!(f, "bitcoind unreachable: {}", e),
+            RpcError::Node { code, message } => write!(f, "bitcoind error {}: {}", code, message),
+            RpcError::InvalidResponse(e) => write!(f, "invalid bitcoind response: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for RpcError {}
+
+#[derive(Clone, Deserialize)]
+pub struct RpcConfig {
+    pub rpc_url: String,
+    /// Wallet that funds and signs transactions, for nodes with several.
+    #[serde(default)]
+    pub wallet: Option<String>,
+    /// bitcoind's `.cookie` file; otherwise `rpc_user` and the password from
+    /// `BITCOIND_RPC_PASSWORD` are used.
+    #[serde(default)]
+    pub rpc_cookie_file: Option<String>,
+    #[serde(default)]
+    pub rpc_user: Option<String>,
+}
+
+#[derive(Deserialize)]
+pub struct BlockchainInfo {
+    /// `main`, `test`, `testnet4`, `signet` or `regtest`.
+    pub chain: String,
+    /// Height of the node's best chain.
+    pub blocks: u64,
+}
+
+#[derive(Deserialize)]
+pub struct WalletTransaction {
+    /// Negative when the transaction conflicts with one in the chain.
+    pub confirmations: i64,
+    #[serde(default)]
+    pub blockhash: Option<String>,
+    /// Serialized transaction, including witness data.
+    pub hex: String,
+}
+
+#[derive(Deserialize)]
+pub struct BlockHeader {
+    pub hash: String,
+    pub height: u64,
+}
+
+#[derive(Deserialize)]
+pub struct Block {
+    /// Display (byte-reversed) hex, like the txids.
+    pub merkleroot: String,
+    pub tx: Vec<String>,
+}
+
+#[derive(Deserialize)]
+struct RpcResponse {
+    result: Option<Value>,
+    error: Option<RpcErrorBody>,
+}
+
+#[derive(Deserialize)]
+struct RpcErrorBody {
+    code: i64,
+    message: String,
+}
+
+/// Minimal bitcoind JSON-RPC client for the calls popchain needs.
+pub struct BitcoinRpc {
+    http: Client,
+    url: String,
+    user: String,
+    password: String,
+}
+
+impl BitcoinRpc {
+    pub fn new(config: &RpcConfig) -> Result<Self, String> {
+        let (user, password) = match &config.rpc_cookie_file {
+            Some(path) => {
+                let cookie = fs::read_to_string(path).map_err(|e| format!("failed to read {}: {}", path, e))?;
+                let (user, password) = cookie.trim().split_once(':').ok_or_else(|| format!("malformed cookie file {}", path))?;
+                (user.to_string(), password.to_string())
+            }
+            None => {
+                let user = config.rpc_user.clone().ok_or("rpc_user or rpc_cookie_file must be set")?;
+                let password = std::env::var(RPC_PASSWORD_ENV).map_err(|_| format!("{} must be set", RPC_PASSWORD_ENV))?;
+                (user, password)
+            }
+        };
+        let base = config.rpc_url.trim_end_matches('/');
+        let url = match &config.wallet {
+            Some(wallet) => format!("{}/wallet/{}", base, wallet),
+            None => base.to_string(),
+        };
+        let http = Client::builder().timeout(Duration::from_secs(30)).build().map_err(|e| e.to_string())?;
+        Ok(BitcoinRpc { http, url, user, password })
+    }
+
+    pub async fn call<T: DeserializeOwned>(&self, method: &str, params: Value) -> Result<T, RpcError> {
+        let body = json!({ "jsonrpc": "1.0", "id": "popchain", "method": method, "params": params });
+        let response = self
+            .http
+            .post(&self.url)
+            .basic_auth(&self.user, Some(&self.password))
+            .json(&body)
+            .send()
+            .await
+            .map_err(|e| RpcError::Transport(e.to_string()))?;
+        // bitcoind reports RPC errors with HTTP 500 and a JSON body.
+        let status = response.status();
+        let response: RpcResponse = response.json().await.map_err(|e| RpcError::InvalidResponse(format!("{}: {}", status, e)))?;
+        if let Some(error) = response.error {
+            return Err(RpcError::Node { code: error.code, message: error.message });
+        }
+        serde_json::from_value(response.result.unwrap_or(Value::Null)).map_err(|e| RpcError::InvalidResponse(e.to_string()))
+    }
+
+    pub async fn blockchain_info(&self) -> Result<BlockchainInfo, RpcError> {
+        self.call("getblockchaininfo", json!([])).await
+    }
+
+    /// Builds, funds, signs and broadcasts a transaction whose only
+    /// non-change output is `OP_RETURN <data>`; returns the txid.
+    pub async fn send_op_return(&self, data: &[u8]) -> Result<String, RpcError> {
+        let raw: String = self.call("createrawtransaction", json!([[], [{ "data": hex::encode(data) }]])).await?;
+        let funded: Value = self.call("fundrawtransaction", json!([raw])).await?;
+        let funded = funded["hex"].as_str().ok_or_else(|| RpcError::InvalidResponse("fundrawtransaction without hex".into()))?;
+        let signed: Value = self.call("signrawtransactionwithwallet", json!([funded])).await?;
+        if signed["complete"].as_bool() != Some(true) {
+            return Err(RpcError::InvalidResponse("wallet could not sign the anchor transaction".into()));
+        }
+        let signed = signed["hex"].as_str().ok_or_else(|| RpcError::InvalidResponse("signrawtransactionwithwallet without hex".into()))?;
+        self.call("sendrawtransaction", json!([signed])).await
+    }
+
+    pub async fn wallet_transaction(&self, txid: &str) -> Result<WalletTransaction, RpcError> {
+        self.call("gettransaction", json!([txid])).await
+    }
+
+    /// Whether the node's mempool holds `txid`.
+    pub async fn in_mempool(&self, txid: &str) -> Result<bool, RpcError> {
+        match self.call::<Value>("getmempoolentry", json!([txid])).await {
+            Ok(_) => Ok(true),
+            // RPC_INVALID_ADDRESS_OR_KEY: not in the mempool.
+            Err(RpcError::Node { code: -5, .. }) => Ok(false),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Submits a signed transaction again, e.g. after the mempool evicted it.
+    pub async fn rebroadcast(&self, tx_hex: &str) -> Result<String, RpcError> {
+        self.call("sendrawtransaction", json!([tx_hex])).await
+    }
+
+    /// Marks an unconfirmed wallet transaction as abandoned, so its inputs
+    /// can be spent again and it is never rebroadcast.
+    pub async fn abandon(&self, txid: &str) -> Result<(), RpcError> {
+        self.call::<Value>("abandontransaction", json!([txid])).await.map(|_| ())
+    }
+
+    pub async fn block_header(&self, block_hash: &str) -> Result<BlockHeader, RpcError> {
+        self.call("getblockheader", json!([block_hash, true])).await
+    }
+
+    pub async fn block_hash(&self, height: u64) -> Result<String, RpcError> {
+        self.call("getblockhash", json!([height])).await
+    }
+
+    /// The serialized header, hex.
+    pub async fn raw_block_header(&self, block_hash: &str) -> Result<String, RpcError> {
+        self.call("getblockheader", json!([block_hash, false])).await
+    }
+
+    /// The block with the txids of its transactions.
+    pub async fn block(&self, block_hash: &str) -> Result<Block, RpcError> {
+        self.call("getblock", json!([block_hash, 1])).await
+    }
+}
@@ -0,0 +1,179 @@
+//! Bitcoin block headers and transactions, as needed to check an anchor
+//! against the chain without a node: the merkle root a transaction commits
+//! into, the outputs it carries, and the header fields proof-of-work is
+//! checked on.
+
+use sha2::{Digest, Sha256};
+
+use crate::merkle::Hash;
+
+/// Serialized header length.
+pub const HEADER_LEN: usize = 80;
+
+#[derive(Debug, PartialEq)]
+pub enum BlockError {
+    MalformedHeader,
+    MalformedTransaction,
+}
+
+impl std::fmt::Display for BlockError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BlockError::MalformedHeader => write!(f, "block header must be {} hex-encoded bytes", HEADER_LEN),
+            BlockError::MalformedTransaction => write!(f, "malformed transaction"),
+        }
+    }
+}
+
+impl std::error::Error for BlockError {}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct BlockHeader {
+    raw: [u8; HEADER_LEN],
+}
+
+impl BlockHeader {
+    /// Parses a header as printed by `bitcoin-cli getblockheader <hash> false`.
+    pub fn from_hex(value: &str) -> Result<Self, BlockError> {
+        let raw = hex::decode(value.trim()).map_err(|_| BlockError::MalformedHeader)?;
+        Ok(BlockHeader { raw: raw.try_into().map_err(|_| BlockError::MalformedHeader)? })
+    }
+
+    pub fn to_hex(&self) -> String {
+        hex::encode(self.raw)
+    }
+
+    /// Hash of the previous block, internal byte order.
+    pub fn prev_hash(&self) -> Hash {
+        self.raw[4..36].try_into().unwrap()
+    }
+
+    /// Merkle root in internal byte order, as committed to by the header.
+    pub fn merkle_root(&self) -> Hash {
+        self.raw[36..68].try_into().unwrap()
+    }
+
+    /// Block time in seconds since the Unix epoch.
+    pub fn time(&self) -> u32 {
+        u32::from_le_bytes(self.raw[68..72].try_into().unwrap())
+    }
+
+    /// Proof-of-work target in compact form.
+    pub fn bits(&self) -> u32 {
+        u32::from_le_bytes(self.raw[72..76].try_into().unwrap())
+    }
+
+    /// Block hash in internal byte order.
+    pub fn hash(&self) -> Hash {
+        sha256d(&self.raw)
+    }
+
+    /// Block hash as displayed by Bitcoin tools (byte-reversed).
+    pub fn hash_hex(&self) -> String {
+        display_hex(&self.hash())
+    }
+}
+
+/// What an anchor check needs from a transaction.
+pub struct Transaction {
+    /// Serialization without witness data, which is what the txid hashes.
+    pub legacy: Vec<u8>,
+    pub output_scripts: Vec<Vec<u8>>,
+}
+
+impl Transaction {
+    /// Parses a serialized transaction, with or without witness data.
+    pub fn parse(raw: &[u8]) -> Result<Self, BlockError> {
+        let mut reader = TxReader { raw, pos: 0 };
+        reader.take(4)?;
+        // A legacy transaction never has zero inputs, so a zero here is the
+        // segwit marker.
+        let segwit = raw.get(4) == Some(&0);
+        if segwit && reader.take(2)? != [0, 1] {
+            return Err(BlockError::MalformedTransaction);
+        }
+        let body_start = reader.pos;
+        let inputs = reader.compact_size()?;
+        for _ in 0..inputs {
+            reader.take(36)?;
+            let script_len = reader.compact_size()?;
+            reader.take(script_len)?;
+            reader.take(4)?;
+        }
+        let outputs = reader.compact_size()?;
+        let mut output_scripts = Vec::new();
+        for _ in 0..outputs {
+            reader.take(8)?;
+            let script_len = reader.compact_size()?;
+            output_scripts.push(reader.take(script_len)?.to_vec());
+        }
+        let body_end = reader.pos;
+        if segwit {
+            for _ in 0..inputs {
+                for _ in 0..reader.compact_size()? {
+                    let item_len = reader.compact_size()?;
+                    reader.take(item_len)?;
+                }
+            }
+        }
+        let lock_time = reader.take(4)?;
+        if reader.pos != raw.len() {
+            return Err(BlockError::MalformedTransaction);
+        }
+        let legacy = [&raw[..4], &raw[body_start..body_end], lock_time].concat();
+        Ok(Transaction { legacy, output_scripts })
+    }
+
+    /// Txid in internal byte order.
+    pub fn txid(&self) -> Hash {
+        sha256d(&self.legacy)
+    }
+}
+
+/// The merkle root reached from the txid at `index` in a block by hashing up
+/// `branch`.
+pub fn merkle_root_from_branch(txid: &Hash, mut index: usize, branch: &[Hash]) -> Hash {
+    let mut hash = *txid;
+    for sibling in branch {
+        hash = if index & 1 == 0 { sha256d(&[hash, *sibling].concat()) } else { sha256d(&[*sibling, hash].concat()) };
+        index >>= 1;
+    }
+    hash
+}
+
+/// Bitcoin's double SHA-256.
+pub fn sha256d(data: &[u8]) -> Hash {
+    Sha256::digest(Sha256::digest(data)).into()
+}
+
+/// Hex of a hash in the byte-reversed order Bitcoin tools display.
+pub fn display_hex(hash: &Hash) -> String {
+    let mut hash = *hash;
+    hash.reverse();
+    hex::encode(hash)
+}
+
+struct TxReader<'a> {
+    raw: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> TxReader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], BlockError> {
+        let end = self.pos.checked_add(len).filter(|end| *end <= self.raw.len()).ok_or(BlockError::MalformedTransaction)?;
+        let bytes = &self.raw[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn compact_size(&mut self) -> Result<usize, BlockError> {
+        let width = match self.take(1)?[0] {
+            0xfd => 2,
+            0xfe => 4,
+            0xff => 8,
+            value => return Ok(usize::from(value)),
+        };
+        let value = self.take(width)?.iter().rev().fold(0u64, |value, byte| value << 8 | u64::from(*byte));
+        usize::try_from(value).map_err(|_| BlockError::MalformedTransaction)
+    }
+}
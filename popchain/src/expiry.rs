@@ -0,0 +1,216 @@
+//! Attestation expiry: attestations are valid for a fixed period, holders
+//! are reminded ahead of the end of it, and a sweeper records the moment an
+//! attestation lapses. Renewal moves the expiry forward and requires a fresh
+//! verification of the holder.
+
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+
+const DAY: u64 = 24 * 60 * 60;
+
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct ExpiryConfig {
+    /// How long an attestation, or a renewal of it, is valid for.
+    pub validity_secs: u64,
+    /// How close to expiry an attestation is reported as expiring soon and
+    /// may be renewed.
+    pub expiring_soon_secs: u64,
+    /// How long before expiry reminders are sent, one per entry.
+    pub reminder_lead_secs: Vec<u64>,
+    pub sweep_interval_secs: u64,
+    /// Oldest verification accepted for a renewal.
+    pub renewal_max_verification_age_secs: u64,
+}
+
+impl Default for ExpiryConfig {
+    fn default() -> Self {
+        ExpiryConfig {
+            validity_secs: 365 * DAY,
+            expiring_soon_secs: 30 * DAY,
+            reminder_lead_secs: vec![30 * DAY, 7 * DAY, DAY],
+            sweep_interval_secs: 60 * 60,
+            renewal_max_verification_age_secs: 15 * 60,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AttestationStatus {
+    Active,
+    ExpiringSoon,
+    Expired,
+}
+
+impl AttestationStatus {
+    pub fn at(expires_at: u64, now: u64, expiring_soon_secs: u64) -> Self {
+        if now >= expires_at {
+            AttestationStatus::Expired
+        } else if now + expiring_soon_secs >= expires_at {
+            AttestationStatus::ExpiringSoon
+        } else {
+            AttestationStatus::Active
+        }
+    }
+}
+
+/// What a sweep has to record.
+#[derive(Debug, Default, PartialEq)]
+pub struct Due {
+    /// `(human_hash_id, expires_at)` of attestations that have lapsed.
+    pub expired: Vec<(String, u64)>,
+    /// `(human_hash_id, expires_at)` of attestations owed a reminder.
+    pub reminders: Vec<(String, u64)>,
+}
+
+/// Unexpired, unrevoked attestations ordered by expiry, with the last
+/// reminder sent for each. Rebuilt from the ledger on startup, so reminders
+/// and expiries already recorded are not repeated.
+pub struct ExpiryIndex {
+    /// Descending.
+    reminder_leads: Vec<u64>,
+    by_expiry: BTreeSet<(u64, String)>,
+    expiry_of: HashMap<String, u64>,
+    reminded_at: HashMap<String, u64>,
+}
+
+impl ExpiryIndex {
+    pub fn new(reminder_lead_secs: &[u64]) -> Self {
+        let mut reminder_leads = reminder_lead_secs.to_vec();
+        reminder_leads.sort_unstable_by(|a, b| b.cmp(a));
+        reminder_leads.dedup();
+        ExpiryIndex {
+            reminder_leads,
+            by_expiry: BTreeSet::new(),
+            expiry_of: HashMap::new(),
+            reminded_at: HashMap::new(),
+        }
+    }
+
+    /// Tracks `human_hash_id` as expiring at `expires_at`, replacing any
+    /// earlier expiry along with the reminders sent for it.
+    pub fn track(&mut self, human_hash_id: &str, expires_at: u64) {
+        self.remove(human_hash_id);
+        self.by_expiry.insert((expires_at, human_hash_id.to_string()));
+        self.expiry_of.insert(human_hash_id.to_string(), expires_at);
+    }
+
+    /// Stops tracking an attestation that expired or was revoked.
+    pub fn remove(&mut self, human_hash_id: &str) {
+        if let Some(expires_at) = self.expiry_of.remove(human_hash_id) {
+            self.by_expiry.remove(&(expires_at, human_hash_id.to_string()));
+        }
+        self.reminded_at.remove(human_hash_id);
+    }
+
+    /// Notes a reminder sent at `at` for the expiry at `expires_at`; one for
+    /// an expiry since replaced is ignored.
+    pub fn reminded(&mut self, human_hash_id: &str, expires_at: u64, at: u64) {
+        if self.expiry_of.get(human_hash_id) == Some(&expires_at) {
+            self.reminded_at.insert(human_hash_id.to_string(), at);
+        }
+    }
+
+    pub fn expires_at(&self, human_hash_id: &str) -> Option<u64> {
+        self.expiry_of.get(human_hash_id).copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.by_expiry.len()
+    }
+
+    /// Attestations that have lapsed by `now`, and those that have passed a
+    /// reminder lead since they were last reminded. When several leads have
+    /// passed, as after downtime, only one reminder is owed.
+    pub fn due(&self, now: u64) -> Due {
+        let mut due = Due::default();
+        let horizon = now.saturating_add(self.reminder_leads.first().copied().unwrap_or(0));
+        for (expires_at, human_hash_id) in &self.by_expiry {
+            if *expires_at > horizon {
+                break;
+            }
+            if *expires_at <= now {
+                due.expired.push((human_hash_id.clone(), *expires_at));
+                continue;
+            }
+            let reminded = self.reminded_at.get(human_hash_id).map_or(0, |at| self.leads_passed(*expires_at, *at));
+            if self.leads_passed(*expires_at, now) > reminded {
+                due.reminders.push((human_hash_id.clone(), *expires_at));
+            }
+        }
+        due
+    }
+
+    fn leads_passed(&self, expires_at: u64, at: u64) -> usize {
+        self.reminder_leads.iter().filter(|lead| at.saturating_add(**lead) >= expires_at).count()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index() -> ExpiryIndex {
+        ExpiryIndex::new(&[DAY, 30 * DAY, 7 * DAY])
+    }
+
+    #[test]
+    fn status_follows_expiry() {
+        assert_eq!(AttestationStatus::at(100 * DAY, DAY, 30 * DAY), AttestationStatus::Active);
+        assert_eq!(AttestationStatus::at(100 * DAY, 70 * DAY, 30 * DAY), AttestationStatus::ExpiringSoon);
+        assert_eq!(AttestationStatus::at(100 * DAY, 100 * DAY, 30 * DAY), AttestationStatus::Expired);
+    }
+
+    #[test]
+    fn reminds_once_per_lead() {
+        let mut index = index();
+        index.track("a", 100 * DAY);
+        assert_eq!(index.due(50 * DAY), Due::default());
+
+        let now = 71 * DAY;
+        assert_eq!(index.due(now).reminders, vec![("a".to_string(), 100 * DAY)]);
+        index.reminded("a", 100 * DAY, now);
+        assert_eq!(index.due(80 * DAY), Due::default());
+
+        let now = 94 * DAY;
+        assert_eq!(index.due(now).reminders.len(), 1);
+        index.reminded("a", 100 * DAY, now);
+        assert_eq!(index.due(98 * DAY), Due::default());
+    }
+
+    #[test]
+    fn catches_up_with_a_single_reminder() {
+        let mut index = index();
+        index.track("a", 100 * DAY);
+        let now = 99 * DAY + 1;
+        assert_eq!(index.due(now).reminders.len(), 1);
+        index.reminded("a", 100 * DAY, now);
+        assert_eq!(index.due(now + 60), Due::default());
+    }
+
+    #[test]
+    fn lapsed_attestations_are_due_until_removed() {
+        let mut index = index();
+        index.track("a", 10 * DAY);
+        index.track("b", 100 * DAY);
+        assert_eq!(index.due(10 * DAY).expired, vec![("a".to_string(), 10 * DAY)]);
+        index.remove("a");
+        assert_eq!(index.due(10 * DAY), Due::default());
+        assert_eq!(index.len(), 1);
+    }
+
+    #[test]
+    fn renewal_resets_reminders() {
+        let mut index = index();
+        index.track("a", 100 * DAY);
+        index.reminded("a", 100 * DAY, 71 * DAY);
+        index.track("a", 465 * DAY);
+        assert_eq!(index.expires_at("a"), Some(465 * DAY));
+        assert_eq!(index.due(436 * DAY).reminders.len(), 1);
+
+        // A reminder recorded for the old expiry, replayed late, is ignored.
+        index.reminded("a", 100 * DAY, 436 * DAY);
+        assert_eq!(index.due(436 * DAY).reminders.len(), 1);
+    }
+}
@@ -0,0 +1,57 @@
+use popchain::sth::{Cosignature, CosignedTreeHead, SignedTreeHead, SthError};
+use secp256k1::{Secp256k1, XOnlyPublicKey};
+use std::collections::VecDeque;
+
+/// Number of published heads kept for witnesses to cosign. Heads are
+/// published every interval, so this bounds how far behind a witness may
+/// poll and still have its cosignature accepted.
+const RETAINED_HEADS: usize = 16;
+
+/// Recently published tree heads, newest last, with the witness
+/// cosignatures collected for each. Only the configured witnesses may
+/// cosign, once per head, so a head holds at most one cosignature for each.
+pub struct TreeHeads {
+    heads: VecDeque<CosignedTreeHead>,
+    witnesses: Vec<XOnlyPublicKey>,
+}
+
+impl TreeHeads {
+    pub fn new(witnesses: Vec<XOnlyPublicKey>) -> Self {
+        TreeHeads { heads: VecDeque::new(), witnesses }
+    }
+
+    pub fn publish(&mut self, sth: SignedTreeHead) {
+        if self.heads.len() == RETAINED_HEADS {
+            self.heads.pop_front();
+        }
+        self.heads.push_back(CosignedTreeHead { sth, cosignatures: Vec::new() });
+    }
+
+    pub fn latest(&self) -> Option<&CosignedTreeHead> {
+        self.heads.back()
+    }
+
+    /// The newest head with valid cosignatures from at least `min` of the
+    /// trusted witnesses.
+    pub fn latest_cosigned(&self, log_key: &XOnlyPublicKey, min: usize) -> Option<&CosignedTreeHead> {
+        let secp = Secp256k1::verification_only();
+        self.heads.iter().rev().find(|head| head.verify(&secp, log_key, &self.witnesses, min).is_ok())
+    }
+
+    /// Attaches a witness cosignature to the head it covers, replacing any
+    /// earlier one from the same witness. Returns `Ok(false)` when the head
+    /// is no longer retained.
+    pub fn add_cosignature(&mut self, log_key: &XOnlyPublicKey, tree_size: usize, timestamp: u64, cosignature: Cosignature) -> Result<bool, SthError> {
+        let Some(head) = self.heads.iter_mut().find(|head| head.sth.tree_size == tree_size && head.sth.timestamp == timestamp) else {
+            return Ok(false);
+        };
+        if !self.witnesses.contains(&cosignature.witness_key()?) {
+            return Err(SthError::UntrustedWitness);
+        }
+        cosignature.verify(&Secp256k1::verification_only(), log_key, &head.sth)?;
+        let witness = cosignature.witness_key()?;
+        head.cosignatures.retain(|existing| existing.witness_key().ok() != Some(witness));
+        head.cosignatures.push(cosignature);
+        Ok(true)
+    }
+}
@@ -0,0 +1,234 @@
+use ark_bn254::Fr;
+use ark_ff::{BigInteger, PrimeField};
+use light_poseidon::{Poseidon, PoseidonHasher};
+use std::collections::{HashMap, VecDeque};
+
+/// Depth of the enrolled-identity tree (2^20 identities).
+pub const TREE_DEPTH: usize = 20;
+/// Number of past roots a membership proof may still be checked against.
+pub const ROOT_HISTORY_SIZE: usize = 30;
+
+#[derive(Debug)]
+pub enum TreeError {
+    Full,
+    Duplicate,
+    UnknownCommitment,
+}
+
+impl std::fmt::Display for TreeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TreeError::Full => write!(f, "identity tree is full"),
+            TreeError::Duplicate => write!(f, "identity commitment already enrolled"),
+            TreeError::UnknownCommitment => write!(f, "identity commitment not enrolled"),
+        }
+    }
+}
+
+impl std::error::Error for TreeError {}
+
+pub struct AuthenticationPath {
+    pub leaf_index: usize,
+    pub siblings: Vec<Fr>,
+    /// 0 when the node on the path is a left child, 1 when it is a right child.
+    pub path_indices: Vec<u8>,
+}
+
+/// Poseidon Merkle tree over the BN254 scalar field holding every enrolled
+/// identity commitment, in the layout circom set-membership circuits expect.
+pub struct IdentityTree {
+    hasher: Poseidon<Fr>,
+    zeros: Vec<Fr>,
+    levels: Vec<Vec<Fr>>,
+    leaf_index: HashMap<Fr, usize>,
+    root_history: VecDeque<Fr>,
+}
+
+impl IdentityTree {
+    pub fn new() -> Self {
+        let mut hasher = Poseidon::<Fr>::new_circom(2).expect("two-input Poseidon parameters");
+        let mut zeros = vec![Fr::from(0u64)];
+        for level in 0..TREE_DEPTH {
+            let zero = hasher.hash(&[zeros[level], zeros[level]]).expect("two inputs");
+            zeros.push(zero);
+        }
+        let mut root_history = VecDeque::with_capacity(ROOT_HISTORY_SIZE);
+        root_history.push_back(zeros[TREE_DEPTH]);
+        IdentityTree {
+            hasher,
+            zeros,
+            levels: vec![Vec::new(); TREE_DEPTH],
+            leaf_index: HashMap::new(),
+            root_history,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.levels[0].len()
+    }
+
+    pub fn root(&self) -> Fr {
+        *self.root_history.back().expect("root history is never empty")
+    }
+
+    /// Most recent roots, oldest first.
+    pub fn root_history(&self) -> impl Iterator<Item = &Fr> {
+        self.root_history.iter()
+    }
+
+    pub fn is_known_root(&self, root: &Fr) -> bool {
+        self.root_history.contains(root)
+    }
+
+    /// Whether `insert` would accept `commitment`, without inserting it.
+    pub fn check_insert(&self, commitment: &Fr) -> Result<(), TreeError> {
+        if self.leaf_index.contains_key(commitment) {
+            return Err(TreeError::Duplicate);
+        }
+        if self.len() >= 1 << TREE_DEPTH {
+            return Err(TreeError::Full);
+        }
+        Ok(())
+    }
+
+    pub fn insert(&mut self, commitment: Fr) -> Result<usize, TreeError> {
+        self.check_insert(&commitment)?;
+        let index = self.len();
+        self.set_leaf(index, commitment);
+        self.leaf_index.insert(commitment, index);
+        Ok(index)
+    }
+
+    /// Zeroes the commitment's leaf, so it no longer proves membership and
+    /// may be enrolled again. The leaf's position is not reused.
+    pub fn remove(&mut self, commitment: &Fr) -> Result<usize, TreeError> {
+        let index = self.leaf_index.remove(commitment).ok_or(TreeError::UnknownCommitment)?;
+        self.set_leaf(index, self.zeros[0]);
+        Ok(index)
+    }
+
+    /// Writes a leaf, rehashes the path above it and records the new root.
+    fn set_leaf(&mut self, index: usize, leaf: Fr) {
+        let mut node = leaf;
+        let mut position = index;
+        for level in 0..TREE_DEPTH {
+            if position == self.levels[level].len() {
+                self.levels[level].push(node);
+            } else {
+                self.levels[level][position] = node;
+            }
+            let (left, right) = if position & 1 == 0 {
+                (node, self.node(level, position + 1))
+            } else {
+                (self.node(level, position - 1), node)
+            };
+            node = self.hasher.hash(&[left, right]).expect("two inputs");
+            position /= 2;
+        }
+
+        if self.root_history.len() == ROOT_HISTORY_SIZE {
+            self.root_history.pop_front();
+        }
+        self.root_history.push_back(node);
+    }
+
+    pub fn authentication_path(&self, commitment: &Fr) -> Result<AuthenticationPath, TreeError> {
+        let leaf_index = *self.leaf_index.get(commitment).ok_or(TreeError::UnknownCommitment)?;
+        let mut siblings = Vec::with_capacity(TREE_DEPTH);
+        let mut path_indices = Vec::with_capacity(TREE_DEPTH);
+        let mut position = leaf_index;
+        for level in 0..TREE_DEPTH {
+            siblings.push(self.node(level, position ^ 1));
+            path_indices.push((position & 1) as u8);
+            position /= 2;
+        }
+        Ok(AuthenticationPath { leaf_index, siblings, path_indices })
+    }
+
+    fn node(&self, level: usize, position: usize) -> Fr {
+        self.levels[level].get(position).copied().unwrap_or(self.zeros[level])
+    }
+}
+
+/// Parses a 0x-prefixed, big-endian field element, rejecting non-canonical values.
+pub fn parse_field_element(value: &str) -> Option<Fr> {
+    let bytes = hex::decode(value.strip_prefix("0x").unwrap_or(value)).ok()?;
+    if bytes.len() != 32 {
+        return None;
+    }
+    let element = Fr::from_be_bytes_mod_order(&bytes);
+    if element.into_bigint().to_bytes_be() != bytes {
+        return None;
+    }
+    Some(element)
+}
+
+pub fn format_field_element(element: &Fr) -> String {
+    format!("0x{}", hex::encode(element.into_bigint().to_bytes_be()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn element(value: &str) -> Fr {
+        Fr::from_be_bytes_mod_order(&num_bigint::BigUint::parse_bytes(value.as_bytes(), 10).unwrap().to_bytes_be())
+    }
+
+    // From the circomlibjs Poseidon tests.
+    #[test]
+    fn poseidon_matches_circomlib() {
+        let mut hasher = Poseidon::<Fr>::new_circom(2).unwrap();
+        assert_eq!(
+            hasher.hash(&[Fr::from(1u64), Fr::from(2u64)]).unwrap(),
+            element("7853200120776062878684798364095072458815029376092732009249414926327459813530")
+        );
+        assert_eq!(
+            hasher.hash(&[Fr::from(3u64), Fr::from(4u64)]).unwrap(),
+            element("14763215145315200506921711489642608356394854266165572616578112107564877678998")
+        );
+    }
+
+    #[test]
+    fn authentication_path_leads_to_root() {
+        let mut tree = IdentityTree::new();
+        for value in 1..=5u64 {
+            tree.insert(Fr::from(value)).unwrap();
+        }
+        let path = tree.authentication_path(&Fr::from(4u64)).unwrap();
+        assert_eq!(path.leaf_index, 3);
+        let mut hasher = Poseidon::<Fr>::new_circom(2).unwrap();
+        let mut node = Fr::from(4u64);
+        for (sibling, index) in path.siblings.iter().zip(&path.path_indices) {
+            node = match index {
+                0 => hasher.hash(&[node, *sibling]).unwrap(),
+                _ => hasher.hash(&[*sibling, node]).unwrap(),
+            };
+        }
+        assert_eq!(node, tree.root());
+    }
+
+    #[test]
+    fn removal_zeroes_the_leaf_and_allows_reenrolment() {
+        let mut tree = IdentityTree::new();
+        tree.insert(Fr::from(1u64)).unwrap();
+        tree.insert(Fr::from(2u64)).unwrap();
+        tree.insert(Fr::from(3u64)).unwrap();
+        let before = tree.root();
+
+        assert_eq!(tree.remove(&Fr::from(2u64)).unwrap(), 1);
+        assert!(matches!(tree.authentication_path(&Fr::from(2u64)), Err(TreeError::UnknownCommitment)));
+        assert!(matches!(tree.remove(&Fr::from(2u64)), Err(TreeError::UnknownCommitment)));
+
+        let mut zeroed = IdentityTree::new();
+        zeroed.insert(Fr::from(1u64)).unwrap();
+        zeroed.set_leaf(1, Fr::from(0u64));
+        zeroed.insert(Fr::from(3u64)).unwrap();
+        assert_eq!(tree.root(), zeroed.root());
+        assert_ne!(tree.root(), before);
+        assert!(tree.is_known_root(&before));
+
+        assert_eq!(tree.insert(Fr::from(2u64)).unwrap(), 3);
+        assert_eq!(tree.authentication_path(&Fr::from(3u64)).unwrap().leaf_index, 2);
+    }
+}
@@ -0,0 +1,261 @@
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+
+use popchain::merkle::{self, Hash};
+
+/// Where an appended entry landed.
+pub struct Appended {
+    pub leaf_index: usize,
+    pub leaf_hash: Hash,
+    pub root: Hash,
+}
+
+/// Append-only log of ledger entries, one JSON line per entry, with an
+/// RFC 6962 Merkle tree whose leaves are those lines. The file is the only
+/// source of truth: opening it replays every entry and rebuilds the tree.
+pub struct MerkleLog {
+    file: File,
+    subtrees: Subtrees,
+    /// First index of each leaf hash, for proof lookups by hash.
+    positions: HashMap<Hash, usize>,
+    root: Hash,
+}
+
+impl MerkleLog {
+    /// Opens or creates the log at `path` and returns the entries in it. A
+    /// final line without a newline is a write that never completed and was
+    /// never acknowledged, so it is cut off.
+    pub fn open<T: DeserializeOwned>(path: impl AsRef<Path>) -> io::Result<(Self, Vec<T>)> {
+        let path = path.as_ref();
+        let mut file = OpenOptions::new().read(true).append(true).create(true).open(path)?;
+        let mut reader = BufReader::new(&mut file);
+        let mut subtrees = Subtrees::default();
+        let mut entries = Vec::new();
+        let mut valid_len = 0u64;
+        let mut line = Vec::new();
+        loop {
+            line.clear();
+            let read = reader.read_until(b'\n', &mut line)?;
+            if read == 0 {
+                break;
+            }
+            if line.last() != Some(&b'\n') {
+                eprintln!("Discarding incomplete ledger entry at byte {} of {}", valid_len, path.display());
+                break;
+            }
+            let data = &line[..line.len() - 1];
+            let entry = serde_json::from_slice(data).map_err(|e| {
+                io::Error::new(io::ErrorKind::InvalidData, format!("ledger entry {}: {}", subtrees.len(), e))
+            })?;
+            subtrees.push(merkle::leaf_hash(data));
+            entries.push(entry);
+            valid_len += read as u64;
+        }
+        drop(reader);
+        if file.metadata()?.len() != valid_len {
+            file.set_len(valid_len)?;
+        }
+        let mut positions = HashMap::with_capacity(subtrees.len());
+        for (index, leaf) in subtrees.leaves().iter().enumerate() {
+            positions.entry(*leaf).or_insert(index);
+        }
+        let root = subtrees.root(0, subtrees.len());
+        Ok((MerkleLog { file, subtrees, positions, root }, entries))
+    }
+
+    /// Durably appends `entry`; only once the line is synced is it part of
+    /// the tree.
+    pub fn append<T: Serialize>(&mut self, entry: &T) -> io::Result<Appended> {
+        let mut line = serde_json::to_vec(entry).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let leaf_hash = merkle::leaf_hash(&line);
+        line.push(b'\n');
+        let offset = self.file.metadata()?.len();
+        if let Err(e) = self.file.write_all(&line).and_then(|()| self.file.sync_data()) {
+            // Cut a partial line off so the next append starts a fresh one.
+            let _ = self.file.set_len(offset);
+            return Err(e);
+        }
+        self.positions.entry(leaf_hash).or_insert(self.subtrees.len());
+        self.subtrees.push(leaf_hash);
+        self.root = self.subtrees.root(0, self.subtrees.len());
+        Ok(Appended {
+            leaf_index: self.subtrees.len() - 1,
+            leaf_hash,
+            root: self.root,
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.subtrees.len()
+    }
+
+    pub fn root(&self) -> Hash {
+        self.root
+    }
+
+    /// Root of the tree as it was when it had `tree_size` leaves.
+    pub fn root_at(&self, tree_size: usize) -> Option<Hash> {
+        (tree_size <= self.len()).then(|| self.subtrees.root(0, tree_size))
+    }
+
+    pub fn leaf_hash(&self, leaf_index: usize) -> Option<Hash> {
+        self.subtrees.leaves().get(leaf_index).copied()
+    }
+
+    /// Index of the first leaf equal to `leaf_hash`.
+    pub fn position(&self, leaf_hash: &Hash) -> Option<usize> {
+        self.positions.get(leaf_hash).copied()
+    }
+
+    /// Index and audit path of `leaf_hash` in the tree of `tree_size`
+    /// leaves; `None` if the leaf was not yet in the tree at that size.
+    pub fn inclusion_proof(&self, leaf_hash: &Hash, tree_size: usize) -> Option<(usize, Vec<Hash>)> {
+        let index = self.position(leaf_hash)?;
+        if index >= tree_size || tree_size > self.len() {
+            return None;
+        }
+        Some((index, self.subtrees.inclusion_proof(0, tree_size, index)))
+    }
+
+    /// Proof that the tree of `second` leaves extends the tree of `first`.
+    pub fn consistency_proof(&self, first: usize, second: usize) -> Option<Vec<Hash>> {
+        if first > second || second > self.len() {
+            return None;
+        }
+        if first == 0 {
+            return Some(Vec::new());
+        }
+        Some(self.subtrees.consistency_proof(first, 0, second, true))
+    }
+}
+
+/// Hashes of every complete subtree of the tree: `levels[h][i]` covers the
+/// `2^h` leaves from `i * 2^h`. Every node of an RFC 6962 tree, of any size
+/// up to the current one, splits into O(log n) of these, so appends, roots
+/// and proofs never rehash the whole log.
+#[derive(Default)]
+struct Subtrees {
+    levels: Vec<Vec<Hash>>,
+}
+
+impl Subtrees {
+    fn len(&self) -> usize {
+        self.levels.first().map_or(0, Vec::len)
+    }
+
+    fn leaves(&self) -> &[Hash] {
+        self.levels.first().map_or(&[], Vec::as_slice)
+    }
+
+    fn push(&mut self, leaf_hash: Hash) {
+        let mut node = leaf_hash;
+        for height in 0.. {
+            if self.levels.len() == height {
+                self.levels.push(Vec::new());
+            }
+            let level = &mut self.levels[height];
+            level.push(node);
+            if level.len() % 2 == 1 {
+                break;
+            }
+            node = merkle::node_hash(&level[level.len() - 2], &level[level.len() - 1]);
+        }
+    }
+
+    /// Merkle Tree Hash of the leaves in `start..end`, where `start` is a
+    /// multiple of the smallest power of two at least `end - start`, as it
+    /// is for every node of a tree rooted at leaf 0.
+    fn root(&self, start: usize, end: usize) -> Hash {
+        if start == end {
+            return merkle::root(&[]);
+        }
+        // The range is its complete subtrees, largest first; the tree over
+        // them nests to the right.
+        let mut subtrees = Vec::new();
+        let mut offset = start;
+        for height in (0..usize::BITS as usize).rev() {
+            if (end - offset) >> height & 1 == 1 {
+                subtrees.push(self.levels[height][offset >> height]);
+                offset += 1 << height;
+            }
+        }
+        let last = subtrees.pop().expect("range is not empty");
+        subtrees.iter().rev().fold(last, |right, left| merkle::node_hash(left, &right))
+    }
+
+    /// [`merkle::inclusion_proof`] over the leaves in `start..end`.
+    fn inclusion_proof(&self, start: usize, end: usize, index: usize) -> Vec<Hash> {
+        if end - start <= 1 {
+            return Vec::new();
+        }
+        let split = start + merkle::split_point(end - start);
+        let (mut proof, sibling) = if index < split - start {
+            (self.inclusion_proof(start, split, index), self.root(split, end))
+        } else {
+            (self.inclusion_proof(split, end, index - (split - start)), self.root(start, split))
+        };
+        proof.push(sibling);
+        proof
+    }
+
+    /// [`merkle::consistency_proof`]'s subproof over the leaves in
+    /// `start..end`, for the first `first` of them.
+    fn consistency_proof(&self, first: usize, start: usize, end: usize, complete: bool) -> Vec<Hash> {
+        if first == end - start {
+            return if complete { Vec::new() } else { vec![self.root(start, end)] };
+        }
+        let split = start + merkle::split_point(end - start);
+        let (mut proof, sibling) = if first <= split - start {
+            (self.consistency_proof(first, start, split, complete), self.root(split, end))
+        } else {
+            (self.consistency_proof(first - (split - start), split, end, false), self.root(start, split))
+        };
+        proof.push(sibling);
+        proof
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn subtrees_agree_with_the_reference_tree() {
+        let leaves: Vec<Hash> = (0..70u32).map(|i| merkle::leaf_hash(&i.to_be_bytes())).collect();
+        let mut subtrees = Subtrees::default();
+        assert_eq!(subtrees.root(0, 0), merkle::root(&[]));
+        for leaf in &leaves {
+            subtrees.push(*leaf);
+        }
+        for size in 1..=leaves.len() {
+            assert_eq!(subtrees.root(0, size), merkle::root(&leaves[..size]), "root of {}", size);
+            for index in 0..size {
+                assert_eq!(subtrees.inclusion_proof(0, size, index), merkle::inclusion_proof(&leaves[..size], index), "inclusion of {} in {}", index, size);
+            }
+            for first in 1..=size {
+                assert_eq!(subtrees.consistency_proof(first, 0, size, true), merkle::consistency_proof(&leaves[..size], first), "consistency of {} with {}", first, size);
+            }
+        }
+    }
+
+    #[test]
+    fn reopened_logs_keep_their_roots() {
+        let path = std::env::temp_dir().join(format!("popchain-ledger-{}.jsonl", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let (mut log, entries) = MerkleLog::open::<u32>(&path).unwrap();
+        assert!(entries.is_empty());
+        let roots: Vec<Hash> = (0..10u32).map(|i| log.append(&i).unwrap().root).collect();
+        let (log, entries) = MerkleLog::open::<u32>(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(entries, (0..10).collect::<Vec<_>>());
+        assert_eq!(log.root(), roots[9]);
+        for (size, root) in roots.iter().enumerate() {
+            assert_eq!(log.root_at(size + 1), Some(*root));
+        }
+        assert_eq!(log.root_at(11), None);
+    }
+}
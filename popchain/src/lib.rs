@@ -0,0 +1,12 @@
+//! Client-side PoPChain verification: the Merkle proof checks verifiers and
+//! auditors run against proofs served by the ledger, the signed tree head
+//! checks that tie those proofs to a root witnesses agree on, and the
+//! OpenTimestamps and SPV checks that tie a root to a Bitcoin block, and the
+//! revocation status lists verifiers check attestations against offline.
+
+pub mod block;
+pub mod merkle;
+pub mod ots;
+pub mod spv;
+pub mod status_list;
+pub mod sth;
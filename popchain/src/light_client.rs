@@ -0,0 +1,135 @@
+//! Light-client mode (`popchain spv`): keeps a verifier's own header chain,
+//! fed from a bitcoind it runs or from a file of headers, and checks anchor
+//! proofs against it.
+//!
+//! - `popchain spv sync` follows the node's best chain from the checkpoint.
+//! - `popchain spv import <headers.txt>` appends hex headers, one per line.
+//! - `popchain spv verify <proof.json>` checks a `/ledger/anchor-proof`
+//!   response.
+
+use popchain::block::BlockHeader;
+use popchain::spv::{AnchorProof, HeaderChain, StoredHeaders};
+use serde::Deserialize;
+use std::fs;
+use std::io;
+
+use crate::anchor::chain_name;
+use crate::bitcoind::{BitcoinRpc, RpcConfig};
+
+#[derive(Deserialize)]
+pub struct SpvConfig {
+    /// `mainnet`, `testnet`, `testnet4`, `signet` or `regtest`.
+    pub network: String,
+    pub headers_path: String,
+    /// Where a new chain starts; on retargeting networks this must be a
+    /// multiple of 2016. The header there is trusted, not checked.
+    pub checkpoint_height: u64,
+    /// Needed only for `sync`.
+    #[serde(default)]
+    pub bitcoind: Option<RpcConfig>,
+}
+
+const USAGE: &str = "usage: popchain spv sync | import <headers.txt> | verify <proof.json>";
+
+pub async fn run(config: SpvConfig, args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    match args {
+        [command] if command == "sync" => sync(&config).await,
+        [command, path] if command == "import" => import(&config, path),
+        [command, path] if command == "verify" => verify(&config, path),
+        _ => Err(USAGE.into()),
+    }
+}
+
+/// Brings the chain up to the node's tip, first dropping any headers the
+/// node has since reorganized away.
+async fn sync(config: &SpvConfig) -> Result<(), Box<dyn std::error::Error>> {
+    let rpc = BitcoinRpc::new(config.bitcoind.as_ref().ok_or("bitcoind must be configured to sync")?)?;
+    let info = rpc.blockchain_info().await?;
+    if Some(info.chain.as_str()) != chain_name(&config.network) {
+        return Err(format!("bitcoind is on {}, configured for {}", info.chain, config.network).into());
+    }
+    let mut chain = match load(config)? {
+        Some(chain) => chain,
+        None => {
+            let hash = rpc.block_hash(config.checkpoint_height).await?;
+            let checkpoint = BlockHeader::from_hex(&rpc.raw_block_header(&hash).await?).map_err(|e| e.to_string())?;
+            println!("Starting header chain at height {} ({})", config.checkpoint_height, hash);
+            HeaderChain::new(&config.network, config.checkpoint_height, checkpoint).map_err(|e| e.to_string())?
+        }
+    };
+
+    if info.blocks < chain.start_height() {
+        return Err("bitcoind has not reached the checkpoint".into());
+    }
+    let mut height = chain.tip_height().min(info.blocks);
+    while rpc.block_hash(height).await? != chain.header_at(height).unwrap().hash_hex() {
+        if height == chain.start_height() {
+            return Err("bitcoind's chain does not contain the checkpoint".into());
+        }
+        height -= 1;
+    }
+    if height < chain.tip_height() {
+        println!("Reorganization: dropping {} headers above height {}", chain.tip_height() - height, height);
+        chain.truncate(height);
+    }
+    for height in chain.tip_height() + 1..=info.blocks {
+        let header = BlockHeader::from_hex(&rpc.raw_block_header(&rpc.block_hash(height).await?).await?).map_err(|e| e.to_string())?;
+        chain.push(header).map_err(|e| e.to_string())?;
+    }
+    save(config, &chain)?;
+    println!("Header chain at height {} ({})", chain.tip_height(), chain.tip().hash_hex());
+    Ok(())
+}
+
+/// Appends headers from a file, skipping those already in the chain. A new
+/// chain takes the first header as its checkpoint.
+fn import(config: &SpvConfig, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut chain = load(config)?;
+    let mut added = 0;
+    for line in fs::read_to_string(path)?.lines().filter(|line| !line.trim().is_empty()) {
+        let header = BlockHeader::from_hex(line).map_err(|e| e.to_string())?;
+        match &mut chain {
+            Some(chain) if chain.height_of(&header.hash()).is_some() => continue,
+            Some(chain) => chain.push(header).map_err(|e| e.to_string())?,
+            None => chain = Some(HeaderChain::new(&config.network, config.checkpoint_height, header).map_err(|e| e.to_string())?),
+        }
+        added += 1;
+    }
+    let chain = chain.ok_or("no headers to import")?;
+    save(config, &chain)?;
+    println!("Imported {} headers, chain at height {} ({})", added, chain.tip_height(), chain.tip().hash_hex());
+    Ok(())
+}
+
+fn verify(config: &SpvConfig, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let chain = load(config)?.ok_or("no header chain; run `popchain spv sync` or `import` first")?;
+    let proof: AnchorProof = serde_json::from_str(&fs::read_to_string(path)?)?;
+    let verified = chain.verify_anchor(&proof).map_err(|e| e.to_string())?;
+    println!(
+        "Leaf {} is in tree size {} with root {}, anchored in block {} at height {} (block time {})",
+        proof.leaf_hash, proof.tree_size, proof.root_hash, verified.block_hash, verified.block_height, verified.block_time
+    );
+    println!("{} confirmations, {:#x} work on top of the anchor", verified.confirmations, verified.work);
+    Ok(())
+}
+
+/// The stored chain, every header checked again on load.
+fn load(config: &SpvConfig) -> Result<Option<HeaderChain>, Box<dyn std::error::Error>> {
+    let contents = match fs::read_to_string(&config.headers_path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => return Err(e.into()),
+    };
+    let stored: StoredHeaders = serde_json::from_str(&contents)?;
+    if stored.network != config.network {
+        return Err(format!("{} holds {} headers, configured for {}", config.headers_path, stored.network, config.network).into());
+    }
+    Ok(Some(HeaderChain::from_stored(&stored).map_err(|e| e.to_string())?))
+}
+
+fn save(config: &SpvConfig, chain: &HeaderChain) -> io::Result<()> {
+    let contents = serde_json::to_string(&chain.to_stored()).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let tmp = format!("{}.tmp", config.headers_path);
+    fs::write(&tmp, contents)?;
+    fs::rename(&tmp, &config.headers_path)
+}
@@ -1,10 +1,52 @@
-use axum::{routing::{post, get}, Router, Json, extract::State, http::StatusCode, Server};
+use axum::{routing::{post, get}, Router, Json, extract::{FromRequestParts, Path, Query, State}, http::{header, request::Parts, HeaderMap, StatusCode}, response::{IntoResponse, Response}, Server};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs;
 use std::net::SocketAddr;
-use reqwest::Client;
+use std::sync::{Arc, Mutex, RwLock};
 use rand::Rng;
 use sha2::{Digest, Sha256};
+use secp256k1::{Keypair, Secp256k1, SecretKey, XOnlyPublicKey};
+use sequence_code::{Action, SequenceCode};
+use std::time::Duration;
+
+mod anchor;
+mod bitcoind;
+mod expiry;
+mod heads;
+mod identity_tree;
+mod ledger;
+mod light_client;
+mod payments;
+mod reporter;
+mod status_lists;
+mod witness;
+
+use identity_tree::{format_field_element, parse_field_element, IdentityTree, TreeError};
+use anchor::{Anchor, AnchorConfig, AnchorStore};
+use bitcoind::BitcoinRpc;
+use expiry::{AttestationStatus, ExpiryConfig, ExpiryIndex};
+use heads::TreeHeads;
+use ledger::MerkleLog;
+use lnd_client::LndClient;
+use payments::{PaidWrite, PaymentError, PendingWrite, PendingWrites, Receipt};
+use reporter::EventReporter;
+use status_lists::{StatusListConfig, StatusLists, StatusSlot};
+use popchain::block::BlockHeader;
+use popchain::merkle::{self, Hash};
+use popchain::ots::{Attestation, DetachedTimestamp, Op, Timestamp};
+use popchain::spv::AnchorProof;
+use popchain::status_list::StatusListEntry;
+use popchain::sth::{Cosignature, CosignedTreeHead, SignedTreeHead, SthError};
+
+/// Environment variable holding the hex secp256k1 key tree heads are signed with.
+const SIGNING_KEY_ENV: &str = "POPCHAIN_SIGNING_KEY";
+/// Environment variable holding the bearer token HumanHash services present
+/// to record events and revocations on the ledger.
+const SERVICE_TOKEN_ENV: &str = "POPCHAIN_SERVICE_TOKEN";
+/// Writers of ledger events, as recorded in [`LedgerEvent::service`].
+const SYSTEM_SERVICE: &str = "system";
+const POPCHAIN_SERVICE: &str = "popchain";
 
 #[derive(Clone, Deserialize)]
 struct Config {
@@ -13,12 +55,101 @@ struct Config {
     lnd_tls_cert_path: String,
     port: u16,
     ledger_endpoint: String,
+    /// Price of an attestation write, paid over Lightning before it is
+    /// committed.
+    write_price_sat: u64,
+    invoice_expiry_secs: u64,
+    /// Invoices an identity may have open at once for different writes.
+    max_open_invoices_per_identity: usize,
+    /// How long expired and committed writes are kept after their invoice
+    /// expires, for status lookups.
+    pending_write_retention_secs: u64,
+    /// How often open invoices are checked for settlement.
+    settlement_poll_secs: u64,
+    pending_writes_path: String,
+    /// Append-only log every ledger write goes to; replayed on startup.
+    ledger_path: String,
+    /// How often a signed tree head is published.
+    sth_interval_secs: u64,
+    /// Bitcoin anchoring of tree roots; off when absent.
+    #[serde(default)]
+    anchoring: Option<AnchorConfig>,
+    #[serde(default)]
+    expiry: ExpiryConfig,
+    #[serde(default)]
+    status_lists: StatusListConfig,
+    /// Hex x-only keys of the witnesses whose cosignatures are accepted.
+    #[serde(default)]
+    witnesses: Vec<String>,
+    /// The system service's `/report/events` endpoint.
+    event_report_url: String,
+}
+
+#[derive(Clone)]
+struct AppState {
+    config: Config,
+    identity_tree: Arc<RwLock<IdentityTree>>,
+    attestations: Arc<RwLock<HashMap<String, AttestationRecord>>>,
+    events: Arc<RwLock<Vec<LedgerEvent>>>,
+    /// Held for the whole of a write so log order and state order agree.
+    log: Arc<Mutex<MerkleLog>>,
+    heads: Arc<RwLock<TreeHeads>>,
+    log_key: XOnlyPublicKey,
+    anchors: Option<Arc<RwLock<AnchorStore>>>,
+    lnd: Arc<LndClient>,
+    pending_writes: Arc<RwLock<PendingWrites>>,
+    expiry: Arc<RwLock<ExpiryIndex>>,
+    /// Key the sequence codes of verifications are checked with and those of
+    /// renewals, reminders and expiries issued with.
+    sequence_secret: Arc<[u8]>,
+    status_lists: Arc<RwLock<StatusLists>>,
+    /// SHA-256 of the service token.
+    service_token_hash: Arc<[u8]>,
+    reporter: EventReporter,
+}
+
+/// A call from the system service, the only holder of the bearer token in
+/// `POPCHAIN_SERVICE_TOKEN`.
+struct Service;
+
+#[axum::async_trait]
+impl FromRequestParts<AppState> for Service {
+    type Rejection = StatusCode;
+
+    async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+        let token = parts
+            .headers
+            .get(header::AUTHORIZATION)
+            .and_then(|value| value.to_str().ok())
+            .and_then(|value| value.strip_prefix("Bearer "));
+        match token {
+            Some(token) if Sha256::digest(token.as_bytes())[..] == state.service_token_hash[..] => Ok(Service),
+            _ => Err(StatusCode::UNAUTHORIZED),
+        }
+    }
 }
 
 #[derive(Deserialize)]
 struct LedgerRequest {
     human_hash_id: String,
-    biometric_data: String,
+    /// Hex biometric template; only its hash is kept.
+    #[serde(default)]
+    biometric_data: Option<String>,
+    /// The hash itself, for callers that do not hold on to the template.
+    #[serde(default)]
+    biometric_hash: Option<String>,
+    identity_commitment: String,
+}
+
+impl LedgerRequest {
+    /// Exactly one of `biometric_data` and `biometric_hash` must be given.
+    fn biometric_hash(&self) -> Option<String> {
+        match (&self.biometric_data, &self.biometric_hash) {
+            (Some(data), None) => Some(hash_biometric_data(data)),
+            (None, Some(hash)) if hash.len() == 64 && hash.bytes().all(|b| matches!(b, b'0'..=b'9' | b'a'..=b'f')) => Some(hash.clone()),
+            _ => None,
+        }
+    }
 }
 
 #[derive(Serialize)]
@@ -26,87 +157,880 @@ struct HealthResponse {
     status: String,
 }
 
+/// Answer to a write: the invoice to pay before it is committed.
 #[derive(Serialize)]
-struct AttestationResponse {
+struct PaymentRequiredResponse {
+    payment_hash: String,
+    payment_request: String,
+    amount_sat: u64,
+    /// The invoice's description hash is the SHA-256 of this.
+    description: String,
+    description_hash: String,
+    expires_at: u64,
+    /// Where to follow the write after paying.
+    status_path: String,
+}
+
+/// What the ledger keeps per enrolled identity; served to verifiers.
+#[derive(Clone, Serialize, Deserialize)]
+struct AttestationRecord {
     attestation_id: String,
     human_hash_id: String,
-    biometric_proof: ZKProof,
-    transaction_hash: String,
+    biometric_hash: String,
+    identity_commitment: String,
+    /// Position of the attestation's entry in the ledger log.
+    leaf_index: usize,
     timestamp: u64,
     expires_at: u64,
+    #[serde(default)]
+    revoked: bool,
+    /// Where the attestation's revocation bit is. Attestations written
+    /// before status lists are given one on replay.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    status_slot: Option<StatusSlot>,
+}
+
+/// An attestation as served to readers, with where it stands against its
+/// expiry.
+#[derive(Serialize)]
+struct AttestationView {
+    #[serde(flatten)]
+    record: AttestationRecord,
+    status: AttestationStatus,
+    /// For embedding in credentials that rest on the attestation.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    credential_status: Option<StatusListEntry>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct LedgerEvent {
+    human_hash_id: String,
+    action: String,
+    sequence_code: String,
+    #[serde(default)]
+    recorded_at: u64,
+    /// The expiry a renewal, reminder or expiry event is about.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    expires_at: Option<u64>,
+    /// Who wrote the event: `system` through `/ledger/event`, or `popchain`
+    /// for the events it records itself. Set here, never by the caller.
+    #[serde(default)]
+    service: String,
+}
+
+#[derive(Deserialize)]
+struct RevokeRequest {
+    human_hash_id: String,
+    sequence_code: String,
+    reason: String,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct RevocationRecord {
+    human_hash_id: String,
+    sequence_code: String,
+    reason: String,
+    revoked_at: u64,
+}
+
+#[derive(Deserialize)]
+struct RenewRequest {
+    human_hash_id: String,
+    /// Sequence code of a successful verification of the holder, as logged
+    /// to the ledger by the verifier.
+    verification_code: String,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct RenewalRecord {
+    human_hash_id: String,
+    /// Issued by PoPChain for the renewal.
+    sequence_code: String,
+    verification_code: String,
+    previous_expires_at: u64,
+    expires_at: u64,
+    renewed_at: u64,
+}
+
+/// One line of the ledger log.
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum LedgerEntry {
+    Attestation(AttestationRecord),
+    Event(LedgerEvent),
+    Revocation(RevocationRecord),
+    Renewal(RenewalRecord),
+}
+
+#[derive(Serialize)]
+struct EventResponse {
+    event_index: usize,
+    recorded_at: u64,
+    leaf_index: usize,
+    merkle_root: String,
+}
+
+#[derive(Serialize)]
+struct LedgerRootResponse {
+    merkle_root: String,
+    tree_size: usize,
+}
+
+#[derive(Deserialize)]
+struct CosignedQuery {
+    #[serde(default)]
+    min_cosignatures: usize,
+}
+
+#[derive(Deserialize)]
+struct CosignatureSubmission {
+    tree_size: usize,
+    timestamp: u64,
+    cosignature: Cosignature,
 }
 
 #[derive(Serialize)]
-struct ZKProof {
-    circuit: String,
-    proof: String,
-    public_inputs: Vec<String>,
-    verification_key: String,
+struct LogKeyResponse {
+    alg: &'static str,
+    public_key: String,
 }
 
-async fn write_ledger(State(config): State<Config>, Json(payload): Json<LedgerRequest>) -> Result<Json<AttestationResponse>, StatusCode> {
+#[derive(Deserialize)]
+struct InclusionQuery {
+    /// Hex leaf hash, as returned by the write.
+    hash: String,
+    /// Defaults to the current tree size.
+    tree_size: Option<usize>,
+    /// Prove against the first tree anchored in Bitcoin that contains the
+    /// leaf, instead of `tree_size`.
+    #[serde(default)]
+    anchored: bool,
+}
+
+#[derive(Serialize)]
+struct InclusionProofResponse {
+    leaf_index: usize,
+    tree_size: usize,
+    audit_path: Vec<String>,
+    merkle_root: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    anchor: Option<Anchor>,
+}
+
+#[derive(Deserialize)]
+struct LeafQuery {
+    /// Hex leaf hash, as returned by the write.
+    hash: String,
+}
+
+#[derive(Deserialize)]
+struct ConsistencyQuery {
+    first: usize,
+    second: usize,
+}
+
+#[derive(Serialize)]
+struct ConsistencyProofResponse {
+    first: usize,
+    second: usize,
+    consistency: Vec<String>,
+    first_root: String,
+    second_root: String,
+}
+
+#[derive(Serialize)]
+struct IdentityRootResponse {
+    root: String,
+    size: usize,
+    depth: usize,
+    root_history: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct KnownRootResponse {
+    root: String,
+    known: bool,
+}
+
+#[derive(Serialize)]
+struct AuthenticationPathResponse {
+    identity_commitment: String,
+    leaf_index: usize,
+    siblings: Vec<String>,
+    path_indices: Vec<u8>,
+    root: String,
+}
+
+/// Validates a write and answers 402 with the invoice that pays for it. The
+/// same write gets the same invoice until it expires; the write is
+/// committed by the settlement loop once the invoice is paid, after which
+/// asking again is a conflict. Only the system service may ask, since each
+/// new write costs an LND invoice, and an identity has a bounded number of
+/// invoices open at once.
+async fn write_ledger(State(state): State<AppState>, _: Service, Json(payload): Json<LedgerRequest>) -> Result<(StatusCode, Json<PaymentRequiredResponse>), StatusCode> {
     println!("Received identity commitment: {}", payload.human_hash_id);
-    let biometric_hash = hash_biometric_data(&payload.biometric_data);
-    let zk_proof = generate_zk_proof(&payload.human_hash_id, &biometric_hash);
-    
-    match get_lnd_info(config.clone()).await {
-        Ok(info) => println!("LND connection for commitment: {}", info),
+    let identity_commitment = parse_field_element(&payload.identity_commitment).ok_or(StatusCode::BAD_REQUEST)?;
+    let biometric_hash = payload.biometric_hash().ok_or(StatusCode::BAD_REQUEST)?;
+    // A revoked identity may enrol again, replacing its attestation.
+    if state.attestations.read().unwrap().get(&payload.human_hash_id).is_some_and(|record| !record.revoked) {
+        return Err(StatusCode::CONFLICT);
+    }
+    match state.identity_tree.read().unwrap().check_insert(&identity_commitment) {
+        Ok(()) => {}
+        Err(TreeError::Duplicate) => return Err(StatusCode::CONFLICT),
         Err(e) => {
-            eprintln!("LND connection failed: {}", e);
+            eprintln!("Identity tree insert failed: {}", e);
             return Err(StatusCode::INTERNAL_SERVER_ERROR);
         }
     }
 
-    let transaction_hash = format!("tx_{}", generate_nonce());
-    let attestation_id = generate_attestation_id();
-    
-    let response = AttestationResponse {
-        attestation_id,
+    let write = PaidWrite {
         human_hash_id: payload.human_hash_id,
-        biometric_proof: zk_proof,
-        transaction_hash,
-        timestamp: std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs(),
-        expires_at: std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs() + 365 * 24 * 60 * 60,
+        biometric_hash,
+        identity_commitment: format_field_element(&identity_commitment),
     };
-    
-    Ok(Json(response))
+    let description = write.description();
+    let human_hash_id = write.human_hash_id.clone();
+    let pending = payments::request_payment(
+        &state.lnd,
+        &state.pending_writes,
+        write,
+        state.config.write_price_sat,
+        state.config.invoice_expiry_secs,
+        state.config.max_open_invoices_per_identity,
+    )
+    .await
+    .map_err(|e| match e {
+        PaymentError::TooManyOpenInvoices(_) => {
+            eprintln!("Refusing another invoice for {}: {}", human_hash_id, e);
+            StatusCode::TOO_MANY_REQUESTS
+        }
+        e => {
+            eprintln!("Failed to create invoice: {}", e);
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    })?;
+    Ok((
+        StatusCode::PAYMENT_REQUIRED,
+        Json(PaymentRequiredResponse {
+            status_path: format!("{}/{}", state.config.ledger_endpoint, pending.payment_hash),
+            payment_hash: pending.payment_hash,
+            payment_request: pending.payment_request,
+            amount_sat: pending.amount_sat,
+            description,
+            description_hash: pending.description_hash,
+            expires_at: pending.expires_at,
+        }),
+    ))
 }
 
-async fn health() -> Json<HealthResponse> {
-    Json(HealthResponse {
-        status: "healthy".to_string(),
+async fn write_status(State(state): State<AppState>, Path(payment_hash): Path<String>) -> Result<Json<PendingWrite>, StatusCode> {
+    state.pending_writes.read().unwrap().get(&payment_hash).cloned().map(Json).ok_or(StatusCode::NOT_FOUND)
+}
+
+/// Commits a paid write. A write already committed (the loop was stopped
+/// before recording it) gets its original receipt back.
+fn commit_write(state: &AppState, write: &PaidWrite) -> Result<Receipt, String> {
+    let identity_commitment = parse_field_element(&write.identity_commitment).ok_or("invalid identity commitment")?;
+    let mut log = state.log.lock().unwrap();
+    if let Some(record) = state.attestations.read().unwrap().get(&write.human_hash_id).filter(|record| !record.revoked) {
+        if record.biometric_hash != write.biometric_hash || record.identity_commitment != write.identity_commitment {
+            return Err(format!("{} is already enrolled", write.human_hash_id));
+        }
+        return Ok(Receipt {
+            attestation_id: record.attestation_id.clone(),
+            leaf_index: record.leaf_index,
+            leaf_hash: log.leaf_hash(record.leaf_index).map(hex::encode).unwrap_or_default(),
+            merkle_root: log.root_at(record.leaf_index + 1).map(hex::encode).unwrap_or_default(),
+            tree_size: record.leaf_index + 1,
+            timestamp: record.timestamp,
+            expires_at: record.expires_at,
+        });
+    }
+    state.identity_tree.read().unwrap().check_insert(&identity_commitment).map_err(|e| e.to_string())?;
+
+    let timestamp = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
+    let status_slot = state.status_lists.read().unwrap().allocate();
+    let record = AttestationRecord {
+        attestation_id: generate_attestation_id(),
+        human_hash_id: write.human_hash_id.clone(),
+        biometric_hash: write.biometric_hash.clone(),
+        identity_commitment: write.identity_commitment.clone(),
+        leaf_index: log.len(),
+        timestamp,
+        expires_at: timestamp + state.config.expiry.validity_secs,
+        revoked: false,
+        status_slot: Some(status_slot),
+    };
+    let appended = log.append(&LedgerEntry::Attestation(record.clone())).map_err(|e| format!("ledger append failed: {}", e))?;
+    state.identity_tree.write().unwrap().insert(identity_commitment).expect("checked before appending");
+    state.expiry.write().unwrap().track(&record.human_hash_id, record.expires_at);
+    {
+        let mut status_lists = state.status_lists.write().unwrap();
+        status_lists.assign(status_slot).expect("allocated a free slot");
+        status_lists.publish(timestamp);
+    }
+    state.attestations.write().unwrap().insert(record.human_hash_id.clone(), record.clone());
+    Ok(Receipt {
+        attestation_id: record.attestation_id,
+        leaf_index: appended.leaf_index,
+        leaf_hash: hex::encode(appended.leaf_hash),
+        merkle_root: hex::encode(appended.root),
+        tree_size: appended.leaf_index + 1,
+        timestamp: record.timestamp,
+        expires_at: record.expires_at,
     })
 }
 
-async fn get_lnd_info(config: Config) -> Result<String, Box<dyn std::error::Error>> {
-    println!("Attempting to read TLS cert at: {}", config.lnd_tls_cert_path);
-    let tls_cert = fs::read(&config.lnd_tls_cert_path)?;
-    println!("Successfully read TLS cert (length: {} bytes)", tls_cert.len());
-
-    println!("Attempting to read macaroon at: {}", config.lnd_macaroon_path);
-    let macaroon = fs::read(&config.lnd_macaroon_path)?;
-    println!("Successfully read macaroon (length: {} bytes)", macaroon.len());
-    let macaroon = hex::encode(macaroon);
-
-    let client = Client::builder()
-        .add_root_certificate(reqwest::Certificate::from_pem(&tls_cert)?)
-        .build()?;
-    
-    let rest_endpoint = format!("{}/v1/getinfo", config.lnd_host);
-    println!("Attempting to connect to LND REST API at: {}", rest_endpoint);
-    let response = client
-        .get(&rest_endpoint)
-        .header("Grpc-Metadata-macaroon", macaroon)
-        .send()
-        .await?;
+async fn read_attestation(State(state): State<AppState>, Path(human_hash_id): Path<String>) -> Result<Json<AttestationView>, StatusCode> {
+    let record = state.attestations.read().unwrap().get(&human_hash_id).cloned().ok_or(StatusCode::NOT_FOUND)?;
+    Ok(Json(attestation_view(&state, record)))
+}
+
+fn attestation_view(state: &AppState, record: AttestationRecord) -> AttestationView {
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
+    let status = AttestationStatus::at(record.expires_at, now, state.config.expiry.expiring_soon_secs);
+    let credential_status = record.status_slot.map(|slot| state.status_lists.read().unwrap().entry(slot));
+    AttestationView { record, status, credential_status }
+}
+
+/// A signed `BitstringStatusListCredential`. The ETag changes with every
+/// revocation in the list, so a verifier revalidating its cached copy gets
+/// a 304 until there is something new to fetch.
+async fn status_list(State(state): State<AppState>, Path(list): Path<usize>, headers: HeaderMap) -> Result<Response, StatusCode> {
+    let status_lists = state.status_lists.read().unwrap();
+    let published = status_lists.published(list).ok_or(StatusCode::NOT_FOUND)?;
+    let caching = [
+        (header::ETAG, published.etag.clone()),
+        (header::CACHE_CONTROL, format!("public, max-age={}", status_lists.max_age_secs())),
+        (header::LAST_MODIFIED, published.last_modified.clone()),
+    ];
+    let etag = published.etag.trim_start_matches("W/");
+    let not_modified = headers
+        .get(header::IF_NONE_MATCH)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.split(',').map(|tag| tag.trim().trim_start_matches("W/")).any(|tag| tag == etag || tag == "*"));
+    if not_modified {
+        return Ok((StatusCode::NOT_MODIFIED, caching).into_response());
+    }
+    Ok((caching, [(header::CONTENT_TYPE, popchain::status_list::MEDIA_TYPE)], published.body.clone()).into_response())
+}
 
-    if response.status().is_success() {
-        let json: serde_json::Value = response.json().await?;
-        println!("Successfully connected to LND REST API");
-        Ok(format!("LND Identity Pubkey: {}", json["identity_pubkey"].as_str().unwrap_or("unknown")))
+
+/// Extends an attestation that is expiring soon by another validity period.
+/// The holder must have been verified within the last few minutes: the
+/// verification's sequence code has to carry a valid MAC bound to the
+/// holder, be recent, and have been logged for this holder by the system
+/// service since the attestation was last renewed, so each verification
+/// renews at most once.
+async fn renew_attestation(State(state): State<AppState>, Json(request): Json<RenewRequest>) -> Result<Json<AttestationView>, StatusCode> {
+    let verification = SequenceCode::parse(&request.verification_code).map_err(|_| StatusCode::BAD_REQUEST)?;
+    if verification.action != Action::Verify {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
+    let age = (now as i64).saturating_sub(verification.timestamp);
+    if !verification.verify_for(&state.sequence_secret, &request.human_hash_id) || !(0..=state.config.expiry.renewal_max_verification_age_secs as i64).contains(&age) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let mut log = state.log.lock().unwrap();
+    let record = state.attestations.read().unwrap().get(&request.human_hash_id).cloned().ok_or(StatusCode::NOT_FOUND)?;
+    if record.revoked {
+        return Err(StatusCode::GONE);
+    }
+    match AttestationStatus::at(record.expires_at, now, state.config.expiry.expiring_soon_secs) {
+        AttestationStatus::ExpiringSoon => {}
+        AttestationStatus::Active => return Err(StatusCode::CONFLICT),
+        AttestationStatus::Expired => return Err(StatusCode::GONE),
+    }
+    let verified_since_renewal = state
+        .events
+        .read()
+        .unwrap()
+        .iter()
+        .rev()
+        .filter(|event| event.human_hash_id == request.human_hash_id)
+        .take_while(|event| event.action != Action::Renew.as_str())
+        .any(|event| event.action == Action::Verify.as_str() && event.service == SYSTEM_SERVICE && event.sequence_code == request.verification_code);
+    if !verified_since_renewal {
+        return Err(StatusCode::FORBIDDEN);
+    }
+
+    let renewal = RenewalRecord {
+        human_hash_id: request.human_hash_id,
+        sequence_code: SequenceCode::issue(Action::Renew, &state.sequence_secret).to_string(),
+        verification_code: request.verification_code,
+        previous_expires_at: record.expires_at,
+        expires_at: record.expires_at + state.config.expiry.validity_secs,
+        renewed_at: now,
+    };
+    log.append(&LedgerEntry::Renewal(renewal.clone())).map_err(|e| {
+        eprintln!("Ledger append failed: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    println!("Renewed attestation for {} until {} ({})", renewal.human_hash_id, renewal.expires_at, renewal.sequence_code);
+    let human_hash_id = renewal.human_hash_id.clone();
+    state.reporter.report(&renewal.sequence_code, Action::Renew, &human_hash_id);
+    apply_renewal(&state, renewal);
+    let record = state.attestations.read().unwrap()[&human_hash_id].clone();
+    Ok(Json(attestation_view(&state, record)))
+}
+
+/// Revokes an attestation on behalf of a service, under a `REV` sequence
+/// code that service issued.
+async fn revoke_attestation(State(state): State<AppState>, _: Service, Json(request): Json<RevokeRequest>) -> Result<Json<AttestationRecord>, StatusCode> {
+    let code = SequenceCode::parse(&request.sequence_code).map_err(|_| StatusCode::BAD_REQUEST)?;
+    if code.action != Action::Revoke || !code.verify(&state.sequence_secret) {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    let mut log = state.log.lock().unwrap();
+    if !state.attestations.read().unwrap().contains_key(&request.human_hash_id) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    let revocation = RevocationRecord {
+        human_hash_id: request.human_hash_id,
+        sequence_code: request.sequence_code,
+        reason: request.reason,
+        revoked_at: std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs(),
+    };
+    println!("Revoked attestation for {} ({}): {}", revocation.human_hash_id, revocation.sequence_code, revocation.reason);
+    log.append(&LedgerEntry::Revocation(revocation.clone())).map_err(|e| {
+        eprintln!("Ledger append failed: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    let human_hash_id = revocation.human_hash_id.clone();
+    let revoked_at = revocation.revoked_at;
+    state.reporter.report(&revocation.sequence_code, Action::Revoke, &human_hash_id);
+    apply_revocation(&state, revocation);
+    let record = state.attestations.read().unwrap()[&human_hash_id].clone();
+    if let Some(slot) = record.status_slot {
+        let mut status_lists = state.status_lists.write().unwrap();
+        status_lists.revoke(slot);
+        status_lists.publish(revoked_at);
+    }
+    Ok(Json(record))
+}
+
+/// Records an event the system service issued a sequence code for. The code
+/// must carry a valid MAC and name the event's action; a verification's code
+/// must also be bound to the identity verified.
+async fn write_event(State(state): State<AppState>, _: Service, Json(mut event): Json<LedgerEvent>) -> Result<Json<EventResponse>, StatusCode> {
+    let action: Action = event.action.parse().map_err(|_| StatusCode::BAD_REQUEST)?;
+    // Renewals, reminders and expiries are recorded by PoPChain itself.
+    if matches!(action, Action::Renew | Action::Remind | Action::Expire) {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let code = SequenceCode::parse(&event.sequence_code).map_err(|_| StatusCode::BAD_REQUEST)?;
+    let authentic = match action {
+        Action::Verify => code.verify_for(&state.sequence_secret, &event.human_hash_id),
+        _ => code.verify(&state.sequence_secret),
+    };
+    if code.action != action || !authentic {
+        return Err(StatusCode::FORBIDDEN);
+    }
+    event.action = action.to_string();
+    event.service = SYSTEM_SERVICE.to_string();
+    let mut log = state.log.lock().unwrap();
+    if !state.attestations.read().unwrap().contains_key(&event.human_hash_id) {
+        return Err(StatusCode::NOT_FOUND);
+    }
+    event.recorded_at = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
+    event.expires_at = None;
+    let appended = log.append(&LedgerEntry::Event(event.clone())).map_err(|e| {
+        eprintln!("Ledger append failed: {}", e);
+        StatusCode::INTERNAL_SERVER_ERROR
+    })?;
+    println!("Recorded {} event for {}: {}", event.action, event.human_hash_id, event.sequence_code);
+    let recorded_at = event.recorded_at;
+    let event_index = apply_event(&state, event);
+    Ok(Json(EventResponse {
+        event_index,
+        recorded_at,
+        leaf_index: appended.leaf_index,
+        merkle_root: hex::encode(appended.root),
+    }))
+}
+
+async fn ledger_root(State(state): State<AppState>) -> Json<LedgerRootResponse> {
+    let log = state.log.lock().unwrap();
+    Json(LedgerRootResponse {
+        merkle_root: hex::encode(log.root()),
+        tree_size: log.len(),
+    })
+}
+
+/// The most recently published signed tree head, with the cosignatures
+/// collected for it so far.
+async fn latest_sth(State(state): State<AppState>) -> Result<Json<CosignedTreeHead>, StatusCode> {
+    state.heads.read().unwrap().latest().cloned().map(Json).ok_or(StatusCode::SERVICE_UNAVAILABLE)
+}
+
+/// The newest head witnesses have cosigned enough; what clients that require
+/// cosignatures should fetch.
+async fn cosigned_sth(State(state): State<AppState>, Query(query): Query<CosignedQuery>) -> Result<Json<CosignedTreeHead>, StatusCode> {
+    state.heads.read().unwrap().latest_cosigned(&state.log_key, query.min_cosignatures).cloned().map(Json).ok_or(StatusCode::NOT_FOUND)
+}
+
+async fn submit_cosignature(State(state): State<AppState>, Json(submission): Json<CosignatureSubmission>) -> StatusCode {
+    let witness = submission.cosignature.witness.clone();
+    match state.heads.write().unwrap().add_cosignature(&state.log_key, submission.tree_size, submission.timestamp, submission.cosignature) {
+        Ok(true) => {
+            println!("Witness {} cosigned tree size {}", witness, submission.tree_size);
+            StatusCode::NO_CONTENT
+        }
+        Ok(false) => StatusCode::NOT_FOUND,
+        Err(e) => {
+            eprintln!("Rejected cosignature from {}: {}", witness, e);
+            match e {
+                SthError::UntrustedWitness => StatusCode::FORBIDDEN,
+                _ => StatusCode::BAD_REQUEST,
+            }
+        }
+    }
+}
+
+async fn list_anchors(State(state): State<AppState>) -> Result<Json<Vec<Anchor>>, StatusCode> {
+    let anchors = state.anchors.as_ref().ok_or(StatusCode::NOT_FOUND)?;
+    let anchors = anchors.read().unwrap().list().to_vec();
+    Ok(Json(anchors))
+}
+
+async fn log_key(State(state): State<AppState>) -> Json<LogKeyResponse> {
+    Json(LogKeyResponse {
+        alg: "BIP340",
+        public_key: hex::encode(state.log_key.serialize()),
+    })
+}
+
+/// Records expiries and reminders as they fall due, every `interval`.
+async fn sweep_expiry(state: AppState, interval: Duration) {
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
+        if let Err(e) = sweep_expiry_once(&state, now) {
+            eprintln!("Expiry sweep failed: {}", e);
+        }
+    }
+}
+
+/// Appends an `EXP` event for every attestation that lapsed by `now` and an
+/// `RMD` event for every one owed a reminder, and reports each to the
+/// system service's event store.
+fn sweep_expiry_once(state: &AppState, now: u64) -> std::io::Result<()> {
+    let mut log = state.log.lock().unwrap();
+    let due = state.expiry.read().unwrap().due(now);
+    let events = due
+        .expired
+        .into_iter()
+        .map(|(human_hash_id, expires_at)| (Action::Expire, human_hash_id, expires_at))
+        .chain(due.reminders.into_iter().map(|(human_hash_id, expires_at)| (Action::Remind, human_hash_id, expires_at)));
+    for (action, human_hash_id, expires_at) in events {
+        let event = LedgerEvent {
+            human_hash_id,
+            action: action.to_string(),
+            sequence_code: SequenceCode::issue(action, &state.sequence_secret).to_string(),
+            recorded_at: now,
+            expires_at: Some(expires_at),
+            service: POPCHAIN_SERVICE.to_string(),
+        };
+        log.append(&LedgerEntry::Event(event.clone()))?;
+        state.reporter.report(&event.sequence_code, action, &event.human_hash_id);
+        match action {
+            Action::Expire => println!("Attestation for {} expired at {}", event.human_hash_id, expires_at),
+            _ => println!("Reminded {} of expiry at {}", event.human_hash_id, expires_at),
+        }
+        apply_event(state, event);
+    }
+    Ok(())
+}
+
+/// Signs and publishes the current tree head every `interval`, so clients
+/// and witnesses always have a fresh timestamped root to check against.
+async fn publish_tree_heads(state: AppState, keypair: Keypair, interval: Duration) {
+    let secp = Secp256k1::signing_only();
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        let (tree_size, root) = {
+            let log = state.log.lock().unwrap();
+            (log.len(), log.root())
+        };
+        let timestamp = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs();
+        let sth = SignedTreeHead::sign(&secp, &keypair, tree_size, timestamp, &root);
+        state.heads.write().unwrap().publish(sth);
+    }
+}
+
+/// CT `get-proof-by-hash`: where a leaf sits in the tree of a given size,
+/// with the audit path to that tree's root.
+async fn proof_by_hash(State(state): State<AppState>, Query(query): Query<InclusionQuery>) -> Result<Json<InclusionProofResponse>, StatusCode> {
+    let leaf_hash: Hash = hex::decode(&query.hash)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or(StatusCode::BAD_REQUEST)?;
+    let log = state.log.lock().unwrap();
+    let anchor = if query.anchored {
+        let leaf_index = log.position(&leaf_hash).ok_or(StatusCode::NOT_FOUND)?;
+        let anchors = state.anchors.as_ref().ok_or(StatusCode::NOT_FOUND)?.read().unwrap();
+        Some(anchors.covering(leaf_index).cloned().ok_or(StatusCode::NOT_FOUND)?)
     } else {
-        Err(format!("Failed to connect to LND REST API: {}", response.status()).into())
+        None
+    };
+    let tree_size = anchor.as_ref().map(|anchor| anchor.tree_size).or(query.tree_size).unwrap_or(log.len());
+    if tree_size > log.len() {
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let (leaf_index, audit_path) = log.inclusion_proof(&leaf_hash, tree_size).ok_or(StatusCode::NOT_FOUND)?;
+    let merkle_root = log.root_at(tree_size).ok_or(StatusCode::BAD_REQUEST)?;
+    Ok(Json(InclusionProofResponse {
+        leaf_index,
+        tree_size,
+        audit_path: audit_path.iter().map(hex::encode).collect(),
+        merkle_root: hex::encode(merkle_root),
+        anchor,
+    }))
+}
+
+/// Exports an OpenTimestamps proof that the leaf existed when the first
+/// confirmed anchor covering it was mined. The proof timestamps the leaf
+/// hash, i.e. the file `0x00 || entry` for the entry's ledger line, so
+/// auditors check it with `ots verify -d <leaf hash>`.
+async fn export_ots(State(state): State<AppState>, Query(query): Query<LeafQuery>) -> Result<impl IntoResponse, StatusCode> {
+    let leaf_hash: Hash = hex::decode(&query.hash)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or(StatusCode::BAD_REQUEST)?;
+    let log = state.log.lock().unwrap();
+    let leaf_index = log.position(&leaf_hash).ok_or(StatusCode::NOT_FOUND)?;
+    let anchor = state
+        .anchors
+        .as_ref()
+        .ok_or(StatusCode::NOT_FOUND)?
+        .read()
+        .unwrap()
+        .covering(leaf_index)
+        .cloned()
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let (_, audit_path) = log.inclusion_proof(&leaf_hash, anchor.tree_size).ok_or(StatusCode::NOT_FOUND)?;
+    drop(log);
+
+    let mut ops = Vec::new();
+    for (sibling, on_left) in audit_path.iter().zip(merkle::sibling_on_left(leaf_index, anchor.tree_size)) {
+        if on_left {
+            ops.push(Op::Prepend([&[0x01], &sibling[..]].concat()));
+        } else {
+            ops.push(Op::Append(sibling.to_vec()));
+            ops.push(Op::Prepend(vec![0x01]));
+        }
+        ops.push(Op::Sha256);
+    }
+    ops.extend(anchor.timestamp_ops().ok_or(StatusCode::NOT_FOUND)?);
+    let height = anchor.block_height.ok_or(StatusCode::NOT_FOUND)?;
+    let proof = DetachedTimestamp {
+        digest: leaf_hash,
+        timestamp: Timestamp::chain(ops, Attestation::Bitcoin { height }),
+    };
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/vnd.opentimestamps.v1".to_string()),
+            (header::CONTENT_DISPOSITION, format!("attachment; filename=\"{}.ots\"", query.hash)),
+        ],
+        proof.serialize(),
+    ))
+}
+
+/// Everything a light client needs to check, against its own header chain,
+/// that the leaf is in a root anchored by the first confirmed anchor
+/// covering it.
+async fn anchor_proof(State(state): State<AppState>, Query(query): Query<LeafQuery>) -> Result<Json<AnchorProof>, StatusCode> {
+    let leaf_hash: Hash = hex::decode(&query.hash)
+        .ok()
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or(StatusCode::BAD_REQUEST)?;
+    let log = state.log.lock().unwrap();
+    let leaf_index = log.position(&leaf_hash).ok_or(StatusCode::NOT_FOUND)?;
+    let anchor = state
+        .anchors
+        .as_ref()
+        .ok_or(StatusCode::NOT_FOUND)?
+        .read()
+        .unwrap()
+        .covering(leaf_index)
+        .cloned()
+        .ok_or(StatusCode::NOT_FOUND)?;
+    let (_, audit_path) = log.inclusion_proof(&leaf_hash, anchor.tree_size).ok_or(StatusCode::NOT_FOUND)?;
+    let inclusion = anchor.inclusion.ok_or(StatusCode::NOT_FOUND)?;
+    Ok(Json(AnchorProof {
+        leaf_hash: query.hash,
+        leaf_index,
+        tree_size: anchor.tree_size,
+        audit_path: audit_path.iter().map(hex::encode).collect(),
+        root_hash: anchor.root_hash,
+        raw_tx: inclusion.raw_tx,
+        tx_index: inclusion.tx_index,
+        merkle_branch: inclusion.merkle_branch,
+        block_height: anchor.block_height.ok_or(StatusCode::NOT_FOUND)?,
+    }))
+}
+
+/// CT `get-sth-consistency`: proof that the tree of `second` leaves
+/// extends the tree of `first` leaves.
+async fn consistency_proof(State(state): State<AppState>, Query(query): Query<ConsistencyQuery>) -> Result<Json<ConsistencyProofResponse>, StatusCode> {
+    let log = state.log.lock().unwrap();
+    let consistency = log.consistency_proof(query.first, query.second).ok_or(StatusCode::BAD_REQUEST)?;
+    let (first_root, second_root) = match (log.root_at(query.first), log.root_at(query.second)) {
+        (Some(first_root), Some(second_root)) => (first_root, second_root),
+        _ => return Err(StatusCode::BAD_REQUEST),
+    };
+    Ok(Json(ConsistencyProofResponse {
+        first: query.first,
+        second: query.second,
+        consistency: consistency.iter().map(hex::encode).collect(),
+        first_root: hex::encode(first_root),
+        second_root: hex::encode(second_root),
+    }))
+}
+
+/// Records an event, noting reminders and expiries in the expiry index.
+/// Returns the event's index.
+fn apply_event(state: &AppState, event: LedgerEvent) -> usize {
+    if let Some(expires_at) = event.expires_at {
+        let mut expiry = state.expiry.write().unwrap();
+        match event.action.parse() {
+            Ok(Action::Remind) => expiry.reminded(&event.human_hash_id, expires_at, event.recorded_at),
+            Ok(Action::Expire) if expiry.expires_at(&event.human_hash_id) == Some(expires_at) => expiry.remove(&event.human_hash_id),
+            _ => {}
+        }
+    }
+    let mut events = state.events.write().unwrap();
+    events.push(event);
+    events.len() - 1
+}
+
+/// Marks the attestation revoked, takes its commitment out of the identity
+/// tree and records the matching `REV` event.
+fn apply_revocation(state: &AppState, revocation: RevocationRecord) {
+    if let Some(record) = state.attestations.write().unwrap().get_mut(&revocation.human_hash_id) {
+        // Only the first revocation zeroes the leaf; by a later one the
+        // commitment may belong to a new enrolment.
+        if !record.revoked {
+            if let Some(commitment) = parse_field_element(&record.identity_commitment) {
+                let _ = state.identity_tree.write().unwrap().remove(&commitment);
+            }
+        }
+        record.revoked = true;
+    }
+    state.expiry.write().unwrap().remove(&revocation.human_hash_id);
+    state.events.write().unwrap().push(LedgerEvent {
+        human_hash_id: revocation.human_hash_id,
+        action: "REV".to_string(),
+        sequence_code: revocation.sequence_code,
+        recorded_at: revocation.revoked_at,
+        expires_at: None,
+        service: SYSTEM_SERVICE.to_string(),
+    });
+}
+
+/// Moves the attestation's expiry forward and records the matching `RNW`
+/// event.
+fn apply_renewal(state: &AppState, renewal: RenewalRecord) {
+    if let Some(record) = state.attestations.write().unwrap().get_mut(&renewal.human_hash_id) {
+        record.expires_at = renewal.expires_at;
     }
+    state.expiry.write().unwrap().track(&renewal.human_hash_id, renewal.expires_at);
+    state.events.write().unwrap().push(LedgerEvent {
+        human_hash_id: renewal.human_hash_id,
+        action: Action::Renew.to_string(),
+        sequence_code: renewal.sequence_code,
+        recorded_at: renewal.renewed_at,
+        expires_at: Some(renewal.expires_at),
+        service: POPCHAIN_SERVICE.to_string(),
+    });
+}
+
+/// Rebuilds attestations, events, the expiry index and the identity tree
+/// from the log.
+/// Returns the attestations replaced by a re-enrolment, which still hold
+/// their status list slots.
+fn replay(state: &AppState, entries: Vec<LedgerEntry>) -> Result<Vec<AttestationRecord>, Box<dyn std::error::Error>> {
+    let mut replaced = Vec::new();
+    for entry in entries {
+        match entry {
+            LedgerEntry::Attestation(record) => {
+                let commitment = parse_field_element(&record.identity_commitment).ok_or("invalid identity commitment in ledger")?;
+                state.identity_tree.write().unwrap().insert(commitment)?;
+                state.expiry.write().unwrap().track(&record.human_hash_id, record.expires_at);
+                replaced.extend(state.attestations.write().unwrap().insert(record.human_hash_id.clone(), record));
+            }
+            LedgerEntry::Event(event) => {
+                apply_event(state, event);
+            }
+            LedgerEntry::Revocation(revocation) => apply_revocation(state, revocation),
+            LedgerEntry::Renewal(renewal) => apply_renewal(state, renewal),
+        }
+    }
+    Ok(replaced)
+}
+
+/// Gives replayed attestations their status list slots, older ones without
+/// a slot getting the lowest free ones in ledger order, sets the bits of
+/// revoked ones and signs the lists. Attestations replaced by a re-enrolment
+/// keep their slots.
+fn index_status_lists(state: &AppState, mut replaced: Vec<AttestationRecord>) -> Result<(), String> {
+    let mut attestations = state.attestations.write().unwrap();
+    let mut records: Vec<&mut AttestationRecord> = attestations.values_mut().chain(replaced.iter_mut()).collect();
+    records.sort_by_key(|record| record.leaf_index);
+    let mut status_lists = state.status_lists.write().unwrap();
+    for record in records.iter().filter(|record| record.status_slot.is_some()) {
+        status_lists.assign(record.status_slot.unwrap())?;
+    }
+    for record in records.iter_mut().filter(|record| record.status_slot.is_none()) {
+        let slot = status_lists.allocate_lowest();
+        status_lists.assign(slot)?;
+        record.status_slot = Some(slot);
+    }
+    for slot in records.iter().filter(|record| record.revoked).filter_map(|record| record.status_slot) {
+        status_lists.revoke(slot);
+    }
+    status_lists.publish(std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs());
+    Ok(())
+}
+
+async fn identity_root(State(state): State<AppState>) -> Json<IdentityRootResponse> {
+    let tree = state.identity_tree.read().unwrap();
+    Json(IdentityRootResponse {
+        root: format_field_element(&tree.root()),
+        size: tree.len(),
+        depth: identity_tree::TREE_DEPTH,
+        root_history: tree.root_history().map(format_field_element).collect(),
+    })
+}
+
+async fn identity_root_known(State(state): State<AppState>, Path(root): Path<String>) -> Result<Json<KnownRootResponse>, StatusCode> {
+    let root = parse_field_element(&root).ok_or(StatusCode::BAD_REQUEST)?;
+    let known = state.identity_tree.read().unwrap().is_known_root(&root);
+    Ok(Json(KnownRootResponse { root: format_field_element(&root), known }))
+}
+
+async fn identity_path(State(state): State<AppState>, Path(commitment): Path<String>) -> Result<Json<AuthenticationPathResponse>, StatusCode> {
+    let commitment = parse_field_element(&commitment).ok_or(StatusCode::BAD_REQUEST)?;
+    let tree = state.identity_tree.read().unwrap();
+    let path = tree.authentication_path(&commitment).map_err(|_| StatusCode::NOT_FOUND)?;
+    Ok(Json(AuthenticationPathResponse {
+        identity_commitment: format_field_element(&commitment),
+        leaf_index: path.leaf_index,
+        siblings: path.siblings.iter().map(format_field_element).collect(),
+        path_indices: path.path_indices,
+        root: format_field_element(&tree.root()),
+    }))
+}
+
+async fn health() -> Json<HealthResponse> {
+    Json(HealthResponse {
+        status: "healthy".to_string(),
+    })
 }
 
 fn hash_biometric_data(data: &str) -> String {
@@ -115,15 +1039,6 @@ fn hash_biometric_data(data: &str) -> String {
     format!("{:x}", hasher.finalize())
 }
 
-fn generate_zk_proof(human_hash_id: &str, biometric_hash: &str) -> ZKProof {
-    ZKProof {
-        circuit: "biometric_verification".to_string(),
-        proof: format!("proof_{}", generate_nonce()),
-        public_inputs: vec![human_hash_id.to_string(), biometric_hash.to_string()],
-        verification_key: "ver_key_123".to_string(),
-    }
-}
-
 fn generate_attestation_id() -> String {
     format!("att_{}_{}", std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs(), generate_nonce())
 }
@@ -133,20 +1048,140 @@ fn generate_nonce() -> String {
     rng.gen::<u64>().to_string()
 }
 
+/// `popchain verify-ots <proof.ots> <header.hex> [leaf hash]`: checks an
+/// exported proof against a block header from the auditor's own node
+/// (`bitcoin-cli getblockheader <hash> false`), without contacting PoPChain.
+fn verify_ots(args: &[String]) -> Result<(), Box<dyn std::error::Error>> {
+    let [proof_path, header_path, rest @ ..] = args else {
+        return Err("usage: popchain verify-ots <proof.ots> <header.hex> [leaf hash]".into());
+    };
+    let proof = DetachedTimestamp::deserialize(&fs::read(proof_path)?).map_err(|e| e.to_string())?;
+    if let Some(expected) = rest.first() {
+        if !expected.eq_ignore_ascii_case(&hex::encode(proof.digest)) {
+            return Err(format!("proof timestamps {}, not {}", hex::encode(proof.digest), expected).into());
+        }
+    }
+    let header = BlockHeader::from_hex(&fs::read_to_string(header_path)?).map_err(|e| e.to_string())?;
+    let height = proof.verify(&header).map_err(|e| e.to_string())?;
+    println!(
+        "Leaf {} existed by block {} at height {} (block time {})",
+        hex::encode(proof.digest),
+        header.hash_hex(),
+        height,
+        header.time()
+    );
+    Ok(())
+}
+
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let args: Vec<String> = std::env::args().collect();
+    match args.get(1).map(String::as_str) {
+        Some("witness") => {
+            let config: witness::WitnessConfig = serde_json::from_str(&fs::read_to_string("witness_config.json")?)?;
+            return witness::run(config).await;
+        }
+        Some("verify-ots") => return verify_ots(&args[2..]),
+        Some("spv") => {
+            let config: light_client::SpvConfig = serde_json::from_str(&fs::read_to_string("spv_config.json")?)?;
+            return light_client::run(config, &args[2..]).await;
+        }
+        _ => {}
+    }
+
     let config: Config = serde_json::from_str(
         &fs::read_to_string("popchain_config.json")?
     )?;
     println!("Loaded config for LND at {}", config.lnd_host);
-    match get_lnd_info(config.clone()).await {
-        Ok(info) => println!("LND connection successful: {}", info),
+    let lnd = Arc::new(LndClient::new(&config.lnd_host, &config.lnd_macaroon_path, Some(config.lnd_tls_cert_path.clone().into()))?);
+    match lnd.get_info().await {
+        Ok(info) => println!("LND connection successful: LND Identity Pubkey: {}", info.identity_pubkey),
         Err(e) => eprintln!("LND connection failed: {}", e),
     }
+    let secp = Secp256k1::new();
+    let secret = std::env::var(SIGNING_KEY_ENV).map_err(|_| format!("{} must be set to a hex secp256k1 key", SIGNING_KEY_ENV))?;
+    let keypair = Keypair::from_secret_key(&secp, &SecretKey::from_slice(&hex::decode(secret)?)?);
+    let (log, entries) = MerkleLog::open(&config.ledger_path)?;
+    let witnesses = config
+        .witnesses
+        .iter()
+        .map(|key| hex::decode(key).ok().and_then(|bytes| XOnlyPublicKey::from_slice(&bytes).ok()).ok_or_else(|| format!("invalid witness key {}", key)))
+        .collect::<Result<Vec<_>, _>>()?;
+    let state = AppState {
+        config: config.clone(),
+        identity_tree: Arc::new(RwLock::new(IdentityTree::new())),
+        attestations: Arc::new(RwLock::new(HashMap::new())),
+        events: Arc::new(RwLock::new(Vec::new())),
+        log: Arc::new(Mutex::new(log)),
+        heads: Arc::new(RwLock::new(TreeHeads::new(witnesses))),
+        log_key: keypair.x_only_public_key().0,
+        anchors: match &config.anchoring {
+            Some(anchoring) => Some(Arc::new(RwLock::new(AnchorStore::open(&anchoring.store_path)?))),
+            None => None,
+        },
+        lnd: lnd.clone(),
+        pending_writes: Arc::new(RwLock::new(PendingWrites::open(&config.pending_writes_path)?)),
+        expiry: Arc::new(RwLock::new(ExpiryIndex::new(&config.expiry.reminder_lead_secs))),
+        sequence_secret: sequence_code::secret_from_env()?.into(),
+        status_lists: Arc::new(RwLock::new(StatusLists::new(
+            &config.status_lists,
+            config.status_lists.public_url.as_deref().unwrap_or(&format!("http://127.0.0.1:{}", config.port)),
+            keypair,
+        ))),
+        service_token_hash: match std::env::var(SERVICE_TOKEN_ENV) {
+            Ok(token) if !token.is_empty() => Sha256::digest(token.as_bytes()).to_vec().into(),
+            _ => return Err(format!("{} must be set", SERVICE_TOKEN_ENV).into()),
+        },
+        reporter: match std::env::var(reporter::INGEST_TOKEN_ENV) {
+            Ok(token) if !token.is_empty() => EventReporter::new(&config.event_report_url, &token),
+            _ => return Err(format!("{} must be set", reporter::INGEST_TOKEN_ENV).into()),
+        },
+    };
+    let replaced = replay(&state, entries)?;
+    index_status_lists(&state, replaced)?;
+    {
+        let log = state.log.lock().unwrap();
+        println!("Replayed {} ledger entries, root {}", log.len(), hex::encode(log.root()));
+        println!("Tracking {} attestations for expiry", state.expiry.read().unwrap().len());
+    }
+    if let (Some(anchoring), Some(anchors)) = (config.anchoring.clone(), state.anchors.clone()) {
+        let rpc = BitcoinRpc::new(&anchoring.rpc)?;
+        println!("Anchoring tree roots on {} every {}s", anchoring.network, anchoring.interval_secs);
+        tokio::spawn(anchor::run(anchoring, rpc, state.log.clone(), anchors));
+    }
+    let settlement_state = state.clone();
+    tokio::spawn(payments::run(
+        lnd,
+        state.pending_writes.clone(),
+        Duration::from_secs(config.settlement_poll_secs.max(1)),
+        config.pending_write_retention_secs,
+        move |write| commit_write(&settlement_state, write),
+    ));
+    tokio::spawn(sweep_expiry(state.clone(), Duration::from_secs(config.expiry.sweep_interval_secs.max(1))));
+    tokio::spawn(publish_tree_heads(state.clone(), keypair, Duration::from_secs(config.sth_interval_secs.max(1))));
     let app = Router::new()
         .route(&config.ledger_endpoint, post(write_ledger))
+        .route(&format!("{}/:payment_hash", config.ledger_endpoint), get(write_status))
+        .route("/ledger/attestation/:human_hash_id", get(read_attestation))
+        .route("/ledger/event", post(write_event))
+        .route("/ledger/revoke", post(revoke_attestation))
+        .route("/ledger/renew", post(renew_attestation))
+        .route("/ledger/status-list/:list", get(status_list))
+        .route("/ledger/root", get(ledger_root))
+        .route("/ledger/proof-by-hash", get(proof_by_hash))
+        .route("/ledger/consistency", get(consistency_proof))
+        .route("/ledger/sth", get(latest_sth))
+        .route("/ledger/sth/cosigned", get(cosigned_sth))
+        .route("/ledger/sth/cosignatures", post(submit_cosignature))
+        .route("/ledger/sth/key", get(log_key))
+        .route("/ledger/anchors", get(list_anchors))
+        .route("/ledger/ots", get(export_ots))
+        .route("/ledger/anchor-proof", get(anchor_proof))
+        .route("/identity/root", get(identity_root))
+        .route("/identity/root/:root", get(identity_root_known))
+        .route("/identity/path/:commitment", get(identity_path))
         .route("/health", get(health))
-        .with_state(config.clone());
+        .with_state(state);
     let addr = SocketAddr::from(([127, 0, 0, 1], config.port));
     println!("Starting PoPChain server on {}", addr);
     Server::bind(&addr)
@@ -0,0 +1,353 @@
+//! RFC 6962 Merkle trees: hashing, audit paths and consistency proofs, and
+//! the checks a client runs on them. Verifiers use the inclusion check to
+//! confirm an attestation is in the ledger; auditors use the consistency
+//! check to confirm a later tree extends an earlier one without rewriting it.
+
+use sha2::{Digest, Sha256};
+
+pub type Hash = [u8; 32];
+
+/// RFC 6962 leaf hash: `SHA-256(0x00 || data)`.
+pub fn leaf_hash(data: &[u8]) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([0x00]);
+    hasher.update(data);
+    hasher.finalize().into()
+}
+
+/// RFC 6962 interior node hash: `SHA-256(0x01 || left || right)`.
+pub fn node_hash(left: &Hash, right: &Hash) -> Hash {
+    let mut hasher = Sha256::new();
+    hasher.update([0x01]);
+    hasher.update(left);
+    hasher.update(right);
+    hasher.finalize().into()
+}
+
+/// Merkle Tree Hash of a list of leaf hashes (RFC 6962 section 2.1). The
+/// empty tree hashes to `SHA-256("")`.
+pub fn root(leaves: &[Hash]) -> Hash {
+    match leaves.len() {
+        0 => Sha256::digest([]).into(),
+        1 => leaves[0],
+        n => {
+            let k = split_point(n);
+            node_hash(&root(&leaves[..k]), &root(&leaves[k..]))
+        }
+    }
+}
+
+/// Audit path for the leaf at `index` in the tree over `leaves`
+/// (RFC 6962 section 2.1.1), ordered from the leaf up.
+pub fn inclusion_proof(leaves: &[Hash], index: usize) -> Vec<Hash> {
+    let n = leaves.len();
+    if n <= 1 {
+        return Vec::new();
+    }
+    let k = split_point(n);
+    if index < k {
+        let mut proof = inclusion_proof(&leaves[..k], index);
+        proof.push(root(&leaves[k..]));
+        proof
+    } else {
+        let mut proof = inclusion_proof(&leaves[k..], index - k);
+        proof.push(root(&leaves[..k]));
+        proof
+    }
+}
+
+/// Proof that the tree over the first `first` leaves is a prefix of the tree
+/// over `leaves` (RFC 6962 section 2.1.2).
+pub fn consistency_proof(leaves: &[Hash], first: usize) -> Vec<Hash> {
+    if first == 0 || first > leaves.len() {
+        return Vec::new();
+    }
+    subproof(first, leaves, true)
+}
+
+fn subproof(m: usize, leaves: &[Hash], complete: bool) -> Vec<Hash> {
+    let n = leaves.len();
+    if m == n {
+        return if complete { Vec::new() } else { vec![root(leaves)] };
+    }
+    let k = split_point(n);
+    if m <= k {
+        let mut proof = subproof(m, &leaves[..k], complete);
+        proof.push(root(&leaves[k..]));
+        proof
+    } else {
+        let mut proof = subproof(m - k, &leaves[k..], false);
+        proof.push(root(&leaves[..k]));
+        proof
+    }
+}
+
+/// Checks that `leaf_hash` is the leaf at `leaf_index` of the tree of
+/// `tree_size` leaves with root `root` (RFC 9162 section 2.1.3.2).
+pub fn verify_inclusion(leaf_hash: &Hash, leaf_index: usize, tree_size: usize, proof: &[Hash], root: &Hash) -> bool {
+    if leaf_index >= tree_size {
+        return false;
+    }
+    let (mut f, mut s) = (leaf_index, tree_size - 1);
+    let mut r = *leaf_hash;
+    for p in proof {
+        if s == 0 {
+            return false;
+        }
+        if f & 1 == 1 || f == s {
+            r = node_hash(p, &r);
+            while f & 1 == 0 && f != 0 {
+                f >>= 1;
+                s >>= 1;
+            }
+        } else {
+            r = node_hash(&r, p);
+        }
+        f >>= 1;
+        s >>= 1;
+    }
+    s == 0 && r == *root
+}
+
+/// For each sibling on the audit path of the leaf at `leaf_index` in a tree
+/// of `tree_size` leaves, whether it is the left input of the node hash.
+/// This is the walk `verify_inclusion` makes, for callers that replay a path
+/// as a sequence of hash operations.
+pub fn sibling_on_left(leaf_index: usize, tree_size: usize) -> Vec<bool> {
+    let mut sides = Vec::new();
+    if leaf_index >= tree_size {
+        return sides;
+    }
+    let (mut f, mut s) = (leaf_index, tree_size - 1);
+    while s != 0 {
+        if f & 1 == 1 || f == s {
+            sides.push(true);
+            while f & 1 == 0 && f != 0 {
+                f >>= 1;
+                s >>= 1;
+            }
+        } else {
+            sides.push(false);
+        }
+        f >>= 1;
+        s >>= 1;
+    }
+    sides
+}
+
+/// Checks that the tree of `second` leaves with root `second_root` extends
+/// the tree of `first` leaves with root `first_root` (RFC 9162 section
+/// 2.1.4.2).
+pub fn verify_consistency(first: usize, second: usize, first_root: &Hash, second_root: &Hash, proof: &[Hash]) -> bool {
+    if first > second {
+        return false;
+    }
+    if first == second {
+        return proof.is_empty() && first_root == second_root;
+    }
+    if first == 0 {
+        // Every tree extends the empty one.
+        return proof.is_empty();
+    }
+    let mut path = Vec::with_capacity(proof.len() + 1);
+    if first.is_power_of_two() {
+        path.push(*first_root);
+    }
+    path.extend_from_slice(proof);
+    let Some((start, rest)) = path.split_first() else {
+        return false;
+    };
+    let (mut f, mut s) = (first - 1, second - 1);
+    while f & 1 == 1 {
+        f >>= 1;
+        s >>= 1;
+    }
+    let (mut fr, mut sr) = (*start, *start);
+    for c in rest {
+        if s == 0 {
+            return false;
+        }
+        if f & 1 == 1 || f == s {
+            fr = node_hash(c, &fr);
+            sr = node_hash(c, &sr);
+            while f & 1 == 0 && f != 0 {
+                f >>= 1;
+                s >>= 1;
+            }
+        } else {
+            sr = node_hash(&sr, c);
+        }
+        f >>= 1;
+        s >>= 1;
+    }
+    s == 0 && fr == *first_root && sr == *second_root
+}
+
+/// Largest power of two strictly smaller than `n`; `n` must be at least 2.
+pub fn split_point(n: usize) -> usize {
+    1 << (usize::BITS - 1 - (n - 1).leading_zeros())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Test vectors from the Certificate Transparency reference implementation.
+    const LEAVES: [&str; 8] = ["", "00", "10", "2021", "3031", "40414243", "5051525354555657", "606162636465666768696a6b6c6d6e6f"];
+
+    const ROOTS: [&str; 8] = [
+        "6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d",
+        "fac54203e7cc696cf0dfcb42c92a1d9dbaf70ad9e621f4bd8d98662f00e3c125",
+        "aeb6bcfe274b70a14fb067a5e5578264db0fa9b51af5e0ba159158f329e06e77",
+        "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+        "4e3bbb1f7b478dcfe71fb631631519a3bca12c9aefca1612bfce4c13a86264d4",
+        "76e67dadbcdf1e10e1b74ddc608abd2f98dfb16fbce75277b5232a127f2087ef",
+        "ddb89be403809e325750d3d263cd78929c2942b7942a34b77e122c9594a74c8c",
+        "5dc9da79a70659a9ad559cb701ded9a2ab9d823aad2f4960cfe370eff4604328",
+    ];
+
+    fn leaves() -> Vec<Hash> {
+        LEAVES.iter().map(|leaf| leaf_hash(&hex::decode(leaf).unwrap())).collect()
+    }
+
+    fn hashes(values: &[&str]) -> Vec<Hash> {
+        values.iter().map(|value| hex::decode(value).unwrap().try_into().unwrap()).collect()
+    }
+
+    fn root_of(size: usize) -> Hash {
+        hashes(&[ROOTS[size - 1]])[0]
+    }
+
+    #[test]
+    fn roots_match_reference() {
+        let leaves = leaves();
+        assert_eq!(hex::encode(root(&[])), "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855");
+        for size in 1..=8 {
+            assert_eq!(hex::encode(root(&leaves[..size])), ROOTS[size - 1], "tree of {} leaves", size);
+        }
+    }
+
+    #[test]
+    fn inclusion_proofs_match_reference() {
+        let leaves = leaves();
+        let cases: [(usize, usize, Vec<Hash>); 5] = [
+            (0, 1, vec![]),
+            (
+                0,
+                8,
+                hashes(&[
+                    "96a296d224f285c67bee93c30f8a309157f0daa35dc5b87e410b78630a09cfc7",
+                    "5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+                    "6b47aaf29ee3c2af9af889bc1fb9254dabd31177f16232dd6aab035ca39bf6e4",
+                ]),
+            ),
+            (
+                5,
+                8,
+                hashes(&[
+                    "bc1a0643b12e4d2d7c77918f44e0f4f79a838b6cf9ec5b5c283e1f4d88599e6b",
+                    "ca854ea128ed050b41b35ffc1b87b8eb2bde461e9e3b5596ece6b9d5975a0ae0",
+                    "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+                ]),
+            ),
+            (2, 3, hashes(&["fac54203e7cc696cf0dfcb42c92a1d9dbaf70ad9e621f4bd8d98662f00e3c125"])),
+            (
+                1,
+                5,
+                hashes(&[
+                    "6e340b9cffb37a989ca544e6bb780a2c78901d3fb33738768511a30617afa01d",
+                    "5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+                    "bc1a0643b12e4d2d7c77918f44e0f4f79a838b6cf9ec5b5c283e1f4d88599e6b",
+                ]),
+            ),
+        ];
+        for (index, size, expected) in cases {
+            let proof = inclusion_proof(&leaves[..size], index);
+            assert_eq!(proof, expected, "leaf {} of {}", index, size);
+            assert!(verify_inclusion(&leaves[index], index, size, &proof, &root_of(size)));
+        }
+    }
+
+    #[test]
+    fn consistency_proofs_match_reference() {
+        let leaves = leaves();
+        let cases: [(usize, usize, Vec<Hash>); 4] = [
+            (1, 1, vec![]),
+            (
+                1,
+                8,
+                hashes(&[
+                    "96a296d224f285c67bee93c30f8a309157f0daa35dc5b87e410b78630a09cfc7",
+                    "5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+                    "6b47aaf29ee3c2af9af889bc1fb9254dabd31177f16232dd6aab035ca39bf6e4",
+                ]),
+            ),
+            (
+                6,
+                8,
+                hashes(&[
+                    "0ebc5d3437fbe2db158b9f126a1d118e308181031d0a949f8dededebc558ef6a",
+                    "ca854ea128ed050b41b35ffc1b87b8eb2bde461e9e3b5596ece6b9d5975a0ae0",
+                    "d37ee418976dd95753c1c73862b9398fa2a2cf9b4ff0fdfe8b30cd95209614b7",
+                ]),
+            ),
+            (
+                2,
+                5,
+                hashes(&[
+                    "5f083f0a1a33ca076a95279832580db3e0ef4584bdff1f54c8a360f50de3031e",
+                    "bc1a0643b12e4d2d7c77918f44e0f4f79a838b6cf9ec5b5c283e1f4d88599e6b",
+                ]),
+            ),
+        ];
+        for (first, second, expected) in cases {
+            let proof = consistency_proof(&leaves[..second], first);
+            assert_eq!(proof, expected, "{} -> {}", first, second);
+            assert!(verify_consistency(first, second, &root_of(first), &root_of(second), &proof));
+        }
+    }
+
+    #[test]
+    fn every_proof_verifies() {
+        let leaves = leaves();
+        for size in 1..=8 {
+            let root = root_of(size);
+            for index in 0..size {
+                let proof = inclusion_proof(&leaves[..size], index);
+                assert!(verify_inclusion(&leaves[index], index, size, &proof, &root));
+                let replayed = proof.iter().zip(sibling_on_left(index, size)).fold(leaves[index], |hash, (sibling, on_left)| {
+                    if on_left {
+                        node_hash(sibling, &hash)
+                    } else {
+                        node_hash(&hash, sibling)
+                    }
+                });
+                assert_eq!(replayed, root);
+                assert_eq!(sibling_on_left(index, size).len(), proof.len());
+            }
+            for first in 1..=size {
+                let proof = consistency_proof(&leaves[..size], first);
+                assert!(verify_consistency(first, size, &root_of(first), &root, &proof));
+            }
+        }
+    }
+
+    #[test]
+    fn tampered_proofs_are_rejected() {
+        let leaves = leaves();
+        let root = root_of(8);
+        let mut proof = inclusion_proof(&leaves, 5);
+        assert!(!verify_inclusion(&leaves[4], 5, 8, &proof, &root));
+        assert!(!verify_inclusion(&leaves[5], 4, 8, &proof, &root));
+        assert!(!verify_inclusion(&leaves[5], 5, 6, &proof, &root));
+        proof[1][0] ^= 1;
+        assert!(!verify_inclusion(&leaves[5], 5, 8, &proof, &root));
+
+        let mut proof = consistency_proof(&leaves, 6);
+        assert!(!verify_consistency(6, 8, &root_of(5), &root, &proof));
+        assert!(!verify_consistency(6, 8, &root_of(6), &root_of(7), &proof));
+        assert!(!verify_consistency(8, 6, &root, &root_of(6), &proof));
+        proof.pop();
+        assert!(!verify_consistency(6, 8, &root_of(6), &root, &proof));
+    }
+}
@@ -0,0 +1,418 @@
+//! OpenTimestamps proofs. A detached `.ots` file names a SHA-256 file digest
+//! and a tree of operations (append, prepend, hash) that carry it to
+//! messages attested in Bitcoin block headers. PoPChain exports the path
+//! from an attestation's leaf to an anchored root and on through the anchor
+//! transaction to its block, so standard `ots verify` tooling can check the
+//! timestamp; `verify` below does the same against a header supplied
+//! locally.
+
+use ripemd::Ripemd160;
+use sha2::{Digest, Sha256};
+
+use crate::block::BlockHeader;
+use crate::merkle::Hash;
+
+pub const HEADER_MAGIC: &[u8] = b"\x00OpenTimestamps\x00\x00Proof\x00\xbf\x89\xe2\xe8\x84\xe8\x92\x94";
+const MAJOR_VERSION: u64 = 1;
+
+const TAG_ATTESTATION: u8 = 0x00;
+const TAG_SHA256: u8 = 0x08;
+const TAG_RIPEMD160: u8 = 0x03;
+const TAG_APPEND: u8 = 0xf0;
+const TAG_PREPEND: u8 = 0xf1;
+const TAG_REVERSE: u8 = 0xf2;
+const TAG_HEXLIFY: u8 = 0xf3;
+/// Precedes every branch of a timestamp except the last.
+const TAG_FORK: u8 = 0xff;
+
+const BITCOIN_ATTESTATION: [u8; 8] = [0x05, 0x88, 0x96, 0x0d, 0x73, 0xd7, 0x19, 0x01];
+const PENDING_ATTESTATION: [u8; 8] = [0x83, 0xdf, 0xe3, 0x0d, 0x2e, 0xf9, 0x0c, 0x8e];
+
+/// Limits from the reference implementation.
+const MAX_ARG_LENGTH: usize = 4096;
+const MAX_RESULT_LENGTH: usize = 4096;
+const MAX_PAYLOAD_LENGTH: usize = 8192;
+const MAX_URI_LENGTH: usize = 1000;
+const MAX_DEPTH: usize = 256;
+
+#[derive(Debug, PartialEq)]
+pub enum OtsError {
+    BadMagic,
+    UnsupportedVersion(u64),
+    UnsupportedOp(u8),
+    Truncated,
+    Malformed(&'static str),
+    /// No Bitcoin attestation in the proof commits to the header's merkle
+    /// root.
+    NotAttested,
+}
+
+impl std::fmt::Display for OtsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OtsError::BadMagic => write!(f, "not an OpenTimestamps proof"),
+            OtsError::UnsupportedVersion(version) => write!(f, "unsupported proof version {}", version),
+            OtsError::UnsupportedOp(tag) => write!(f, "unsupported operation 0x{:02x}", tag),
+            OtsError::Truncated => write!(f, "proof is truncated"),
+            OtsError::Malformed(reason) => write!(f, "malformed proof: {}", reason),
+            OtsError::NotAttested => write!(f, "no Bitcoin attestation in the proof matches the block header"),
+        }
+    }
+}
+
+impl std::error::Error for OtsError {}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Op {
+    Sha256,
+    Ripemd160,
+    Append(Vec<u8>),
+    Prepend(Vec<u8>),
+    Reverse,
+    Hexlify,
+}
+
+impl Op {
+    pub fn apply(&self, msg: &[u8]) -> Vec<u8> {
+        match self {
+            Op::Sha256 => Sha256::digest(msg).to_vec(),
+            Op::Ripemd160 => Ripemd160::digest(msg).to_vec(),
+            Op::Append(arg) => [msg, arg].concat(),
+            Op::Prepend(arg) => [arg, msg].concat(),
+            Op::Reverse => msg.iter().rev().copied().collect(),
+            Op::Hexlify => hex::encode(msg).into_bytes(),
+        }
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        match self {
+            Op::Sha256 => out.push(TAG_SHA256),
+            Op::Ripemd160 => out.push(TAG_RIPEMD160),
+            Op::Append(arg) => {
+                out.push(TAG_APPEND);
+                write_varbytes(out, arg);
+            }
+            Op::Prepend(arg) => {
+                out.push(TAG_PREPEND);
+                write_varbytes(out, arg);
+            }
+            Op::Reverse => out.push(TAG_REVERSE),
+            Op::Hexlify => out.push(TAG_HEXLIFY),
+        }
+    }
+
+    fn read(reader: &mut Reader, tag: u8) -> Result<Self, OtsError> {
+        match tag {
+            TAG_SHA256 => Ok(Op::Sha256),
+            TAG_RIPEMD160 => Ok(Op::Ripemd160),
+            TAG_APPEND | TAG_PREPEND => {
+                let arg = reader.varbytes(MAX_ARG_LENGTH)?;
+                if arg.is_empty() {
+                    return Err(OtsError::Malformed("empty operation argument"));
+                }
+                Ok(if tag == TAG_APPEND { Op::Append(arg) } else { Op::Prepend(arg) })
+            }
+            TAG_REVERSE => Ok(Op::Reverse),
+            TAG_HEXLIFY => Ok(Op::Hexlify),
+            tag => Err(OtsError::UnsupportedOp(tag)),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Attestation {
+    /// The message is the merkle root of the block at `height`.
+    Bitcoin { height: u64 },
+    /// Submitted to a calendar that has not yet anchored it.
+    Pending { uri: String },
+    Unknown { tag: [u8; 8], payload: Vec<u8> },
+}
+
+impl Attestation {
+    fn write(&self, out: &mut Vec<u8>) {
+        let mut payload = Vec::new();
+        let tag = match self {
+            Attestation::Bitcoin { height } => {
+                write_varuint(&mut payload, *height);
+                &BITCOIN_ATTESTATION
+            }
+            Attestation::Pending { uri } => {
+                write_varbytes(&mut payload, uri.as_bytes());
+                &PENDING_ATTESTATION
+            }
+            Attestation::Unknown { tag, payload: unknown } => {
+                payload.extend_from_slice(unknown);
+                tag
+            }
+        };
+        out.extend_from_slice(tag);
+        write_varbytes(out, &payload);
+    }
+
+    fn read(reader: &mut Reader) -> Result<Self, OtsError> {
+        let tag: [u8; 8] = reader.bytes(8)?.try_into().unwrap();
+        let payload = reader.varbytes(MAX_PAYLOAD_LENGTH)?;
+        let mut inner = Reader { bytes: &payload, pos: 0 };
+        let attestation = match tag {
+            BITCOIN_ATTESTATION => Attestation::Bitcoin { height: inner.varuint()? },
+            PENDING_ATTESTATION => {
+                let uri = inner.varbytes(MAX_URI_LENGTH)?;
+                Attestation::Pending { uri: String::from_utf8(uri).map_err(|_| OtsError::Malformed("calendar URI"))? }
+            }
+            _ => return Ok(Attestation::Unknown { tag, payload }),
+        };
+        if inner.pos != payload.len() {
+            return Err(OtsError::Malformed("trailing attestation payload"));
+        }
+        Ok(attestation)
+    }
+}
+
+/// What a message commits to: attestations of the message itself, and
+/// operations leading to further timestamps.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Timestamp {
+    pub attestations: Vec<Attestation>,
+    pub ops: Vec<(Op, Timestamp)>,
+}
+
+impl Timestamp {
+    /// A single path: `ops` applied in order, then `attestation`.
+    pub fn chain(ops: Vec<Op>, attestation: Attestation) -> Self {
+        ops.into_iter().rev().fold(Timestamp { attestations: vec![attestation], ops: Vec::new() }, |next, op| Timestamp {
+            attestations: Vec::new(),
+            ops: vec![(op, next)],
+        })
+    }
+
+    /// Every attestation in the tree with the message it attests, starting
+    /// from `msg`.
+    pub fn attested_messages(&self, msg: &[u8]) -> Vec<(Vec<u8>, &Attestation)> {
+        let mut attested: Vec<_> = self.attestations.iter().map(|attestation| (msg.to_vec(), attestation)).collect();
+        for (op, next) in &self.ops {
+            attested.extend(next.attested_messages(&op.apply(msg)));
+        }
+        attested
+    }
+
+    fn write(&self, out: &mut Vec<u8>) {
+        let branches = self.attestations.len() + self.ops.len();
+        let mut written = 0;
+        for attestation in &self.attestations {
+            written += 1;
+            if written < branches {
+                out.push(TAG_FORK);
+            }
+            out.push(TAG_ATTESTATION);
+            attestation.write(out);
+        }
+        for (op, next) in &self.ops {
+            written += 1;
+            if written < branches {
+                out.push(TAG_FORK);
+            }
+            op.write(out);
+            next.write(out);
+        }
+    }
+
+    fn read(reader: &mut Reader, msg: &[u8], depth: usize) -> Result<Self, OtsError> {
+        if depth > MAX_DEPTH {
+            return Err(OtsError::Malformed("timestamp nested too deeply"));
+        }
+        let mut timestamp = Timestamp::default();
+        loop {
+            let tag = reader.byte()?;
+            let last = tag != TAG_FORK;
+            let tag = if last { tag } else { reader.byte()? };
+            if tag == TAG_ATTESTATION {
+                timestamp.attestations.push(Attestation::read(reader)?);
+            } else {
+                let op = Op::read(reader, tag)?;
+                let result = op.apply(msg);
+                if result.len() > MAX_RESULT_LENGTH {
+                    return Err(OtsError::Malformed("operation result too long"));
+                }
+                let next = Timestamp::read(reader, &result, depth + 1)?;
+                timestamp.ops.push((op, next));
+            }
+            if last {
+                return Ok(timestamp);
+            }
+        }
+    }
+}
+
+/// A `.ots` file: a timestamp for the SHA-256 digest of some file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DetachedTimestamp {
+    pub digest: Hash,
+    pub timestamp: Timestamp,
+}
+
+impl DetachedTimestamp {
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut out = HEADER_MAGIC.to_vec();
+        write_varuint(&mut out, MAJOR_VERSION);
+        out.push(TAG_SHA256);
+        out.extend_from_slice(&self.digest);
+        self.timestamp.write(&mut out);
+        out
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<Self, OtsError> {
+        let mut reader = Reader { bytes, pos: 0 };
+        if reader.bytes(HEADER_MAGIC.len()).map_err(|_| OtsError::BadMagic)? != HEADER_MAGIC {
+            return Err(OtsError::BadMagic);
+        }
+        let version = reader.varuint()?;
+        if version != MAJOR_VERSION {
+            return Err(OtsError::UnsupportedVersion(version));
+        }
+        // Proofs of non-SHA-256 file digests are valid OpenTimestamps but
+        // never produced for ledger entries.
+        match reader.byte()? {
+            TAG_SHA256 => {}
+            tag => return Err(OtsError::UnsupportedOp(tag)),
+        }
+        let digest: Hash = reader.bytes(32)?.try_into().unwrap();
+        let timestamp = Timestamp::read(&mut reader, &digest, 0)?;
+        if reader.pos != bytes.len() {
+            return Err(OtsError::Malformed("trailing data"));
+        }
+        Ok(DetachedTimestamp { digest, timestamp })
+    }
+
+    /// Walks the proof to a Bitcoin attestation of `header`'s merkle root and
+    /// returns the height it claims. The header itself is trusted: it should
+    /// come from the verifier's own node or a header chain they have checked.
+    pub fn verify(&self, header: &BlockHeader) -> Result<u64, OtsError> {
+        self.timestamp
+            .attested_messages(&self.digest)
+            .into_iter()
+            .find_map(|(msg, attestation)| match attestation {
+                Attestation::Bitcoin { height } if msg == header.merkle_root() => Some(*height),
+                _ => None,
+            })
+            .ok_or(OtsError::NotAttested)
+    }
+}
+
+fn write_varuint(out: &mut Vec<u8>, mut value: u64) {
+    loop {
+        let byte = (value & 0x7f) as u8;
+        value >>= 7;
+        if value == 0 {
+            out.push(byte);
+            return;
+        }
+        out.push(byte | 0x80);
+    }
+}
+
+fn write_varbytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    write_varuint(out, bytes.len() as u64);
+    out.extend_from_slice(bytes);
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn byte(&mut self) -> Result<u8, OtsError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn bytes(&mut self, len: usize) -> Result<&[u8], OtsError> {
+        let end = self.pos.checked_add(len).filter(|end| *end <= self.bytes.len()).ok_or(OtsError::Truncated)?;
+        let bytes = &self.bytes[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+
+    fn varuint(&mut self) -> Result<u64, OtsError> {
+        let mut value = 0u64;
+        for shift in (0..64).step_by(7) {
+            let byte = self.byte()?;
+            value |= u64::from(byte & 0x7f) << shift;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+        Err(OtsError::Malformed("varuint overflow"))
+    }
+
+    fn varbytes(&mut self, max: usize) -> Result<Vec<u8>, OtsError> {
+        let len = self.varuint()?;
+        if len > max as u64 {
+            return Err(OtsError::Malformed("field too long"));
+        }
+        Ok(self.bytes(len as usize)?.to_vec())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn header_committing_to(merkle_root: &[u8]) -> BlockHeader {
+        let mut raw = [0u8; 80];
+        raw[36..68].copy_from_slice(merkle_root);
+        BlockHeader::from_hex(&hex::encode(raw)).unwrap()
+    }
+
+    #[test]
+    fn varuints_match_reference_encoding() {
+        for (value, encoded) in [(0u64, "00"), (1, "01"), (127, "7f"), (128, "8001"), (300, "ac02"), (16384, "808001")] {
+            let mut out = Vec::new();
+            write_varuint(&mut out, value);
+            assert_eq!(hex::encode(&out), encoded);
+            assert_eq!(Reader { bytes: &out, pos: 0 }.varuint().unwrap(), value);
+        }
+    }
+
+    #[test]
+    fn chain_round_trips_and_verifies() {
+        let digest = [7u8; 32];
+        let ops = vec![Op::Append(vec![1, 2]), Op::Sha256, Op::Prepend(vec![3]), Op::Sha256, Op::Sha256];
+        let expected = ops.iter().fold(digest.to_vec(), |msg, op| op.apply(&msg));
+        let proof = DetachedTimestamp { digest, timestamp: Timestamp::chain(ops, Attestation::Bitcoin { height: 840_000 }) };
+
+        let bytes = proof.serialize();
+        assert!(bytes.starts_with(HEADER_MAGIC));
+        let parsed = DetachedTimestamp::deserialize(&bytes).unwrap();
+        assert_eq!(parsed, proof);
+        assert_eq!(parsed.verify(&header_committing_to(&expected)), Ok(840_000));
+        assert_eq!(parsed.verify(&header_committing_to(&[0u8; 32])), Err(OtsError::NotAttested));
+    }
+
+    #[test]
+    fn forked_timestamps_round_trip() {
+        let digest = [9u8; 32];
+        let timestamp = Timestamp {
+            attestations: vec![Attestation::Pending { uri: "https://calendar.example".into() }],
+            ops: vec![
+                (Op::Append(vec![0xaa]), Timestamp::chain(vec![Op::Sha256], Attestation::Bitcoin { height: 1 })),
+                (Op::Hexlify, Timestamp::chain(vec![Op::Ripemd160], Attestation::Unknown { tag: [1; 8], payload: vec![2, 3] })),
+            ],
+        };
+        let proof = DetachedTimestamp { digest, timestamp };
+        let parsed = DetachedTimestamp::deserialize(&proof.serialize()).unwrap();
+        assert_eq!(parsed, proof);
+        assert_eq!(parsed.timestamp.attested_messages(&digest).len(), 3);
+    }
+
+    #[test]
+    fn malformed_proofs_are_rejected() {
+        let proof = DetachedTimestamp { digest: [0u8; 32], timestamp: Timestamp::chain(vec![Op::Sha256], Attestation::Bitcoin { height: 5 }) };
+        let bytes = proof.serialize();
+        assert_eq!(DetachedTimestamp::deserialize(&bytes[1..]), Err(OtsError::BadMagic));
+        assert_eq!(DetachedTimestamp::deserialize(&bytes[..bytes.len() - 1]), Err(OtsError::Truncated));
+        assert_eq!(DetachedTimestamp::deserialize(&[&bytes[..], &[0]].concat()), Err(OtsError::Malformed("trailing data")));
+        let mut unknown_op = bytes.clone();
+        unknown_op[HEADER_MAGIC.len() + 2 + 32] = 0x67;
+        assert_eq!(DetachedTimestamp::deserialize(&unknown_op), Err(OtsError::UnsupportedOp(0x67)));
+    }
+}
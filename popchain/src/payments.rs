@@ -0,0 +1,453 @@
+//! Paid ledger writes: a write is first answered with a Lightning invoice
+//! whose description hash commits to the attestation it pays for, and is
+//! committed to the ledger only once that invoice settles. Invoices and
+//! their outcomes are written through to a JSON file so a restart neither
+//! loses a paid write nor commits one twice. A write paid for but not
+//! committed is recorded as failed and refunded by hand; see the Refunds
+//! section of docs/operational-guide.md. Expired and committed writes are
+//! pruned once past their retention, so the file holds little more than the
+//! invoices still open.
+
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+use std::time::Duration;
+
+use lnd_client::{AddInvoice, InvoiceState, LndClient};
+use popchain::merkle::Hash;
+
+/// Prefix of the invoice description, so a payment for a PoPChain write
+/// cannot be mistaken for anything else.
+const DESCRIPTION_TAG: &str = "popchain-write-v1";
+
+/// The attestation a payment is for, already validated.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct PaidWrite {
+    pub human_hash_id: String,
+    pub biometric_hash: String,
+    pub identity_commitment: String,
+}
+
+impl PaidWrite {
+    /// What the invoice's description hash is the SHA-256 of; handed to the
+    /// payer so their wallet can check what it is paying for.
+    pub fn description(&self) -> String {
+        format!("{} {}", DESCRIPTION_TAG, serde_json::to_string(self).expect("plain strings serialize"))
+    }
+
+    pub fn description_hash(&self) -> Hash {
+        Sha256::digest(self.description()).into()
+    }
+}
+
+/// Why no invoice was issued for a write.
+#[derive(Debug)]
+pub enum PaymentError {
+    /// The identity already has this many open invoices for other writes.
+    TooManyOpenInvoices(usize),
+    Lnd(String),
+    Store(io::Error),
+}
+
+impl std::fmt::Display for PaymentError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PaymentError::TooManyOpenInvoices(open) => write!(f, "{} invoices are already open for this identity", open),
+            PaymentError::Lnd(e) => write!(f, "LND: {}", e),
+            PaymentError::Store(e) => write!(f, "pending writes: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for PaymentError {}
+
+/// Where a committed write landed.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Receipt {
+    pub attestation_id: String,
+    pub leaf_index: usize,
+    pub leaf_hash: String,
+    /// Root of the log right after the entry was appended, hex.
+    pub merkle_root: String,
+    pub tree_size: usize,
+    pub timestamp: u64,
+    pub expires_at: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum WriteStatus {
+    AwaitingPayment,
+    Committed { receipt: Receipt },
+    /// Unpaid when the invoice expired or was canceled.
+    Expired,
+    /// Paid but not committed, so the payment is owed back.
+    Failed { reason: String },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PendingWrite {
+    pub payment_hash: String,
+    /// BOLT11 invoice.
+    pub payment_request: String,
+    pub amount_sat: u64,
+    pub description_hash: String,
+    pub created_at: u64,
+    pub expires_at: u64,
+    pub write: PaidWrite,
+    #[serde(flatten)]
+    pub status: WriteStatus,
+}
+
+/// Invoiced writes in creation order, written through to a JSON file.
+pub struct PendingWrites {
+    path: PathBuf,
+    writes: Vec<PendingWrite>,
+}
+
+impl PendingWrites {
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let writes = match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(PendingWrites { path, writes })
+    }
+
+    pub fn get(&self, payment_hash: &str) -> Option<&PendingWrite> {
+        self.writes.iter().find(|pending| pending.payment_hash.eq_ignore_ascii_case(payment_hash))
+    }
+
+    /// An unexpired invoice already issued for `write`, so asking twice does
+    /// not produce two invoices.
+    fn awaiting(&self, write: &PaidWrite, now: u64) -> Option<&PendingWrite> {
+        self.writes.iter().find(|pending| pending.status == WriteStatus::AwaitingPayment && pending.expires_at > now && pending.write == *write)
+    }
+
+    /// Unexpired invoices open for any write of `human_hash_id`.
+    fn open_for(&self, human_hash_id: &str, now: u64) -> usize {
+        self.writes
+            .iter()
+            .filter(|pending| pending.status == WriteStatus::AwaitingPayment && pending.expires_at > now && pending.write.human_hash_id == human_hash_id)
+            .count()
+    }
+
+    /// Writes still waiting on their invoice.
+    fn unsettled(&self) -> Vec<PendingWrite> {
+        self.writes.iter().filter(|pending| pending.status == WriteStatus::AwaitingPayment).cloned().collect()
+    }
+
+    fn push(&mut self, pending: PendingWrite) -> io::Result<()> {
+        self.writes.push(pending);
+        self.save()
+    }
+
+    fn update(&mut self, updated: PendingWrite) -> io::Result<()> {
+        if let Some(pending) = self.writes.iter_mut().find(|pending| pending.payment_hash == updated.payment_hash) {
+            *pending = updated;
+        }
+        self.save()
+    }
+
+    /// Drops expired and committed writes whose invoice expired more than
+    /// `retention_secs` ago; a committed attestation is on the ledger by
+    /// then. Failed writes stay until they are refunded by hand.
+    pub fn prune(&mut self, retention_secs: u64, now: u64) -> io::Result<usize> {
+        let before = self.writes.len();
+        self.writes.retain(|pending| {
+            !matches!(pending.status, WriteStatus::Expired | WriteStatus::Committed { .. }) || pending.expires_at.saturating_add(retention_secs) > now
+        });
+        let pruned = before - self.writes.len();
+        if pruned > 0 {
+            self.save()?;
+        }
+        Ok(pruned)
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let contents = serde_json::to_string_pretty(&self.writes).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, contents)?;
+        fs::rename(&tmp, &self.path)
+    }
+}
+
+/// The invoice to pay for `write`: the one already issued if it is still
+/// open, otherwise a new one from LND, unless `human_hash_id` already has
+/// `max_open` invoices open for other writes.
+pub async fn request_payment(
+    lnd: &LndClient,
+    writes: &RwLock<PendingWrites>,
+    write: PaidWrite,
+    amount_sat: u64,
+    expiry_secs: u64,
+    max_open: usize,
+) -> Result<PendingWrite, PaymentError> {
+    let now = unix_now();
+    {
+        let writes = writes.read().unwrap();
+        if let Some(pending) = writes.awaiting(&write, now) {
+            return Ok(pending.clone());
+        }
+        let open = writes.open_for(&write.human_hash_id, now);
+        if open >= max_open {
+            return Err(PaymentError::TooManyOpenInvoices(open));
+        }
+    }
+    let description_hash = write.description_hash();
+    let invoice = lnd
+        .add_invoice(&AddInvoice { value: amount_sat, description_hash: Some(description_hash), expiry: expiry_secs, ..Default::default() })
+        .await
+        .map_err(|e| PaymentError::Lnd(e.to_string()))?;
+    let pending = PendingWrite {
+        payment_hash: hex::encode(invoice.payment_hash),
+        payment_request: invoice.payment_request,
+        amount_sat,
+        description_hash: hex::encode(description_hash),
+        created_at: now,
+        expires_at: now + expiry_secs,
+        write,
+        status: WriteStatus::AwaitingPayment,
+    };
+    // Asked again while LND was answering: the invoice that got in first
+    // stands, and the cap holds.
+    let mut writes = writes.write().unwrap();
+    if let Some(pending) = writes.awaiting(&pending.write, now) {
+        return Ok(pending.clone());
+    }
+    let open = writes.open_for(&pending.write.human_hash_id, now);
+    if open >= max_open {
+        return Err(PaymentError::TooManyOpenInvoices(open));
+    }
+    writes.push(pending.clone()).map_err(PaymentError::Store)?;
+    Ok(pending)
+}
+
+/// Checks every unsettled invoice with LND every `interval`, committing the
+/// writes whose invoices have been paid, and prunes outcomes older than
+/// `retention_secs`.
+pub async fn run<F>(lnd: Arc<LndClient>, writes: Arc<RwLock<PendingWrites>>, interval: Duration, retention_secs: u64, commit: F)
+where
+    F: Fn(&PaidWrite) -> Result<Receipt, String>,
+{
+    let mut ticker = tokio::time::interval(interval);
+    loop {
+        ticker.tick().await;
+        if let Err(e) = settle(&lnd, &writes, &commit).await {
+            eprintln!("Failed to check invoices: {}", e);
+        }
+        match writes.write().unwrap().prune(retention_secs, unix_now()) {
+            Ok(0) => {}
+            Ok(pruned) => println!("Pruned {} settled or expired writes", pruned),
+            Err(e) => eprintln!("Failed to prune pending writes: {}", e),
+        }
+    }
+}
+
+/// One pass over the unsettled invoices. `commit` must accept a write it
+/// already committed and return the same receipt, since a crash between
+/// committing and recording the outcome means it is asked again.
+pub async fn settle<F>(lnd: &LndClient, writes: &RwLock<PendingWrites>, commit: &F) -> Result<(), Box<dyn std::error::Error>>
+where
+    F: Fn(&PaidWrite) -> Result<Receipt, String>,
+{
+    let unsettled = writes.read().unwrap().unsettled();
+    for mut pending in unsettled {
+        // One invoice LND cannot answer for must not hold up the others.
+        let Some(payment_hash) = hex::decode(&pending.payment_hash).ok().and_then(|bytes| <Hash>::try_from(bytes).ok()) else {
+            eprintln!("Stored payment hash {} is not a hash", pending.payment_hash);
+            continue;
+        };
+        let invoice = match lnd.lookup_invoice(&payment_hash).await {
+            Ok(invoice) => invoice,
+            Err(e) => {
+                eprintln!("Failed to look up invoice {}: {}", pending.payment_hash, e);
+                continue;
+            }
+        };
+        pending.status = match invoice.state {
+            InvoiceState::Settled if invoice.amt_paid_sat < pending.amount_sat => {
+                WriteStatus::Failed { reason: format!("paid {} sat of {}", invoice.amt_paid_sat, pending.amount_sat) }
+            }
+            InvoiceState::Settled => match commit(&pending.write) {
+                Ok(receipt) => {
+                    println!("Invoice {} settled, committed {} as {}", pending.payment_hash, pending.write.human_hash_id, receipt.attestation_id);
+                    WriteStatus::Committed { receipt }
+                }
+                Err(reason) => {
+                    eprintln!("Invoice {} settled but {} could not be committed: {}", pending.payment_hash, pending.write.human_hash_id, reason);
+                    WriteStatus::Failed { reason }
+                }
+            },
+            InvoiceState::Canceled => WriteStatus::Expired,
+            InvoiceState::Open if unix_now() >= pending.expires_at => WriteStatus::Expired,
+            InvoiceState::Open | InvoiceState::Accepted => continue,
+        };
+        writes.write().unwrap().update(pending)?;
+    }
+    Ok(())
+}
+
+fn unix_now() -> u64 {
+    std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH).unwrap().as_secs()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use lnd_client::mock::MockLnd;
+    use std::sync::Mutex;
+
+    fn temp_store(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("popchain-{}-{}.json", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        path
+    }
+
+    fn write(human_hash_id: &str) -> PaidWrite {
+        PaidWrite {
+            human_hash_id: human_hash_id.to_string(),
+            biometric_hash: hex::encode(Sha256::digest(human_hash_id)),
+            identity_commitment: "0x01".to_string(),
+        }
+    }
+
+    fn receipt(write: &PaidWrite) -> Receipt {
+        Receipt { attestation_id: format!("att_{}", write.human_hash_id), leaf_index: 0, leaf_hash: "00".repeat(32), merkle_root: "00".repeat(32), tree_size: 1, timestamp: 1, expires_at: 2 }
+    }
+
+    fn payment_hash(pending: &PendingWrite) -> Hash {
+        hex::decode(&pending.payment_hash).unwrap().try_into().unwrap()
+    }
+
+    #[tokio::test]
+    async fn writes_commit_once_their_invoice_settles() {
+        let mock = MockLnd::start().await;
+        let lnd = mock.client();
+        let path = temp_store("settles");
+        let writes = RwLock::new(PendingWrites::open(&path).unwrap());
+        let commits = Mutex::new(Vec::new());
+        let commit = |write: &PaidWrite| {
+            commits.lock().unwrap().push(write.clone());
+            Ok(receipt(write))
+        };
+
+        let pending = request_payment(&lnd, &writes, write("alice"), 1000, 600, 3).await.unwrap();
+        let again = request_payment(&lnd, &writes, write("alice"), 1000, 600, 3).await.unwrap();
+        assert_eq!(pending.payment_hash, again.payment_hash);
+        assert_eq!(mock.invoice_count(), 1);
+        let description_hash: Hash = Sha256::digest(write("alice").description()).into();
+        assert_eq!(mock.invoice(&payment_hash(&pending)).unwrap().description_hash, Some(description_hash));
+
+        settle(&lnd, &writes, &commit).await.unwrap();
+        assert!(commits.lock().unwrap().is_empty());
+        assert_eq!(writes.read().unwrap().get(&pending.payment_hash).unwrap().status, WriteStatus::AwaitingPayment);
+
+        mock.settle(&payment_hash(&pending), 1000);
+        settle(&lnd, &writes, &commit).await.unwrap();
+        settle(&lnd, &writes, &commit).await.unwrap();
+        assert_eq!(*commits.lock().unwrap(), vec![write("alice")]);
+        assert_eq!(writes.read().unwrap().get(&pending.payment_hash).unwrap().status, WriteStatus::Committed { receipt: receipt(&write("alice")) });
+
+        let reopened = PendingWrites::open(&path).unwrap();
+        assert_eq!(reopened.get(&pending.payment_hash).unwrap().status, WriteStatus::Committed { receipt: receipt(&write("alice")) });
+        let next = request_payment(&lnd, &writes, write("alice"), 1000, 600, 3).await.unwrap();
+        assert_ne!(next.payment_hash, pending.payment_hash);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn unpaid_and_underpaid_invoices_do_not_commit() {
+        let mock = MockLnd::start().await;
+        let lnd = mock.client();
+        let path = temp_store("unpaid");
+        let writes = RwLock::new(PendingWrites::open(&path).unwrap());
+        let commit = |_: &PaidWrite| -> Result<Receipt, String> { panic!("nothing here is paid in full") };
+
+        let underpaid = request_payment(&lnd, &writes, write("bob"), 1000, 600, 3).await.unwrap();
+        let canceled = request_payment(&lnd, &writes, write("carol"), 1000, 600, 3).await.unwrap();
+        let expired = request_payment(&lnd, &writes, write("dave"), 1000, 0, 3).await.unwrap();
+        mock.settle(&payment_hash(&underpaid), 999);
+        mock.cancel(&payment_hash(&canceled));
+        settle(&lnd, &writes, &commit).await.unwrap();
+
+        let writes = writes.read().unwrap();
+        assert_eq!(writes.get(&underpaid.payment_hash).unwrap().status, WriteStatus::Failed { reason: "paid 999 sat of 1000".to_string() });
+        assert_eq!(writes.get(&canceled.payment_hash).unwrap().status, WriteStatus::Expired);
+        assert_eq!(writes.get(&expired.payment_hash).unwrap().status, WriteStatus::Expired);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn paid_writes_that_cannot_commit_are_marked_failed() {
+        let mock = MockLnd::start().await;
+        let lnd = mock.client();
+        let path = temp_store("failed");
+        let writes = RwLock::new(PendingWrites::open(&path).unwrap());
+        let pending = request_payment(&lnd, &writes, write("erin"), 1000, 600, 3).await.unwrap();
+        mock.settle(&payment_hash(&pending), 1000);
+        settle(&lnd, &writes, &|_: &PaidWrite| Err("identity commitment already enrolled".to_string())).await.unwrap();
+        assert_eq!(
+            writes.read().unwrap().get(&pending.payment_hash).unwrap().status,
+            WriteStatus::Failed { reason: "identity commitment already enrolled".to_string() }
+        );
+        fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn open_invoices_per_identity_are_capped() {
+        let mock = MockLnd::start().await;
+        let lnd = mock.client();
+        let path = temp_store("capped");
+        let writes = RwLock::new(PendingWrites::open(&path).unwrap());
+        let variant = |n: u8| PaidWrite { identity_commitment: format!("0x0{}", n), ..write("frank") };
+
+        for n in 1..=2 {
+            request_payment(&lnd, &writes, variant(n), 1000, 600, 2).await.unwrap();
+        }
+        // The same write gets its open invoice back; a third distinct one is refused.
+        request_payment(&lnd, &writes, variant(1), 1000, 600, 2).await.unwrap();
+        assert!(matches!(request_payment(&lnd, &writes, variant(3), 1000, 600, 2).await, Err(PaymentError::TooManyOpenInvoices(2))));
+        assert_eq!(mock.invoice_count(), 2);
+        // Other identities are not held to frank's invoices.
+        request_payment(&lnd, &writes, write("grace"), 1000, 600, 2).await.unwrap();
+
+        // Once an invoice is no longer open, another may be issued.
+        let first = writes.read().unwrap().awaiting(&variant(1), unix_now()).cloned().unwrap();
+        mock.cancel(&payment_hash(&first));
+        settle(&lnd, &writes, &|write: &PaidWrite| Ok(receipt(write))).await.unwrap();
+        request_payment(&lnd, &writes, variant(3), 1000, 600, 2).await.unwrap();
+        fs::remove_file(path).unwrap();
+    }
+
+    #[tokio::test]
+    async fn settled_and_expired_writes_are_pruned_after_retention() {
+        let mock = MockLnd::start().await;
+        let lnd = mock.client();
+        let path = temp_store("pruned");
+        let writes = RwLock::new(PendingWrites::open(&path).unwrap());
+        let committed = request_payment(&lnd, &writes, write("heidi"), 1000, 600, 3).await.unwrap();
+        let expired = request_payment(&lnd, &writes, write("ivan"), 1000, 600, 3).await.unwrap();
+        let failed = request_payment(&lnd, &writes, write("judy"), 1000, 600, 3).await.unwrap();
+        let open = request_payment(&lnd, &writes, write("mallory"), 1000, 600, 3).await.unwrap();
+        mock.settle(&payment_hash(&committed), 1000);
+        mock.cancel(&payment_hash(&expired));
+        mock.settle(&payment_hash(&failed), 1);
+        settle(&lnd, &writes, &|write: &PaidWrite| Ok(receipt(write))).await.unwrap();
+
+        let expires_at = committed.expires_at;
+        assert_eq!(writes.write().unwrap().prune(3600, expires_at + 3599).unwrap(), 0);
+        assert_eq!(writes.write().unwrap().prune(3600, expires_at + 3600).unwrap(), 2);
+        let reopened = PendingWrites::open(&path).unwrap();
+        assert!(reopened.get(&committed.payment_hash).is_none());
+        assert!(reopened.get(&expired.payment_hash).is_none());
+        assert!(matches!(reopened.get(&failed.payment_hash).unwrap().status, WriteStatus::Failed { .. }));
+        assert_eq!(reopened.get(&open.payment_hash).unwrap().status, WriteStatus::AwaitingPayment);
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,47 @@
+//! Reports the events PoPChain records on its own authority, such as
+//! revocations, renewals and expiries, to the system service's event store,
+//! which passes them on to the webhooks of relying parties.
+
+use sequence_code::Action;
+use serde::Serialize;
+use std::sync::Arc;
+
+/// Environment variable holding the bearer token the event store accepts.
+pub const INGEST_TOKEN_ENV: &str = "EVENT_INGEST_TOKEN";
+
+#[derive(Serialize)]
+struct ReportedEvent<'a> {
+    sequence_code: &'a str,
+    action: &'a str,
+    service: &'a str,
+    human_hash_id: &'a str,
+}
+
+#[derive(Clone)]
+pub struct EventReporter {
+    http: reqwest::Client,
+    url: Arc<str>,
+    token: Arc<str>,
+}
+
+impl EventReporter {
+    pub fn new(url: &str, token: &str) -> Self {
+        EventReporter { http: reqwest::Client::new(), url: url.into(), token: token.into() }
+    }
+
+    /// Reports the event in the background, so ledger locks are never held
+    /// across the request. A code the store already holds, e.g. a
+    /// revocation the system service issued itself, is not an error.
+    pub fn report(&self, sequence_code: &str, action: Action, human_hash_id: &str) {
+        let reporter = self.clone();
+        let (sequence_code, human_hash_id) = (sequence_code.to_string(), human_hash_id.to_string());
+        tokio::spawn(async move {
+            let event = ReportedEvent { sequence_code: &sequence_code, action: action.as_str(), service: "popchain", human_hash_id: &human_hash_id };
+            match reporter.http.post(&*reporter.url).bearer_auth(&reporter.token).json(&event).send().await {
+                Ok(response) if response.status().is_success() || response.status() == reqwest::StatusCode::CONFLICT => {}
+                Ok(response) => eprintln!("Event store rejected {}: {}", sequence_code, response.status()),
+                Err(e) => eprintln!("Failed to report {} to the event store: {}", sequence_code, e),
+            }
+        });
+    }
+}
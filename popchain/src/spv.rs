@@ -0,0 +1,522 @@
+//! Light-client (SPV) verification of anchors. A verifier keeps its own
+//! chain of Bitcoin block headers from a checkpoint it trusts, checking
+//! every header's proof-of-work and that its difficulty follows the
+//! consensus retargeting rules, so neither PoPChain nor a node has to be
+//! believed that a block exists. An `AnchorProof` then carries an
+//! attestation's leaf up the ledger tree to its root, into the anchor
+//! transaction's `OP_RETURN` output and up the block's merkle tree to a
+//! header in that chain.
+
+use num_bigint::BigUint;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+use crate::block::{self, BlockHeader, Transaction};
+use crate::merkle::{self, Hash};
+
+/// Marks PoPChain anchors among other `OP_RETURN` outputs.
+const ANCHOR_MAGIC: &[u8; 4] = b"PoPC";
+
+const RETARGET_INTERVAL: u64 = 2016;
+const TARGET_SPACING: u32 = 10 * 60;
+const TARGET_TIMESPAN: u32 = 14 * 24 * 60 * 60;
+/// Headers whose median time a new header must exceed.
+const MEDIAN_TIME_SPAN: usize = 11;
+
+#[derive(Debug, PartialEq)]
+pub enum SpvError {
+    UnknownNetwork(String),
+    /// Retargeting networks start chains at a difficulty period boundary so
+    /// every retarget can be recomputed.
+    CheckpointNotAtRetarget(u64),
+    Disconnected { height: u64 },
+    InvalidTarget { height: u64 },
+    InsufficientWork { height: u64 },
+    UnexpectedDifficulty { height: u64, expected: u32, found: u32 },
+    TimeTooEarly { height: u64 },
+    NotInChain(u64),
+    Malformed(&'static str),
+    /// The leaf is not in the tree whose root was anchored.
+    NotInLedger,
+    /// The transaction has no `OP_RETURN` output committing to the root.
+    NotAnchored,
+    /// The transaction is not in the block the proof names.
+    NotInBlock,
+}
+
+impl std::fmt::Display for SpvError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SpvError::UnknownNetwork(network) => write!(f, "unknown network {}", network),
+            SpvError::CheckpointNotAtRetarget(height) => {
+                write!(f, "checkpoint height {} does not start a difficulty period", height)
+            }
+            SpvError::Disconnected { height } => write!(f, "header {} does not extend the chain", height),
+            SpvError::InvalidTarget { height } => write!(f, "header {} has an invalid target", height),
+            SpvError::InsufficientWork { height } => write!(f, "header {} does not meet its target", height),
+            SpvError::UnexpectedDifficulty { height, expected, found } => {
+                write!(f, "header {} has bits {:08x}, expected {:08x}", height, found, expected)
+            }
+            SpvError::TimeTooEarly { height } => write!(f, "header {} is timestamped too early", height),
+            SpvError::NotInChain(height) => write!(f, "no header at height {} in the chain", height),
+            SpvError::Malformed(what) => write!(f, "malformed {}", what),
+            SpvError::NotInLedger => write!(f, "leaf is not in the anchored tree"),
+            SpvError::NotAnchored => write!(f, "transaction does not anchor the root"),
+            SpvError::NotInBlock => write!(f, "transaction is not in the block"),
+        }
+    }
+}
+
+impl std::error::Error for SpvError {}
+
+/// Consensus difficulty rules of a network.
+#[derive(Clone, Copy, Debug)]
+pub struct ChainParams {
+    /// Easiest allowed target, compact form.
+    pow_limit: u32,
+    retargeting: bool,
+    /// A block timestamped more than two spacings after its parent may use
+    /// the easiest target (testnets and regtest).
+    min_difficulty_blocks: bool,
+    /// BIP94 (testnet4): retarget from the period's first block, and no
+    /// period may start more than ten minutes before the previous block.
+    bip94: bool,
+}
+
+impl ChainParams {
+    /// Rules for the network names `AnchorConfig` uses.
+    pub fn for_network(network: &str) -> Result<Self, SpvError> {
+        let (pow_limit, retargeting, min_difficulty_blocks, bip94) = match network {
+            "mainnet" => (0x1d00ffff, true, false, false),
+            "testnet" => (0x1d00ffff, true, true, false),
+            "testnet4" => (0x1d00ffff, true, true, true),
+            "signet" => (0x1e0377ae, true, false, false),
+            "regtest" => (0x207fffff, false, true, false),
+            _ => return Err(SpvError::UnknownNetwork(network.to_string())),
+        };
+        Ok(ChainParams { pow_limit, retargeting, min_difficulty_blocks, bip94 })
+    }
+}
+
+/// A header chain as stored by verifiers.
+#[derive(Serialize, Deserialize)]
+pub struct StoredHeaders {
+    pub network: String,
+    pub start_height: u64,
+    /// Hex headers from the checkpoint up.
+    pub headers: Vec<String>,
+}
+
+/// Headers from a trusted checkpoint to the best tip seen, each checked
+/// against its parent.
+pub struct HeaderChain {
+    network: String,
+    params: ChainParams,
+    start_height: u64,
+    headers: Vec<BlockHeader>,
+    heights: HashMap<Hash, u64>,
+    /// Cumulative work of `headers[..=i]`.
+    work: Vec<BigUint>,
+}
+
+impl HeaderChain {
+    /// Starts a chain at `checkpoint`, which the verifier trusts to be the
+    /// block at `start_height`.
+    pub fn new(network: &str, start_height: u64, checkpoint: BlockHeader) -> Result<Self, SpvError> {
+        let params = ChainParams::for_network(network)?;
+        if params.retargeting && start_height % RETARGET_INTERVAL != 0 {
+            return Err(SpvError::CheckpointNotAtRetarget(start_height));
+        }
+        let work = check_pow(&params, &checkpoint, start_height)?;
+        Ok(HeaderChain {
+            network: network.to_string(),
+            params,
+            start_height,
+            heights: HashMap::from([(checkpoint.hash(), start_height)]),
+            headers: vec![checkpoint],
+            work: vec![work],
+        })
+    }
+
+    /// Rebuilds a stored chain, checking every header again.
+    pub fn from_stored(stored: &StoredHeaders) -> Result<Self, SpvError> {
+        let mut headers = stored.headers.iter().map(|header| BlockHeader::from_hex(header).map_err(|_| SpvError::Malformed("header")));
+        let checkpoint = headers.next().ok_or(SpvError::Malformed("header chain"))??;
+        let mut chain = HeaderChain::new(&stored.network, stored.start_height, checkpoint)?;
+        for header in headers {
+            chain.push(header?)?;
+        }
+        Ok(chain)
+    }
+
+    pub fn to_stored(&self) -> StoredHeaders {
+        StoredHeaders {
+            network: self.network.clone(),
+            start_height: self.start_height,
+            headers: self.headers.iter().map(BlockHeader::to_hex).collect(),
+        }
+    }
+
+    pub fn network(&self) -> &str {
+        &self.network
+    }
+
+    pub fn start_height(&self) -> u64 {
+        self.start_height
+    }
+
+    pub fn tip_height(&self) -> u64 {
+        self.start_height + self.headers.len() as u64 - 1
+    }
+
+    pub fn tip(&self) -> &BlockHeader {
+        self.headers.last().unwrap()
+    }
+
+    pub fn header_at(&self, height: u64) -> Option<&BlockHeader> {
+        self.headers.get(usize::try_from(height.checked_sub(self.start_height)?).ok()?)
+    }
+
+    pub fn height_of(&self, hash: &Hash) -> Option<u64> {
+        self.heights.get(hash).copied()
+    }
+
+    /// Work in the chain from the block at `height` to the tip, inclusive.
+    pub fn work_from(&self, height: u64) -> BigUint {
+        let index = height.saturating_sub(self.start_height) as usize;
+        let before = index.checked_sub(1).map_or_else(BigUint::default, |i| self.work[i].clone());
+        self.work.last().unwrap() - before
+    }
+
+    /// Drops headers above `height` (never the checkpoint), to follow a
+    /// reorganization.
+    pub fn truncate(&mut self, height: u64) {
+        let keep = (height.max(self.start_height) - self.start_height + 1) as usize;
+        for header in self.headers.drain(keep.min(self.headers.len())..) {
+            self.heights.remove(&header.hash());
+        }
+        self.work.truncate(keep);
+    }
+
+    /// Extends the chain by `header` after checking it links to the tip,
+    /// meets its target, is not timestamped before the median of its
+    /// predecessors and carries the difficulty consensus requires.
+    pub fn push(&mut self, header: BlockHeader) -> Result<(), SpvError> {
+        let height = self.tip_height() + 1;
+        let prev = self.tip();
+        if header.prev_hash() != prev.hash() {
+            return Err(SpvError::Disconnected { height });
+        }
+        if header.time() <= self.median_time_past() {
+            return Err(SpvError::TimeTooEarly { height });
+        }
+        if self.params.bip94 && height % RETARGET_INTERVAL == 0 && header.time() < prev.time().saturating_sub(TARGET_SPACING) {
+            return Err(SpvError::TimeTooEarly { height });
+        }
+        let expected = self.expected_bits(height, &header)?;
+        if header.bits() != expected {
+            return Err(SpvError::UnexpectedDifficulty { height, expected, found: header.bits() });
+        }
+        let work = check_pow(&self.params, &header, height)? + self.work.last().unwrap();
+        self.heights.insert(header.hash(), height);
+        self.headers.push(header);
+        self.work.push(work);
+        Ok(())
+    }
+
+    /// Checks that `proof` carries its leaf to a root anchored in a block of
+    /// this chain.
+    pub fn verify_anchor(&self, proof: &AnchorProof) -> Result<AnchorVerification, SpvError> {
+        let leaf_hash = decode_hash(&proof.leaf_hash)?;
+        let root = decode_hash(&proof.root_hash)?;
+        let audit_path = proof.audit_path.iter().map(|hash| decode_hash(hash)).collect::<Result<Vec<_>, _>>()?;
+        if !merkle::verify_inclusion(&leaf_hash, proof.leaf_index, proof.tree_size, &audit_path, &root) {
+            return Err(SpvError::NotInLedger);
+        }
+
+        let raw_tx = hex::decode(&proof.raw_tx).map_err(|_| SpvError::Malformed("transaction"))?;
+        let tx = Transaction::parse(&raw_tx).map_err(|_| SpvError::Malformed("transaction"))?;
+        // A 64-byte transaction could pass for an inner node of the block's
+        // merkle tree.
+        if tx.legacy.len() == 64 {
+            return Err(SpvError::Malformed("transaction"));
+        }
+        let payload = anchor_payload(proof.tree_size, &root);
+        let script = [&[0x6a, payload.len() as u8], &payload[..]].concat();
+        if !tx.output_scripts.contains(&script) {
+            return Err(SpvError::NotAnchored);
+        }
+
+        let header = self.header_at(proof.block_height).ok_or(SpvError::NotInChain(proof.block_height))?;
+        let branch = proof.merkle_branch.iter().map(|hash| decode_hash(hash)).collect::<Result<Vec<_>, _>>()?;
+        if proof.tx_index >> branch.len() != 0 || block::merkle_root_from_branch(&tx.txid(), proof.tx_index, &branch) != header.merkle_root() {
+            return Err(SpvError::NotInBlock);
+        }
+        Ok(AnchorVerification {
+            block_height: proof.block_height,
+            block_hash: header.hash_hex(),
+            block_time: header.time(),
+            confirmations: self.tip_height() - proof.block_height + 1,
+            work: self.work_from(proof.block_height),
+        })
+    }
+
+    fn median_time_past(&self) -> u32 {
+        let mut times: Vec<u32> = self.headers.iter().rev().take(MEDIAN_TIME_SPAN).map(BlockHeader::time).collect();
+        times.sort_unstable();
+        times[times.len() / 2]
+    }
+
+    /// The compact target the header at `height` must carry (Bitcoin Core's
+    /// `GetNextWorkRequired`).
+    fn expected_bits(&self, height: u64, header: &BlockHeader) -> Result<u32, SpvError> {
+        let prev = self.tip();
+        if height % RETARGET_INTERVAL != 0 {
+            if !self.params.min_difficulty_blocks {
+                return Ok(prev.bits());
+            }
+            if header.time() > prev.time().saturating_add(TARGET_SPACING * 2) {
+                return Ok(self.params.pow_limit);
+            }
+            // Otherwise the last target that was not a minimum-difficulty
+            // exception.
+            let mut at = height - 1;
+            while at > self.start_height && at % RETARGET_INTERVAL != 0 && self.header_at(at).unwrap().bits() == self.params.pow_limit {
+                at -= 1;
+            }
+            return Ok(self.header_at(at).unwrap().bits());
+        }
+        if !self.params.retargeting {
+            return Ok(prev.bits());
+        }
+        let first = self.header_at(height - RETARGET_INTERVAL).ok_or(SpvError::NotInChain(height - RETARGET_INTERVAL))?;
+        let timespan = prev.time().saturating_sub(first.time()).clamp(TARGET_TIMESPAN / 4, TARGET_TIMESPAN * 4);
+        let base = if self.params.bip94 { first.bits() } else { prev.bits() };
+        let target = target_from_bits(base).ok_or(SpvError::InvalidTarget { height })? * timespan / TARGET_TIMESPAN;
+        let limit = target_from_bits(self.params.pow_limit).unwrap();
+        Ok(bits_from_target(&target.min(limit)))
+    }
+}
+
+/// What the verifier's own chain says about an anchor.
+#[derive(Debug)]
+pub struct AnchorVerification {
+    pub block_height: u64,
+    pub block_hash: String,
+    pub block_time: u32,
+    /// Blocks from the anchor's to the chain tip, inclusive.
+    pub confirmations: u64,
+    /// Work from the anchor's block to the tip: what rewriting the chain to
+    /// drop the anchor would cost.
+    pub work: BigUint,
+}
+
+/// Everything needed to go from an attestation's ledger leaf to a block
+/// header: the audit path to the anchored root, the anchor transaction and
+/// the merkle branch placing it in its block.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct AnchorProof {
+    pub leaf_hash: String,
+    pub leaf_index: usize,
+    pub tree_size: usize,
+    pub audit_path: Vec<String>,
+    pub root_hash: String,
+    /// The anchor transaction, hex.
+    pub raw_tx: String,
+    pub tx_index: usize,
+    /// Internal byte order, from the txid up.
+    pub merkle_branch: Vec<String>,
+    pub block_height: u64,
+}
+
+/// `OP_RETURN` payload: magic, big-endian tree size, root hash (44 bytes).
+pub fn anchor_payload(tree_size: usize, root: &Hash) -> Vec<u8> {
+    let mut payload = Vec::with_capacity(44);
+    payload.extend_from_slice(ANCHOR_MAGIC);
+    payload.extend_from_slice(&(tree_size as u64).to_be_bytes());
+    payload.extend_from_slice(root);
+    payload
+}
+
+/// Checks the header's hash meets the target its bits encode and returns
+/// the header's work.
+fn check_pow(params: &ChainParams, header: &BlockHeader, height: u64) -> Result<BigUint, SpvError> {
+    let target = target_from_bits(header.bits())
+        .filter(|target| *target <= target_from_bits(params.pow_limit).unwrap())
+        .ok_or(SpvError::InvalidTarget { height })?;
+    if BigUint::from_bytes_le(&header.hash()) > target {
+        return Err(SpvError::InsufficientWork { height });
+    }
+    // Expected hashes to find a header meeting the target: 2^256 / (target + 1).
+    Ok((BigUint::from(1u8) << 256) / (target + 1u8))
+}
+
+/// Decodes compact bits; negative, zero and overflowing targets are invalid.
+fn target_from_bits(bits: u32) -> Option<BigUint> {
+    let exponent = bits >> 24;
+    let mantissa = bits & 0x007f_ffff;
+    if mantissa == 0 || bits & 0x0080_0000 != 0 {
+        return None;
+    }
+    if exponent > 34 || (mantissa > 0xff && exponent > 33) || (mantissa > 0xffff && exponent > 32) {
+        return None;
+    }
+    let target = if exponent <= 3 {
+        BigUint::from(mantissa >> (8 * (3 - exponent)))
+    } else {
+        BigUint::from(mantissa) << (8 * (exponent - 3))
+    };
+    Some(target).filter(|target| *target != BigUint::default())
+}
+
+/// Encodes a target in compact form, truncating as Bitcoin Core does.
+fn bits_from_target(target: &BigUint) -> u32 {
+    let mut size = target.bits().div_ceil(8) as u32;
+    let shifted = if size <= 3 { target << (8 * (3 - size)) } else { target >> (8 * (size - 3)) };
+    let mut compact = shifted.iter_u32_digits().next().unwrap_or(0);
+    if compact & 0x0080_0000 != 0 {
+        compact >>= 8;
+        size += 1;
+    }
+    compact | size << 24
+}
+
+fn decode_hash(value: &str) -> Result<Hash, SpvError> {
+    hex::decode(value).ok().and_then(|bytes| bytes.try_into().ok()).ok_or(SpvError::Malformed("hash"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Mainnet blocks 0 to 2.
+    const MAINNET: [&str; 3] = [
+        "0100000000000000000000000000000000000000000000000000000000000000000000003ba3edfd7a7b12b27ac72c3e67768f617fc81bc3888a51323a9fb8aa4b1e5e4a29ab5f49ffff001d1dac2b7c",
+        "010000006fe28c0ab6f1b372c1a6a246ae63f74f931e8365e15a089c68d6190000000000982051fd1e4ba744bbbe680e1fee14677ba1a3c3540bf7b1cdb606e857233e0e61bc6649ffff001d01e36299",
+        "010000004860eb18bf1b1620e37e9490fc8a427514416fd75159ab86688e9a8300000000d5fdcc541e25de1c7a5addedf24858b8bb665c9f36ef744ee42c316022c90f9bb0bc6649ffff001d08d2bd61",
+    ];
+
+    fn header(hex: &str) -> BlockHeader {
+        BlockHeader::from_hex(hex).unwrap()
+    }
+
+    /// Mines a header on `prev` meeting `bits`.
+    fn mine(prev: &BlockHeader, merkle_root: &Hash, time: u32, bits: u32) -> BlockHeader {
+        let target = target_from_bits(bits).unwrap();
+        (0u32..)
+            .map(|nonce| {
+                let raw = [&1u32.to_le_bytes()[..], &prev.hash(), merkle_root, &time.to_le_bytes(), &bits.to_le_bytes(), &nonce.to_le_bytes()].concat();
+                header(&hex::encode(raw))
+            })
+            .find(|header| BigUint::from_bytes_le(&header.hash()) <= target)
+            .unwrap()
+    }
+
+    fn test_chain(retargeting: bool) -> HeaderChain {
+        let params = ChainParams { pow_limit: 0x207fffff, retargeting, min_difficulty_blocks: false, bip94: false };
+        let genesis = mine(&header(MAINNET[0]), &[0; 32], 1_700_000_000, params.pow_limit);
+        HeaderChain {
+            network: "test".into(),
+            params,
+            start_height: 0,
+            heights: HashMap::from([(genesis.hash(), 0)]),
+            work: vec![check_pow(&params, &genesis, 0).unwrap()],
+            headers: vec![genesis],
+        }
+    }
+
+    #[test]
+    fn compact_targets_round_trip() {
+        for bits in [0x1d00ffff, 0x1b0404cb, 0x207fffff, 0x1e0377ae, 0x03123456] {
+            assert_eq!(bits_from_target(&target_from_bits(bits).unwrap()), bits);
+        }
+        assert_eq!(target_from_bits(0x1d00ffff).unwrap(), BigUint::from(0xffffu32) << 208);
+        assert_eq!(target_from_bits(0x04923456), None);
+        assert_eq!(target_from_bits(0x01003456), None);
+        assert_eq!(target_from_bits(0xff123456), None);
+    }
+
+    #[test]
+    fn mainnet_headers_link_and_meet_their_targets() {
+        let mut chain = HeaderChain::new("mainnet", 0, header(MAINNET[0])).unwrap();
+        assert_eq!(chain.tip().hash_hex(), "000000000019d6689c085ae165831e934ff763ae46a2a6c172b3f1b60a8ce26f");
+        chain.push(header(MAINNET[1])).unwrap();
+        chain.push(header(MAINNET[2])).unwrap();
+        assert_eq!(chain.tip_height(), 2);
+        assert_eq!(chain.height_of(&header(MAINNET[1]).hash()), Some(1));
+        // Each block at difficulty 1 is worth 0x100010001 hashes.
+        assert_eq!(chain.work_from(1), BigUint::from(0x1_0001_0001u64) * 2u8);
+
+        let mut tampered = hex::decode(MAINNET[2]).unwrap();
+        tampered[79] ^= 1;
+        chain.truncate(1);
+        assert_eq!(chain.push(header(&hex::encode(tampered))), Err(SpvError::InsufficientWork { height: 2 }));
+        assert_eq!(chain.push(header(MAINNET[1])), Err(SpvError::Disconnected { height: 2 }));
+        assert_eq!(HeaderChain::new("mainnet", 1, header(MAINNET[1])).err(), Some(SpvError::CheckpointNotAtRetarget(1)));
+    }
+
+    #[test]
+    fn difficulty_retargets_on_period_boundaries() {
+        let mut chain = test_chain(true);
+        let limit = chain.params.pow_limit;
+        let mut time = chain.tip().time();
+        for _ in 1..RETARGET_INTERVAL {
+            time += TARGET_SPACING / 2;
+            chain.push(mine(chain.tip(), &[0; 32], time, limit)).unwrap();
+        }
+        // Blocks came twice as fast as intended, so the target halves (as
+        // far as the compact encoding allows).
+        let timespan = TARGET_SPACING / 2 * (RETARGET_INTERVAL as u32 - 1);
+        let expected = bits_from_target(&(target_from_bits(limit).unwrap() * timespan / TARGET_TIMESPAN));
+        assert_ne!(expected, limit);
+        time += TARGET_SPACING / 2;
+        let stale = mine(chain.tip(), &[0; 32], time, limit);
+        assert_eq!(chain.push(stale), Err(SpvError::UnexpectedDifficulty { height: RETARGET_INTERVAL, expected, found: limit }));
+        chain.push(mine(chain.tip(), &[0; 32], time, expected)).unwrap();
+
+        let early = mine(chain.tip(), &[0; 32], chain.median_time_past(), expected);
+        assert_eq!(chain.push(early), Err(SpvError::TimeTooEarly { height: RETARGET_INTERVAL + 1 }));
+    }
+
+    #[test]
+    fn anchor_proofs_verify_against_the_header_chain() {
+        let leaves: Vec<Hash> = (0u8..5).map(|i| merkle::leaf_hash(&[i])).collect();
+        let root = merkle::root(&leaves);
+        let payload = anchor_payload(leaves.len(), &root);
+        let script = [&[0x6a, payload.len() as u8], &payload[..]].concat();
+        let raw_tx = [&[2, 0, 0, 0, 1][..], &[7; 36], &[0, 0xff, 0xff, 0xff, 0xff, 1], &[0; 8], &[script.len() as u8], &script, &[0; 4]].concat();
+        let txid = Transaction::parse(&raw_tx).unwrap().txid();
+        let coinbase = block::sha256d(b"coinbase");
+        let mut chain = test_chain(false);
+        let merkle_root = block::sha256d(&[coinbase, txid].concat());
+        let time = chain.tip().time() + 600;
+        chain.push(mine(chain.tip(), &merkle_root, time, 0x207fffff)).unwrap();
+        chain.push(mine(chain.tip(), &[0; 32], time + 600, 0x207fffff)).unwrap();
+
+        let proof = AnchorProof {
+            leaf_hash: hex::encode(leaves[3]),
+            leaf_index: 3,
+            tree_size: leaves.len(),
+            audit_path: merkle::inclusion_proof(&leaves, 3).iter().map(hex::encode).collect(),
+            root_hash: hex::encode(root),
+            raw_tx: hex::encode(&raw_tx),
+            tx_index: 1,
+            merkle_branch: vec![hex::encode(coinbase)],
+            block_height: 1,
+        };
+        let verified = chain.verify_anchor(&proof).unwrap();
+        assert_eq!(verified.confirmations, 2);
+        assert_eq!(verified.block_hash, chain.header_at(1).unwrap().hash_hex());
+
+        let wrong_leaf = AnchorProof { leaf_index: 2, ..proof.clone() };
+        assert_eq!(chain.verify_anchor(&wrong_leaf).unwrap_err(), SpvError::NotInLedger);
+        let wrong_root = AnchorProof { tree_size: 4, root_hash: hex::encode(merkle::root(&leaves[..4])), audit_path: merkle::inclusion_proof(&leaves[..4], 3).iter().map(hex::encode).collect(), ..proof.clone() };
+        assert_eq!(chain.verify_anchor(&wrong_root).unwrap_err(), SpvError::NotAnchored);
+        let wrong_block = AnchorProof { block_height: 2, ..proof.clone() };
+        assert_eq!(chain.verify_anchor(&wrong_block).unwrap_err(), SpvError::NotInBlock);
+        let wrong_index = AnchorProof { tx_index: 0, ..proof.clone() };
+        assert_eq!(chain.verify_anchor(&wrong_index).unwrap_err(), SpvError::NotInBlock);
+        let beyond_chain = AnchorProof { block_height: 3, ..proof };
+        assert_eq!(chain.verify_anchor(&beyond_chain).unwrap_err(), SpvError::NotInChain(3));
+    }
+}
@@ -0,0 +1,155 @@
+//! PoPChain's revocation status lists as verifiers check them: the W3C
+//! Bitstring Status List credential shared with the system service (see the
+//! `status-list` crate), secured as a VC-JWT signed with ES256K by the log
+//! key. The issuer is the log key's `did:key`, so the signature can be
+//! checked without resolving anything online.
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64URL;
+use base64::Engine;
+use secp256k1::ecdsa::Signature;
+use secp256k1::{Keypair, Message, PublicKey, Secp256k1, Signing, Verification};
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+pub use status_list::{Bitstring, StatusListCredential, StatusListEntry, StatusListError, MEDIA_TYPE, MIN_LIST_BITS, REVOCATION};
+
+const ALGORITHM: &str = "ES256K";
+/// Multicodec prefix of a compressed secp256k1 public key.
+const SECP256K1_PUB: [u8; 2] = [0xe7, 0x01];
+
+#[derive(Debug, PartialEq)]
+pub enum VcJwtError {
+    Malformed,
+    UnsupportedAlgorithm,
+    InvalidSignature,
+}
+
+impl std::fmt::Display for VcJwtError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            VcJwtError::Malformed => write!(f, "malformed status list VC-JWT"),
+            VcJwtError::UnsupportedAlgorithm => write!(f, "status list VC-JWT is not signed with {}", ALGORITHM),
+            VcJwtError::InvalidSignature => write!(f, "invalid status list signature"),
+        }
+    }
+}
+
+impl std::error::Error for VcJwtError {}
+
+/// Secures `credential` as a VC-JWT signed by `keypair`, which must be the
+/// key of the issuer's `did:key`.
+pub fn sign<C: Signing>(secp: &Secp256k1<C>, keypair: &Keypair, credential: &StatusListCredential) -> String {
+    let did = did_key(&keypair.public_key());
+    let header = serde_json::json!({ "alg": ALGORITHM, "typ": status_list::JWT_TYPE, "kid": verification_method(&did) });
+    let signing_input = format!(
+        "{}.{}",
+        BASE64URL.encode(header.to_string()),
+        BASE64URL.encode(serde_json::to_vec(credential).expect("credential serialises"))
+    );
+    let signature = secp.sign_ecdsa(&message(&signing_input), &keypair.secret_key());
+    format!("{}.{}", signing_input, BASE64URL.encode(signature.serialize_compact()))
+}
+
+/// Checks the signature and that it is by the issuer's key; returns the
+/// credential and that key.
+pub fn verify<C: Verification>(secp: &Secp256k1<C>, token: &str) -> Result<(StatusListCredential, PublicKey), VcJwtError> {
+    let (signing_input, signature) = token.rsplit_once('.').ok_or(VcJwtError::Malformed)?;
+    let (header, claims) = signing_input.split_once('.').ok_or(VcJwtError::Malformed)?;
+    let header: Value = decode_part(header)?;
+    if header["alg"] != ALGORITHM {
+        return Err(VcJwtError::UnsupportedAlgorithm);
+    }
+    let credential: StatusListCredential = decode_part(claims)?;
+    let (did, _) = header["kid"].as_str().and_then(|kid| kid.split_once('#')).ok_or(VcJwtError::Malformed)?;
+    if did != credential.issuer {
+        return Err(VcJwtError::InvalidSignature);
+    }
+    let key = parse_did_key(did)?;
+    let mut signature = BASE64URL
+        .decode(signature)
+        .ok()
+        .and_then(|bytes| Signature::from_compact(&bytes).ok())
+        .ok_or(VcJwtError::Malformed)?;
+    // JOSE does not require low-S signatures; libsecp256k1 does.
+    signature.normalize_s();
+    secp.verify_ecdsa(&message(signing_input), &signature, &key).map_err(|_| VcJwtError::InvalidSignature)?;
+    Ok((credential, key))
+}
+
+/// `did:key` of a secp256k1 key: its compressed point.
+pub fn did_key(key: &PublicKey) -> String {
+    let mut bytes = SECP256K1_PUB.to_vec();
+    bytes.extend_from_slice(&key.serialize());
+    format!("did:key:z{}", bs58::encode(bytes).into_string())
+}
+
+fn verification_method(did: &str) -> String {
+    format!("{}#{}", did, &did["did:key:".len()..])
+}
+
+fn parse_did_key(did: &str) -> Result<PublicKey, VcJwtError> {
+    let bytes = did
+        .strip_prefix("did:key:z")
+        .and_then(|value| bs58::decode(value).into_vec().ok())
+        .ok_or(VcJwtError::Malformed)?;
+    let key = bytes.strip_prefix(&SECP256K1_PUB[..]).ok_or(VcJwtError::Malformed)?;
+    PublicKey::from_slice(key).map_err(|_| VcJwtError::Malformed)
+}
+
+fn message(signing_input: &str) -> Message {
+    Message::from_digest(Sha256::digest(signing_input.as_bytes()).into())
+}
+
+fn decode_part<T: serde::de::DeserializeOwned>(part: &str) -> Result<T, VcJwtError> {
+    let bytes = BASE64URL.decode(part).map_err(|_| VcJwtError::Malformed)?;
+    serde_json::from_slice(&bytes).map_err(|_| VcJwtError::Malformed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URL: &str = "https://popchain.example/ledger/status-list/0";
+
+    fn keypair() -> Keypair {
+        Keypair::from_seckey_slice(&Secp256k1::new(), &[7; 32]).unwrap()
+    }
+
+    fn credential(list: &Bitstring) -> StatusListCredential {
+        let issuer = did_key(&keypair().public_key());
+        StatusListCredential::new(URL, &issuer, REVOCATION, list, "2026-10-19T12:00:00Z", None, Some(300_000))
+    }
+
+    #[test]
+    fn signed_credential_verifies_against_its_issuer() {
+        let mut list = Bitstring::new(MIN_LIST_BITS);
+        list.set(42, true).unwrap();
+        let secp = Secp256k1::new();
+        let token = sign(&secp, &keypair(), &credential(&list));
+        let (verified, key) = verify(&secp, &token).unwrap();
+        assert_eq!(key, keypair().public_key());
+        assert_eq!(verified, credential(&list));
+        assert_eq!(verified.status(&StatusListEntry::new(URL, REVOCATION, 42)), Ok(true));
+    }
+
+    #[test]
+    fn tampering_breaks_the_signature() {
+        let secp = Secp256k1::new();
+        let token = sign(&secp, &keypair(), &credential(&Bitstring::new(MIN_LIST_BITS)));
+        let (header, rest) = token.split_once('.').unwrap();
+        let (_, signature) = rest.split_once('.').unwrap();
+
+        let mut revoked = Bitstring::new(MIN_LIST_BITS);
+        revoked.set(42, true).unwrap();
+        let claims = BASE64URL.encode(serde_json::to_vec(&credential(&revoked)).unwrap());
+        assert_eq!(verify(&secp, &format!("{}.{}.{}", header, claims, signature)).err(), Some(VcJwtError::InvalidSignature));
+
+        let mut reissued = credential(&Bitstring::new(MIN_LIST_BITS));
+        reissued.issuer = did_key(&Keypair::from_seckey_slice(&secp, &[8; 32]).unwrap().public_key());
+        let claims = BASE64URL.encode(serde_json::to_vec(&reissued).unwrap());
+        assert_eq!(verify(&secp, &format!("{}.{}.{}", header, claims, signature)).err(), Some(VcJwtError::InvalidSignature));
+
+        let unsigned = BASE64URL.encode(r#"{"alg":"none","typ":"vc+jwt"}"#);
+        assert_eq!(verify(&secp, &format!("{}.{}.", unsigned, rest.split_once('.').unwrap().0)).err(), Some(VcJwtError::UnsupportedAlgorithm));
+    }
+}
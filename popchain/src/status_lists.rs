@@ -0,0 +1,243 @@
+//! The revocation status lists PoPChain publishes: every attestation is
+//! given a slot in a list when it is committed, and a revocation sets its
+//! bit. Each list is signed when it changes and served from that copy with
+//! caching headers, so verifiers can hold on to it and check any number of
+//! attestations without asking about each one.
+
+use popchain::status_list::{self, did_key, Bitstring, StatusListCredential, StatusListEntry, MIN_LIST_BITS};
+use rand::Rng;
+use secp256k1::{Keypair, Secp256k1};
+use serde::{Deserialize, Serialize};
+
+const STATUS_PURPOSE: &str = status_list::REVOCATION;
+
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct StatusListConfig {
+    /// Origin the lists are fetched from, which their URLs are built on;
+    /// the listen address when absent.
+    pub public_url: Option<String>,
+    /// Slots per list. Must not shrink once attestations have slots.
+    pub list_size: usize,
+    /// How long a verifier may use a cached list.
+    pub max_age_secs: u64,
+}
+
+impl Default for StatusListConfig {
+    fn default() -> Self {
+        StatusListConfig {
+            public_url: None,
+            list_size: MIN_LIST_BITS,
+            max_age_secs: 300,
+        }
+    }
+}
+
+/// Where an attestation's status bit is.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct StatusSlot {
+    pub list: usize,
+    pub index: usize,
+}
+
+/// A signed list as served: the VC-JWT.
+pub struct Published {
+    pub body: Vec<u8>,
+    /// Weak, since a list is signed afresh on restart without changing.
+    pub etag: String,
+    pub last_modified: String,
+}
+
+struct List {
+    statuses: Bitstring,
+    assigned: Bitstring,
+    assigned_count: usize,
+    /// Number of revocations in the list, so each change has a new one.
+    version: u64,
+    published: Option<Published>,
+}
+
+impl List {
+    fn new(size: usize) -> Self {
+        List {
+            statuses: Bitstring::new(size),
+            assigned: Bitstring::new(size),
+            assigned_count: 0,
+            version: 0,
+            published: None,
+        }
+    }
+
+    fn is_assigned(&self, index: usize) -> bool {
+        self.assigned.get(index).unwrap_or(true)
+    }
+}
+
+pub struct StatusLists {
+    base_url: String,
+    list_size: usize,
+    max_age_secs: u64,
+    keypair: Keypair,
+    issuer: String,
+    lists: Vec<List>,
+}
+
+impl StatusLists {
+    pub fn new(config: &StatusListConfig, base_url: &str, keypair: Keypair) -> Self {
+        let list_size = config.list_size.max(MIN_LIST_BITS);
+        StatusLists {
+            base_url: base_url.trim_end_matches('/').to_string(),
+            list_size,
+            max_age_secs: config.max_age_secs,
+            issuer: did_key(&keypair.public_key()),
+            keypair,
+            lists: vec![List::new(list_size)],
+        }
+    }
+
+    pub fn url(&self, list: usize) -> String {
+        format!("{}/ledger/status-list/{}", self.base_url, list)
+    }
+
+    pub fn entry(&self, slot: StatusSlot) -> StatusListEntry {
+        StatusListEntry::new(&self.url(slot.list), STATUS_PURPOSE, slot.index)
+    }
+
+    pub fn max_age_secs(&self) -> u64 {
+        self.max_age_secs
+    }
+
+    /// A free slot at a random index of the first list with room, so an
+    /// index says nothing about when the attestation was committed.
+    pub fn allocate(&self) -> StatusSlot {
+        let list = self.lists.iter().position(|list| list.assigned_count < self.list_size).unwrap_or(self.lists.len());
+        let Some(current) = self.lists.get(list) else {
+            return StatusSlot { list, index: rand::thread_rng().gen_range(0..self.list_size) };
+        };
+        let mut rng = rand::thread_rng();
+        let index = (0..64)
+            .map(|_| rng.gen_range(0..self.list_size))
+            .find(|index| !current.is_assigned(*index))
+            .or_else(|| (0..self.list_size).find(|index| !current.is_assigned(*index)))
+            .expect("list has room");
+        StatusSlot { list, index }
+    }
+
+    /// The lowest free slot; deterministic, for attestations committed
+    /// before status lists existed.
+    pub fn allocate_lowest(&self) -> StatusSlot {
+        for (list, current) in self.lists.iter().enumerate() {
+            if let Some(index) = (0..self.list_size).find(|index| !current.is_assigned(*index)) {
+                return StatusSlot { list, index };
+            }
+        }
+        StatusSlot { list: self.lists.len(), index: 0 }
+    }
+
+    pub fn assign(&mut self, slot: StatusSlot) -> Result<(), String> {
+        if slot.index >= self.list_size {
+            return Err(format!("status list index {} is beyond the list size of {}", slot.index, self.list_size));
+        }
+        while self.lists.len() <= slot.list {
+            self.lists.push(List::new(self.list_size));
+        }
+        let list = &mut self.lists[slot.list];
+        if list.is_assigned(slot.index) {
+            return Err(format!("status list {} index {} is assigned twice", slot.list, slot.index));
+        }
+        list.assigned.set(slot.index, true).map_err(|e| e.to_string())?;
+        list.assigned_count += 1;
+        Ok(())
+    }
+
+    /// Sets the slot's bit; the list is signed again on the next
+    /// [`publish`](StatusLists::publish).
+    pub fn revoke(&mut self, slot: StatusSlot) {
+        let Some(list) = self.lists.get_mut(slot.list) else {
+            return;
+        };
+        if list.statuses.get(slot.index) == Ok(false) && list.statuses.set(slot.index, true).is_ok() {
+            list.version += 1;
+            list.published = None;
+        }
+    }
+
+    /// Signs every list that changed since it was last signed.
+    pub fn publish(&mut self, now: u64) {
+        let secp = Secp256k1::signing_only();
+        let date_time = date_time(now);
+        for number in 0..self.lists.len() {
+            if self.lists[number].published.is_some() {
+                continue;
+            }
+            let url = self.url(number);
+            let list = &self.lists[number];
+            let credential = StatusListCredential::new(&url, &self.issuer, STATUS_PURPOSE, &list.statuses, &date_time, None, Some(self.max_age_secs * 1000));
+            let published = Published {
+                body: status_list::sign(&secp, &self.keypair, &credential).into_bytes(),
+                etag: format!("W/\"{}-{}\"", number, list.version),
+                last_modified: http_date(now),
+            };
+            self.lists[number].published = Some(published);
+        }
+    }
+
+    pub fn published(&self, list: usize) -> Option<&Published> {
+        self.lists.get(list)?.published.as_ref()
+    }
+}
+
+fn date_time(timestamp: u64) -> String {
+    chrono::DateTime::from_timestamp(timestamp as i64, 0).unwrap_or_default().format("%Y-%m-%dT%H:%M:%SZ").to_string()
+}
+
+fn http_date(timestamp: u64) -> String {
+    chrono::DateTime::from_timestamp(timestamp as i64, 0).unwrap_or_default().format("%a, %d %b %Y %H:%M:%S GMT").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status_lists() -> StatusLists {
+        let keypair = Keypair::from_seckey_slice(&Secp256k1::new(), &[7; 32]).unwrap();
+        StatusLists::new(&StatusListConfig::default(), "https://popchain.example/", keypair)
+    }
+
+    fn credential(status_lists: &StatusLists, list: usize) -> StatusListCredential {
+        let token = std::str::from_utf8(&status_lists.published(list).unwrap().body).unwrap();
+        status_list::verify(&Secp256k1::new(), token).unwrap().0
+    }
+
+    #[test]
+    fn slots_are_assigned_once() {
+        let mut status_lists = status_lists();
+        let slot = status_lists.allocate();
+        status_lists.assign(slot).unwrap();
+        assert!(status_lists.assign(slot).is_err());
+        assert!(status_lists.assign(StatusSlot { list: 0, index: MIN_LIST_BITS }).is_err());
+
+        let lowest = status_lists.allocate_lowest();
+        assert_eq!(lowest, StatusSlot { list: 0, index: if slot.index == 0 { 1 } else { 0 } });
+    }
+
+    #[test]
+    fn revocation_republishes_the_list() {
+        let mut status_lists = status_lists();
+        let slot = StatusSlot { list: 0, index: 94_567 };
+        status_lists.assign(slot).unwrap();
+        status_lists.publish(1_792_400_000);
+        let before = status_lists.published(0).unwrap().etag.clone();
+        assert!(!credential(&status_lists, 0).list().unwrap().get(slot.index).unwrap());
+
+        status_lists.revoke(slot);
+        assert!(status_lists.published(0).is_none());
+        status_lists.publish(1_792_400_060);
+        assert_ne!(status_lists.published(0).unwrap().etag, before);
+        let credential = credential(&status_lists, 0);
+        assert_eq!(credential.status(&status_lists.entry(slot)), Ok(true));
+        assert_eq!(credential.id, "https://popchain.example/ledger/status-list/0");
+        assert_eq!(status_lists.entry(slot).status_list_credential, credential.id);
+        assert_eq!(status_lists.entry(slot).status_list_index, "94567");
+    }
+}
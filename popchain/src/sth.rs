@@ -0,0 +1,261 @@
+//! Signed tree heads. The log signs `(tree_size, timestamp, root)` with a
+//! BIP-340 Schnorr key; independent witnesses that have checked the head is
+//! consistent with every earlier one they saw add cosignatures. A client
+//! that requires cosignatures from enough witnesses it trusts knows the log
+//! is not showing it a view the witnesses were not shown.
+
+use secp256k1::schnorr::Signature;
+use secp256k1::{Keypair, Message, Secp256k1, Signing, Verification, XOnlyPublicKey};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::merkle::Hash;
+
+const STH_DOMAIN: &[u8] = b"popchain-sth-v1";
+const COSIGNATURE_DOMAIN: &[u8] = b"popchain-cosignature-v1";
+
+#[derive(Debug, PartialEq)]
+pub enum SthError {
+    Malformed,
+    InvalidSignature,
+    UntrustedWitness,
+    NotEnoughCosignatures { valid: usize, required: usize },
+}
+
+impl std::fmt::Display for SthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SthError::Malformed => write!(f, "malformed tree head"),
+            SthError::InvalidSignature => write!(f, "invalid tree head signature"),
+            SthError::UntrustedWitness => write!(f, "cosignature is not from a trusted witness"),
+            SthError::NotEnoughCosignatures { valid, required } => {
+                write!(f, "{} valid witness cosignatures, {} required", valid, required)
+            }
+        }
+    }
+}
+
+impl std::error::Error for SthError {}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SignedTreeHead {
+    pub tree_size: usize,
+    /// Seconds since the Unix epoch.
+    pub timestamp: u64,
+    /// Hex RFC 6962 root hash.
+    pub root_hash: String,
+    /// Hex BIP-340 signature by the log key.
+    pub signature: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Cosignature {
+    /// Hex x-only public key of the witness.
+    pub witness: String,
+    /// When the witness cosigned.
+    pub timestamp: u64,
+    pub signature: String,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CosignedTreeHead {
+    #[serde(flatten)]
+    pub sth: SignedTreeHead,
+    #[serde(default)]
+    pub cosignatures: Vec<Cosignature>,
+}
+
+impl SignedTreeHead {
+    pub fn sign<C: Signing>(secp: &Secp256k1<C>, keypair: &Keypair, tree_size: usize, timestamp: u64, root: &Hash) -> Self {
+        let signature = secp.sign_schnorr(&sth_message(tree_size, timestamp, root), keypair);
+        SignedTreeHead {
+            tree_size,
+            timestamp,
+            root_hash: hex::encode(root),
+            signature: hex::encode(signature.serialize()),
+        }
+    }
+
+    pub fn root(&self) -> Result<Hash, SthError> {
+        decode_hash(&self.root_hash)
+    }
+
+    /// Checks the log's signature.
+    pub fn verify<C: Verification>(&self, secp: &Secp256k1<C>, log_key: &XOnlyPublicKey) -> Result<(), SthError> {
+        let message = sth_message(self.tree_size, self.timestamp, &self.root()?);
+        secp.verify_schnorr(&decode_signature(&self.signature)?, &message, log_key)
+            .map_err(|_| SthError::InvalidSignature)
+    }
+
+    /// A witness's cosignature over this head, as published by `log_key`.
+    pub fn cosign<C: Signing>(&self, secp: &Secp256k1<C>, log_key: &XOnlyPublicKey, witness: &Keypair, timestamp: u64) -> Result<Cosignature, SthError> {
+        let message = cosignature_message(log_key, self, timestamp)?;
+        Ok(Cosignature {
+            witness: hex::encode(witness.x_only_public_key().0.serialize()),
+            timestamp,
+            signature: hex::encode(secp.sign_schnorr(&message, witness).serialize()),
+        })
+    }
+}
+
+impl Cosignature {
+    pub fn witness_key(&self) -> Result<XOnlyPublicKey, SthError> {
+        hex::decode(&self.witness)
+            .ok()
+            .and_then(|bytes| XOnlyPublicKey::from_slice(&bytes).ok())
+            .ok_or(SthError::Malformed)
+    }
+
+    pub fn verify<C: Verification>(&self, secp: &Secp256k1<C>, log_key: &XOnlyPublicKey, sth: &SignedTreeHead) -> Result<(), SthError> {
+        let message = cosignature_message(log_key, sth, self.timestamp)?;
+        secp.verify_schnorr(&decode_signature(&self.signature)?, &message, &self.witness_key()?)
+            .map_err(|_| SthError::InvalidSignature)
+    }
+}
+
+impl CosignedTreeHead {
+    /// Checks the log signature and that at least `required` of the trusted
+    /// `witnesses` validly cosigned the head. Cosignatures from other keys
+    /// are ignored.
+    pub fn verify<C: Verification>(
+        &self,
+        secp: &Secp256k1<C>,
+        log_key: &XOnlyPublicKey,
+        witnesses: &[XOnlyPublicKey],
+        required: usize,
+    ) -> Result<(), SthError> {
+        self.sth.verify(secp, log_key)?;
+        let valid = witnesses
+            .iter()
+            .filter(|witness| {
+                self.cosignatures.iter().any(|cosignature| {
+                    cosignature.witness_key().ok().as_ref() == Some(*witness) && cosignature.verify(secp, log_key, &self.sth).is_ok()
+                })
+            })
+            .count();
+        if valid < required {
+            return Err(SthError::NotEnoughCosignatures { valid, required });
+        }
+        Ok(())
+    }
+}
+
+fn sth_message(tree_size: usize, timestamp: u64, root: &Hash) -> Message {
+    let mut hasher = Sha256::new();
+    hasher.update(STH_DOMAIN);
+    hasher.update((tree_size as u64).to_be_bytes());
+    hasher.update(timestamp.to_be_bytes());
+    hasher.update(root);
+    Message::from_digest(hasher.finalize().into())
+}
+
+/// Binds the cosignature to the log key as well as to the head, so it cannot
+/// be replayed for another log with the same tree.
+fn cosignature_message(log_key: &XOnlyPublicKey, sth: &SignedTreeHead, timestamp: u64) -> Result<Message, SthError> {
+    let mut hasher = Sha256::new();
+    hasher.update(COSIGNATURE_DOMAIN);
+    hasher.update(log_key.serialize());
+    hasher.update((sth.tree_size as u64).to_be_bytes());
+    hasher.update(sth.timestamp.to_be_bytes());
+    hasher.update(sth.root()?);
+    hasher.update(timestamp.to_be_bytes());
+    Ok(Message::from_digest(hasher.finalize().into()))
+}
+
+fn decode_hash(value: &str) -> Result<Hash, SthError> {
+    hex::decode(value).ok().and_then(|bytes| bytes.try_into().ok()).ok_or(SthError::Malformed)
+}
+
+fn decode_signature(value: &str) -> Result<Signature, SthError> {
+    hex::decode(value)
+        .ok()
+        .and_then(|bytes| Signature::from_slice(&bytes).ok())
+        .ok_or(SthError::Malformed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Keys {
+        secp: Secp256k1<secp256k1::All>,
+        log: Keypair,
+        witnesses: Vec<Keypair>,
+    }
+
+    impl Keys {
+        fn new() -> Self {
+            let secp = Secp256k1::new();
+            let keypair = |byte: u8| Keypair::from_seckey_slice(&secp, &[byte; 32]).unwrap();
+            Keys { log: keypair(1), witnesses: (2..5).map(keypair).collect(), secp }
+        }
+
+        fn log_key(&self) -> XOnlyPublicKey {
+            self.log.x_only_public_key().0
+        }
+
+        fn trusted(&self) -> Vec<XOnlyPublicKey> {
+            self.witnesses.iter().map(|witness| witness.x_only_public_key().0).collect()
+        }
+
+        fn head(&self) -> SignedTreeHead {
+            SignedTreeHead::sign(&self.secp, &self.log, 42, 1_760_000_000, &[7; 32])
+        }
+    }
+
+    #[test]
+    fn signed_heads_verify_under_the_log_key() {
+        let keys = Keys::new();
+        let sth = keys.head();
+        sth.verify(&keys.secp, &keys.log_key()).unwrap();
+        let round_tripped: SignedTreeHead = serde_json::from_str(&serde_json::to_string(&sth).unwrap()).unwrap();
+        round_tripped.verify(&keys.secp, &keys.log_key()).unwrap();
+
+        let other_log = keys.witnesses[0].x_only_public_key().0;
+        assert_eq!(sth.verify(&keys.secp, &other_log), Err(SthError::InvalidSignature));
+        let resized = SignedTreeHead { tree_size: 43, ..sth.clone() };
+        assert_eq!(resized.verify(&keys.secp, &keys.log_key()), Err(SthError::InvalidSignature));
+        let truncated = SignedTreeHead { root_hash: "07".repeat(31), ..sth };
+        assert_eq!(truncated.verify(&keys.secp, &keys.log_key()), Err(SthError::Malformed));
+    }
+
+    #[test]
+    fn cosignatures_are_bound_to_the_head_and_the_log() {
+        let keys = Keys::new();
+        let sth = keys.head();
+        let cosignature = sth.cosign(&keys.secp, &keys.log_key(), &keys.witnesses[0], 1_760_000_010).unwrap();
+        cosignature.verify(&keys.secp, &keys.log_key(), &sth).unwrap();
+
+        let other_head = SignedTreeHead::sign(&keys.secp, &keys.log, 43, 1_760_000_000, &[7; 32]);
+        assert_eq!(cosignature.verify(&keys.secp, &keys.log_key(), &other_head), Err(SthError::InvalidSignature));
+        let other_log = keys.witnesses[1].x_only_public_key().0;
+        assert_eq!(cosignature.verify(&keys.secp, &other_log, &sth), Err(SthError::InvalidSignature));
+        let backdated = Cosignature { timestamp: 1_760_000_009, ..cosignature };
+        assert_eq!(backdated.verify(&keys.secp, &keys.log_key(), &sth), Err(SthError::InvalidSignature));
+    }
+
+    #[test]
+    fn only_trusted_witnesses_count_towards_the_threshold() {
+        let keys = Keys::new();
+        let sth = keys.head();
+        let cosign = |witness: &Keypair| sth.cosign(&keys.secp, &keys.log_key(), witness, 1_760_000_010).unwrap();
+        let untrusted = Keypair::from_seckey_slice(&keys.secp, &[9; 32]).unwrap();
+        let head = CosignedTreeHead {
+            sth: sth.clone(),
+            // One witness twice, and one nobody trusts.
+            cosignatures: vec![cosign(&keys.witnesses[0]), cosign(&keys.witnesses[0]), cosign(&untrusted), cosign(&keys.witnesses[1])],
+        };
+        head.verify(&keys.secp, &keys.log_key(), &keys.trusted(), 2).unwrap();
+        assert_eq!(
+            head.verify(&keys.secp, &keys.log_key(), &keys.trusted(), 3),
+            Err(SthError::NotEnoughCosignatures { valid: 2, required: 3 })
+        );
+
+        let mut forged = head.clone();
+        forged.cosignatures[3].signature = forged.cosignatures[0].signature.clone();
+        assert_eq!(
+            forged.verify(&keys.secp, &keys.log_key(), &keys.trusted(), 2),
+            Err(SthError::NotEnoughCosignatures { valid: 1, required: 2 })
+        );
+    }
+}
@@ -0,0 +1,154 @@
+//! Witness mode (`popchain witness`): polls a log's latest signed tree head,
+//! checks it is consistent with the last head this witness accepted and
+//! submits a cosignature. A head that would rewrite or roll back the history
+//! the witness has already seen is never cosigned.
+
+use popchain::merkle;
+use popchain::sth::SignedTreeHead;
+use reqwest::Client;
+use secp256k1::{Keypair, Secp256k1, SecretKey, XOnlyPublicKey};
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::io;
+use std::time::Duration;
+
+/// Environment variable holding the witness's hex secp256k1 secret key.
+pub const SIGNING_KEY_ENV: &str = "WITNESS_SIGNING_KEY";
+
+/// Heads timestamped further ahead of the witness clock are not cosigned.
+const MAX_CLOCK_SKEW_SECS: u64 = 300;
+
+#[derive(Deserialize)]
+pub struct WitnessConfig {
+    pub log_url: String,
+    /// Hex x-only public key the log signs tree heads with.
+    pub log_public_key: String,
+    pub poll_interval_secs: u64,
+    /// Where the last accepted head is kept across restarts.
+    pub state_path: String,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct WitnessState {
+    last: Option<SignedTreeHead>,
+}
+
+#[derive(Deserialize)]
+struct ConsistencyProof {
+    consistency: Vec<String>,
+}
+
+#[derive(Serialize)]
+struct CosignatureSubmission<'a> {
+    tree_size: usize,
+    timestamp: u64,
+    cosignature: &'a popchain::sth::Cosignature,
+}
+
+pub async fn run(config: WitnessConfig) -> Result<(), Box<dyn std::error::Error>> {
+    let secp = Secp256k1::new();
+    let secret = std::env::var(SIGNING_KEY_ENV).map_err(|_| format!("{} must be set to a hex secp256k1 key", SIGNING_KEY_ENV))?;
+    let keypair = Keypair::from_secret_key(&secp, &SecretKey::from_slice(&hex::decode(secret)?)?);
+    let log_key = XOnlyPublicKey::from_slice(&hex::decode(&config.log_public_key)?)?;
+    let client = Client::builder().timeout(Duration::from_secs(10)).build()?;
+    let mut state = load_state(&config.state_path)?;
+    println!(
+        "Witness {} watching {}, last accepted tree size {}",
+        hex::encode(keypair.x_only_public_key().0.serialize()),
+        config.log_url,
+        state.last.as_ref().map_or(0, |last| last.tree_size)
+    );
+
+    let mut interval = tokio::time::interval(Duration::from_secs(config.poll_interval_secs.max(1)));
+    loop {
+        interval.tick().await;
+        match witness_once(&secp, &client, &config, &log_key, &keypair, &state).await {
+            Ok(Some(sth)) => {
+                state.last = Some(sth);
+                save_state(&config.state_path, &state)?;
+            }
+            Ok(None) => {}
+            Err(e) => eprintln!("Not cosigning: {}", e),
+        }
+    }
+}
+
+/// Checks and cosigns the log's latest head; returns it when accepted.
+async fn witness_once(
+    secp: &Secp256k1<secp256k1::All>,
+    client: &Client,
+    config: &WitnessConfig,
+    log_key: &XOnlyPublicKey,
+    keypair: &Keypair,
+    state: &WitnessState,
+) -> Result<Option<SignedTreeHead>, Box<dyn std::error::Error>> {
+    let log_url = config.log_url.trim_end_matches('/');
+    let sth: SignedTreeHead = client.get(format!("{}/ledger/sth", log_url)).send().await?.error_for_status()?.json().await?;
+    sth.verify(secp, log_key)?;
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?.as_secs();
+    if sth.timestamp > now + MAX_CLOCK_SKEW_SECS {
+        return Err(format!("tree head timestamp {} is in the future", sth.timestamp).into());
+    }
+
+    match &state.last {
+        Some(last) if last.tree_size == sth.tree_size && last.timestamp == sth.timestamp => return Ok(None),
+        Some(last) if sth.timestamp < last.timestamp => {
+            return Err(format!("tree head timestamp went backwards from {} to {}", last.timestamp, sth.timestamp).into())
+        }
+        Some(last) if sth.tree_size < last.tree_size => {
+            eprintln!("ALERT: log rolled back from tree size {} to {}", last.tree_size, sth.tree_size);
+            return Err("log rolled back".into());
+        }
+        Some(last) if sth.tree_size == last.tree_size => {
+            if sth.root_hash != last.root_hash {
+                eprintln!("ALERT: log presented two roots for tree size {}: {} and {}", sth.tree_size, last.root_hash, sth.root_hash);
+                return Err("conflicting roots".into());
+            }
+        }
+        Some(last) => {
+            let proof: ConsistencyProof = client
+                .get(format!("{}/ledger/consistency?first={}&second={}", log_url, last.tree_size, sth.tree_size))
+                .send()
+                .await?
+                .error_for_status()?
+                .json()
+                .await?;
+            let proof = proof
+                .consistency
+                .iter()
+                .map(|hash| hex::decode(hash).ok().and_then(|bytes| bytes.try_into().ok()))
+                .collect::<Option<Vec<merkle::Hash>>>()
+                .ok_or("malformed consistency proof")?;
+            if !merkle::verify_consistency(last.tree_size, sth.tree_size, &last.root()?, &sth.root()?, &proof) {
+                eprintln!("ALERT: tree size {} is not consistent with tree size {}", sth.tree_size, last.tree_size);
+                return Err("inconsistent tree heads".into());
+            }
+        }
+        None => println!("Trusting first tree head seen: size {}, root {}", sth.tree_size, sth.root_hash),
+    }
+
+    let cosignature = sth.cosign(secp, log_key, keypair, now)?;
+    client
+        .post(format!("{}/ledger/sth/cosignatures", log_url))
+        .json(&CosignatureSubmission { tree_size: sth.tree_size, timestamp: sth.timestamp, cosignature: &cosignature })
+        .send()
+        .await?
+        .error_for_status()?;
+    println!("Cosigned tree size {}, root {}", sth.tree_size, sth.root_hash);
+    Ok(Some(sth))
+}
+
+fn load_state(path: &str) -> io::Result<WitnessState> {
+    match fs::read_to_string(path) {
+        Ok(contents) => serde_json::from_str(&contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(WitnessState::default()),
+        Err(e) => Err(e),
+    }
+}
+
+fn save_state(path: &str, state: &WitnessState) -> io::Result<()> {
+    let contents = serde_json::to_string_pretty(state).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+    let tmp = format!("{}.tmp", path);
+    fs::write(&tmp, contents)?;
+    fs::rename(&tmp, path)
+}
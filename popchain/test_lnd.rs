@@ -1,5 +1,4 @@
-use std::fs;
-use reqwest::Client;
+use lnd_client::LndClient;
 
 #[tokio::main]
 async fn main() -> Result<(), Box<dyn std::error::Error>> {
@@ -7,33 +6,19 @@ async fn main() -> Result<(), Box<dyn std::error::Error>> {
     let tls_cert_path = "/Users/pieterwjbouwer/lnd-test/tls.cert";
     let macaroon_path = "/Users/pieterwjbouwer/lnd-test/data/chain/bitcoin/testnet/admin.macaroon";
 
-    println!("Attempting to read TLS cert at: {}", tls_cert_path);
-    let tls_cert = fs::read(tls_cert_path)?;
-    println!("Successfully read TLS cert (length: {} bytes)", tls_cert.len());
+    println!("Connecting to LND REST API at {}", lnd_host);
+    let client = LndClient::new(lnd_host, macaroon_path, Some(tls_cert_path.into()))?;
+    let info = client.get_info().await?;
+    println!("Successfully connected to LND REST API");
+    println!("LND Identity Pubkey: {}", info.identity_pubkey);
+    println!("Block height {}, synced to chain: {}", info.block_height, info.synced_to_chain);
 
-    println!("Attempting to read macaroon at: {}", macaroon_path);
-    let macaroon = fs::read(macaroon_path)?;
-    println!("Successfully read macaroon (length: {} bytes)", macaroon.len());
-    let macaroon = hex::encode(macaroon);
-
-    let client = Client::builder()
-        .add_root_certificate(reqwest::Certificate::from_pem(&tls_cert)?)
-        .build()?;
-    
-    let rest_endpoint = format!("{}/v1/getinfo", lnd_host);
-    println!("Attempting to connect to LND REST API at: {}", rest_endpoint);
-    let response = client
-        .get(&rest_endpoint)
-        .header("Grpc-Metadata-macaroon", macaroon)
-        .send()
-        .await?;
-
-    if response.status().is_success() {
-        let json: serde_json::Value = response.json().await?;
-        println!("Successfully connected to LND REST API");
-        println!("LND Identity Pubkey: {}", json["identity_pubkey"].as_str().unwrap_or("unknown"));
-        Ok(())
-    } else {
-        Err(format!("Failed to connect to LND REST API: {}", response.status()).into())
+    let wallet = client.wallet_balance().await?;
+    let channels = client.channel_balance().await?;
+    println!("On-chain balance: {} sat ({} unconfirmed)", wallet.confirmed_balance, wallet.unconfirmed_balance);
+    println!("Channel balance: {} sat local, {} sat remote", channels.local_balance.sat, channels.remote_balance.sat);
+    for channel in client.channels().await? {
+        println!("Channel {} with {}: {} of {} sat local, active: {}", channel.chan_id, channel.remote_pubkey, channel.local_balance, channel.capacity, channel.active);
     }
+    Ok(())
 }
@@ -0,0 +1,288 @@
+//! Sequence codes identify every enrollment, verification and audit event:
+//! `TX-<ACTION>-<uuid>-<unix timestamp>-<mac>`. The MAC is an HMAC-SHA256 over
+//! the other fields, keyed by a secret shared by the issuing services, so a
+//! code can be checked for authenticity without a database lookup. A code
+//! can also be bound to a subject, such as the identity verified, which its
+//! MAC then covers too.
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::fmt;
+use std::str::FromStr;
+use uuid::Uuid;
+
+/// Environment variable holding the MAC key shared by the issuing services.
+pub const SECRET_ENV: &str = "SEQUENCE_CODE_SECRET";
+
+const PREFIX: &str = "TX";
+const MAC_LEN: usize = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum Action {
+    Enroll,
+    Verify,
+    Authenticate,
+    Update,
+    Query,
+    Log,
+    Revoke,
+    Renew,
+    Remind,
+    Expire,
+}
+
+impl Action {
+    pub const ALL: [Action; 10] = [
+        Action::Enroll,
+        Action::Verify,
+        Action::Authenticate,
+        Action::Update,
+        Action::Query,
+        Action::Log,
+        Action::Revoke,
+        Action::Renew,
+        Action::Remind,
+        Action::Expire,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Action::Enroll => "ENR",
+            Action::Verify => "VER",
+            Action::Authenticate => "AUTH",
+            Action::Update => "UPDATE",
+            Action::Query => "QUERY",
+            Action::Log => "LOG",
+            Action::Revoke => "REV",
+            Action::Renew => "RNW",
+            Action::Remind => "RMD",
+            Action::Expire => "EXP",
+        }
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Action {
+    type Err = ParseError;
+
+    /// Accepts the action code in either case, e.g. `VER` or `ver`.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Action::ALL
+            .into_iter()
+            .find(|action| action.as_str().eq_ignore_ascii_case(s))
+            .ok_or_else(|| ParseError::UnknownAction(s.to_string()))
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ParseError {
+    Malformed,
+    UnknownAction(String),
+    InvalidUuid,
+    InvalidTimestamp,
+    InvalidMac,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ParseError::Malformed => write!(f, "expected TX-<ACTION>-<uuid>-<timestamp>-<mac>"),
+            ParseError::UnknownAction(action) => write!(f, "unknown action {}", action),
+            ParseError::InvalidUuid => write!(f, "invalid uuid"),
+            ParseError::InvalidTimestamp => write!(f, "invalid timestamp"),
+            ParseError::InvalidMac => write!(f, "mac must be {} hex characters", MAC_LEN * 2),
+        }
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SequenceCode {
+    pub action: Action,
+    pub uuid: Uuid,
+    pub timestamp: i64,
+    mac: [u8; MAC_LEN],
+}
+
+impl SequenceCode {
+    /// Issues a fresh code for `action` at the current time.
+    pub fn issue(action: Action, secret: &[u8]) -> Self {
+        Self::issue_at(action, Uuid::new_v4(), chrono::Utc::now().timestamp(), secret)
+    }
+
+    pub fn issue_at(action: Action, uuid: Uuid, timestamp: i64, secret: &[u8]) -> Self {
+        Self::mint(action, uuid, timestamp, None, secret)
+    }
+
+    /// Issues a code bound to `subject`, e.g. the identity a verification
+    /// was of. It verifies only with [`verify_for`] and the same subject.
+    ///
+    /// [`verify_for`]: SequenceCode::verify_for
+    pub fn issue_for(action: Action, subject: &str, secret: &[u8]) -> Self {
+        Self::issue_for_at(action, subject, Uuid::new_v4(), chrono::Utc::now().timestamp(), secret)
+    }
+
+    pub fn issue_for_at(action: Action, subject: &str, uuid: Uuid, timestamp: i64, secret: &[u8]) -> Self {
+        Self::mint(action, uuid, timestamp, Some(subject), secret)
+    }
+
+    fn mint(action: Action, uuid: Uuid, timestamp: i64, subject: Option<&str>, secret: &[u8]) -> Self {
+        let tag = keyed_mac(secret, action, &uuid, timestamp, subject).finalize().into_bytes();
+        let mut mac = [0u8; MAC_LEN];
+        mac.copy_from_slice(&tag[..MAC_LEN]);
+        SequenceCode { action, uuid, timestamp, mac }
+    }
+
+    /// Parses the textual form without checking the MAC; call [`verify`]
+    /// before trusting a code handed in from outside.
+    ///
+    /// [`verify`]: SequenceCode::verify
+    pub fn parse(s: &str) -> Result<Self, ParseError> {
+        let rest = s.strip_prefix(PREFIX).and_then(|rest| rest.strip_prefix('-')).ok_or(ParseError::Malformed)?;
+        let (action, rest) = rest.split_once('-').ok_or(ParseError::Malformed)?;
+        let (rest, mac) = rest.rsplit_once('-').ok_or(ParseError::Malformed)?;
+        let (uuid, timestamp) = rest.rsplit_once('-').ok_or(ParseError::Malformed)?;
+
+        let action = action.parse()?;
+        let uuid = Uuid::try_parse(uuid).map_err(|_| ParseError::InvalidUuid)?;
+        let timestamp = timestamp.parse().map_err(|_| ParseError::InvalidTimestamp)?;
+        let mac = hex::decode(mac)
+            .ok()
+            .and_then(|bytes| bytes.try_into().ok())
+            .ok_or(ParseError::InvalidMac)?;
+        Ok(SequenceCode { action, uuid, timestamp, mac })
+    }
+
+    /// Whether the code was issued by a holder of `secret`. Constant-time in
+    /// the MAC comparison.
+    pub fn verify(&self, secret: &[u8]) -> bool {
+        keyed_mac(secret, self.action, &self.uuid, self.timestamp, None).verify_truncated_left(&self.mac).is_ok()
+    }
+
+    /// Whether the code was issued by a holder of `secret` for `subject`.
+    pub fn verify_for(&self, secret: &[u8], subject: &str) -> bool {
+        keyed_mac(secret, self.action, &self.uuid, self.timestamp, Some(subject)).verify_truncated_left(&self.mac).is_ok()
+    }
+}
+
+impl fmt::Display for SequenceCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}-{}-{}-{}", PREFIX, self.action, self.uuid, self.timestamp, hex::encode(self.mac))
+    }
+}
+
+impl FromStr for SequenceCode {
+    type Err = ParseError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        SequenceCode::parse(s)
+    }
+}
+
+/// Reads the MAC key from [`SECRET_ENV`].
+pub fn secret_from_env() -> Result<Vec<u8>, String> {
+    match std::env::var(SECRET_ENV) {
+        Ok(secret) if !secret.is_empty() => Ok(secret.into_bytes()),
+        _ => Err(format!("{} must be set to the sequence code MAC key", SECRET_ENV)),
+    }
+}
+
+fn keyed_mac(secret: &[u8], action: Action, uuid: &Uuid, timestamp: i64, subject: Option<&str>) -> Hmac<Sha256> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret).expect("HMAC accepts keys of any length");
+    mac.update(b"humanhash-sequence-code");
+    mac.update(action.as_str().as_bytes());
+    mac.update(uuid.as_bytes());
+    mac.update(&timestamp.to_be_bytes());
+    if let Some(subject) = subject {
+        mac.update(&(subject.len() as u64).to_be_bytes());
+        mac.update(subject.as_bytes());
+    }
+    mac
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECRET: &[u8] = b"test-secret";
+
+    fn uuid() -> Uuid {
+        Uuid::parse_str("67e55044-10b1-426f-9247-bb680e5fe0c8").unwrap()
+    }
+
+    #[test]
+    fn codes_round_trip() {
+        let code = SequenceCode::issue_at(Action::Verify, uuid(), 1_760_000_000, SECRET);
+        let text = code.to_string();
+        assert_eq!(text, "TX-VER-67e55044-10b1-426f-9247-bb680e5fe0c8-1760000000-15de866f07888dd5");
+        assert_eq!(SequenceCode::parse(&text), Ok(code.clone()));
+        assert!(code.verify(SECRET));
+
+        for action in Action::ALL {
+            let code = SequenceCode::issue(action, SECRET);
+            let parsed: SequenceCode = code.to_string().parse().unwrap();
+            assert_eq!(parsed, code);
+            assert!(parsed.verify(SECRET));
+        }
+        assert_eq!(SequenceCode::parse(&text.replace("VER", "ver")).unwrap().action, Action::Verify);
+    }
+
+    #[test]
+    fn tampered_codes_fail_verification() {
+        let text = SequenceCode::issue_at(Action::Verify, uuid(), 1_760_000_000, SECRET).to_string();
+        let tampered = [
+            text.replace("15de866f07888dd5", "15de866f07888dd4"),
+            text.replace("TX-VER-", "TX-REV-"),
+            text.replace("1760000000", "1760000001"),
+            text.replace("67e55044", "67e55045"),
+        ];
+        for text in tampered {
+            assert!(!SequenceCode::parse(&text).unwrap().verify(SECRET), "{} verified", text);
+        }
+    }
+
+    #[test]
+    fn codes_fail_verification_under_another_key() {
+        let code = SequenceCode::issue(Action::Enroll, SECRET);
+        assert!(!code.verify(b"other-secret"));
+        assert!(!code.verify(b""));
+    }
+
+    #[test]
+    fn bound_codes_verify_only_for_their_subject() {
+        let code = SequenceCode::issue_for_at(Action::Verify, "0xholder", uuid(), 1_760_000_000, SECRET);
+        assert!(code.verify_for(SECRET, "0xholder"));
+        assert!(!code.verify_for(SECRET, "0xsomeone-else"));
+        assert!(!code.verify_for(b"other-secret", "0xholder"));
+        assert!(!code.verify(SECRET));
+
+        let unbound = SequenceCode::issue_at(Action::Verify, uuid(), 1_760_000_000, SECRET);
+        assert!(!unbound.verify_for(SECRET, "0xholder"));
+        assert!(!unbound.verify_for(SECRET, ""));
+    }
+
+    #[test]
+    fn malformed_codes_are_rejected() {
+        let uuid = "67e55044-10b1-426f-9247-bb680e5fe0c8";
+        let cases = [
+            (String::new(), ParseError::Malformed),
+            ("TX".to_string(), ParseError::Malformed),
+            (format!("RX-VER-{}-1760000000-15de866f07888dd5", uuid), ParseError::Malformed),
+            ("TX-VER-1760000000".to_string(), ParseError::Malformed),
+            (format!("TX-XYZ-{}-1760000000-15de866f07888dd5", uuid), ParseError::UnknownAction("XYZ".to_string())),
+            ("TX-VER-67e55044-10b1-426f-9247-1760000000-15de866f07888dd5".to_string(), ParseError::InvalidUuid),
+            (format!("TX-VER-{}-soon-15de866f07888dd5", uuid), ParseError::InvalidTimestamp),
+            (format!("TX-VER-{}-1760000000-15de866f07888d", uuid), ParseError::InvalidMac),
+            (format!("TX-VER-{}-1760000000-15de866f07888dzz", uuid), ParseError::InvalidMac),
+        ];
+        for (text, error) in cases {
+            assert_eq!(SequenceCode::parse(&text), Err(error), "{:?}", text);
+        }
+    }
+}
@@ -0,0 +1,240 @@
+//! W3C Bitstring Status List v1.0, shared by the services that publish
+//! revocation lists: the system service for the credentials it issues and
+//! PoPChain for attestations. The revocation status of every entry is one
+//! bit in a compressed list, published as a `BitstringStatusListCredential`
+//! the issuer secures as a VC-JWT (typ `vc+jwt`) with its own key. A
+//! verifier fetches the list once, caches it, and checks any entry's bit
+//! locally, so the issuer never learns which entry was being checked.
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD as BASE64URL;
+use base64::Engine;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use serde::{Deserialize, Serialize};
+use std::io::{Read, Write};
+
+/// Smallest list the specification allows, 16 KiB uncompressed, so that a
+/// list says little about how many entries there are.
+pub const MIN_LIST_BITS: usize = 131_072;
+/// JWS `typ` of a status list credential secured as a VC-JWT.
+pub const JWT_TYPE: &str = "vc+jwt";
+/// Media type the credential is served with.
+pub const MEDIA_TYPE: &str = "application/vc+jwt";
+pub const REVOCATION: &str = "revocation";
+
+/// Largest list accepted when decoding, against compression bombs.
+const MAX_LIST_BYTES: u64 = 16 * 1024 * 1024;
+const CREDENTIALS_CONTEXT: &str = "https://www.w3.org/ns/credentials/v2";
+
+#[derive(Debug, PartialEq)]
+pub enum StatusListError {
+    InvalidEncoding,
+    IndexOutOfRange(usize),
+    /// The entry points at another list or purpose than the credential's.
+    WrongList,
+}
+
+impl std::fmt::Display for StatusListError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StatusListError::InvalidEncoding => write!(f, "encoded list is not multibase base64url gzip"),
+            StatusListError::IndexOutOfRange(index) => write!(f, "status list index {} is out of range", index),
+            StatusListError::WrongList => write!(f, "status entry is not for this list"),
+        }
+    }
+}
+
+impl std::error::Error for StatusListError {}
+
+/// Uncompressed list; index 0 is the most significant bit of the first
+/// byte.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Bitstring {
+    bytes: Vec<u8>,
+}
+
+impl Bitstring {
+    /// All-zero list of at least `bits` bits.
+    pub fn new(bits: usize) -> Self {
+        Bitstring { bytes: vec![0; bits.div_ceil(8)] }
+    }
+
+    pub fn len(&self) -> usize {
+        self.bytes.len() * 8
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.bytes.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Result<bool, StatusListError> {
+        let byte = self.bytes.get(index / 8).ok_or(StatusListError::IndexOutOfRange(index))?;
+        Ok(byte & (0x80 >> (index % 8)) != 0)
+    }
+
+    pub fn set(&mut self, index: usize, value: bool) -> Result<(), StatusListError> {
+        let byte = self.bytes.get_mut(index / 8).ok_or(StatusListError::IndexOutOfRange(index))?;
+        if value {
+            *byte |= 0x80 >> (index % 8);
+        } else {
+            *byte &= !(0x80 >> (index % 8));
+        }
+        Ok(())
+    }
+
+    /// The `encodedList` form: GZIP, then multibase base64url.
+    pub fn encode(&self) -> String {
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+        encoder.write_all(&self.bytes).expect("writing to a Vec cannot fail");
+        let compressed = encoder.finish().expect("writing to a Vec cannot fail");
+        format!("u{}", BASE64URL.encode(compressed))
+    }
+
+    pub fn decode(encoded: &str) -> Result<Self, StatusListError> {
+        let compressed = encoded
+            .strip_prefix('u')
+            .and_then(|rest| BASE64URL.decode(rest.trim_end_matches('=')).ok())
+            .ok_or(StatusListError::InvalidEncoding)?;
+        let mut bytes = Vec::new();
+        GzDecoder::new(compressed.as_slice())
+            .take(MAX_LIST_BYTES + 1)
+            .read_to_end(&mut bytes)
+            .map_err(|_| StatusListError::InvalidEncoding)?;
+        if bytes.len() as u64 > MAX_LIST_BYTES {
+            return Err(StatusListError::InvalidEncoding);
+        }
+        Ok(Bitstring { bytes })
+    }
+}
+
+/// A `BitstringStatusListCredential`: the claims of the VC-JWT published
+/// for a list.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StatusListCredential {
+    #[serde(rename = "@context")]
+    pub context: Vec<String>,
+    /// The URL the credential is published at.
+    pub id: String,
+    #[serde(rename = "type")]
+    pub types: Vec<String>,
+    pub issuer: String,
+    pub valid_from: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub valid_until: Option<String>,
+    pub credential_subject: StatusListSubject,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StatusListSubject {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub status_purpose: String,
+    pub encoded_list: String,
+    /// How long, in milliseconds, a copy may be relied on before fetching
+    /// the list again.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl: Option<u64>,
+}
+
+/// The `credentialStatus` of a credential or attestation: where its bit is.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct StatusListEntry {
+    pub id: String,
+    #[serde(rename = "type")]
+    pub kind: String,
+    pub status_purpose: String,
+    /// A decimal string, as the specification requires.
+    pub status_list_index: String,
+    pub status_list_credential: String,
+}
+
+impl StatusListEntry {
+    pub fn new(credential_url: &str, status_purpose: &str, index: usize) -> Self {
+        StatusListEntry {
+            id: format!("{}#{}", credential_url, index),
+            kind: "BitstringStatusListEntry".to_string(),
+            status_purpose: status_purpose.to_string(),
+            status_list_index: index.to_string(),
+            status_list_credential: credential_url.to_string(),
+        }
+    }
+}
+
+impl StatusListCredential {
+    /// Credential for `list`, published at `url`. Times are XML Schema
+    /// dateTimes; `ttl_ms` tells verifiers how long to cache it.
+    pub fn new(url: &str, issuer: &str, status_purpose: &str, list: &Bitstring, valid_from: &str, valid_until: Option<&str>, ttl_ms: Option<u64>) -> Self {
+        StatusListCredential {
+            context: vec![CREDENTIALS_CONTEXT.to_string()],
+            id: url.to_string(),
+            types: vec!["VerifiableCredential".to_string(), "BitstringStatusListCredential".to_string()],
+            issuer: issuer.to_string(),
+            valid_from: valid_from.to_string(),
+            valid_until: valid_until.map(str::to_string),
+            credential_subject: StatusListSubject {
+                id: format!("{}#list", url),
+                kind: "BitstringStatusList".to_string(),
+                status_purpose: status_purpose.to_string(),
+                encoded_list: list.encode(),
+                ttl: ttl_ms,
+            },
+        }
+    }
+
+    pub fn list(&self) -> Result<Bitstring, StatusListError> {
+        Bitstring::decode(&self.credential_subject.encoded_list)
+    }
+
+    /// Whether `entry`'s bit is set. The signature over the credential is
+    /// for the caller to have checked.
+    pub fn status(&self, entry: &StatusListEntry) -> Result<bool, StatusListError> {
+        if entry.status_list_credential != self.id || entry.status_purpose != self.credential_subject.status_purpose {
+            return Err(StatusListError::WrongList);
+        }
+        let index = entry.status_list_index.parse().map_err(|_| StatusListError::WrongList)?;
+        self.list()?.get(index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URL: &str = "https://issuer.example/status/1";
+
+    #[test]
+    fn decodes_the_specification_example() {
+        let list = Bitstring::decode("uH4sIAAAAAAAAA-3BMQEAAADCoPVPbQwfoAAAAAAAAAAAAAAAAAAAAIC3AYbSVKsAQAAA").unwrap();
+        assert_eq!(list, Bitstring::new(MIN_LIST_BITS));
+    }
+
+    #[test]
+    fn bits_round_trip_most_significant_first() {
+        let mut list = Bitstring::new(MIN_LIST_BITS);
+        list.set(0, true).unwrap();
+        list.set(94_567, true).unwrap();
+        assert_eq!(list.bytes[0], 0x80);
+        let decoded = Bitstring::decode(&list.encode()).unwrap();
+        assert!(decoded.get(0).unwrap());
+        assert!(decoded.get(94_567).unwrap());
+        assert!(!decoded.get(94_566).unwrap());
+        assert_eq!(decoded.get(MIN_LIST_BITS), Err(StatusListError::IndexOutOfRange(MIN_LIST_BITS)));
+    }
+
+    #[test]
+    fn entries_are_checked_against_their_list() {
+        let mut list = Bitstring::new(MIN_LIST_BITS);
+        list.set(42, true).unwrap();
+        let credential = StatusListCredential::new(URL, "did:web:issuer.example", REVOCATION, &list, "2026-10-19T12:00:00Z", None, Some(300_000));
+        assert_eq!(credential.status(&StatusListEntry::new(URL, REVOCATION, 42)), Ok(true));
+        assert_eq!(credential.status(&StatusListEntry::new(URL, REVOCATION, 43)), Ok(false));
+        assert_eq!(credential.status(&StatusListEntry::new("https://issuer.example/status/2", REVOCATION, 42)), Err(StatusListError::WrongList));
+        assert_eq!(credential.status(&StatusListEntry::new(URL, "suspension", 42)), Err(StatusListError::WrongList));
+        assert_eq!(credential.status(&StatusListEntry::new(URL, REVOCATION, MIN_LIST_BITS)), Err(StatusListError::IndexOutOfRange(MIN_LIST_BITS)));
+    }
+}
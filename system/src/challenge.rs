@@ -0,0 +1,192 @@
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+/// Verifier-issued part of the public inputs, so a proof shown to one
+/// verifier cannot be replayed to another or after it expires.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Challenge {
+    pub challenge: String,
+    pub verifier_id: String,
+    pub expires_at: i64,
+}
+
+/// Public inputs every presentation proof commits to: the identity, the
+/// biometric hash committed on PoPChain, and the verifier's challenge.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PublicInputs {
+    pub human_hash_id: String,
+    pub commitment: String,
+    #[serde(flatten)]
+    pub binding: Challenge,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum BindingError {
+    WrongVerifier,
+    Expired,
+    UnknownChallenge,
+    InvalidProof,
+}
+
+impl std::fmt::Display for BindingError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BindingError::WrongVerifier => write!(f, "wrong_verifier"),
+            BindingError::Expired => write!(f, "expired"),
+            BindingError::UnknownChallenge => write!(f, "unknown_challenge"),
+            BindingError::InvalidProof => write!(f, "invalid_proof"),
+        }
+    }
+}
+
+struct IssuedChallenge {
+    verifier_id: String,
+    expires_at: i64,
+}
+
+/// Single-use challenge nonces handed out to verifiers.
+#[derive(Default)]
+pub struct ChallengeStore {
+    issued: HashMap<String, IssuedChallenge>,
+}
+
+impl ChallengeStore {
+    pub fn issue(&mut self, verifier_id: &str, ttl_secs: i64, now: i64) -> Challenge {
+        self.issued.retain(|_, issued| issued.expires_at > now);
+
+        let mut nonce = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let challenge = hex::encode(nonce);
+        let expires_at = now + ttl_secs;
+        self.issued.insert(challenge.clone(), IssuedChallenge { verifier_id: verifier_id.to_string(), expires_at });
+        Challenge { challenge, verifier_id: verifier_id.to_string(), expires_at }
+    }
+
+    /// Consumes the challenge if `inputs` were issued to `verifier_id` and are
+    /// still live. A proof meant for another verifier leaves the challenge in
+    /// place for its rightful owner.
+    pub fn redeem(&mut self, inputs: &Challenge, verifier_id: &str, now: i64) -> Result<(), BindingError> {
+        if inputs.verifier_id != verifier_id {
+            return Err(BindingError::WrongVerifier);
+        }
+        if inputs.expires_at <= now {
+            self.issued.remove(&inputs.challenge);
+            return Err(BindingError::Expired);
+        }
+        match self.issued.get(&inputs.challenge) {
+            Some(issued) if issued.verifier_id == verifier_id && issued.expires_at == inputs.expires_at => {
+                self.issued.remove(&inputs.challenge);
+                Ok(())
+            }
+            _ => Err(BindingError::UnknownChallenge),
+        }
+    }
+
+    /// Consumes a challenge known only by its nonce, as carried in the
+    /// context of a predicate presentation.
+    pub fn redeem_nonce(&mut self, challenge: &str, verifier_id: &str, now: i64) -> Result<(), BindingError> {
+        let issued = self.issued.get(challenge).ok_or(BindingError::UnknownChallenge)?;
+        if issued.verifier_id != verifier_id {
+            return Err(BindingError::WrongVerifier);
+        }
+        if issued.expires_at <= now {
+            self.issued.remove(challenge);
+            return Err(BindingError::Expired);
+        }
+        self.issued.remove(challenge);
+        Ok(())
+    }
+}
+
+/// Digest a mock proof carries over its public inputs; stands in for the
+/// public-input check of the real circuit.
+pub fn binding_digest(payload: &str, inputs: &PublicInputs) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(b"humanhash-proof-binding");
+    for field in [payload, &inputs.human_hash_id, &inputs.commitment, &inputs.binding.challenge, &inputs.binding.verifier_id] {
+        hasher.update((field.len() as u64).to_be_bytes());
+        hasher.update(field.as_bytes());
+    }
+    hasher.update(inputs.binding.expires_at.to_be_bytes());
+    hex::encode(&hasher.finalize()[..8])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1_800_000_000;
+
+    fn inputs(binding: Challenge) -> PublicInputs {
+        PublicInputs { human_hash_id: "human_1".to_string(), commitment: "biometric-hash".to_string(), binding }
+    }
+
+    #[test]
+    fn challenges_are_redeemed_once() {
+        let mut store = ChallengeStore::default();
+        let challenge = store.issue("rp_a", 300, NOW);
+        assert_eq!(store.redeem(&challenge, "rp_a", NOW + 1), Ok(()));
+        assert_eq!(store.redeem(&challenge, "rp_a", NOW + 2), Err(BindingError::UnknownChallenge));
+
+        let challenge = store.issue("rp_a", 300, NOW);
+        assert_eq!(store.redeem_nonce(&challenge.challenge, "rp_a", NOW + 1), Ok(()));
+        assert_eq!(store.redeem_nonce(&challenge.challenge, "rp_a", NOW + 2), Err(BindingError::UnknownChallenge));
+        assert_eq!(store.redeem(&challenge, "rp_a", NOW + 2), Err(BindingError::UnknownChallenge));
+    }
+
+    #[test]
+    fn challenges_expire() {
+        let mut store = ChallengeStore::default();
+        let challenge = store.issue("rp_a", 300, NOW);
+        assert_eq!(store.redeem(&challenge, "rp_a", NOW + 300), Err(BindingError::Expired));
+        // Expiry consumes it, so a clock moving backwards does not revive it.
+        assert_eq!(store.redeem(&challenge, "rp_a", NOW), Err(BindingError::UnknownChallenge));
+
+        let challenge = store.issue("rp_a", 300, NOW);
+        assert_eq!(store.redeem_nonce(&challenge.challenge, "rp_a", NOW + 301), Err(BindingError::Expired));
+
+        // Claiming a later expiry than the one issued does not extend it.
+        let challenge = store.issue("rp_a", 300, NOW);
+        let extended = Challenge { expires_at: NOW + 3600, ..challenge };
+        assert_eq!(store.redeem(&extended, "rp_a", NOW + 400), Err(BindingError::UnknownChallenge));
+    }
+
+    #[test]
+    fn challenges_belong_to_their_verifier() {
+        let mut store = ChallengeStore::default();
+        let challenge = store.issue("rp_a", 300, NOW);
+        assert_eq!(store.redeem(&challenge, "rp_b", NOW + 1), Err(BindingError::WrongVerifier));
+        let relabelled = Challenge { verifier_id: "rp_b".to_string(), ..challenge.clone() };
+        assert_eq!(store.redeem(&relabelled, "rp_b", NOW + 1), Err(BindingError::UnknownChallenge));
+        assert_eq!(store.redeem_nonce(&challenge.challenge, "rp_b", NOW + 1), Err(BindingError::WrongVerifier));
+        // Attempts by another verifier leave it for the one it was issued to.
+        assert_eq!(store.redeem(&challenge, "rp_a", NOW + 1), Ok(()));
+    }
+
+    #[test]
+    fn binding_digest_covers_every_public_input() {
+        let mut store = ChallengeStore::default();
+        let original = inputs(store.issue("rp_a", 300, NOW));
+        let digest = binding_digest("payload", &original);
+        assert_eq!(digest, binding_digest("payload", &original.clone()));
+        assert_ne!(digest, binding_digest("other-payload", &original));
+
+        let mut tampered: Vec<PublicInputs> = vec![original.clone(); 5];
+        tampered[0].human_hash_id = "human_2".to_string();
+        tampered[1].commitment = "other-hash".to_string();
+        tampered[2].binding.challenge = store.issue("rp_a", 300, NOW).challenge;
+        tampered[3].binding.verifier_id = "rp_b".to_string();
+        tampered[4].binding.expires_at += 1;
+        for inputs in &tampered {
+            assert_ne!(binding_digest("payload", inputs), digest);
+        }
+
+        // Fields are length-prefixed, so moving a boundary changes the digest.
+        let mut shifted = original.clone();
+        shifted.human_hash_id = "human_1b".to_string();
+        shifted.commitment = "iometric-hash".to_string();
+        assert_ne!(binding_digest("payload", &shifted), digest);
+    }
+}
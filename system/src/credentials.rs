@@ -0,0 +1,488 @@
+//! W3C Verifiable Credentials for completed enrollments, secured as VC-JWTs
+//! (VC Data Model 2.0 with JOSE, ES256). The issuer is a `did:web` DID whose
+//! document this service publishes, and every credential carries a
+//! `BitstringStatusListEntry` pointing into the issuer's revocation list,
+//! in the status list format shared with PoPChain (the `status-list` crate).
+//!
+//! A list holds [`STATUS_LIST_SIZE`] credentials; once every slot of the
+//! lists so far is taken, the next credential opens a new one.
+//!
+//! Third parties verify offline: with a cached DID document and a cached
+//! status list credential, [`verify`] needs no call back to the issuer.
+
+use p256::ecdsa::SigningKey;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use status_list::{Bitstring, StatusListCredential, StatusListEntry, MIN_LIST_BITS, REVOCATION};
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+use crate::jws::{self, JwsError};
+
+/// Environment variable holding the hex-encoded P-256 issuer key.
+pub const SIGNING_KEY_ENV: &str = "VC_SIGNING_KEY";
+
+/// Entries per status list: 16KB uncompressed, the minimum the Bitstring
+/// Status List spec recommends for herd privacy.
+pub const STATUS_LIST_SIZE: usize = MIN_LIST_BITS;
+
+const CREDENTIALS_CONTEXT: &str = "https://www.w3.org/ns/credentials/v2";
+
+#[derive(Clone, Deserialize)]
+pub struct CredentialConfig {
+    /// Origin the issuer is reachable at; the `did:web` DID is derived from it.
+    pub base_url: String,
+    pub store_path: String,
+    pub validity_days: i64,
+    pub status_list_ttl_secs: i64,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum CredentialError {
+    Malformed,
+    UnknownKey,
+    Signature(JwsError),
+    NotYetValid,
+    Expired,
+    Revoked,
+    InvalidStatusList,
+}
+
+impl std::fmt::Display for CredentialError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CredentialError::Malformed => write!(f, "malformed_credential"),
+            CredentialError::UnknownKey => write!(f, "unknown_key"),
+            CredentialError::Signature(e) => write!(f, "invalid_signature: {}", e),
+            CredentialError::NotYetValid => write!(f, "not_yet_valid"),
+            CredentialError::Expired => write!(f, "expired"),
+            CredentialError::Revoked => write!(f, "revoked"),
+            CredentialError::InvalidStatusList => write!(f, "invalid_status_list"),
+        }
+    }
+}
+
+impl std::error::Error for CredentialError {}
+
+/// Issuer-side record of a credential; the credential itself is only handed
+/// to the holder.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct IssuedCredential {
+    pub id: String,
+    pub human_hash_id: String,
+    pub enrollment: String,
+    pub tier: String,
+    /// Number of the status list the credential's bit is in, from 1.
+    #[serde(default = "first_list")]
+    pub status_list: usize,
+    pub status_index: usize,
+    pub issued_at: i64,
+    pub expires_at: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub revoked_at: Option<i64>,
+}
+
+fn first_list() -> usize {
+    1
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct CredentialRecords {
+    credentials: HashMap<String, IssuedCredential>,
+}
+
+/// One status list: which slots are taken and which are revoked.
+struct StatusListSlots {
+    assigned: Bitstring,
+    assigned_count: usize,
+    revoked: Bitstring,
+}
+
+impl StatusListSlots {
+    fn new() -> Self {
+        StatusListSlots { assigned: Bitstring::new(STATUS_LIST_SIZE), assigned_count: 0, revoked: Bitstring::new(STATUS_LIST_SIZE) }
+    }
+
+    fn is_assigned(&self, index: usize) -> bool {
+        self.assigned.get(index).unwrap_or(true)
+    }
+}
+
+/// The records as persisted, plus the status lists rebuilt from them.
+struct Registry {
+    records: CredentialRecords,
+    lists: Vec<StatusListSlots>,
+}
+
+impl Registry {
+    fn new(records: CredentialRecords) -> io::Result<Self> {
+        let mut registry = Registry { records, lists: Vec::new() };
+        let slots: Vec<(usize, usize, bool)> = registry.records.credentials.values().map(|c| (c.status_list, c.status_index, c.revoked_at.is_some())).collect();
+        for (list, index, revoked) in slots {
+            registry.assign(list, index)?;
+            if revoked {
+                registry.set_revoked(list, index);
+            }
+        }
+        Ok(registry)
+    }
+
+    fn slots(&mut self, list: usize) -> &mut StatusListSlots {
+        while self.lists.len() < list {
+            self.lists.push(StatusListSlots::new());
+        }
+        &mut self.lists[list - 1]
+    }
+
+    fn assign(&mut self, list: usize, index: usize) -> io::Result<()> {
+        if list == 0 || index >= STATUS_LIST_SIZE {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("status list {} index {} is out of range", list, index)));
+        }
+        let slots = self.slots(list);
+        if slots.is_assigned(index) {
+            return Err(io::Error::new(io::ErrorKind::InvalidData, format!("status list {} index {} is assigned twice", list, index)));
+        }
+        slots.assigned.set(index, true).expect("index checked above");
+        slots.assigned_count += 1;
+        Ok(())
+    }
+
+    fn set_revoked(&mut self, list: usize, index: usize) {
+        self.slots(list).revoked.set(index, true).expect("assigned indexes are within the list");
+    }
+
+    /// A free slot at a random index of the first list with room, so a
+    /// list position says nothing about when a credential was issued.
+    fn allocate(&mut self) -> (usize, usize) {
+        let list = self.lists.iter().position(|slots| slots.assigned_count < STATUS_LIST_SIZE).unwrap_or(self.lists.len()) + 1;
+        let slots = self.slots(list);
+        let mut rng = rand::thread_rng();
+        let index = (0..64)
+            .map(|_| rng.gen_range(0..STATUS_LIST_SIZE))
+            .find(|index| !slots.is_assigned(*index))
+            .or_else(|| (0..STATUS_LIST_SIZE).find(|index| !slots.is_assigned(*index)))
+            .expect("list has room");
+        slots.assigned.set(index, true).expect("index is within the list");
+        slots.assigned_count += 1;
+        (list, index)
+    }
+}
+
+pub struct CredentialIssuer {
+    signing_key: SigningKey,
+    config: CredentialConfig,
+    pub did: String,
+    pub key_id: String,
+    path: PathBuf,
+    registry: Mutex<Registry>,
+}
+
+impl CredentialIssuer {
+    pub fn open(config: CredentialConfig, signing_key: SigningKey) -> io::Result<Self> {
+        let path = PathBuf::from(&config.store_path);
+        let records = match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => CredentialRecords::default(),
+            Err(e) => return Err(e),
+        };
+        let did = did_web(&config.base_url).ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "base_url must be an http(s) origin"))?;
+        let key_id = format!("{}#{}", did, jws::key_id(signing_key.verifying_key()));
+        Ok(CredentialIssuer { signing_key, config, did, key_id, path, registry: Mutex::new(Registry::new(records)?) })
+    }
+
+    /// Credential ids are URLs under the issuer's origin.
+    pub fn credential_url(&self, id: &str) -> String {
+        format!("{}/credentials/{}", self.config.base_url.trim_end_matches('/'), id)
+    }
+
+    pub fn status_list_url(&self, list: usize) -> String {
+        format!("{}{}", self.status_list_base(), list)
+    }
+
+    fn status_list_base(&self) -> String {
+        format!("{}/credentials/status/", self.config.base_url.trim_end_matches('/'))
+    }
+
+    pub fn status_list_ttl_secs(&self) -> i64 {
+        self.config.status_list_ttl_secs
+    }
+
+    /// Issues a proof-of-personhood credential for `human_hash_id` and
+    /// records it, returning the VC-JWT.
+    pub fn issue(&self, human_hash_id: &str, enrollment: &str, tier: &str, now: i64) -> io::Result<String> {
+        let mut registry = self.registry.lock().unwrap();
+        let (status_list, status_index) = registry.allocate();
+        let record = IssuedCredential {
+            id: self.credential_url(&hex::encode(rand::thread_rng().gen::<[u8; 16]>())),
+            human_hash_id: human_hash_id.to_string(),
+            enrollment: enrollment.to_string(),
+            tier: tier.to_string(),
+            status_list,
+            status_index,
+            issued_at: now,
+            expires_at: now + self.config.validity_days * 86_400,
+            revoked_at: None,
+        };
+        let credential = json!({
+            "@context": [CREDENTIALS_CONTEXT],
+            "id": record.id,
+            "type": ["VerifiableCredential", "ProofOfPersonhoodCredential"],
+            "issuer": self.did,
+            "validFrom": date_time(record.issued_at),
+            "validUntil": date_time(record.expires_at),
+            "credentialSubject": {
+                "id": format!("urn:humanhash:{}", record.human_hash_id),
+                "proofOfPersonhood": true,
+                "tier": record.tier,
+            },
+            "credentialStatus": StatusListEntry::new(&self.status_list_url(status_list), REVOCATION, status_index),
+        });
+        registry.records.credentials.insert(record.id.clone(), record);
+        self.save(&registry.records)?;
+        Ok(jws::sign(&self.signing_key, &self.key_id, status_list::JWT_TYPE, &credential))
+    }
+
+    /// Flips the credential's status bit. Revoking twice keeps the first
+    /// revocation time.
+    pub fn revoke(&self, id: &str, now: i64) -> io::Result<Option<IssuedCredential>> {
+        let mut registry = self.registry.lock().unwrap();
+        let Some(record) = registry.records.credentials.get_mut(id) else {
+            return Ok(None);
+        };
+        record.revoked_at.get_or_insert(now);
+        let record = record.clone();
+        registry.set_revoked(record.status_list, record.status_index);
+        self.save(&registry.records)?;
+        Ok(Some(record))
+    }
+
+    /// The signed `BitstringStatusListCredential` for list number `list`,
+    /// valid for the configured TTL so verifiers know how long a cached copy
+    /// may be used. `None` for a list not opened yet.
+    pub fn status_list_credential(&self, list: usize, now: i64) -> Option<String> {
+        let bits = {
+            let registry = self.registry.lock().unwrap();
+            registry.lists.get(list.checked_sub(1)?)?.revoked.clone()
+        };
+        let ttl = self.config.status_list_ttl_secs;
+        let credential = StatusListCredential::new(
+            &self.status_list_url(list),
+            &self.did,
+            REVOCATION,
+            &bits,
+            &date_time(now),
+            Some(&date_time(now + ttl)),
+            Some(ttl.max(0) as u64 * 1000),
+        );
+        Some(jws::sign(&self.signing_key, &self.key_id, status_list::JWT_TYPE, &credential))
+    }
+
+    /// Checks one of this issuer's credentials exactly as an offline
+    /// verifier would, against the current DID document and the status list
+    /// the credential points into.
+    pub fn check(&self, credential: &str, now: i64) -> Result<Value, CredentialError> {
+        let did_document = self.did_document();
+        let claims = verify(credential, &did_document, None, now)?;
+        let list = claims["credentialStatus"]["statusListCredential"]
+            .as_str()
+            .and_then(|url| url.strip_prefix(&self.status_list_base()))
+            .and_then(|list| list.parse().ok())
+            .and_then(|list| self.status_list_credential(list, now))
+            .ok_or(CredentialError::InvalidStatusList)?;
+        verify(credential, &did_document, Some(&list), now)
+    }
+
+    /// The DID document served at `/.well-known/did.json`.
+    pub fn did_document(&self) -> Value {
+        json!({
+            "@context": ["https://www.w3.org/ns/did/v1", "https://w3id.org/security/suites/jws-2020/v1"],
+            "id": self.did,
+            "verificationMethod": [{
+                "id": self.key_id,
+                "type": "JsonWebKey2020",
+                "controller": self.did,
+                "publicKeyJwk": jws::public_jwk(self.signing_key.verifying_key()),
+            }],
+            "assertionMethod": [self.key_id],
+        })
+    }
+
+    fn save(&self, records: &CredentialRecords) -> io::Result<()> {
+        let contents = serde_json::to_string_pretty(records).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, contents)?;
+        fs::rename(&tmp, &self.path)
+    }
+}
+
+/// Verifies a VC-JWT against the issuer's DID document and, when given, a
+/// status list credential, all of which a verifier may have cached. Returns
+/// the credential on success.
+pub fn verify(credential: &str, did_document: &Value, status_list: Option<&str>, now: i64) -> Result<Value, CredentialError> {
+    let credential = verify_signed(credential, did_document)?;
+    check_validity(&credential, now)?;
+
+    let Some(status_list) = status_list else {
+        return Ok(credential);
+    };
+    let entry: StatusListEntry = serde_json::from_value(credential["credentialStatus"].clone()).map_err(|_| CredentialError::Malformed)?;
+    let list = verify_signed(status_list, did_document).map_err(|_| CredentialError::InvalidStatusList)?;
+    check_validity(&list, now).map_err(|_| CredentialError::InvalidStatusList)?;
+    let list: StatusListCredential = serde_json::from_value(list).map_err(|_| CredentialError::InvalidStatusList)?;
+    if entry.status_purpose != REVOCATION {
+        return Err(CredentialError::InvalidStatusList);
+    }
+    match list.status(&entry) {
+        Ok(true) => Err(CredentialError::Revoked),
+        Ok(false) => Ok(credential),
+        Err(_) => Err(CredentialError::InvalidStatusList),
+    }
+}
+
+fn verify_signed(token: &str, did_document: &Value) -> Result<Value, CredentialError> {
+    let header = jws::header(token).map_err(|_| CredentialError::Malformed)?;
+    let kid = header["kid"].as_str().ok_or(CredentialError::UnknownKey)?;
+    let key = did_document["verificationMethod"]
+        .as_array()
+        .into_iter()
+        .flatten()
+        .find(|method| method["id"] == kid)
+        .and_then(|method| jws::key_from_jwk(&method["publicKeyJwk"]))
+        .ok_or(CredentialError::UnknownKey)?;
+    let credential: Value = jws::verify(token, &key).map_err(CredentialError::Signature)?;
+    if credential["issuer"] != did_document["id"] {
+        return Err(CredentialError::UnknownKey);
+    }
+    Ok(credential)
+}
+
+fn check_validity(credential: &Value, now: i64) -> Result<(), CredentialError> {
+    let timestamp = |field: &str| {
+        credential[field]
+            .as_str()
+            .and_then(|value| chrono::DateTime::parse_from_rfc3339(value).ok())
+            .map(|value| value.timestamp())
+    };
+    if timestamp("validFrom").is_none_or(|from| from > now) {
+        return Err(CredentialError::NotYetValid);
+    }
+    if timestamp("validUntil").is_some_and(|until| until <= now) {
+        return Err(CredentialError::Expired);
+    }
+    Ok(())
+}
+
+/// `did:web` for an origin: the host, with a port percent-encoded.
+fn did_web(base_url: &str) -> Option<String> {
+    let url = reqwest::Url::parse(base_url).ok()?;
+    let host = url.host_str()?;
+    Some(match url.port() {
+        Some(port) => format!("did:web:{}%3A{}", host, port),
+        None => format!("did:web:{}", host),
+    })
+}
+
+fn date_time(timestamp: i64) -> String {
+    chrono::DateTime::from_timestamp(timestamp, 0).unwrap_or_default().format("%Y-%m-%dT%H:%M:%SZ").to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1_700_000_000;
+
+    fn issuer(name: &str) -> CredentialIssuer {
+        let path = std::env::temp_dir().join(format!("credentials-{}-{}.json", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        reopen(&path)
+    }
+
+    fn reopen(path: &std::path::Path) -> CredentialIssuer {
+        let config = CredentialConfig {
+            base_url: "https://issuer.example".to_string(),
+            store_path: path.to_string_lossy().into_owned(),
+            validity_days: 365,
+            status_list_ttl_secs: 300,
+        };
+        CredentialIssuer::open(config, SigningKey::from_slice(&[7; 32]).unwrap()).unwrap()
+    }
+
+    fn credential_id(issuer: &CredentialIssuer, credential: &str) -> String {
+        issuer.check(credential, NOW).unwrap()["id"].as_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn issued_credentials_verify_until_revoked() {
+        let issuer = issuer("revoke");
+        let credential = issuer.issue("hh_1", "ENR-1", "kyc", NOW).unwrap();
+        let other = issuer.issue("hh_2", "ENR-2", "kyc", NOW).unwrap();
+        let claims = issuer.check(&credential, NOW).unwrap();
+        assert_eq!(claims["credentialSubject"]["id"], "urn:humanhash:hh_1");
+        assert_eq!(claims["credentialStatus"]["statusListCredential"], "https://issuer.example/credentials/status/1");
+
+        let revoked = issuer.revoke(&credential_id(&issuer, &credential), NOW + 10).unwrap().unwrap();
+        assert_eq!(revoked.revoked_at, Some(NOW + 10));
+        assert_eq!(issuer.check(&credential, NOW + 20), Err(CredentialError::Revoked));
+        assert!(issuer.check(&other, NOW + 20).is_ok());
+        // A second revocation keeps the first time.
+        assert_eq!(issuer.revoke(&revoked.id, NOW + 30).unwrap().unwrap().revoked_at, Some(NOW + 10));
+        assert!(issuer.revoke("https://issuer.example/credentials/unknown", NOW).unwrap().is_none());
+    }
+
+    #[test]
+    fn verification_checks_signature_validity_and_list() {
+        let issuer = issuer("verify");
+        let credential = issuer.issue("hh_1", "ENR-1", "kyc", NOW).unwrap();
+        let did_document = issuer.did_document();
+        let list = issuer.status_list_credential(1, NOW).unwrap();
+        assert!(verify(&credential, &did_document, Some(&list), NOW).is_ok());
+
+        assert_eq!(verify(&credential, &did_document, Some(&list), NOW - 1), Err(CredentialError::NotYetValid));
+        assert_eq!(verify(&credential, &did_document, Some(&list), NOW + 365 * 86_400), Err(CredentialError::Expired));
+        // A cached list past its validUntil is not trusted.
+        assert_eq!(verify(&credential, &did_document, Some(&list), NOW + 301), Err(CredentialError::InvalidStatusList));
+
+        let (header, rest) = credential.split_once('.').unwrap();
+        let (_, signature) = rest.split_once('.').unwrap();
+        let forged_claims = jws::sign(&SigningKey::from_slice(&[8; 32]).unwrap(), &issuer.key_id, status_list::JWT_TYPE, &json!({ "issuer": issuer.did }));
+        let forged_payload = forged_claims.split('.').nth(1).unwrap();
+        let tampered = format!("{}.{}.{}", header, forged_payload, signature);
+        assert!(matches!(verify(&tampered, &did_document, None, NOW), Err(CredentialError::Signature(_))));
+
+        assert!(issuer.status_list_credential(0, NOW).is_none());
+        assert!(issuer.status_list_credential(2, NOW).is_none());
+    }
+
+    #[test]
+    fn a_full_list_opens_the_next() {
+        let issuer = issuer("lists");
+        let first = issuer.issue("hh_1", "ENR-1", "kyc", NOW).unwrap();
+        {
+            let mut registry = issuer.registry.lock().unwrap();
+            let slots = &mut registry.lists[0];
+            for index in 0..STATUS_LIST_SIZE {
+                slots.assigned.set(index, true).unwrap();
+            }
+            slots.assigned_count = STATUS_LIST_SIZE;
+        }
+        let second = issuer.issue("hh_2", "ENR-2", "kyc", NOW).unwrap();
+        let claims = issuer.check(&second, NOW).unwrap();
+        assert_eq!(claims["credentialStatus"]["statusListCredential"], "https://issuer.example/credentials/status/2");
+
+        issuer.revoke(&credential_id(&issuer, &second), NOW).unwrap();
+        assert_eq!(issuer.check(&second, NOW), Err(CredentialError::Revoked));
+        assert!(issuer.check(&first, NOW).is_ok());
+
+        // Slots and revocations are rebuilt from the records on restart.
+        let reopened = reopen(&issuer.path);
+        assert_eq!(reopened.check(&second, NOW), Err(CredentialError::Revoked));
+        assert!(reopened.check(&first, NOW).is_ok());
+        let registry = reopened.registry.lock().unwrap();
+        assert_eq!((registry.lists.len(), registry.lists[0].assigned_count, registry.lists[1].assigned_count), (2, 1, 1));
+    }
+}
@@ -0,0 +1,335 @@
+use sequence_code::{Action, SequenceCode};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeSet, HashMap};
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use tracing::error;
+
+use crate::webhooks::{self, EventType, WebhookStore};
+
+/// Environment variable holding the bearer token other services present to
+/// report the sequence codes they issue.
+pub const INGEST_TOKEN_ENV: &str = "EVENT_INGEST_TOKEN";
+
+pub const DEFAULT_PAGE_SIZE: usize = 100;
+pub const MAX_PAGE_SIZE: usize = 1000;
+
+/// An issued sequence code, as reported by the service that generated it.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct NewEvent {
+    pub sequence_code: String,
+    pub action: String,
+    pub service: String,
+    /// Relying party the event was made for, so reports and billing can be
+    /// split per tenant.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub human_hash_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verifier_id: Option<String>,
+    /// Outcome of a verification; unset for other actions.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verified: Option<bool>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct EventRecord {
+    pub id: u64,
+    pub recorded_at: i64,
+    #[serde(flatten)]
+    pub event: NewEvent,
+}
+
+#[derive(Default)]
+pub struct EventFilter {
+    pub action: Option<Action>,
+    pub from: Option<i64>,
+    pub to: Option<i64>,
+    pub tenant_id: Option<String>,
+    pub human_hash_id: Option<String>,
+    pub verifier_id: Option<String>,
+}
+
+impl EventFilter {
+    fn matches(&self, record: &EventRecord) -> bool {
+        self.action.is_none_or(|action| record.event.action == action.as_str())
+            && self.from.is_none_or(|from| record.recorded_at >= from)
+            && self.to.is_none_or(|to| record.recorded_at < to)
+            && self.tenant_id.as_ref().is_none_or(|id| record.event.tenant_id.as_ref() == Some(id))
+            && self.human_hash_id.as_ref().is_none_or(|id| record.event.human_hash_id.as_ref() == Some(id))
+            && self.verifier_id.as_ref().is_none_or(|id| record.event.verifier_id.as_ref() == Some(id))
+    }
+}
+
+pub struct EventPage<'a> {
+    pub events: Vec<&'a EventRecord>,
+    /// Pass back as `cursor` to fetch the next page; `None` on the last page.
+    pub next_cursor: Option<String>,
+}
+
+/// Append-only event log: one JSON record per line, loaded into memory on
+/// startup and appended to on every event. Each sequence code is recorded
+/// at most once.
+pub struct EventStore {
+    file: File,
+    records: Vec<EventRecord>,
+    /// Index into `records` by sequence code.
+    codes: HashMap<String, usize>,
+    /// Verifiers that successfully verified each human_hash_id.
+    verifiers: HashMap<String, BTreeSet<String>>,
+}
+
+impl EventStore {
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let mut records = Vec::new();
+        if path.exists() {
+            for line in BufReader::new(fs::File::open(path)?).lines() {
+                let line = line?;
+                if line.trim().is_empty() {
+                    continue;
+                }
+                records.push(serde_json::from_str(&line).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?);
+            }
+        }
+        let file = OpenOptions::new().create(true).append(true).open(path)?;
+        let mut store = EventStore { file, records: Vec::new(), codes: HashMap::new(), verifiers: HashMap::new() };
+        for record in records {
+            store.index(record);
+        }
+        Ok(store)
+    }
+
+    fn index(&mut self, record: EventRecord) {
+        if let (Some(true), Some(human_hash_id), Some(verifier_id)) = (record.event.verified, &record.event.human_hash_id, &record.event.verifier_id) {
+            self.verifiers.entry(human_hash_id.clone()).or_default().insert(verifier_id.clone());
+        }
+        self.codes.insert(record.event.sequence_code.clone(), self.records.len());
+        self.records.push(record);
+    }
+
+    /// Fails with `AlreadyExists` for a sequence code already recorded.
+    pub fn append(&mut self, event: NewEvent, now: i64) -> io::Result<EventRecord> {
+        if self.codes.contains_key(&event.sequence_code) {
+            return Err(io::Error::new(io::ErrorKind::AlreadyExists, format!("{} is already recorded", event.sequence_code)));
+        }
+        let record = EventRecord {
+            id: self.records.last().map_or(1, |last| last.id + 1),
+            recorded_at: now,
+            event,
+        };
+        let mut line = serde_json::to_vec(&record).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        line.push(b'\n');
+        self.file.write_all(&line)?;
+        self.file.flush()?;
+        self.index(record.clone());
+        Ok(record)
+    }
+
+    pub fn get(&self, sequence_code: &str) -> Option<&EventRecord> {
+        self.codes.get(sequence_code).map(|&index| &self.records[index])
+    }
+
+    /// Events matching `filter` with an id after `cursor`, oldest first.
+    pub fn query(&self, filter: &EventFilter, cursor: Option<u64>, limit: usize) -> EventPage<'_> {
+        let start = cursor.map_or(0, |cursor| self.records.partition_point(|record| record.id <= cursor));
+        let mut matching = self.records[start..].iter().filter(|record| filter.matches(record));
+        let events: Vec<&EventRecord> = matching.by_ref().take(limit).collect();
+        let next_cursor = match (events.last(), matching.next()) {
+            (Some(last), Some(_)) => Some(last.id.to_string()),
+            _ => None,
+        };
+        EventPage { events, next_cursor }
+    }
+
+    /// Verifiers that successfully verified `human_hash_id`.
+    pub fn verifiers_of(&self, human_hash_id: &str) -> Vec<String> {
+        self.verifiers.get(human_hash_id).map(|verifiers| verifiers.iter().cloned().collect()).unwrap_or_default()
+    }
+}
+
+/// Issues sequence codes and records events. Everything recorded here also
+/// fans out to webhook subscribers.
+#[derive(Clone)]
+pub struct Recorder {
+    pub store: Arc<Mutex<EventStore>>,
+    pub webhooks: Arc<Mutex<WebhookStore>>,
+    secret: Arc<[u8]>,
+}
+
+impl Recorder {
+    pub fn new(store: EventStore, webhooks: Arc<Mutex<WebhookStore>>, secret: Arc<[u8]>) -> Self {
+        Recorder { store: Arc::new(Mutex::new(store)), webhooks, secret }
+    }
+
+    pub fn secret(&self) -> &[u8] {
+        &self.secret
+    }
+
+    /// Generates a sequence code for `action` and records it. A verification
+    /// of a known identity gets a code bound to it, since PoPChain renews
+    /// that identity's attestation on the strength of it.
+    pub fn issue(&self, action: Action, tenant_id: Option<&str>, human_hash_id: Option<&str>, verifier_id: Option<&str>, verified: Option<bool>) -> String {
+        let sequence_code = match (action, human_hash_id) {
+            (Action::Verify, Some(human_hash_id)) => SequenceCode::issue_for(action, human_hash_id, &self.secret),
+            _ => SequenceCode::issue(action, &self.secret),
+        }
+        .to_string();
+        let event = NewEvent {
+            sequence_code: sequence_code.clone(),
+            action: action.to_string(),
+            service: "system".to_string(),
+            tenant_id: tenant_id.map(str::to_string),
+            human_hash_id: human_hash_id.map(str::to_string),
+            verifier_id: verifier_id.map(str::to_string),
+            verified,
+        };
+        if let Err(e) = self.record(event) {
+            error!("Failed to record {} in the event store: {}", sequence_code, e);
+        }
+        sequence_code
+    }
+
+    pub fn record(&self, event: NewEvent) -> io::Result<EventRecord> {
+        let now = chrono::Utc::now();
+        let (record, recipients) = {
+            let mut store = self.store.lock().unwrap();
+            let record = store.append(event, now.timestamp())?;
+            let payload = webhooks::payload_for(&record);
+            // A relying party hears about its own verifications, and about
+            // revocations and updates of identities it has verified.
+            let recipients = match payload.as_ref().map(|payload| payload.event_type) {
+                Some(EventType::Verified) => record.event.verifier_id.iter().cloned().collect(),
+                Some(_) => record.event.human_hash_id.as_deref().map(|id| store.verifiers_of(id)).unwrap_or_default(),
+                None => Vec::new(),
+            };
+            (record, payload.map(|payload| (payload, recipients)))
+        };
+        if let Some((payload, recipients)) = recipients {
+            self.webhooks.lock().unwrap().enqueue(&payload, &recipients, now.timestamp_millis())?;
+        }
+        Ok(record)
+    }
+}
+
+pub fn to_csv(events: &[&EventRecord]) -> String {
+    let mut csv = String::from("id,recorded_at,action,sequence_code,service,tenant_id,human_hash_id,verifier_id,verified\n");
+    for record in events {
+        let fields = [
+            record.id.to_string(),
+            record.recorded_at.to_string(),
+            record.event.action.clone(),
+            record.event.sequence_code.clone(),
+            record.event.service.clone(),
+            record.event.tenant_id.clone().unwrap_or_default(),
+            record.event.human_hash_id.clone().unwrap_or_default(),
+            record.event.verifier_id.clone().unwrap_or_default(),
+            record.event.verified.map(|verified| verified.to_string()).unwrap_or_default(),
+        ];
+        let row: Vec<String> = fields.iter().map(|field| csv_field(field)).collect();
+        csv.push_str(&row.join(","));
+        csv.push('\n');
+    }
+    csv
+}
+
+/// Quotes `value` where needed. Values a spreadsheet would read as a
+/// formula get a leading `'`, since reported ids come from callers.
+fn csv_field(value: &str) -> String {
+    let value = if value.starts_with(['=', '+', '-', '@', '\t', '\r']) { format!("'{}", value) } else { value.to_string() };
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store(name: &str) -> EventStore {
+        let path = std::env::temp_dir().join(format!("events-{}-{}.jsonl", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        EventStore::open(path).unwrap()
+    }
+
+    fn event(sequence_code: &str, action: Action, tenant_id: &str, human_hash_id: &str, verifier_id: Option<&str>, verified: Option<bool>) -> NewEvent {
+        NewEvent {
+            sequence_code: sequence_code.to_string(),
+            action: action.to_string(),
+            service: "system".to_string(),
+            tenant_id: Some(tenant_id.to_string()),
+            human_hash_id: Some(human_hash_id.to_string()),
+            verifier_id: verifier_id.map(str::to_string),
+            verified,
+        }
+    }
+
+    fn codes<'a>(page: &'a EventPage<'_>) -> Vec<&'a str> {
+        page.events.iter().map(|record| record.event.sequence_code.as_str()).collect()
+    }
+
+    #[test]
+    fn queries_filter_and_page_through_events() {
+        let mut store = store("query");
+        store.append(event("c1", Action::Enroll, "rp_a", "hh_1", None, None), 100).unwrap();
+        store.append(event("c2", Action::Verify, "rp_a", "hh_1", Some("rp_a"), Some(true)), 110).unwrap();
+        store.append(event("c3", Action::Verify, "rp_b", "hh_2", Some("rp_b"), Some(false)), 120).unwrap();
+        store.append(event("c4", Action::Verify, "rp_a", "hh_2", Some("rp_a"), Some(true)), 130).unwrap();
+        store.append(event("c5", Action::Revoke, "rp_a", "hh_1", None, None), 140).unwrap();
+
+        let verifications = EventFilter { action: Some(Action::Verify), ..Default::default() };
+        assert_eq!(codes(&store.query(&verifications, None, 10)), ["c2", "c3", "c4"]);
+        let window = EventFilter { from: Some(110), to: Some(140), tenant_id: Some("rp_a".to_string()), ..Default::default() };
+        assert_eq!(codes(&store.query(&window, None, 10)), ["c2", "c4"]);
+        let identity = EventFilter { human_hash_id: Some("hh_1".to_string()), ..Default::default() };
+        assert_eq!(codes(&store.query(&identity, None, 10)), ["c1", "c2", "c5"]);
+
+        let everything = EventFilter::default();
+        let first = store.query(&everything, None, 2);
+        assert_eq!(codes(&first), ["c1", "c2"]);
+        let cursor = first.next_cursor.unwrap().parse().unwrap();
+        let second = store.query(&everything, Some(cursor), 2);
+        assert_eq!(codes(&second), ["c3", "c4"]);
+        let cursor = second.next_cursor.unwrap().parse().unwrap();
+        let last = store.query(&everything, Some(cursor), 2);
+        assert_eq!(codes(&last), ["c5"]);
+        assert!(last.next_cursor.is_none());
+        // A page that exactly exhausts the matches has no next cursor.
+        assert!(store.query(&verifications, None, 3).next_cursor.is_none());
+    }
+
+    #[test]
+    fn sequence_codes_are_recorded_once_and_verifiers_survive_a_reload() {
+        let path = std::env::temp_dir().join(format!("events-reload-{}.jsonl", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let mut store = EventStore::open(&path).unwrap();
+        store.append(event("c1", Action::Verify, "rp_b", "hh_1", Some("rp_b"), Some(true)), 100).unwrap();
+        store.append(event("c2", Action::Verify, "rp_a", "hh_1", Some("rp_a"), Some(true)), 110).unwrap();
+        store.append(event("c3", Action::Verify, "rp_c", "hh_1", Some("rp_c"), Some(false)), 120).unwrap();
+        store.append(event("c4", Action::Verify, "rp_a", "hh_1", Some("rp_a"), Some(true)), 130).unwrap();
+        let duplicate = store.append(event("c4", Action::Verify, "rp_a", "hh_1", Some("rp_a"), Some(true)), 140);
+        assert_eq!(duplicate.err().unwrap().kind(), io::ErrorKind::AlreadyExists);
+        assert_eq!(store.verifiers_of("hh_1"), ["rp_a", "rp_b"]);
+        assert!(store.verifiers_of("hh_2").is_empty());
+
+        let reloaded = EventStore::open(&path).unwrap();
+        assert_eq!(reloaded.verifiers_of("hh_1"), ["rp_a", "rp_b"]);
+        assert_eq!(reloaded.get("c3").unwrap().id, 3);
+    }
+
+    #[test]
+    fn csv_neutralises_formulas() {
+        let mut store = store("csv");
+        let mut record = event("=HYPERLINK(\"http://x\")", Action::Verify, "+rp", "-1", Some("@SUM(A1)"), Some(true));
+        record.service = "plain,value".to_string();
+        let record = store.append(record, 100).unwrap();
+        let csv = to_csv(&[&record]);
+        let row = csv.lines().nth(1).unwrap();
+        assert_eq!(row, "1,100,VER,\"'=HYPERLINK(\"\"http://x\"\")\",\"plain,value\",'+rp,'-1,'@SUM(A1),true");
+    }
+}
@@ -0,0 +1,163 @@
+//! Compact JWS with ES256 (P-256), shared by the OIDC provider and the
+//! credential issuer.
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use p256::ecdsa::signature::{Signer, Verifier};
+use p256::ecdsa::{Signature, SigningKey, VerifyingKey};
+use p256::EncodedPoint;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+use serde_json::Value;
+use sha2::{Digest, Sha256};
+
+#[derive(Debug, PartialEq)]
+pub enum JwsError {
+    Malformed,
+    UnsupportedAlgorithm,
+    InvalidSignature,
+}
+
+impl std::fmt::Display for JwsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JwsError::Malformed => write!(f, "malformed JWS"),
+            JwsError::UnsupportedAlgorithm => write!(f, "unsupported JWS algorithm"),
+            JwsError::InvalidSignature => write!(f, "invalid signature"),
+        }
+    }
+}
+
+impl std::error::Error for JwsError {}
+
+/// Signs `claims` under a header with `alg: ES256` plus `kid` and `typ`.
+pub fn sign(key: &SigningKey, kid: &str, typ: &str, claims: &impl Serialize) -> String {
+    let header = serde_json::json!({ "alg": "ES256", "typ": typ, "kid": kid });
+    let signing_input = format!(
+        "{}.{}",
+        URL_SAFE_NO_PAD.encode(header.to_string()),
+        URL_SAFE_NO_PAD.encode(serde_json::to_vec(claims).expect("claims always serialize"))
+    );
+    let signature: Signature = key.sign(signing_input.as_bytes());
+    format!("{}.{}", signing_input, URL_SAFE_NO_PAD.encode(signature.to_bytes()))
+}
+
+/// The protected header, unverified; used to pick the key to verify with.
+pub fn header(token: &str) -> Result<Value, JwsError> {
+    let (header, _) = token.split_once('.').ok_or(JwsError::Malformed)?;
+    decode_part(header)
+}
+
+/// Checks the ES256 signature and returns the payload.
+pub fn verify<T: DeserializeOwned>(token: &str, key: &VerifyingKey) -> Result<T, JwsError> {
+    let (signing_input, signature) = token.rsplit_once('.').ok_or(JwsError::Malformed)?;
+    let (header, payload) = signing_input.split_once('.').ok_or(JwsError::Malformed)?;
+    if decode_part::<Value>(header)?.get("alg").and_then(Value::as_str) != Some("ES256") {
+        return Err(JwsError::UnsupportedAlgorithm);
+    }
+    let signature = URL_SAFE_NO_PAD
+        .decode(signature)
+        .ok()
+        .and_then(|bytes| Signature::from_slice(&bytes).ok())
+        .ok_or(JwsError::Malformed)?;
+    key.verify(signing_input.as_bytes(), &signature).map_err(|_| JwsError::InvalidSignature)?;
+    decode_part(payload)
+}
+
+/// Public key as an RFC 7517 JWK.
+pub fn public_jwk(key: &VerifyingKey) -> Value {
+    let point = key.to_encoded_point(false);
+    serde_json::json!({
+        "kty": "EC",
+        "crv": "P-256",
+        "x": URL_SAFE_NO_PAD.encode(point.x().expect("uncompressed point")),
+        "y": URL_SAFE_NO_PAD.encode(point.y().expect("uncompressed point")),
+    })
+}
+
+pub fn key_from_jwk(jwk: &Value) -> Option<VerifyingKey> {
+    if jwk.get("kty")?.as_str()? != "EC" || jwk.get("crv")?.as_str()? != "P-256" {
+        return None;
+    }
+    let coordinate = |name: &str| URL_SAFE_NO_PAD.decode(jwk.get(name)?.as_str()?).ok().filter(|c| c.len() == 32);
+    let (x, y) = (coordinate("x")?, coordinate("y")?);
+    let point = EncodedPoint::from_affine_coordinates(x.as_slice().into(), y.as_slice().into(), false);
+    VerifyingKey::from_encoded_point(&point).ok()
+}
+
+/// Short key identifier: the first 8 bytes of the SHA-256 of the
+/// compressed public key, hex-encoded.
+pub fn key_id(key: &VerifyingKey) -> String {
+    hex::encode(&Sha256::digest(key.to_encoded_point(true).as_bytes())[..8])
+}
+
+/// Reads a hex-encoded P-256 signing key from the environment variable `name`.
+pub fn signing_key_from_env(name: &str) -> Result<SigningKey, String> {
+    std::env::var(name)
+        .ok()
+        .and_then(|key| hex::decode(key).ok())
+        .and_then(|bytes| SigningKey::from_slice(&bytes).ok())
+        .ok_or_else(|| format!("{} must be set to a hex P-256 key", name))
+}
+
+fn decode_part<T: DeserializeOwned>(part: &str) -> Result<T, JwsError> {
+    let bytes = URL_SAFE_NO_PAD.decode(part).map_err(|_| JwsError::Malformed)?;
+    serde_json::from_slice(&bytes).map_err(|_| JwsError::Malformed)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signing_key() -> SigningKey {
+        SigningKey::from_slice(&[7; 32]).unwrap()
+    }
+
+    #[test]
+    fn verifies_the_rfc_7515_es256_example() {
+        // RFC 7515 appendix A.3.
+        let jwk = serde_json::json!({
+            "kty": "EC",
+            "crv": "P-256",
+            "x": "f83OJ3D2xF1Bg8vub9tLe1gHMzV76e8Tus9uPHvRVEU",
+            "y": "x_FEzRu9m36HLN_tue659LNpXW6pCyStikYjKIWI5a0",
+        });
+        let token = "eyJhbGciOiJFUzI1NiJ9\
+            .eyJpc3MiOiJqb2UiLA0KICJleHAiOjEzMDA4MTkzODAsDQogImh0dHA6Ly9leGFtcGxlLmNvbS9pc19yb290Ijp0cnVlfQ\
+            .DtEhU3ljbEg8L38VWAfUAqOyKAM6-Xx-F4GawxaepmXFCgfTjDxw5djxLa8ISlSApmWQxfKTUJqPP3-Kg6NU1Q";
+        let key = key_from_jwk(&jwk).unwrap();
+        let claims: Value = verify(token, &key).unwrap();
+        assert_eq!(claims["iss"], "joe");
+        assert_eq!(claims["exp"], 1300819380);
+        assert_eq!(claims["http://example.com/is_root"], true);
+        assert_eq!(public_jwk(&key), jwk);
+    }
+
+    #[test]
+    fn signed_tokens_round_trip() {
+        let key = signing_key();
+        let claims = serde_json::json!({ "sub": "alice", "iat": 1_760_000_000 });
+        let token = sign(&key, "kid-1", "JWT", &claims);
+        assert_eq!(verify::<Value>(&token, key.verifying_key()).unwrap(), claims);
+        assert_eq!(header(&token).unwrap(), serde_json::json!({ "alg": "ES256", "typ": "JWT", "kid": "kid-1" }));
+    }
+
+    #[test]
+    fn altered_tokens_are_rejected() {
+        let key = signing_key();
+        let token = sign(&key, "kid-1", "JWT", &serde_json::json!({ "sub": "alice" }));
+        let (header, rest) = token.split_once('.').unwrap();
+        let (_, signature) = rest.split_once('.').unwrap();
+
+        let other_payload = URL_SAFE_NO_PAD.encode(br#"{"sub":"mallory"}"#);
+        let forged = format!("{}.{}.{}", header, other_payload, signature);
+        assert_eq!(verify::<Value>(&forged, key.verifying_key()), Err(JwsError::InvalidSignature));
+
+        let other_key = SigningKey::from_slice(&[8; 32]).unwrap();
+        assert_eq!(verify::<Value>(&token, other_key.verifying_key()), Err(JwsError::InvalidSignature));
+
+        let unsigned = format!("{}.{}.", URL_SAFE_NO_PAD.encode(br#"{"alg":"none"}"#), other_payload);
+        assert_eq!(verify::<Value>(&unsigned, key.verifying_key()), Err(JwsError::UnsupportedAlgorithm));
+        assert_eq!(verify::<Value>("not a token", key.verifying_key()), Err(JwsError::Malformed));
+    }
+}
@@ -0,0 +1,210 @@
+use reqwest::{Client, StatusCode, Url};
+use serde::{Deserialize, Serialize};
+
+/// Environment variable holding the bearer token popchain requires to write
+/// attestations and record events and revocations.
+pub const SERVICE_TOKEN_ENV: &str = "POPCHAIN_SERVICE_TOKEN";
+
+/// Attestation as served by popchain's `/ledger/attestation/:human_hash_id`.
+#[derive(Clone, Deserialize)]
+pub struct Attestation {
+    pub attestation_id: String,
+    pub biometric_hash: String,
+    /// When the attestation was written.
+    #[serde(default)]
+    pub timestamp: Option<u64>,
+    pub expires_at: u64,
+    pub revoked: bool,
+}
+
+/// Response of popchain's `/ledger/write`; only the fields the system keeps.
+#[derive(Clone, Debug, Deserialize)]
+pub struct WrittenAttestation {
+    pub attestation_id: String,
+}
+
+/// popchain's 402 answer to `/ledger/write`: the Lightning invoice to pay
+/// before the write is committed; only the fields the system reports.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct PaymentRequired {
+    pub payment_hash: String,
+    /// BOLT11 invoice.
+    pub payment_request: String,
+    pub amount_sat: u64,
+    /// When the invoice lapses unpaid.
+    pub expires_at: u64,
+}
+
+/// Outcome of an invoiced write, from popchain's `/ledger/write/:payment_hash`.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum PaidWriteStatus {
+    AwaitingPayment,
+    Committed { receipt: WrittenAttestation },
+    Expired,
+    /// Paid but not committed; the payment is owed back.
+    Failed { reason: String },
+}
+
+#[derive(Serialize)]
+struct LedgerWrite<'a> {
+    human_hash_id: &'a str,
+    biometric_hash: &'a str,
+    identity_commitment: &'a str,
+}
+
+#[derive(Serialize)]
+struct Revocation<'a> {
+    human_hash_id: &'a str,
+    sequence_code: &'a str,
+    reason: &'a str,
+}
+
+#[derive(Serialize)]
+struct LedgerEvent<'a> {
+    human_hash_id: &'a str,
+    action: &'a str,
+    sequence_code: &'a str,
+}
+
+#[derive(Debug)]
+pub enum LedgerError {
+    UnknownIdentity,
+    AlreadyEnrolled,
+    Revoked,
+    Expired,
+    CommitmentMismatch,
+    /// The write is committed once this invoice is paid; writing again
+    /// after that reports `AlreadyEnrolled`.
+    PaymentRequired(PaymentRequired),
+    Unavailable(String),
+}
+
+impl std::fmt::Display for LedgerError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LedgerError::UnknownIdentity => write!(f, "unknown_identity"),
+            LedgerError::AlreadyEnrolled => write!(f, "already_enrolled"),
+            LedgerError::Revoked => write!(f, "revoked"),
+            LedgerError::Expired => write!(f, "attestation_expired"),
+            LedgerError::CommitmentMismatch => write!(f, "commitment_mismatch"),
+            LedgerError::PaymentRequired(invoice) => write!(f, "ledger_payment_required: {} sat, {}", invoice.amount_sat, invoice.payment_request),
+            LedgerError::Unavailable(e) => write!(f, "ledger_unavailable: {}", e),
+        }
+    }
+}
+
+pub async fn fetch_attestation(client: &Client, popchain_url: &str, human_hash_id: &str) -> Result<Attestation, LedgerError> {
+    let url = ledger_url(popchain_url, &["ledger", "attestation", human_hash_id])?;
+    let response = client.get(url).send().await.map_err(|e| LedgerError::Unavailable(e.to_string()))?;
+    match response.status() {
+        StatusCode::NOT_FOUND => Err(LedgerError::UnknownIdentity),
+        status if status.is_success() => response.json().await.map_err(|e| LedgerError::Unavailable(e.to_string())),
+        status => Err(LedgerError::Unavailable(status.to_string())),
+    }
+}
+
+/// The attestation must be live and commit to the hash the proof was made over.
+pub fn check_attestation(attestation: &Attestation, commitment: &str, now: i64) -> Result<(), LedgerError> {
+    if attestation.revoked {
+        return Err(LedgerError::Revoked);
+    }
+    if attestation.expires_at as i64 <= now {
+        return Err(LedgerError::Expired);
+    }
+    if attestation.biometric_hash != commitment {
+        return Err(LedgerError::CommitmentMismatch);
+    }
+    Ok(())
+}
+
+pub async fn write_attestation(
+    client: &Client,
+    popchain_url: &str,
+    service_token: &str,
+    human_hash_id: &str,
+    biometric_hash: &str,
+    identity_commitment: &str,
+) -> Result<WrittenAttestation, LedgerError> {
+    let url = ledger_url(popchain_url, &["ledger", "write"])?;
+    let response = client
+        .post(url)
+        .bearer_auth(service_token)
+        .json(&LedgerWrite { human_hash_id, biometric_hash, identity_commitment })
+        .send()
+        .await
+        .map_err(|e| LedgerError::Unavailable(e.to_string()))?;
+    match response.status() {
+        StatusCode::CONFLICT => Err(LedgerError::AlreadyEnrolled),
+        StatusCode::PAYMENT_REQUIRED => {
+            let invoice = response.json().await.map_err(|e| LedgerError::Unavailable(e.to_string()))?;
+            Err(LedgerError::PaymentRequired(invoice))
+        }
+        status if status.is_success() => response.json().await.map_err(|e| LedgerError::Unavailable(e.to_string())),
+        status => Err(LedgerError::Unavailable(status.to_string())),
+    }
+}
+
+pub async fn write_status(client: &Client, popchain_url: &str, payment_hash: &str) -> Result<PaidWriteStatus, LedgerError> {
+    let url = ledger_url(popchain_url, &["ledger", "write", payment_hash])?;
+    let response = client.get(url).send().await.map_err(|e| LedgerError::Unavailable(e.to_string()))?;
+    match response.status() {
+        status if status.is_success() => response.json().await.map_err(|e| LedgerError::Unavailable(e.to_string())),
+        status => Err(LedgerError::Unavailable(status.to_string())),
+    }
+}
+
+pub async fn revoke(
+    client: &Client,
+    popchain_url: &str,
+    service_token: &str,
+    human_hash_id: &str,
+    sequence_code: &str,
+    reason: &str,
+) -> Result<(), LedgerError> {
+    let url = ledger_url(popchain_url, &["ledger", "revoke"])?;
+    let response = client
+        .post(url)
+        .bearer_auth(service_token)
+        .json(&Revocation { human_hash_id, sequence_code, reason })
+        .send()
+        .await
+        .map_err(|e| LedgerError::Unavailable(e.to_string()))?;
+    match response.status() {
+        StatusCode::NOT_FOUND => Err(LedgerError::UnknownIdentity),
+        status if status.is_success() => Ok(()),
+        status => Err(LedgerError::Unavailable(status.to_string())),
+    }
+}
+
+pub async fn record_event(
+    client: &Client,
+    popchain_url: &str,
+    service_token: &str,
+    human_hash_id: &str,
+    action: &str,
+    sequence_code: &str,
+) -> Result<(), LedgerError> {
+    let url = ledger_url(popchain_url, &["ledger", "event"])?;
+    let response = client
+        .post(url)
+        .bearer_auth(service_token)
+        .json(&LedgerEvent { human_hash_id, action, sequence_code })
+        .send()
+        .await
+        .map_err(|e| LedgerError::Unavailable(e.to_string()))?;
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        Err(LedgerError::Unavailable(response.status().to_string()))
+    }
+}
+
+fn ledger_url(popchain_url: &str, segments: &[&str]) -> Result<Url, LedgerError> {
+    let mut url = Url::parse(popchain_url).map_err(|e| LedgerError::Unavailable(e.to_string()))?;
+    url.path_segments_mut()
+        .map_err(|_| LedgerError::Unavailable(format!("invalid popchain_url {}", popchain_url)))?
+        .pop_if_empty()
+        .extend(segments);
+    Ok(url)
+}
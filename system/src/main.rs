@@ -1,63 +1,1307 @@
-use axum::{routing::post, Json, Router};
+use axum::{
+       async_trait,
+       extract::{FromRequestParts, Path, Query, State},
+       http::{header, request::Parts, HeaderMap, StatusCode},
+       response::{IntoResponse, Redirect, Response},
+       routing::{delete, get, post},
+       Form, Json, Router,
+   };
+   use disclosure::{AttributeCredential, AttributeOpenings, PredicateOutcome, PredicateProof, Presentation};
+   use futures::stream::{self, StreamExt};
+   use secp256k1::{Secp256k1, XOnlyPublicKey};
    use serde::{Deserialize, Serialize};
-   use sha2::{Digest, Sha256};
-   use uuid::Uuid;
+   use sequence_code::{Action, SequenceCode};
    use chrono::Utc;
    use tracing::{info, error};
    use tracing_subscriber::{fmt, EnvFilter};
+   use std::fs;
    use std::net::SocketAddr;
+   use std::sync::{Arc, Mutex};
+   use std::time::Instant;
+
+   mod challenge;
+   mod credentials;
+   mod events;
+   mod jws;
+   mod ledger;
+   mod oidc;
+   mod policy;
+   mod qr;
+   mod saga;
+   mod tenants;
+   mod webhooks;
+
+   use challenge::{binding_digest, BindingError, Challenge, ChallengeStore, PublicInputs};
+   use oidc::{AuthorizationCode, IdTokenClaims, OidcConfig, OidcKeys, OidcStore, PendingAuthorization, UserInfo};
+   use policy::{Facts, Policy, PolicyDecision, PopTier};
+   use credentials::{CredentialConfig, CredentialIssuer, IssuedCredential};
+   use qr::{QrProof, QrSigner};
+   use events::{EventFilter, EventRecord, EventStore, NewEvent, Recorder};
+   use webhooks::{DeliveryStatus, EventType, Subscription, WebhookConfig, WebhookStore};
+   use tenants::{ApiKey, RateLimit, RateLimiter, Scope, Tenant, TenantConfig, TenantSettings, TenantStore, TenantUpdate};
+   use saga::{EnrollmentInput, EnrollmentSaga, Orchestrator, SagaConfig, SagaStatus, SagaStore};
+
+   const MAX_BATCH_SIZE: usize = 1000;
+   /// Batch items checked against PoPChain at once.
+   const BATCH_CONCURRENCY: usize = 16;
+   /// Environment variable holding the x-only key (hex) the oracle signs
+   /// attribute credentials with.
+   const ATTRIBUTE_ISSUER_ENV: &str = "ORACLE_ATTRIBUTE_PUBKEY";
+   /// Environment variable holding the bearer token for the oracle's
+   /// credential endpoint.
+   const ORACLE_TOKEN_ENV: &str = "ORACLE_SERVICE_TOKEN";
+   /// Verifier id of the challenges holders answer to get an attribute
+   /// credential.
+   const ATTRIBUTE_VERIFIER_ID: &str = "humanhash-attributes";
+
+   #[derive(Clone, Deserialize)]
+   struct Config {
+       port: u16,
+       challenge_ttl_secs: i64,
+       popchain_url: String,
+       biometric_url: String,
+       oracle_url: String,
+       events_path: String,
+       saga: SagaConfig,
+       webhooks: WebhookConfig,
+       oidc: OidcConfig,
+       credentials: CredentialConfig,
+       tenants: TenantConfig,
+   }
+
+   #[derive(Clone)]
+   struct AppState {
+       config: Config,
+       challenges: Arc<Mutex<ChallengeStore>>,
+       http: reqwest::Client,
+       enrollments: Orchestrator,
+       events: Recorder,
+       qr_signer: Arc<QrSigner>,
+       oidc_keys: Arc<OidcKeys>,
+       oidc: Arc<Mutex<OidcStore>>,
+       credentials: Arc<CredentialIssuer>,
+       tenants: Arc<Mutex<TenantStore>>,
+       rate_limiter: Arc<Mutex<RateLimiter>>,
+       admin_key_hash: Arc<str>,
+       /// Bearer token for popchain's service endpoints.
+       popchain_token: Arc<str>,
+       ingest_key_hash: Arc<str>,
+       attribute_issuer: XOnlyPublicKey,
+       /// Bearer token for the oracle's credential endpoint.
+       oracle_token: Arc<str>,
+   }
+
+   /// The relying party behind a request: authenticated by API key and
+   /// within its rate limit. Handlers check the scope they need.
+   struct Caller(Tenant);
+
+   impl Caller {
+       fn require(&self, scope: Scope) -> Result<&Tenant, StatusCode> {
+           if self.0.has_scope(scope) {
+               Ok(&self.0)
+           } else {
+               Err(StatusCode::FORBIDDEN)
+           }
+       }
+   }
+
+   #[async_trait]
+   impl FromRequestParts<AppState> for Caller {
+       type Rejection = Response;
+
+       async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+           let tenant = api_key(&parts.headers)
+               .and_then(|key| state.tenants.lock().unwrap().authenticate(key, Utc::now().timestamp()).cloned())
+               .ok_or_else(|| StatusCode::UNAUTHORIZED.into_response())?;
+           if let Err(retry_after) = state.rate_limiter.lock().unwrap().check(&tenant.id, tenant.rate_limit, Instant::now()) {
+               info!("Rate limiting tenant {}", tenant.id);
+               let retry_after = retry_after.as_secs().max(1).to_string();
+               return Err((StatusCode::TOO_MANY_REQUESTS, [(header::RETRY_AFTER, retry_after)]).into_response());
+           }
+           Ok(Caller(tenant))
+       }
+   }
+
+   /// Operator access to `/admin`, by the bearer token in `ADMIN_API_KEY`.
+   struct Admin;
+
+   #[async_trait]
+   impl FromRequestParts<AppState> for Admin {
+       type Rejection = StatusCode;
+
+       async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+           match api_key(&parts.headers) {
+               Some(key) if tenants::key_matches(key, &state.admin_key_hash) => Ok(Admin),
+               _ => Err(StatusCode::UNAUTHORIZED),
+           }
+       }
+   }
+
+   /// Another HumanHash service, by the bearer token in `EVENT_INGEST_TOKEN`.
+   struct Service;
+
+   #[async_trait]
+   impl FromRequestParts<AppState> for Service {
+       type Rejection = StatusCode;
+
+       async fn from_request_parts(parts: &mut Parts, state: &AppState) -> Result<Self, Self::Rejection> {
+           match api_key(&parts.headers) {
+               Some(key) if tenants::key_matches(key, &state.ingest_key_hash) => Ok(Service),
+               _ => Err(StatusCode::UNAUTHORIZED),
+           }
+       }
+   }
+
+   /// API key from `x-api-key` or an `Authorization: Bearer` header.
+   fn api_key(headers: &HeaderMap) -> Option<&str> {
+       headers.get("x-api-key").or_else(|| headers.get(header::AUTHORIZATION)).and_then(|value| {
+           let value = value.to_str().ok()?;
+           Some(value.strip_prefix("Bearer ").unwrap_or(value))
+       })
+   }
 
    #[derive(Serialize, Deserialize)]
    struct Proof {
        proof: String,
+       public_inputs: PublicInputs,
+   }
+
+   #[derive(Deserialize)]
+   struct EnrollmentRequest {
+       session_id: String,
+       face_scan: Vec<u8>,
+       identity_commitment: String,
+   }
+
+   #[derive(Serialize)]
+   struct EnrollmentAccepted {
+       sequence_code: String,
+       status: SagaStatus,
+   }
+
+   #[derive(Deserialize)]
+   struct ReportQuery {
+       action: Option<String>,
+       from: Option<i64>,
+       to: Option<i64>,
+       tenant_id: Option<String>,
+       human_hash_id: Option<String>,
+       verifier_id: Option<String>,
+       cursor: Option<String>,
+       limit: Option<usize>,
+       format: Option<String>,
+   }
+
+   #[derive(Serialize)]
+   struct EventReport {
+       events: Vec<EventRecord>,
+       #[serde(skip_serializing_if = "Option::is_none")]
+       next_cursor: Option<String>,
+   }
+
+   #[derive(Deserialize)]
+   struct SubscriptionRequest {
+       url: String,
+       event_types: Vec<EventType>,
+   }
+
+   #[derive(Deserialize)]
+   struct DeliveryQuery {
+       status: Option<DeliveryStatus>,
+   }
+
+   #[derive(Deserialize)]
+   struct ReplayRequest {
+       since: i64,
+   }
+
+   #[derive(Serialize)]
+   struct ReplayResult {
+       requeued: usize,
+   }
+
+   #[derive(Serialize)]
+   struct SequenceCodeCheck {
+       sequence_code: String,
+       valid: bool,
+       action: String,
+       issued_at: i64,
+   }
+
+   #[derive(Deserialize)]
+   struct TenantRequest {
+       name: String,
+       scopes: Vec<Scope>,
+       rate_limit: Option<RateLimit>,
+       #[serde(default)]
+       settings: TenantSettings,
+   }
+
+   #[derive(Serialize)]
+   struct TenantCreated {
+       tenant: Tenant,
+       /// Shown once; only its digest is stored.
+       api_key: String,
+   }
+
+   #[derive(Deserialize)]
+   struct KeyRotation {
+       grace_secs: Option<i64>,
+   }
+
+   #[derive(Serialize)]
+   struct KeyCreated {
+       key: ApiKey,
+       api_key: String,
    }
 
    #[derive(Serialize, Deserialize)]
    struct VerificationResult {
        verified: bool,
        sequence_code: String,
+       #[serde(skip_serializing_if = "Option::is_none")]
+       reason: Option<String>,
+       #[serde(skip_serializing_if = "Option::is_none")]
+       policy: Option<PolicyDecision>,
+   }
+
+   #[derive(Deserialize)]
+   struct QrVerificationRequest {
+       qr: String,
+   }
+
+   #[derive(Serialize)]
+   struct QrEncoding {
+       qr: String,
+       length: usize,
+   }
+
+   #[derive(Serialize)]
+   struct QrKey {
+       alg: &'static str,
+       kid: String,
+       public_key: String,
+   }
+
+   #[derive(Deserialize)]
+   struct CredentialRevocation {
+       reason: String,
+   }
+
+   #[derive(Deserialize)]
+   struct CredentialVerificationRequest {
+       credential: String,
+   }
+
+   #[derive(Serialize)]
+   struct CredentialVerification {
+       verified: bool,
+       #[serde(skip_serializing_if = "Option::is_none")]
+       credential: Option<serde_json::Value>,
+       #[serde(skip_serializing_if = "Option::is_none")]
+       reason: Option<String>,
+   }
+
+   #[derive(Deserialize)]
+   struct AuthorizationRequest {
+       response_type: String,
+       client_id: String,
+       redirect_uri: String,
+       scope: String,
+       state: Option<String>,
+       nonce: Option<String>,
+       code_challenge: Option<String>,
+       code_challenge_method: Option<String>,
+   }
+
+   #[derive(Serialize)]
+   struct AuthorizationPending {
+       request_id: String,
+       challenge: Challenge,
+   }
+
+   #[derive(Deserialize)]
+   struct AuthenticationRequest {
+       request_id: String,
+       #[serde(flatten)]
+       proof: Proof,
+   }
+
+   #[derive(Deserialize)]
+   struct TokenRequest {
+       grant_type: String,
+       code: String,
+       redirect_uri: String,
+       client_id: String,
+       client_secret: Option<String>,
+       code_verifier: String,
+   }
+
+   #[derive(Serialize)]
+   struct TokenResponse {
+       access_token: String,
+       token_type: &'static str,
+       expires_in: i64,
+       id_token: String,
+       scope: String,
+   }
+
+   #[derive(Serialize)]
+   struct OidcError {
+       error: &'static str,
+       #[serde(skip_serializing_if = "Option::is_none")]
+       error_description: Option<String>,
+   }
+
+   #[derive(Serialize, Deserialize)]
+   struct BatchProofs {
+       proofs: Vec<Proof>,
+   }
+
+   #[derive(Serialize, Deserialize)]
+   struct BatchItemResult {
+       index: usize,
+       verified: bool,
+       sequence_code: String,
+       #[serde(skip_serializing_if = "Option::is_none")]
+       reason: Option<String>,
+       #[serde(skip_serializing_if = "Option::is_none")]
+       policy: Option<PolicyDecision>,
+   }
+
+   #[derive(Serialize, Deserialize)]
+   struct BatchVerificationResult {
+       results: Vec<BatchItemResult>,
+       verified_count: usize,
+       failed_count: usize,
+       verification_latency_ms: f64,
+       total_latency_ms: f64,
+   }
+
+   /// A sequence code reported by another service. Tenant and verdict are
+   /// not taken from the report.
+   #[derive(Deserialize)]
+   struct IngestedEvent {
+       sequence_code: String,
+       action: String,
+       service: String,
+       #[serde(default)]
+       human_hash_id: Option<String>,
+   }
+
+   /// A holder asking for an attribute credential: a presentation proof
+   /// over a challenge from `/identity/attributes/challenge`, and the key
+   /// the holder will sign presentations with.
+   #[derive(Deserialize)]
+   struct AttributeCredentialRequest {
+       #[serde(flatten)]
+       proof: Proof,
+       holder_key: String,
+   }
+
+   #[derive(Serialize, Deserialize)]
+   struct AttributeCredentialIssued {
+       credential: AttributeCredential,
+       openings: AttributeOpenings,
+   }
+
+   #[derive(Deserialize)]
+   struct PredicateVerificationRequest {
+       presentation: Presentation,
+   }
+
+   #[derive(Serialize)]
+   struct PredicateVerificationResult {
+       verified: bool,
+       human_hash_id: String,
+       predicates: Vec<PredicateOutcome>,
+       sequence_code: String,
+       #[serde(skip_serializing_if = "Option::is_none")]
+       policy: Option<PolicyDecision>,
+   }
+
+   async fn start_enrollment(State(state): State<AppState>, caller: Caller, Json(request): Json<EnrollmentRequest>) -> Result<(StatusCode, Json<EnrollmentAccepted>), StatusCode> {
+       let tenant = caller.require(Scope::Kyc)?;
+       let sequence_code = state.events.issue(Action::Enroll, Some(&tenant.id), None, None, None);
+       info!("Starting enrollment for session_id: {}, tenant: {}, sequence_code: {}", request.session_id, tenant.id, sequence_code);
+       let saga = EnrollmentSaga::new(
+           sequence_code.clone(),
+           request.session_id,
+           tenant.id.clone(),
+           EnrollmentInput::new(request.face_scan, request.identity_commitment),
+           Utc::now().timestamp(),
+       );
+       if let Err(e) = state.enrollments.persist(&saga) {
+           error!("Failed to persist enrollment {}: {}", sequence_code, e);
+           return Err(StatusCode::INTERNAL_SERVER_ERROR);
+       }
+       let status = saga.status;
+       tokio::spawn(state.enrollments.clone().drive(saga));
+       Ok((StatusCode::ACCEPTED, Json(EnrollmentAccepted { sequence_code, status })))
+   }
+
+   async fn enrollment_status(State(state): State<AppState>, caller: Caller, Path(sequence_code): Path<String>) -> Result<Json<EnrollmentSaga>, StatusCode> {
+       let tenant = caller.require(Scope::Kyc)?;
+       state
+           .enrollments
+           .status(&sequence_code)
+           .filter(|saga| saga.tenant_id.as_deref() == Some(tenant.id.as_str()))
+           .map(Json)
+           .ok_or(StatusCode::NOT_FOUND)
+   }
+
+   /// Challenges are issued to the calling tenant, whose id is the verifier
+   /// id the proof must bind.
+   async fn issue_challenge(State(state): State<AppState>, caller: Caller) -> Result<Json<Challenge>, StatusCode> {
+       let tenant = caller.require(Scope::Verify)?;
+       let ttl = tenant.settings.challenge_ttl_secs.unwrap_or(state.config.challenge_ttl_secs);
+       let inputs = state.challenges.lock().unwrap().issue(&tenant.id, ttl, Utc::now().timestamp());
+       info!("Issued challenge to verifier {}, expires_at: {}", inputs.verifier_id, inputs.expires_at);
+       Ok(Json(inputs))
+   }
+
+   async fn verify_proof(State(state): State<AppState>, caller: Caller, Json(proof): Json<Proof>) -> Result<Json<VerificationResult>, StatusCode> {
+       let tenant = caller.require(Scope::Verify)?;
+       Ok(Json(verify_presented(&state, Some(tenant), &tenant.id, &proof).await))
+   }
+
+   async fn encode_qr(State(state): State<AppState>, caller: Caller, Json(proof): Json<Proof>) -> Result<Json<QrEncoding>, StatusCode> {
+       caller.require(Scope::Verify)?;
+       // Only well-formed proofs get the service's signature; the challenge and
+       // ledger checks still run when the code is scanned.
+       if !verify_mock_proof(&proof.proof, &proof.public_inputs) {
+           return Err(StatusCode::UNPROCESSABLE_ENTITY);
+       }
+       let proof = QrProof { proof: proof.proof, public_inputs: proof.public_inputs };
+       let qr = qr::encode(&Secp256k1::signing_only(), &state.qr_signer, &proof).map_err(|e| {
+           error!("Failed to encode proof as QR: {}", e);
+           StatusCode::BAD_REQUEST
+       })?;
+       Ok(Json(QrEncoding { length: qr.len(), qr }))
+   }
+
+   async fn qr_key(State(state): State<AppState>) -> Json<QrKey> {
+       Json(QrKey {
+           alg: "ES256K",
+           kid: hex::encode(state.qr_signer.kid),
+           public_key: hex::encode(state.qr_signer.public_key.serialize()),
+       })
+   }
+
+   async fn verify_qr(State(state): State<AppState>, caller: Caller, Json(request): Json<QrVerificationRequest>) -> Result<Json<VerificationResult>, StatusCode> {
+       let tenant = caller.require(Scope::Verify)?;
+       match qr::decode(&Secp256k1::verification_only(), &state.qr_signer.public_key, &request.qr) {
+           Ok(decoded) => {
+               let proof = Proof { proof: decoded.proof, public_inputs: decoded.public_inputs };
+               Ok(Json(verify_presented(&state, Some(tenant), &tenant.id, &proof).await))
+           }
+           Err(e) => {
+               let sequence_code = state.events.issue(Action::Verify, Some(&tenant.id), None, Some(&tenant.id), Some(false));
+               error!("Rejected scanned QR ({}), sequence_code: {}", e, sequence_code);
+               Ok(Json(VerificationResult {
+                   verified: false,
+                   sequence_code,
+                   reason: Some(format!("invalid_qr: {}", e)),
+                   policy: None,
+               }))
+           }
+       }
    }
 
-   async fn verify_proof(Json(proof): Json<Proof>) -> Json<VerificationResult> {
-       info!("Verifying proof: {}", proof.proof);
+   async fn verify_presented(state: &AppState, tenant: Option<&Tenant>, verifier_id: &str, proof: &Proof) -> VerificationResult {
+       info!("Verifying proof for verifier {}: {}", verifier_id, proof.proof);
        
        // Placeholder for BitSNARK proof verification
-       let is_valid = verify_mock_proof(&proof.proof);
+       let proof_valid = verify_mock_proof(&proof.proof, &proof.public_inputs);
+       let (outcome, policy) = match check_presentation(state, proof, verifier_id, proof_valid).await {
+           Ok(attestation) => apply_policy(state, tenant, &proof.public_inputs.human_hash_id, Some(&attestation), None),
+           Err(reason) => (Err(reason), None),
+       };
        
        // Generate unique sequence code
-       let sequence_code = generate_sequence_code("VER");
+       let tenant_id = tenant.map(|tenant| tenant.id.as_str());
+       let sequence_code = state.events.issue(Action::Verify, tenant_id, Some(&proof.public_inputs.human_hash_id), Some(verifier_id), Some(outcome.is_ok()));
        
-       // Log to PoPChain (simplified)
-       if is_valid {
-           log_to_popchain(&proof.proof, &sequence_code);
-           info!("Proof verified successfully, sequence_code: {}", sequence_code);
-       } else {
-           error!("Proof verification failed, sequence_code: {}", sequence_code);
+       match &outcome {
+           Ok(()) => {
+               log_to_popchain(state, &proof.public_inputs.human_hash_id, &sequence_code).await;
+               info!("Proof verified successfully, sequence_code: {}", sequence_code);
+           }
+           Err(reason) => error!("Proof verification failed ({}), sequence_code: {}", reason, sequence_code),
        }
        
-       Json(VerificationResult {
-           verified: is_valid,
+       VerificationResult {
+           verified: outcome.is_ok(),
            sequence_code,
-       })
+           reason: outcome.err(),
+           policy,
+       }
    }
 
-   fn verify_mock_proof(proof: &str) -> bool {
-       // Mock zk-SNARK proof verification
-       proof.starts_with("mock_proof_")
+   /// Evaluates the tenant's policy, if it has one, once the proof itself
+   /// checked out; a failing policy fails the verification.
+   fn apply_policy(
+       state: &AppState,
+       tenant: Option<&Tenant>,
+       human_hash_id: &str,
+       attestation: Option<&ledger::Attestation>,
+       countries: Option<Vec<String>>,
+   ) -> (Result<(), String>, Option<PolicyDecision>) {
+       let Some(policy) = tenant.and_then(|tenant| tenant.policy.as_ref()) else {
+           return (Ok(()), None);
+       };
+       let decision = policy.evaluate(&policy_facts(state, human_hash_id, attestation, countries));
+       let outcome = if decision.passed { Ok(()) } else { Err("policy_failed".to_string()) };
+       (outcome, Some(decision))
    }
 
-   fn log_to_popchain(proof: &str, sequence_code: &str) {
-       // Placeholder for PoPChain logging
-       println!("Logged to PoPChain: proof={}, sequence_code={}", proof, sequence_code);
+   /// What the service knows about an identity: the tier and the liveness
+   /// score of the enrollment scan come from its latest completed
+   /// enrollment, the age from PoPChain.
+   fn policy_facts(state: &AppState, human_hash_id: &str, attestation: Option<&ledger::Attestation>, countries: Option<Vec<String>>) -> Facts {
+       let store = state.enrollments.store.lock().unwrap();
+       let enrollment = store.completed_for(human_hash_id);
+       Facts {
+           tier: if enrollment.is_some() { PopTier::Kyc } else { PopTier::Biometric },
+           kyc_passed: enrollment.is_some(),
+           attested_at: attestation.and_then(|attestation| attestation.timestamp).map(|timestamp| timestamp as i64),
+           enrollment_liveness_score: enrollment.and_then(|saga| saga.liveness_score),
+           countries,
+           now: Utc::now().timestamp(),
+       }
    }
 
-   fn generate_sequence_code(action: &str) -> String {
-       let uuid = Uuid::new_v4();
-       let timestamp = Utc::now().timestamp();
-       let mut hasher = Sha256::new();
-       hasher.update(format!("{}{}", uuid, timestamp));
-       let hash = hex::encode(hasher.finalize());
-       format!("TX-{}-{}-{}-{}", action, uuid, timestamp, &hash[..8])
+   async fn verify_proof_batch(State(state): State<AppState>, caller: Caller, Json(batch): Json<BatchProofs>) -> Result<Json<BatchVerificationResult>, StatusCode> {
+       let tenant = caller.require(Scope::Verify)?;
+       let max_batch_size = tenant.settings.max_batch_size.unwrap_or(MAX_BATCH_SIZE).min(MAX_BATCH_SIZE);
+       if batch.proofs.len() > max_batch_size {
+           error!("Rejecting batch of {} proofs (max {})", batch.proofs.len(), max_batch_size);
+           return Err(StatusCode::PAYLOAD_TOO_LARGE);
+       }
+       info!("Verifying batch of {} proofs", batch.proofs.len());
+       let started = Instant::now();
+
+       let verified: Vec<bool> = batch.proofs.iter().map(|proof| verify_mock_proof(&proof.proof, &proof.public_inputs)).collect();
+       let verification_latency_ms = started.elapsed().as_secs_f64() * 1000.0;
+
+       // Items wait on PoPChain for the lookup and the write-back, so a
+       // bounded number run at once rather than one after another.
+       let mut results: Vec<BatchItemResult> = stream::iter(batch.proofs.into_iter().zip(verified).enumerate())
+           .map(|(index, (proof, proof_valid))| verify_batch_item(&state, tenant, index, proof, proof_valid))
+           .buffer_unordered(BATCH_CONCURRENCY)
+           .collect()
+           .await;
+       results.sort_by_key(|result| result.index);
+
+       let verified_count = results.iter().filter(|r| r.verified).count();
+       let total_latency_ms = started.elapsed().as_secs_f64() * 1000.0;
+       info!(
+           "Batch verified: {}/{} valid in {:.2}ms ({:.2}ms verification)",
+           verified_count, results.len(), total_latency_ms, verification_latency_ms
+       );
+
+       Ok(Json(BatchVerificationResult {
+           failed_count: results.len() - verified_count,
+           verified_count,
+           results,
+           verification_latency_ms,
+           total_latency_ms,
+       }))
+   }
+
+   async fn verify_batch_item(state: &AppState, tenant: &Tenant, index: usize, proof: Proof, proof_valid: bool) -> BatchItemResult {
+       let (outcome, policy) = match check_presentation(state, &proof, &tenant.id, proof_valid).await {
+           Ok(attestation) => apply_policy(state, Some(tenant), &proof.public_inputs.human_hash_id, Some(&attestation), None),
+           Err(reason) => (Err(reason), None),
+       };
+       let sequence_code = state.events.issue(Action::Verify, Some(&tenant.id), Some(&proof.public_inputs.human_hash_id), Some(&tenant.id), Some(outcome.is_ok()));
+       match &outcome {
+           Ok(()) => log_to_popchain(state, &proof.public_inputs.human_hash_id, &sequence_code).await,
+           Err(reason) => error!("Batch item {} failed verification ({}), sequence_code: {}", index, reason, sequence_code),
+       }
+       BatchItemResult {
+           index,
+           verified: outcome.is_ok(),
+           sequence_code,
+           reason: outcome.err(),
+           policy,
+       }
+   }
+
+   async fn verify_predicates(State(state): State<AppState>, caller: Caller, Json(request): Json<PredicateVerificationRequest>) -> Result<Json<PredicateVerificationResult>, StatusCode> {
+       let tenant = caller.require(Scope::Verify)?;
+       let presentation = request.presentation;
+       info!("Verifying {} predicates for {}", presentation.predicates.len(), presentation.credential.human_hash_id);
+
+       let secp = Secp256k1::verification_only();
+       let human_hash_id = presentation.credential.human_hash_id.as_str();
+       // Checks the holder signed the presentation with the key the
+       // credential was issued to, not just that the credential is genuine.
+       let mut predicates = match disclosure::verify_presentation(&secp, &state.attribute_issuer, &presentation) {
+           Ok(outcomes) => outcomes,
+           Err(e) => {
+               let sequence_code = state.events.issue(Action::Verify, Some(&tenant.id), Some(human_hash_id), Some(&tenant.id), Some(false));
+               error!("Predicate presentation rejected: {}, sequence_code: {}", e, sequence_code);
+               return Err(StatusCode::UNAUTHORIZED);
+           }
+       };
+       // The proofs are bound to the context, which must be a challenge issued
+       // to this verifier, so a presentation is accepted once and only here.
+       let redeemed = state.challenges.lock().unwrap().redeem_nonce(&presentation.context, &tenant.id, Utc::now().timestamp());
+       if let Err(e) = redeemed {
+           let sequence_code = state.events.issue(Action::Verify, Some(&tenant.id), Some(human_hash_id), Some(&tenant.id), Some(false));
+           error!("Predicate presentation rejected: {}, sequence_code: {}", e, sequence_code);
+           return Err(StatusCode::UNAUTHORIZED);
+       }
+
+       // An age proof is only meaningful relative to today; older `as_of`
+       // dates would let a holder replay a proof made before a birthday cutoff.
+       let today = Utc::now().format("%Y%m%d").to_string();
+       let yesterday = (Utc::now() - chrono::Duration::days(1)).format("%Y%m%d").to_string();
+       for (outcome, proof) in predicates.iter_mut().zip(&presentation.predicates) {
+           if let PredicateProof::AgeOver { as_of, .. } = proof {
+               let as_of = as_of.to_string();
+               if as_of != today && as_of != yesterday {
+                   outcome.verified = false;
+               }
+           }
+       }
+
+       let mut verified = !predicates.is_empty() && predicates.iter().all(|outcome| outcome.verified);
+       let mut policy = None;
+       if verified && tenant.policy.is_some() {
+           // The holder's country is in every list they proved membership of.
+           let mut countries: Option<Vec<String>> = None;
+           for (outcome, proof) in predicates.iter().zip(&presentation.predicates) {
+               if let (true, PredicateProof::CountryIn { countries: proven, .. }) = (outcome.verified, proof) {
+                   countries = Some(match countries {
+                       Some(known) => known.into_iter().filter(|country| proven.contains(country)).collect(),
+                       None => proven.clone(),
+                   });
+               }
+           }
+           let attestation = ledger::fetch_attestation(&state.http, &state.config.popchain_url, human_hash_id).await.ok();
+           let (outcome, decision) = apply_policy(&state, Some(tenant), human_hash_id, attestation.as_ref(), countries);
+           verified = outcome.is_ok();
+           policy = decision;
+       }
+       let sequence_code = state.events.issue(Action::Verify, Some(&tenant.id), Some(human_hash_id), Some(&tenant.id), Some(verified));
+       if verified {
+           log_to_popchain(&state, &presentation.credential.human_hash_id, &sequence_code).await;
+           info!("Predicates verified, sequence_code: {}", sequence_code);
+       } else {
+           error!("Predicate verification failed, sequence_code: {}", sequence_code);
+       }
+
+       Ok(Json(PredicateVerificationResult {
+           verified,
+           human_hash_id: presentation.credential.human_hash_id,
+           predicates,
+           sequence_code,
+           policy,
+       }))
+   }
+
+   async fn issue_attribute_challenge(State(state): State<AppState>) -> Json<Challenge> {
+       Json(state.challenges.lock().unwrap().issue(ATTRIBUTE_VERIFIER_ID, state.config.challenge_ttl_secs, Utc::now().timestamp()))
+   }
+
+   /// Gets the holder an attribute credential from the oracle, bound to
+   /// their key. The holder authenticates as the identity with a
+   /// presentation proof; the response carries the attribute openings, so
+   /// it goes to the holder alone.
+   async fn request_attribute_credential(State(state): State<AppState>, Json(request): Json<AttributeCredentialRequest>) -> Result<Json<AttributeCredentialIssued>, StatusCode> {
+       let human_hash_id = &request.proof.public_inputs.human_hash_id;
+       if hex::decode(&request.holder_key).ok().and_then(|bytes| XOnlyPublicKey::from_slice(&bytes).ok()).is_none() {
+           return Err(StatusCode::UNPROCESSABLE_ENTITY);
+       }
+       let proof_valid = verify_mock_proof(&request.proof.proof, &request.proof.public_inputs);
+       if let Err(reason) = check_presentation(&state, &request.proof, ATTRIBUTE_VERIFIER_ID, proof_valid).await {
+           info!("Refusing an attribute credential for {}: {}", human_hash_id, reason);
+           return Err(StatusCode::UNAUTHORIZED);
+       }
+
+       let url = format!("{}/oracle/credential", state.config.oracle_url.trim_end_matches('/'));
+       let response = state
+           .http
+           .post(url)
+           .bearer_auth(&state.oracle_token)
+           .json(&serde_json::json!({ "humanHashId": human_hash_id, "holderKey": request.holder_key }))
+           .send()
+           .await
+           .map_err(|e| {
+               error!("Failed to request an attribute credential for {}: {}", human_hash_id, e);
+               StatusCode::BAD_GATEWAY
+           })?;
+       match response.status().as_u16() {
+           200 => response.json().await.map(Json).map_err(|e| {
+               error!("Invalid attribute credential for {}: {}", human_hash_id, e);
+               StatusCode::BAD_GATEWAY
+           }),
+           404 => Err(StatusCode::NOT_FOUND),
+           status => {
+               error!("Oracle refused an attribute credential for {}: {}", human_hash_id, status);
+               Err(StatusCode::BAD_GATEWAY)
+           }
+       }
+   }
+
+   /// A tenant's own events.
+   async fn report_events(State(state): State<AppState>, caller: Caller, Query(mut query): Query<ReportQuery>) -> Result<Response, StatusCode> {
+       query.tenant_id = Some(caller.require(Scope::Report)?.id.clone());
+       events_report(&state, query)
+   }
+
+   /// Events across tenants, optionally filtered by `tenant_id`.
+   async fn admin_report_events(State(state): State<AppState>, _: Admin, Query(query): Query<ReportQuery>) -> Result<Response, StatusCode> {
+       events_report(&state, query)
+   }
+
+   fn events_report(state: &AppState, query: ReportQuery) -> Result<Response, StatusCode> {
+       let action = match query.action.as_deref() {
+           Some(action) => Some(action.parse::<Action>().map_err(|_| StatusCode::BAD_REQUEST)?),
+           None => None,
+       };
+       let cursor = match query.cursor.as_deref() {
+           Some(cursor) => Some(cursor.parse::<u64>().map_err(|_| StatusCode::BAD_REQUEST)?),
+           None => None,
+       };
+       let limit = query.limit.unwrap_or(events::DEFAULT_PAGE_SIZE).clamp(1, events::MAX_PAGE_SIZE);
+       let filter = EventFilter {
+           action,
+           from: query.from,
+           to: query.to,
+           tenant_id: query.tenant_id,
+           human_hash_id: query.human_hash_id,
+           verifier_id: query.verifier_id,
+       };
+
+       let store = state.events.store.lock().unwrap();
+       let page = store.query(&filter, cursor, limit);
+       match query.format.as_deref().unwrap_or("json") {
+           "json" => Ok(Json(EventReport {
+               events: page.events.into_iter().cloned().collect(),
+               next_cursor: page.next_cursor,
+           })
+           .into_response()),
+           "csv" => {
+               let mut response = (
+                   [(header::CONTENT_TYPE, "text/csv"), (header::CONTENT_DISPOSITION, "attachment; filename=\"events.csv\"")],
+                   events::to_csv(&page.events),
+               )
+                   .into_response();
+               if let Some(next_cursor) = page.next_cursor.and_then(|cursor| cursor.parse().ok()) {
+                   response.headers_mut().insert("x-next-cursor", next_cursor);
+               }
+               Ok(response)
+           }
+           _ => Err(StatusCode::BAD_REQUEST),
+       }
+   }
+
+   /// Ingests sequence codes issued by the other services. Only codes
+   /// carrying a valid MAC are accepted, each once, so the store cannot be
+   /// seeded with forged or replayed codes. The tenant is the one that
+   /// enrolled the identity; verdicts are only recorded by this service.
+   async fn ingest_event(State(state): State<AppState>, _: Service, Json(event): Json<IngestedEvent>) -> Result<Json<EventRecord>, StatusCode> {
+       let code = SequenceCode::parse(&event.sequence_code).map_err(|_| StatusCode::BAD_REQUEST)?;
+       if event.action.parse::<Action>() != Ok(code.action) || code.action == Action::Verify {
+           return Err(StatusCode::BAD_REQUEST);
+       }
+       if !code.verify(state.events.secret()) {
+           error!("Rejecting event with forged sequence code {} from {}", event.sequence_code, event.service);
+           return Err(StatusCode::UNAUTHORIZED);
+       }
+       let tenant_id = event
+           .human_hash_id
+           .as_deref()
+           .and_then(|id| state.enrollments.store.lock().unwrap().completed_for(id).and_then(|saga| saga.tenant_id.clone()));
+       let event = NewEvent {
+           sequence_code: event.sequence_code,
+           action: code.action.to_string(),
+           service: event.service,
+           tenant_id,
+           human_hash_id: event.human_hash_id,
+           verifier_id: None,
+           verified: None,
+       };
+       let record = state.events.record(event).map_err(|e| match e.kind() {
+           std::io::ErrorKind::AlreadyExists => StatusCode::CONFLICT,
+           _ => {
+               error!("Failed to persist event: {}", e);
+               StatusCode::INTERNAL_SERVER_ERROR
+           }
+       })?;
+       info!("Recorded {} event from {}: {}", record.event.action, record.event.service, record.event.sequence_code);
+       Ok(Json(record))
+   }
+
+   /// Lets support staff check that a code a user presents was issued by us.
+   async fn check_sequence_code(State(state): State<AppState>, caller: Caller, Path(sequence_code): Path<String>) -> Result<Json<SequenceCodeCheck>, StatusCode> {
+       caller.require(Scope::Report)?;
+       let code = SequenceCode::parse(&sequence_code).map_err(|_| StatusCode::BAD_REQUEST)?;
+       // A verification code is bound to the identity verified, as recorded.
+       let subject = state.events.store.lock().unwrap().get(&sequence_code).and_then(|record| record.event.human_hash_id.clone());
+       let secret = state.events.secret();
+       Ok(Json(SequenceCodeCheck {
+           valid: code.verify(secret) || subject.is_some_and(|subject| code.verify_for(secret, &subject)),
+           action: code.action.to_string(),
+           issued_at: code.timestamp,
+           sequence_code,
+       }))
+   }
+
+   async fn create_subscription(State(state): State<AppState>, caller: Caller, Json(request): Json<SubscriptionRequest>) -> Result<(StatusCode, Json<Subscription>), StatusCode> {
+       let tenant = caller.require(Scope::Verify)?;
+       if request.event_types.is_empty() {
+           return Err(StatusCode::BAD_REQUEST);
+       }
+       let url = webhooks::subscriber_url(&request.url).map_err(|e| {
+           info!("Refusing webhook URL {} for {}: {}", request.url, tenant.id, e);
+           StatusCode::BAD_REQUEST
+       })?;
+       if let Err(e) = webhooks::resolve_public(&url).await {
+           info!("Refusing webhook URL {} for {}: {}", request.url, tenant.id, e);
+           return Err(StatusCode::BAD_REQUEST);
+       }
+       let subscription = state
+           .events
+           .webhooks
+           .lock()
+           .unwrap()
+           .subscribe(tenant.id.clone(), request.url, request.event_types, Utc::now().timestamp())
+           .map_err(|e| {
+               error!("Failed to persist webhook subscription: {}", e);
+               StatusCode::INTERNAL_SERVER_ERROR
+           })?;
+       info!("Relying party {} subscribed {} to {:?}", subscription.relying_party, subscription.url, subscription.event_types);
+       Ok((StatusCode::CREATED, Json(subscription)))
+   }
+
+   async fn read_subscription(State(state): State<AppState>, caller: Caller, Path(id): Path<String>) -> Result<Json<Subscription>, StatusCode> {
+       let tenant = caller.require(Scope::Verify)?;
+       owned_subscription(&state, &tenant.id, &id).map(|s| Json(s.public_view())).ok_or(StatusCode::NOT_FOUND)
+   }
+
+   async fn delete_subscription(State(state): State<AppState>, caller: Caller, Path(id): Path<String>) -> StatusCode {
+       let tenant = match caller.require(Scope::Verify) {
+           Ok(tenant) => tenant,
+           Err(status) => return status,
+       };
+       if owned_subscription(&state, &tenant.id, &id).is_none() {
+           return StatusCode::NOT_FOUND;
+       }
+       match state.events.webhooks.lock().unwrap().unsubscribe(&id) {
+           Ok(true) => StatusCode::NO_CONTENT,
+           Ok(false) => StatusCode::NOT_FOUND,
+           Err(e) => {
+               error!("Failed to persist webhook unsubscription: {}", e);
+               StatusCode::INTERNAL_SERVER_ERROR
+           }
+       }
+   }
+
+   async fn list_deliveries(State(state): State<AppState>, caller: Caller, Path(id): Path<String>, Query(query): Query<DeliveryQuery>) -> Result<Json<Vec<webhooks::Delivery>>, StatusCode> {
+       let tenant = caller.require(Scope::Verify)?;
+       let webhooks = state.events.webhooks.lock().unwrap();
+       if webhooks.subscription(&id).is_none_or(|s| s.relying_party != tenant.id) {
+           return Err(StatusCode::NOT_FOUND);
+       }
+       Ok(Json(webhooks.deliveries(&id, query.status)))
+   }
+
+   async fn replay_subscription(State(state): State<AppState>, caller: Caller, Path(id): Path<String>, Json(request): Json<ReplayRequest>) -> Result<Json<ReplayResult>, StatusCode> {
+       let tenant = caller.require(Scope::Verify)?;
+       let mut webhooks = state.events.webhooks.lock().unwrap();
+       if webhooks.subscription(&id).is_none_or(|s| s.relying_party != tenant.id) {
+           return Err(StatusCode::NOT_FOUND);
+       }
+       let requeued = webhooks.replay_since(&id, request.since, Utc::now().timestamp_millis()).map_err(|e| {
+           error!("Failed to persist webhook replay: {}", e);
+           StatusCode::INTERNAL_SERVER_ERROR
+       })?;
+       info!("Replaying {} deliveries to subscription {}", requeued, id);
+       Ok(Json(ReplayResult { requeued }))
+   }
+
+   async fn replay_delivery(State(state): State<AppState>, caller: Caller, Path(id): Path<u64>) -> Result<Json<webhooks::Delivery>, StatusCode> {
+       let tenant = caller.require(Scope::Verify)?;
+       let mut webhooks = state.events.webhooks.lock().unwrap();
+       let owned = webhooks
+           .delivery(id)
+           .and_then(|delivery| webhooks.subscription(&delivery.subscription_id))
+           .is_some_and(|s| s.relying_party == tenant.id);
+       if !owned {
+           return Err(StatusCode::NOT_FOUND);
+       }
+       match webhooks.replay(id, Utc::now().timestamp_millis()) {
+           Ok(Some(delivery)) => Ok(Json(delivery)),
+           Ok(None) => Err(StatusCode::NOT_FOUND),
+           Err(e) => {
+               error!("Failed to persist webhook replay: {}", e);
+               Err(StatusCode::INTERNAL_SERVER_ERROR)
+           }
+       }
+   }
+
+   fn owned_subscription(state: &AppState, tenant_id: &str, id: &str) -> Option<Subscription> {
+       state.events.webhooks.lock().unwrap().subscription(id).filter(|s| s.relying_party == tenant_id).cloned()
+   }
+
+   async fn did_document(State(state): State<AppState>) -> Json<serde_json::Value> {
+       Json(state.credentials.did_document())
+   }
+
+   /// A revocation list, signed fresh on each request. Verifiers may cache
+   /// it until the `validUntil` inside, which matches the max-age here.
+   async fn status_list(State(state): State<AppState>, Path(list): Path<usize>) -> Result<Response, StatusCode> {
+       let ttl = state.credentials.status_list_ttl_secs();
+       let credential = state.credentials.status_list_credential(list, Utc::now().timestamp()).ok_or(StatusCode::NOT_FOUND)?;
+       Ok((
+           [(header::CONTENT_TYPE, status_list::MEDIA_TYPE.to_string()), (header::CACHE_CONTROL, format!("public, max-age={}", ttl))],
+           credential,
+       )
+           .into_response())
+   }
+
+   async fn revoke_credential(State(state): State<AppState>, _: Admin, Path(id): Path<String>, Json(request): Json<CredentialRevocation>) -> Result<Json<IssuedCredential>, StatusCode> {
+       let record = match state.credentials.revoke(&state.credentials.credential_url(&id), Utc::now().timestamp()) {
+           Ok(Some(record)) => record,
+           Ok(None) => return Err(StatusCode::NOT_FOUND),
+           Err(e) => {
+               error!("Failed to persist credential revocation: {}", e);
+               return Err(StatusCode::INTERNAL_SERVER_ERROR);
+           }
+       };
+       let sequence_code = state.events.issue(Action::Revoke, None, Some(&record.human_hash_id), None, None);
+       info!("Revoked credential {} ({}), sequence_code: {}", record.id, request.reason, sequence_code);
+       Ok(Json(record))
+   }
+
+   /// Checks a credential exactly as an offline verifier would, against the
+   /// current DID document and status list.
+   async fn verify_credential(State(state): State<AppState>, Json(request): Json<CredentialVerificationRequest>) -> Json<CredentialVerification> {
+       match state.credentials.check(&request.credential, Utc::now().timestamp()) {
+           Ok(credential) => Json(CredentialVerification { verified: true, credential: Some(credential), reason: None }),
+           Err(e) => Json(CredentialVerification { verified: false, credential: None, reason: Some(e.to_string()) }),
+       }
+   }
+
+   async fn oidc_discovery(State(state): State<AppState>) -> Json<serde_json::Value> {
+       Json(oidc::discovery(&state.config.oidc))
+   }
+
+   async fn oidc_jwks(State(state): State<AppState>) -> Json<serde_json::Value> {
+       Json(state.oidc_keys.jwks())
+   }
+
+   /// Starts a login. The response carries the challenge the user's wallet
+   /// binds its proof to; the wallet then posts the proof to
+   /// `/oidc/authorize/complete`.
+   async fn oidc_authorize(State(state): State<AppState>, Query(request): Query<AuthorizationRequest>) -> Response {
+       // Without a registered client and redirect URI there is nowhere safe
+       // to send the user back to, so those errors are shown directly.
+       let Some(client) = state.config.oidc.clients.iter().find(|c| c.client_id == request.client_id) else {
+           return oidc_error(StatusCode::BAD_REQUEST, "invalid_client", "unknown client_id");
+       };
+       if !client.redirect_uris.contains(&request.redirect_uri) {
+           return oidc_error(StatusCode::BAD_REQUEST, "invalid_request", "redirect_uri is not registered");
+       }
+
+       let problem = if request.response_type != "code" {
+           Some(("unsupported_response_type", "only the code flow is supported"))
+       } else if !request.scope.split_whitespace().any(|scope| scope == "openid") {
+           Some(("invalid_scope", "scope must include openid"))
+       } else if request.code_challenge.is_none() || request.code_challenge_method.as_deref() != Some("S256") {
+           Some(("invalid_request", "PKCE with S256 is required"))
+       } else {
+           None
+       };
+       if let Some((error, description)) = problem {
+           return redirect_with(&request.redirect_uri, &[("error", error), ("error_description", description)], request.state.as_deref());
+       }
+
+       let now = Utc::now().timestamp();
+       let challenge = state.challenges.lock().unwrap().issue(&client.client_id, state.config.challenge_ttl_secs, now);
+       let request_id = state.oidc.lock().unwrap().begin(
+           PendingAuthorization {
+               client_id: request.client_id,
+               redirect_uri: request.redirect_uri,
+               state: request.state,
+               nonce: request.nonce,
+               code_challenge: request.code_challenge.unwrap_or_default(),
+               scope: request.scope,
+               challenge: challenge.clone(),
+           },
+           now,
+       );
+       info!("Started OIDC authorization for client {}", client.client_id);
+       Json(AuthorizationPending { request_id, challenge }).into_response()
+   }
+
+   /// Authenticates the user with a presentation proof through the regular
+   /// verification path and redirects back to the client with a code.
+   async fn oidc_complete(State(state): State<AppState>, Json(request): Json<AuthenticationRequest>) -> Response {
+       let Some(pending) = state.oidc.lock().unwrap().take_pending(&request.request_id) else {
+           return oidc_error(StatusCode::BAD_REQUEST, "invalid_request", "unknown or expired request_id");
+       };
+       if request.proof.public_inputs.binding.challenge != pending.challenge.challenge {
+           return redirect_with(&pending.redirect_uri, &[("error", "access_denied"), ("error_description", "proof is not bound to this request")], pending.state.as_deref());
+       }
+
+       let result = verify_presented(&state, None, &pending.client_id, &request.proof).await;
+       if !result.verified {
+           let description = result.reason.unwrap_or_default();
+           return redirect_with(&pending.redirect_uri, &[("error", "access_denied"), ("error_description", &description)], pending.state.as_deref());
+       }
+
+       let human_hash_id = request.proof.public_inputs.human_hash_id;
+       let pop_tier = if state.enrollments.store.lock().unwrap().completed_for(&human_hash_id).is_some() { PopTier::Kyc } else { PopTier::Biometric };
+       let sequence_code = state.events.issue(Action::Authenticate, None, Some(&human_hash_id), Some(&pending.client_id), Some(true));
+       info!("Authenticated {} for client {}, sequence_code: {}", human_hash_id, pending.client_id, sequence_code);
+
+       let now = Utc::now().timestamp();
+       let code = state.oidc.lock().unwrap().issue_code(
+           AuthorizationCode {
+               client_id: pending.client_id,
+               redirect_uri: pending.redirect_uri.clone(),
+               nonce: pending.nonce,
+               code_challenge: pending.code_challenge,
+               scope: pending.scope,
+               human_hash_id,
+               pop_tier,
+               auth_time: now,
+               expires_at: now + state.config.oidc.code_ttl_secs,
+           },
+           now,
+       );
+       redirect_with(&pending.redirect_uri, &[("code", &code)], pending.state.as_deref())
+   }
+
+   async fn oidc_token(State(state): State<AppState>, Form(request): Form<TokenRequest>) -> Result<Json<TokenResponse>, Response> {
+       if request.grant_type != "authorization_code" {
+           return Err(oidc_error(StatusCode::BAD_REQUEST, "unsupported_grant_type", "only authorization_code is supported"));
+       }
+       let Some(client) = state.config.oidc.clients.iter().find(|c| c.client_id == request.client_id) else {
+           return Err(oidc_error(StatusCode::UNAUTHORIZED, "invalid_client", "unknown client_id"));
+       };
+       if !client.authenticates(request.client_secret.as_deref()) {
+           return Err(oidc_error(StatusCode::UNAUTHORIZED, "invalid_client", "client authentication failed"));
+       }
+
+       let now = Utc::now().timestamp();
+       let code = state.oidc.lock().unwrap().redeem_code(&request.code, now);
+       let code = match code {
+           Some(code) if code.client_id == request.client_id && code.redirect_uri == request.redirect_uri => code,
+           _ => return Err(oidc_error(StatusCode::BAD_REQUEST, "invalid_grant", "code is invalid, expired or already used")),
+       };
+       if !oidc::verify_pkce(&request.code_verifier, &code.code_challenge) {
+           return Err(oidc_error(StatusCode::BAD_REQUEST, "invalid_grant", "code_verifier does not match"));
+       }
+
+       let sub = state.oidc_keys.pairwise_subject(&client.sector(), &code.human_hash_id);
+       let ttl = state.config.oidc.token_ttl_secs;
+       let id_token = state.oidc_keys.sign_jwt(&IdTokenClaims {
+           iss: state.config.oidc.issuer.trim_end_matches('/'),
+           sub: &sub,
+           aud: &client.client_id,
+           iat: now,
+           exp: now + ttl,
+           auth_time: code.auth_time,
+           nonce: code.nonce.as_deref(),
+           pop_verified: true,
+           pop_tier: code.pop_tier,
+       });
+       let user_info = UserInfo { sub, pop_verified: true, pop_tier: code.pop_tier };
+       let access_token = state.oidc.lock().unwrap().issue_access_token(user_info, now + ttl, now);
+       Ok(Json(TokenResponse {
+           access_token,
+           token_type: "Bearer",
+           expires_in: ttl,
+           id_token,
+           scope: code.scope,
+       }))
+   }
+
+   async fn oidc_userinfo(State(state): State<AppState>, headers: HeaderMap) -> Result<Json<UserInfo>, StatusCode> {
+       let token = headers
+           .get(header::AUTHORIZATION)
+           .and_then(|value| value.to_str().ok())
+           .and_then(|value| value.strip_prefix("Bearer "))
+           .ok_or(StatusCode::UNAUTHORIZED)?;
+       state.oidc.lock().unwrap().user_info(token, Utc::now().timestamp()).map(Json).ok_or(StatusCode::UNAUTHORIZED)
+   }
+
+   fn oidc_error(status: StatusCode, error: &'static str, description: &str) -> Response {
+       (status, Json(OidcError { error, error_description: Some(description.to_string()) })).into_response()
+   }
+
+   fn redirect_with(redirect_uri: &str, params: &[(&str, &str)], client_state: Option<&str>) -> Response {
+       let Ok(mut url) = reqwest::Url::parse(redirect_uri) else {
+           return oidc_error(StatusCode::BAD_REQUEST, "invalid_request", "redirect_uri is not a valid URL");
+       };
+       {
+           let mut query = url.query_pairs_mut();
+           query.extend_pairs(params);
+           if let Some(client_state) = client_state {
+               query.append_pair("state", client_state);
+           }
+       }
+       Redirect::to(url.as_str()).into_response()
+   }
+
+   async fn create_tenant(State(state): State<AppState>, _: Admin, Json(request): Json<TenantRequest>) -> Result<(StatusCode, Json<TenantCreated>), StatusCode> {
+       let rate_limit = request.rate_limit.unwrap_or(state.config.tenants.default_rate_limit);
+       let (tenant, api_key) = state
+           .tenants
+           .lock()
+           .unwrap()
+           .create(request.name, request.scopes, rate_limit, request.settings, Utc::now().timestamp())
+           .map_err(|e| {
+               error!("Failed to persist tenant: {}", e);
+               StatusCode::INTERNAL_SERVER_ERROR
+           })?;
+       info!("Created tenant {} ({}) with scopes {:?}", tenant.id, tenant.name, tenant.scopes);
+       Ok((StatusCode::CREATED, Json(TenantCreated { tenant: tenant.public_view(), api_key })))
+   }
+
+   async fn list_tenants(State(state): State<AppState>, _: Admin) -> Json<Vec<Tenant>> {
+       Json(state.tenants.lock().unwrap().list())
+   }
+
+   async fn read_tenant(State(state): State<AppState>, _: Admin, Path(id): Path<String>) -> Result<Json<Tenant>, StatusCode> {
+       state.tenants.lock().unwrap().get(&id).map(|tenant| Json(tenant.public_view())).ok_or(StatusCode::NOT_FOUND)
+   }
+
+   async fn update_tenant(State(state): State<AppState>, _: Admin, Path(id): Path<String>, Json(update): Json<TenantUpdate>) -> Result<Json<Tenant>, StatusCode> {
+       match state.tenants.lock().unwrap().update(&id, update) {
+           Ok(Some(tenant)) => Ok(Json(tenant.public_view())),
+           Ok(None) => Err(StatusCode::NOT_FOUND),
+           Err(e) => {
+               error!("Failed to persist tenant {}: {}", id, e);
+               Err(StatusCode::INTERNAL_SERVER_ERROR)
+           }
+       }
+   }
+
+   async fn read_policy(caller: Caller) -> Result<Json<Policy>, StatusCode> {
+       let tenant = caller.require(Scope::Verify)?;
+       tenant.policy.clone().map(Json).ok_or(StatusCode::NOT_FOUND)
+   }
+
+   /// Replaces the caller's policy with a JSON or YAML document, chosen by
+   /// the request content type.
+   async fn replace_policy(State(state): State<AppState>, caller: Caller, headers: HeaderMap, body: String) -> Result<Json<Policy>, Response> {
+       let tenant = caller.require(Scope::Verify).map_err(IntoResponse::into_response)?;
+       let yaml = headers
+           .get(header::CONTENT_TYPE)
+           .and_then(|value| value.to_str().ok())
+           .is_some_and(|content_type| content_type.contains("yaml"));
+       let parsed = if yaml { serde_yaml::from_str::<Policy>(&body).map_err(|e| e.to_string()) } else { serde_json::from_str::<Policy>(&body).map_err(|e| e.to_string()) };
+       let policy = parsed.and_then(|policy| policy.validate().map(|()| policy)).map_err(|e| {
+           error!("Rejected policy for tenant {}: {}", tenant.id, e);
+           (StatusCode::BAD_REQUEST, e).into_response()
+       })?;
+       match state.tenants.lock().unwrap().set_policy(&tenant.id, Some(policy.clone())) {
+           Ok(_) => {
+               info!("Updated verification policy for tenant {}", tenant.id);
+               Ok(Json(policy))
+           }
+           Err(e) => {
+               error!("Failed to persist policy for tenant {}: {}", tenant.id, e);
+               Err(StatusCode::INTERNAL_SERVER_ERROR.into_response())
+           }
+       }
+   }
+
+   async fn delete_policy(State(state): State<AppState>, caller: Caller) -> Result<StatusCode, StatusCode> {
+       let tenant = caller.require(Scope::Verify)?;
+       match state.tenants.lock().unwrap().set_policy(&tenant.id, None) {
+           Ok(_) => Ok(StatusCode::NO_CONTENT),
+           Err(e) => {
+               error!("Failed to persist policy for tenant {}: {}", tenant.id, e);
+               Err(StatusCode::INTERNAL_SERVER_ERROR)
+           }
+       }
+   }
+
+   /// Adds a key alongside the tenant's existing ones.
+   async fn create_key(State(state): State<AppState>, _: Admin, Path(id): Path<String>) -> Result<(StatusCode, Json<KeyCreated>), StatusCode> {
+       add_key(&state, &id, None)
+   }
+
+   /// Adds a key and retires the others after a grace period, so the tenant
+   /// can roll the new key out without downtime.
+   async fn rotate_keys(State(state): State<AppState>, _: Admin, Path(id): Path<String>, Json(request): Json<KeyRotation>) -> Result<(StatusCode, Json<KeyCreated>), StatusCode> {
+       let grace_secs = request.grace_secs.unwrap_or(state.config.tenants.key_rotation_grace_secs).max(0);
+       add_key(&state, &id, Some(grace_secs))
+   }
+
+   fn add_key(state: &AppState, id: &str, retire_after: Option<i64>) -> Result<(StatusCode, Json<KeyCreated>), StatusCode> {
+       match state.tenants.lock().unwrap().add_key(id, retire_after, Utc::now().timestamp()) {
+           Ok(Some((key, api_key))) => {
+               info!("Issued API key {} for tenant {}", key.id, id);
+               Ok((StatusCode::CREATED, Json(KeyCreated { key, api_key })))
+           }
+           Ok(None) => Err(StatusCode::NOT_FOUND),
+           Err(e) => {
+               error!("Failed to persist API key for tenant {}: {}", id, e);
+               Err(StatusCode::INTERNAL_SERVER_ERROR)
+           }
+       }
+   }
+
+   async fn revoke_key(State(state): State<AppState>, _: Admin, Path((id, key_id)): Path<(String, String)>) -> Result<Json<ApiKey>, StatusCode> {
+       match state.tenants.lock().unwrap().revoke_key(&id, &key_id, Utc::now().timestamp()) {
+           Ok(Some(key)) => {
+               info!("Revoked API key {} of tenant {}", key_id, id);
+               Ok(Json(key))
+           }
+           Ok(None) => Err(StatusCode::NOT_FOUND),
+           Err(e) => {
+               error!("Failed to persist API key revocation for tenant {}: {}", id, e);
+               Err(StatusCode::INTERNAL_SERVER_ERROR)
+           }
+       }
+   }
+
+   /// Resolves the claimed identity on PoPChain and redeems the verifier's
+   /// challenge; returns the attestation, or the rejection reason on failure.
+   async fn check_presentation(state: &AppState, proof: &Proof, verifier_id: &str, proof_valid: bool) -> Result<ledger::Attestation, String> {
+       if !proof_valid {
+           return Err(BindingError::InvalidProof.to_string());
+       }
+       let inputs = &proof.public_inputs;
+       let attestation = ledger::fetch_attestation(&state.http, &state.config.popchain_url, &inputs.human_hash_id)
+           .await
+           .map_err(|e| e.to_string())?;
+       ledger::check_attestation(&attestation, &inputs.commitment, Utc::now().timestamp()).map_err(|e| e.to_string())?;
+       state
+           .challenges
+           .lock()
+           .unwrap()
+           .redeem(&inputs.binding, verifier_id, Utc::now().timestamp())
+           .map_err(|e| e.to_string())?;
+       Ok(attestation)
+   }
+
+   fn verify_mock_proof(proof: &str, public_inputs: &PublicInputs) -> bool {
+       // Mock zk-SNARK proof verification: `mock_proof_<payload>_<binding>`, where
+       // the binding digest covers the public inputs.
+       let Some(rest) = proof.strip_prefix("mock_proof_") else {
+           return false;
+       };
+       match rest.rsplit_once('_') {
+           Some((payload, binding)) => binding == binding_digest(payload, public_inputs),
+           None => false,
+       }
+   }
+
+   async fn log_to_popchain(state: &AppState, human_hash_id: &str, sequence_code: &str) {
+       match ledger::record_event(&state.http, &state.config.popchain_url, &state.popchain_token, human_hash_id, "VER", sequence_code).await {
+           Ok(()) => info!("Logged to PoPChain: human_hash_id={}, sequence_code={}", human_hash_id, sequence_code),
+           Err(e) => error!("Failed to log {} to PoPChain: {}", sequence_code, e),
+       }
    }
 
    #[tokio::main]
@@ -66,13 +1310,366 @@ use axum::{routing::post, Json, Router};
            .with_env_filter(EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")))
            .with_thread_ids(true)
            .init();
+
+       let config: Config = serde_json::from_str(
+           &fs::read_to_string("system_config.json").expect("Failed to read system_config.json")
+       ).expect("Invalid system_config.json");
+       
+       let http = reqwest::Client::new();
+       let webhook_store = Arc::new(Mutex::new(WebhookStore::open(&config.webhooks.store_path).expect("Failed to open webhook store")));
+       let events = Recorder::new(
+           EventStore::open(&config.events_path).expect("Failed to open event store"),
+           webhook_store.clone(),
+           sequence_code::secret_from_env().expect("Missing sequence code secret").into(),
+       );
+       tokio::spawn(webhooks::run_dispatcher(webhook_store, config.webhooks.clone()));
+
+       let credentials = Arc::new(
+           CredentialIssuer::open(
+               config.credentials.clone(),
+               jws::signing_key_from_env(credentials::SIGNING_KEY_ENV).expect("Missing credential signing key"),
+           )
+           .expect("Failed to open credential store"),
+       );
+       let popchain_token: Arc<str> = match std::env::var(ledger::SERVICE_TOKEN_ENV) {
+           Ok(token) if !token.is_empty() => token.into(),
+           _ => panic!("{} must be set", ledger::SERVICE_TOKEN_ENV),
+       };
+       let store = SagaStore::open(&config.saga.store_path).expect("Failed to open saga store");
+       let enrollments = Orchestrator {
+           http: http.clone(),
+           biometric_url: config.biometric_url.clone(),
+           oracle_url: config.oracle_url.clone(),
+           popchain_url: config.popchain_url.clone(),
+           popchain_token: popchain_token.clone(),
+           config: config.saga.clone(),
+           store: Arc::new(Mutex::new(store)),
+           events: events.clone(),
+           credentials: credentials.clone(),
+       };
+       for saga in enrollments.store.lock().unwrap().unfinished() {
+           info!("Resuming enrollment {} ({:?})", saga.sequence_code, saga.status);
+           tokio::spawn(enrollments.clone().drive(saga));
+       }
+       tokio::spawn(enrollments.clone().watch_payments());
+
+       let state = AppState {
+           config: config.clone(),
+           challenges: Arc::new(Mutex::new(ChallengeStore::default())),
+           http,
+           enrollments,
+           events,
+           qr_signer: Arc::new(QrSigner::from_env(&Secp256k1::signing_only()).expect("Missing QR signing key")),
+           oidc_keys: Arc::new(OidcKeys::from_env().expect("Missing OIDC keys")),
+           oidc: Arc::new(Mutex::new(OidcStore::default())),
+           credentials,
+           tenants: Arc::new(Mutex::new(TenantStore::open(&config.tenants.store_path).expect("Failed to open tenant store"))),
+           rate_limiter: Arc::new(Mutex::new(RateLimiter::default())),
+           admin_key_hash: match std::env::var(tenants::ADMIN_KEY_ENV) {
+               Ok(key) if !key.is_empty() => tenants::hash_key(&key).into(),
+               _ => panic!("{} must be set", tenants::ADMIN_KEY_ENV),
+           },
+           popchain_token,
+           ingest_key_hash: match std::env::var(events::INGEST_TOKEN_ENV) {
+               Ok(key) if !key.is_empty() => tenants::hash_key(&key).into(),
+               _ => panic!("{} must be set", events::INGEST_TOKEN_ENV),
+           },
+           attribute_issuer: std::env::var(ATTRIBUTE_ISSUER_ENV)
+               .ok()
+               .and_then(|key| hex::decode(key.trim()).ok())
+               .and_then(|bytes| XOnlyPublicKey::from_slice(&bytes).ok())
+               .unwrap_or_else(|| panic!("{} must be set to the oracle's x-only attribute key", ATTRIBUTE_ISSUER_ENV)),
+           oracle_token: match std::env::var(ORACLE_TOKEN_ENV) {
+               Ok(token) if !token.is_empty() => token.into(),
+               _ => panic!("{} must be set", ORACLE_TOKEN_ENV),
+           },
+       };
        
        let app = Router::new()
-           .route("/identity/verify", post(verify_proof));
+           .route("/identity/enroll", post(start_enrollment))
+           .route("/identity/enroll/:sequence_code", get(enrollment_status))
+           .route("/identity/challenge", post(issue_challenge))
+           .route("/report/events", get(report_events).post(ingest_event))
+           .route("/report/sequence/:sequence_code", get(check_sequence_code))
+           .route("/webhooks/subscriptions", post(create_subscription))
+           .route("/webhooks/subscriptions/:id", get(read_subscription).delete(delete_subscription))
+           .route("/webhooks/subscriptions/:id/deliveries", get(list_deliveries))
+           .route("/webhooks/subscriptions/:id/replay", post(replay_subscription))
+           .route("/webhooks/deliveries/:id/replay", post(replay_delivery))
+           .route("/identity/verify", post(verify_proof))
+           .route("/identity/verify/batch", post(verify_proof_batch))
+           .route("/identity/verify/predicates", post(verify_predicates))
+           .route("/identity/attributes/challenge", post(issue_attribute_challenge))
+           .route("/identity/attributes", post(request_attribute_credential))
+           .route("/identity/verify/qr", post(verify_qr))
+           .route("/identity/qr", post(encode_qr))
+           .route("/identity/qr/key", get(qr_key))
+           .route("/policy", get(read_policy).put(replace_policy).delete(delete_policy))
+           .route("/admin/tenants", get(list_tenants).post(create_tenant))
+           .route("/admin/tenants/:id", get(read_tenant).patch(update_tenant))
+           .route("/admin/tenants/:id/keys", post(create_key))
+           .route("/admin/tenants/:id/keys/rotate", post(rotate_keys))
+           .route("/admin/tenants/:id/keys/:key_id", delete(revoke_key))
+           .route("/admin/report/events", get(admin_report_events))
+           .route("/.well-known/did.json", get(did_document))
+           .route("/credentials/status/:list", get(status_list))
+           .route("/credentials/verify", post(verify_credential))
+           .route("/credentials/:id/revoke", post(revoke_credential))
+           .route("/.well-known/openid-configuration", get(oidc_discovery))
+           .route("/oidc/jwks", get(oidc_jwks))
+           .route("/oidc/authorize", get(oidc_authorize))
+           .route("/oidc/authorize/complete", post(oidc_complete))
+           .route("/oidc/token", post(oidc_token))
+           .route("/oidc/userinfo", get(oidc_userinfo))
+           .with_state(state);
        
-       let addr = SocketAddr::from(([0, 0, 0, 0], 8081));
+       let addr = SocketAddr::from(([0, 0, 0, 0], config.port));
        info!("Starting system service on {}", addr);
        axum::serve(tokio::net::TcpListener::bind(addr).await.unwrap(), app)
            .await
            .unwrap();
    }
+
+   #[cfg(test)]
+   mod tests {
+       use super::*;
+       use std::sync::atomic::{AtomicUsize, Ordering};
+
+       const COMMITMENT: &str = "biometric-hash";
+
+       /// PoPChain with a live attestation for every identity, counting the
+       /// verification events written back to it.
+       async fn mock_popchain(recorded: Arc<AtomicUsize>) -> String {
+           let app = Router::new()
+               .route(
+                   "/ledger/attestation/:human_hash_id",
+                   get(|Path(human_hash_id): Path<String>| async move {
+                       Json(serde_json::json!({
+                           "attestation_id": format!("att_{}", human_hash_id),
+                           "biometric_hash": COMMITMENT,
+                           "expires_at": Utc::now().timestamp() + 3600,
+                           "revoked": false,
+                       }))
+                   }),
+               )
+               .route(
+                   "/ledger/event",
+                   post(move || async move {
+                       recorded.fetch_add(1, Ordering::SeqCst);
+                       StatusCode::OK
+                   }),
+               );
+           let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+           let url = format!("http://{}", listener.local_addr().unwrap());
+           tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+           url
+       }
+
+       /// The service as configured in `system_config.json`, with its stores
+       /// in a fresh directory.
+       fn test_state(name: &str, popchain_url: &str) -> AppState {
+           let dir = std::env::temp_dir().join(format!("system-{}-{}", name, std::process::id()));
+           let _ = fs::remove_dir_all(&dir);
+           fs::create_dir_all(&dir).unwrap();
+           let path = |file: &str| dir.join(file).to_string_lossy().into_owned();
+           let mut config: serde_json::Value = serde_json::from_str(&fs::read_to_string("system_config.json").unwrap()).unwrap();
+           config["popchain_url"] = popchain_url.into();
+           config["events_path"] = path("events.jsonl").into();
+           for store in ["saga", "webhooks", "credentials", "tenants"] {
+               config[store]["store_path"] = path(&format!("{}.json", store)).into();
+           }
+           let config: Config = serde_json::from_value(config).unwrap();
+
+           let webhook_store = Arc::new(Mutex::new(WebhookStore::open(&config.webhooks.store_path).unwrap()));
+           let events = Recorder::new(EventStore::open(&config.events_path).unwrap(), webhook_store, Arc::from(&b"sequence-secret"[..]));
+           let credentials = Arc::new(CredentialIssuer::open(config.credentials.clone(), p256::ecdsa::SigningKey::from_slice(&[7; 32]).unwrap()).unwrap());
+           let popchain_token: Arc<str> = "popchain-token".into();
+           let http = reqwest::Client::new();
+           let enrollments = Orchestrator {
+               http: http.clone(),
+               biometric_url: config.biometric_url.clone(),
+               oracle_url: config.oracle_url.clone(),
+               popchain_url: config.popchain_url.clone(),
+               popchain_token: popchain_token.clone(),
+               config: config.saga.clone(),
+               store: Arc::new(Mutex::new(SagaStore::open(&config.saga.store_path).unwrap())),
+               events: events.clone(),
+               credentials: credentials.clone(),
+           };
+           AppState {
+               challenges: Arc::new(Mutex::new(ChallengeStore::default())),
+               http,
+               enrollments,
+               events,
+               qr_signer: Arc::new(QrSigner::new(&Secp256k1::signing_only(), secp256k1::SecretKey::from_slice(&[9; 32]).unwrap())),
+               oidc_keys: Arc::new(OidcKeys::new(p256::ecdsa::SigningKey::from_slice(&[8; 32]).unwrap(), vec![10; 32])),
+               oidc: Arc::new(Mutex::new(OidcStore::default())),
+               credentials,
+               tenants: Arc::new(Mutex::new(TenantStore::open(&config.tenants.store_path).unwrap())),
+               rate_limiter: Arc::new(Mutex::new(RateLimiter::default())),
+               admin_key_hash: tenants::hash_key("admin-key").into(),
+               popchain_token,
+               ingest_key_hash: tenants::hash_key("ingest-token").into(),
+               attribute_issuer: secp256k1::Keypair::from_seckey_slice(&Secp256k1::new(), &[11; 32]).unwrap().x_only_public_key().0,
+               oracle_token: "oracle-token".into(),
+               config,
+           }
+       }
+
+       fn tenant(max_batch_size: Option<usize>) -> Tenant {
+           Tenant {
+               id: "rp_test".to_string(),
+               name: "Test RP".to_string(),
+               scopes: vec![Scope::Verify],
+               rate_limit: RateLimit { requests_per_minute: 600, burst: 60 },
+               settings: TenantSettings { challenge_ttl_secs: None, max_batch_size },
+               policy: None,
+               disabled: false,
+               keys: Vec::new(),
+               created_at: 0,
+           }
+       }
+
+       /// A mock proof over a fresh challenge issued to `verifier_id`.
+       fn proof(state: &AppState, verifier_id: &str, human_hash_id: &str) -> Proof {
+           let binding = state.challenges.lock().unwrap().issue(verifier_id, 300, Utc::now().timestamp());
+           let public_inputs = PublicInputs { human_hash_id: human_hash_id.to_string(), commitment: COMMITMENT.to_string(), binding };
+           Proof { proof: format!("mock_proof_payload_{}", binding_digest("payload", &public_inputs)), public_inputs }
+       }
+
+       #[tokio::test]
+       async fn batch_checks_every_item_and_reports_them_in_order() {
+           let recorded = Arc::new(AtomicUsize::new(0));
+           let state = test_state("batch", &mock_popchain(recorded.clone()).await);
+           let mut proofs: Vec<Proof> = (0..40).map(|i| proof(&state, "rp_test", &format!("human_{}", i))).collect();
+           proofs[3].proof.push('0');
+           proofs[17] = proof(&state, "rp_other", "human_17");
+           proofs[29].public_inputs.commitment = "other-hash".to_string();
+           proofs[29].proof = format!("mock_proof_payload_{}", binding_digest("payload", &proofs[29].public_inputs));
+
+           let Json(result) = verify_proof_batch(State(state.clone()), Caller(tenant(None)), Json(BatchProofs { proofs })).await.unwrap();
+           assert_eq!(result.results.iter().map(|item| item.index).collect::<Vec<_>>(), (0..40).collect::<Vec<_>>());
+           let failed: Vec<usize> = result.results.iter().filter(|item| !item.verified).map(|item| item.index).collect();
+           assert_eq!(failed, vec![3, 17, 29]);
+           assert_eq!(result.results[3].reason.as_deref(), Some("invalid_proof"));
+           assert_eq!(result.results[17].reason.as_deref(), Some("wrong_verifier"));
+           assert_eq!(result.results[29].reason.as_deref(), Some("commitment_mismatch"));
+           assert_eq!((result.verified_count, result.failed_count), (37, 3));
+           assert_eq!(recorded.load(Ordering::SeqCst), 37);
+       }
+
+       /// An oracle issuing credentials for one verified identity to callers
+       /// with the service token, counting the credentials issued.
+       async fn mock_oracle(issued: Arc<AtomicUsize>) -> String {
+           let app = Router::new().route(
+               "/oracle/credential",
+               post(move |headers: HeaderMap, Json(request): Json<serde_json::Value>| async move {
+                   if api_key(&headers) != Some("oracle-token") {
+                       return Err(StatusCode::UNAUTHORIZED);
+                   }
+                   let secp = Secp256k1::new();
+                   let issuer = secp256k1::Keypair::from_seckey_slice(&secp, &[11; 32]).unwrap();
+                   let holder_key = hex::decode(request["holderKey"].as_str().unwrap()).unwrap();
+                   let holder_key = XOnlyPublicKey::from_slice(&holder_key).unwrap();
+                   let human_hash_id = request["humanHashId"].as_str().unwrap();
+                   let (credential, openings) =
+                       disclosure::issue_credential(&secp, &issuer, "oracle", human_hash_id, &holder_key, "1990-01-02", "NL", 1_800_000_000).unwrap();
+                   issued.fetch_add(1, Ordering::SeqCst);
+                   Ok(Json(AttributeCredentialIssued { credential, openings }))
+               }),
+           );
+           let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+           let url = format!("http://{}", listener.local_addr().unwrap());
+           tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+           url
+       }
+
+       #[tokio::test]
+       async fn attribute_credentials_go_to_the_authenticated_holder_only() {
+           let issued = Arc::new(AtomicUsize::new(0));
+           let mut state = test_state("attributes", &mock_popchain(Arc::new(AtomicUsize::new(0))).await);
+           state.config.oracle_url = mock_oracle(issued.clone()).await;
+           let secp = Secp256k1::new();
+           let holder = secp256k1::Keypair::from_seckey_slice(&secp, &[12; 32]).unwrap();
+           let request = |proof: Proof| Json(AttributeCredentialRequest { proof, holder_key: hex::encode(holder.x_only_public_key().0.serialize()) });
+
+           // A proof over a challenge meant for a relying party, or a forged one.
+           let rejected = request_attribute_credential(State(state.clone()), request(proof(&state, "rp_test", "human_1"))).await;
+           assert_eq!(rejected.err(), Some(StatusCode::UNAUTHORIZED));
+           let mut forged = proof(&state, ATTRIBUTE_VERIFIER_ID, "human_1");
+           forged.public_inputs.human_hash_id = "human_2".to_string();
+           let rejected = request_attribute_credential(State(state.clone()), request(forged)).await;
+           assert_eq!(rejected.err(), Some(StatusCode::UNAUTHORIZED));
+           assert_eq!(issued.load(Ordering::SeqCst), 0);
+
+           let authenticated = proof(&state, ATTRIBUTE_VERIFIER_ID, "human_1");
+           let challenge = authenticated.public_inputs.binding.clone();
+           let Json(issued_credential) = request_attribute_credential(State(state.clone()), request(authenticated)).await.unwrap();
+           assert_eq!(issued_credential.credential.human_hash_id, "human_1");
+           assert_eq!(issued_credential.credential.holder_key, hex::encode(holder.x_only_public_key().0.serialize()));
+
+           // The challenge is spent.
+           let public_inputs = PublicInputs { human_hash_id: "human_1".to_string(), commitment: COMMITMENT.to_string(), binding: challenge };
+           let replayed = Proof { proof: format!("mock_proof_payload_{}", binding_digest("payload", &public_inputs)), public_inputs };
+           assert_eq!(request_attribute_credential(State(state.clone()), request(replayed)).await.err(), Some(StatusCode::UNAUTHORIZED));
+           assert_eq!(issued.load(Ordering::SeqCst), 1);
+       }
+
+       #[tokio::test]
+       async fn predicate_presentations_must_come_from_the_holder() {
+           let state = test_state("predicates", &mock_popchain(Arc::new(AtomicUsize::new(0))).await);
+           let secp = Secp256k1::new();
+           let issuer = secp256k1::Keypair::from_seckey_slice(&secp, &[11; 32]).unwrap();
+           let holder = secp256k1::Keypair::from_seckey_slice(&secp, &[12; 32]).unwrap();
+           let (credential, openings) =
+               disclosure::issue_credential(&secp, &issuer, "oracle", "human_1", &holder.x_only_public_key().0, "1990-01-02", "NL", 1_800_000_000).unwrap();
+           let requests = [disclosure::PredicateRequest::CountryIn { countries: vec!["NL".to_string(), "BE".to_string()] }];
+           let present = |presenter: &secp256k1::Keypair| {
+               let context = state.challenges.lock().unwrap().issue("rp_test", 300, Utc::now().timestamp()).challenge;
+               let presentation = disclosure::prove(&secp, presenter, &credential, &openings, &requests, &context).unwrap();
+               Json(PredicateVerificationRequest { presentation })
+           };
+
+           let Json(result) = verify_predicates(State(state.clone()), Caller(tenant(None)), present(&holder)).await.unwrap();
+           assert!(result.verified);
+           // Whoever else got hold of the credential and its openings.
+           let other = secp256k1::Keypair::from_seckey_slice(&secp, &[13; 32]).unwrap();
+           let rejected = verify_predicates(State(state.clone()), Caller(tenant(None)), present(&other)).await;
+           assert_eq!(rejected.err(), Some(StatusCode::UNAUTHORIZED));
+       }
+
+       #[tokio::test]
+       async fn callers_are_held_to_their_key_scopes_and_rate() {
+           let state = test_state("caller", "http://127.0.0.1:9");
+           let limit = RateLimit { requests_per_minute: 1, burst: 2 };
+           let (_, key) = state.tenants.lock().unwrap().create("Verifier".to_string(), vec![Scope::Verify], limit, TenantSettings::default(), Utc::now().timestamp()).unwrap();
+           let parts = |key: &str| axum::http::Request::builder().header("x-api-key", key).body(()).unwrap().into_parts().0;
+
+           let caller = Caller::from_request_parts(&mut parts(&key), &state).await.ok().unwrap();
+           assert!(caller.require(Scope::Verify).is_ok());
+           assert_eq!(caller.require(Scope::Report).err(), Some(StatusCode::FORBIDDEN));
+           let request = EnrollmentRequest { session_id: "session".to_string(), face_scan: vec![1, 2, 3], identity_commitment: "0x01".to_string() };
+           let enrollment = start_enrollment(State(state.clone()), caller, Json(request)).await;
+           assert_eq!(enrollment.err(), Some(StatusCode::FORBIDDEN));
+
+           let unknown = Caller::from_request_parts(&mut parts("hhk_0000_secret"), &state).await.err().unwrap();
+           assert_eq!(unknown.status(), StatusCode::UNAUTHORIZED);
+
+           // The burst of two is spent; the next request waits a minute for a token.
+           assert!(Caller::from_request_parts(&mut parts(&key), &state).await.is_ok());
+           let limited = Caller::from_request_parts(&mut parts(&key), &state).await.err().unwrap();
+           assert_eq!(limited.status(), StatusCode::TOO_MANY_REQUESTS);
+           let retry_after: u64 = limited.headers()[header::RETRY_AFTER].to_str().unwrap().parse().unwrap();
+           assert!((59..=60).contains(&retry_after));
+       }
+
+       #[tokio::test]
+       async fn tenant_batch_size_cannot_exceed_the_hard_cap() {
+           let recorded = Arc::new(AtomicUsize::new(0));
+           let state = test_state("batch-cap", &mock_popchain(recorded.clone()).await);
+           let proofs = (0..=MAX_BATCH_SIZE).map(|i| proof(&state, "rp_test", &format!("human_{}", i))).collect();
+           let rejected = verify_proof_batch(State(state.clone()), Caller(tenant(Some(5000))), Json(BatchProofs { proofs })).await;
+           assert_eq!(rejected.err(), Some(StatusCode::PAYLOAD_TOO_LARGE));
+           assert_eq!(recorded.load(Ordering::SeqCst), 0);
+       }
+   }
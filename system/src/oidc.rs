@@ -0,0 +1,277 @@
+//! OpenID Connect provider ("Sign in with HumanHash"). Authentication is a
+//! presentation proof bound to a challenge issued for the client, checked by
+//! the same path as `/identity/verify`; the provider then runs a standard
+//! authorization code flow with PKCE and issues ES256-signed ID tokens.
+//!
+//! Subjects are pairwise: each sector (the host of the client's redirect
+//! URIs) sees a different, stable `sub` for the same person, so relying
+//! parties cannot correlate users through the identifier.
+
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use p256::ecdsa::SigningKey;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+
+use crate::challenge::Challenge;
+use crate::jws;
+use crate::policy::PopTier;
+
+/// Environment variable holding the hex-encoded P-256 ID token signing key.
+pub const SIGNING_KEY_ENV: &str = "OIDC_SIGNING_KEY";
+/// Environment variable holding the key pairwise subjects are derived with.
+pub const PAIRWISE_SECRET_ENV: &str = "OIDC_PAIRWISE_SECRET";
+
+#[derive(Clone, Deserialize)]
+pub struct OidcConfig {
+    pub issuer: String,
+    pub code_ttl_secs: i64,
+    pub token_ttl_secs: i64,
+    pub clients: Vec<OidcClient>,
+}
+
+#[derive(Clone, Deserialize)]
+pub struct OidcClient {
+    pub client_id: String,
+    pub redirect_uris: Vec<String>,
+    /// Confidential clients authenticate with `client_secret_post`; public
+    /// clients rely on PKCE alone.
+    #[serde(default)]
+    pub client_secret: Option<String>,
+}
+
+impl OidcClient {
+    /// Sector identifier for pairwise subjects: the redirect URI host.
+    pub fn sector(&self) -> String {
+        self.redirect_uris
+            .first()
+            .and_then(|uri| reqwest::Url::parse(uri).ok())
+            .and_then(|uri| uri.host_str().map(str::to_string))
+            .unwrap_or_else(|| self.client_id.clone())
+    }
+
+    pub fn authenticates(&self, client_secret: Option<&str>) -> bool {
+        match (&self.client_secret, client_secret) {
+            (None, _) => true,
+            // Compare digests so the comparison time says nothing about the secret.
+            (Some(expected), Some(given)) => Sha256::digest(expected) == Sha256::digest(given),
+            (Some(_), None) => false,
+        }
+    }
+}
+
+/// An authorization request waiting for the user's proof.
+#[derive(Clone)]
+pub struct PendingAuthorization {
+    pub client_id: String,
+    pub redirect_uri: String,
+    pub state: Option<String>,
+    pub nonce: Option<String>,
+    pub code_challenge: String,
+    pub scope: String,
+    pub challenge: Challenge,
+}
+
+#[derive(Clone)]
+pub struct AuthorizationCode {
+    pub client_id: String,
+    pub redirect_uri: String,
+    pub nonce: Option<String>,
+    pub code_challenge: String,
+    pub scope: String,
+    pub human_hash_id: String,
+    pub pop_tier: PopTier,
+    pub auth_time: i64,
+    pub expires_at: i64,
+}
+
+#[derive(Clone, Serialize)]
+pub struct UserInfo {
+    pub sub: String,
+    pub pop_verified: bool,
+    pub pop_tier: PopTier,
+}
+
+struct AccessToken {
+    user_info: UserInfo,
+    expires_at: i64,
+}
+
+#[derive(Serialize)]
+pub struct IdTokenClaims<'a> {
+    pub iss: &'a str,
+    pub sub: &'a str,
+    pub aud: &'a str,
+    pub iat: i64,
+    pub exp: i64,
+    pub auth_time: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<&'a str>,
+    pub pop_verified: bool,
+    pub pop_tier: PopTier,
+}
+
+/// Authorization requests, codes and access tokens; all short-lived, so
+/// they live in memory like verifier challenges.
+#[derive(Default)]
+pub struct OidcStore {
+    pending: HashMap<String, PendingAuthorization>,
+    codes: HashMap<String, AuthorizationCode>,
+    access_tokens: HashMap<String, AccessToken>,
+}
+
+impl OidcStore {
+    pub fn begin(&mut self, request: PendingAuthorization, now: i64) -> String {
+        self.pending.retain(|_, pending| pending.challenge.expires_at > now);
+        let request_id = random_token();
+        self.pending.insert(request_id.clone(), request);
+        request_id
+    }
+
+    pub fn take_pending(&mut self, request_id: &str) -> Option<PendingAuthorization> {
+        self.pending.remove(request_id)
+    }
+
+    pub fn issue_code(&mut self, code: AuthorizationCode, now: i64) -> String {
+        self.codes.retain(|_, code| code.expires_at > now);
+        let value = random_token();
+        self.codes.insert(value.clone(), code);
+        value
+    }
+
+    /// Codes are single-use: redeeming one removes it whether or not the
+    /// rest of the token request checks out.
+    pub fn redeem_code(&mut self, code: &str, now: i64) -> Option<AuthorizationCode> {
+        self.codes.remove(code).filter(|code| code.expires_at > now)
+    }
+
+    pub fn issue_access_token(&mut self, user_info: UserInfo, expires_at: i64, now: i64) -> String {
+        self.access_tokens.retain(|_, token| token.expires_at > now);
+        let value = random_token();
+        self.access_tokens.insert(value.clone(), AccessToken { user_info, expires_at });
+        value
+    }
+
+    pub fn user_info(&self, access_token: &str, now: i64) -> Option<UserInfo> {
+        self.access_tokens.get(access_token).filter(|token| token.expires_at > now).map(|token| token.user_info.clone())
+    }
+}
+
+/// ID token signing key and pairwise subject key.
+pub struct OidcKeys {
+    signing_key: SigningKey,
+    pairwise_secret: Vec<u8>,
+    pub kid: String,
+}
+
+impl OidcKeys {
+    pub fn new(signing_key: SigningKey, pairwise_secret: Vec<u8>) -> Self {
+        let kid = jws::key_id(signing_key.verifying_key());
+        OidcKeys { signing_key, pairwise_secret, kid }
+    }
+
+    pub fn from_env() -> Result<Self, String> {
+        let signing_key = jws::signing_key_from_env(SIGNING_KEY_ENV)?;
+        let pairwise_secret = match std::env::var(PAIRWISE_SECRET_ENV) {
+            Ok(secret) if !secret.is_empty() => secret.into_bytes(),
+            _ => return Err(format!("{} must be set", PAIRWISE_SECRET_ENV)),
+        };
+        Ok(Self::new(signing_key, pairwise_secret))
+    }
+
+    pub fn pairwise_subject(&self, sector: &str, human_hash_id: &str) -> String {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.pairwise_secret).expect("HMAC accepts keys of any length");
+        mac.update(b"humanhash-oidc-pairwise");
+        for field in [sector, human_hash_id] {
+            mac.update(&(field.len() as u64).to_be_bytes());
+            mac.update(field.as_bytes());
+        }
+        URL_SAFE_NO_PAD.encode(mac.finalize().into_bytes())
+    }
+
+    pub fn sign_jwt(&self, claims: &impl Serialize) -> String {
+        jws::sign(&self.signing_key, &self.kid, "JWT", claims)
+    }
+
+    pub fn jwks(&self) -> serde_json::Value {
+        let mut jwk = jws::public_jwk(self.signing_key.verifying_key());
+        jwk["use"] = "sig".into();
+        jwk["alg"] = "ES256".into();
+        jwk["kid"] = self.kid.clone().into();
+        serde_json::json!({ "keys": [jwk] })
+    }
+}
+
+pub fn discovery(config: &OidcConfig) -> serde_json::Value {
+    let issuer = config.issuer.trim_end_matches('/');
+    serde_json::json!({
+        "issuer": issuer,
+        "authorization_endpoint": format!("{}/oidc/authorize", issuer),
+        "token_endpoint": format!("{}/oidc/token", issuer),
+        "userinfo_endpoint": format!("{}/oidc/userinfo", issuer),
+        "jwks_uri": format!("{}/oidc/jwks", issuer),
+        "response_types_supported": ["code"],
+        "grant_types_supported": ["authorization_code"],
+        "subject_types_supported": ["pairwise"],
+        "id_token_signing_alg_values_supported": ["ES256"],
+        "scopes_supported": ["openid"],
+        "token_endpoint_auth_methods_supported": ["none", "client_secret_post"],
+        "code_challenge_methods_supported": ["S256"],
+        "claims_supported": ["iss", "sub", "aud", "iat", "exp", "auth_time", "nonce", "pop_verified", "pop_tier"],
+    })
+}
+
+/// RFC 7636 S256: `BASE64URL(SHA256(code_verifier)) == code_challenge`.
+pub fn verify_pkce(code_verifier: &str, code_challenge: &str) -> bool {
+    let well_formed = (43..=128).contains(&code_verifier.len())
+        && code_verifier.bytes().all(|b| b.is_ascii_alphanumeric() || b"-._~".contains(&b));
+    well_formed && URL_SAFE_NO_PAD.encode(Sha256::digest(code_verifier)) == code_challenge
+}
+
+fn random_token() -> String {
+    let mut bytes = [0u8; 32];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    URL_SAFE_NO_PAD.encode(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pkce_matches_the_rfc_7636_example() {
+        // RFC 7636 appendix B.
+        let verifier = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+        let challenge = "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM";
+        assert!(verify_pkce(verifier, challenge));
+        assert!(!verify_pkce(verifier, "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cN"));
+        assert!(!verify_pkce(&verifier[1..], challenge));
+        // Too short, and outside the unreserved characters.
+        assert!(!verify_pkce("abc", &URL_SAFE_NO_PAD.encode(Sha256::digest("abc"))));
+        let spaced = "dBjftJeZ4CVP mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+        assert!(!verify_pkce(spaced, &URL_SAFE_NO_PAD.encode(Sha256::digest(spaced))));
+    }
+
+    #[test]
+    fn id_tokens_verify_against_the_jwks() {
+        let keys = OidcKeys::new(SigningKey::from_slice(&[9; 32]).unwrap(), b"pairwise".to_vec());
+        let token = keys.sign_jwt(&serde_json::json!({ "iss": "https://id.example", "sub": "abc" }));
+        let jwks = keys.jwks();
+        let jwk = &jwks["keys"][0];
+        assert_eq!(jws::header(&token).unwrap()["kid"], jwk["kid"]);
+        let claims: serde_json::Value = jws::verify(&token, &jws::key_from_jwk(jwk).unwrap()).unwrap();
+        assert_eq!(claims["sub"], "abc");
+    }
+
+    #[test]
+    fn pairwise_subjects_differ_between_sectors() {
+        let keys = OidcKeys::new(SigningKey::from_slice(&[9; 32]).unwrap(), b"pairwise".to_vec());
+        let subject = keys.pairwise_subject("rp.example", "0xabc");
+        assert_eq!(subject, keys.pairwise_subject("rp.example", "0xabc"));
+        assert_ne!(subject, keys.pairwise_subject("other.example", "0xabc"));
+        assert_ne!(subject, keys.pairwise_subject("rp.example", "0xabd"));
+    }
+}
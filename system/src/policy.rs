@@ -0,0 +1,244 @@
+//! Per-tenant verification policies. A policy is a declarative list of rules
+//! (JSON or YAML) evaluated against what is known about the identity at
+//! verify time; every rule reports whether it passed and why, so a relying
+//! party can see exactly which requirement a user fell short of.
+
+use serde::{Deserialize, Serialize};
+
+const SECS_PER_DAY: i64 = 86_400;
+
+/// Assurance level of an identity, lowest first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PopTier {
+    /// Live biometric proof against an unrevoked PoPChain attestation.
+    Biometric,
+    /// As above, and the enrollment passed the oracle KYC check.
+    Kyc,
+}
+
+impl PopTier {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            PopTier::Biometric => "biometric",
+            PopTier::Kyc => "kyc",
+        }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Policy {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    pub rules: Vec<Rule>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(tag = "rule", rename_all = "snake_case")]
+pub enum Rule {
+    MinTier { tier: PopTier },
+    MaxAttestationAge { days: i64 },
+    RequireKyc,
+    /// ISO 3166-1 alpha-2 codes; the holder must prove membership in a
+    /// subset of them.
+    AllowedCountries { countries: Vec<String> },
+    /// Liveness the biometric service scored for the enrollment scan. A
+    /// verification takes no new scan, so this says how live the holder was
+    /// when enrolling, not now.
+    #[serde(alias = "min_liveness")]
+    MinEnrollmentLiveness { score: f64 },
+}
+
+/// What is known about the identity being verified. Facts a verification
+/// path cannot establish are `None`, and rules that need them fail.
+pub struct Facts {
+    pub tier: PopTier,
+    pub kyc_passed: bool,
+    /// When the PoPChain attestation was written.
+    pub attested_at: Option<i64>,
+    /// Liveness score of the scan the identity was enrolled with.
+    pub enrollment_liveness_score: Option<f64>,
+    /// Countries from a verified `country_in` proof: the holder's country is
+    /// one of these.
+    pub countries: Option<Vec<String>>,
+    pub now: i64,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct RuleOutcome {
+    pub rule: String,
+    pub passed: bool,
+    pub detail: String,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct PolicyDecision {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub policy: Option<String>,
+    pub passed: bool,
+    pub rules: Vec<RuleOutcome>,
+}
+
+impl Policy {
+    /// Rejects rules that could never be evaluated meaningfully.
+    pub fn validate(&self) -> Result<(), String> {
+        for rule in &self.rules {
+            match rule {
+                Rule::MaxAttestationAge { days } if *days <= 0 => return Err("max_attestation_age: days must be positive".to_string()),
+                Rule::AllowedCountries { countries } if countries.is_empty() => {
+                    return Err("allowed_countries: at least one country is required".to_string())
+                }
+                Rule::AllowedCountries { countries } => {
+                    if let Some(country) = countries.iter().find(|c| c.len() != 2 || !c.chars().all(|c| c.is_ascii_alphabetic())) {
+                        return Err(format!("allowed_countries: {} is not an ISO 3166-1 alpha-2 code", country));
+                    }
+                }
+                Rule::MinEnrollmentLiveness { score } if !(0.0..=1.0).contains(score) => {
+                    return Err("min_enrollment_liveness: score must be between 0 and 1".to_string())
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    pub fn evaluate(&self, facts: &Facts) -> PolicyDecision {
+        let rules: Vec<RuleOutcome> = self.rules.iter().map(|rule| rule.evaluate(facts)).collect();
+        PolicyDecision {
+            policy: self.name.clone(),
+            passed: rules.iter().all(|outcome| outcome.passed),
+            rules,
+        }
+    }
+}
+
+impl Rule {
+    fn evaluate(&self, facts: &Facts) -> RuleOutcome {
+        let (rule, passed, detail) = match self {
+            Rule::MinTier { tier } => (
+                format!("min_tier {}", tier.as_str()),
+                facts.tier >= *tier,
+                format!("identity tier is {}", facts.tier.as_str()),
+            ),
+            Rule::MaxAttestationAge { days } => {
+                let rule = format!("max_attestation_age {}d", days);
+                match facts.attested_at {
+                    Some(attested_at) => {
+                        let age_days = (facts.now - attested_at).max(0) / SECS_PER_DAY;
+                        (rule, facts.now - attested_at <= days * SECS_PER_DAY, format!("attestation is {} days old", age_days))
+                    }
+                    None => (rule, false, "attestation age unknown".to_string()),
+                }
+            }
+            Rule::RequireKyc => (
+                "require_kyc".to_string(),
+                facts.kyc_passed,
+                if facts.kyc_passed { "oracle KYC passed" } else { "no completed KYC enrollment" }.to_string(),
+            ),
+            Rule::AllowedCountries { countries } => {
+                let rule = format!("allowed_countries {}", countries.join(","));
+                match &facts.countries {
+                    Some(proven) => {
+                        let allowed = proven.iter().all(|p| countries.iter().any(|c| c.eq_ignore_ascii_case(p)));
+                        (rule, allowed, format!("holder proved country in {}", proven.join(",")))
+                    }
+                    None => (rule, false, "country not disclosed".to_string()),
+                }
+            }
+            Rule::MinEnrollmentLiveness { score } => {
+                let rule = format!("min_enrollment_liveness {}", score);
+                match facts.enrollment_liveness_score {
+                    Some(liveness) => (rule, liveness >= *score, format!("enrollment liveness score {}", liveness)),
+                    None => (rule, false, "enrollment liveness score unknown".to_string()),
+                }
+            }
+        };
+        RuleOutcome { rule, passed, detail }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const NOW: i64 = 1_700_000_000;
+
+    fn facts() -> Facts {
+        Facts {
+            tier: PopTier::Kyc,
+            kyc_passed: true,
+            attested_at: Some(NOW - 10 * SECS_PER_DAY),
+            enrollment_liveness_score: Some(0.8),
+            countries: Some(vec!["DE".to_string()]),
+            now: NOW,
+        }
+    }
+
+    fn policy(rules: serde_json::Value) -> Policy {
+        serde_json::from_value(serde_json::json!({ "name": "test", "rules": rules })).unwrap()
+    }
+
+    #[test]
+    fn every_rule_reports_its_outcome() {
+        let policy = policy(serde_json::json!([
+            { "rule": "min_tier", "tier": "kyc" },
+            { "rule": "max_attestation_age", "days": 30 },
+            { "rule": "require_kyc" },
+            { "rule": "allowed_countries", "countries": ["DE", "FR"] },
+            { "rule": "min_enrollment_liveness", "score": 0.7 },
+        ]));
+        policy.validate().unwrap();
+        let decision = policy.evaluate(&facts());
+        assert!(decision.passed);
+        assert_eq!(decision.policy.as_deref(), Some("test"));
+        assert_eq!(decision.rules.len(), 5);
+
+        let facts = Facts {
+            tier: PopTier::Biometric,
+            kyc_passed: false,
+            attested_at: Some(NOW - 31 * SECS_PER_DAY),
+            enrollment_liveness_score: Some(0.6),
+            countries: Some(vec!["DE".to_string(), "US".to_string()]),
+            now: NOW,
+        };
+        let decision = policy.evaluate(&facts);
+        assert!(!decision.passed);
+        assert!(decision.rules.iter().all(|outcome| !outcome.passed));
+        assert_eq!(decision.rules[1].detail, "attestation is 31 days old");
+        assert_eq!(decision.rules[4].rule, "min_enrollment_liveness 0.7");
+    }
+
+    #[test]
+    fn rules_needing_unknown_facts_fail() {
+        let policy = policy(serde_json::json!([
+            { "rule": "max_attestation_age", "days": 30 },
+            { "rule": "allowed_countries", "countries": ["DE"] },
+            { "rule": "min_enrollment_liveness", "score": 0.5 },
+        ]));
+        let facts = Facts { attested_at: None, enrollment_liveness_score: None, countries: None, ..facts() };
+        let details: Vec<String> = policy.evaluate(&facts).rules.into_iter().filter(|outcome| !outcome.passed).map(|outcome| outcome.detail).collect();
+        assert_eq!(details, ["attestation age unknown", "country not disclosed", "enrollment liveness score unknown"]);
+    }
+
+    #[test]
+    fn stored_min_liveness_rules_still_load() {
+        let policy = policy(serde_json::json!([{ "rule": "min_liveness", "score": 0.9 }]));
+        assert!(matches!(policy.rules[0], Rule::MinEnrollmentLiveness { score } if score == 0.9));
+        assert_eq!(serde_json::to_value(&policy.rules[0]).unwrap()["rule"], "min_enrollment_liveness");
+    }
+
+    #[test]
+    fn invalid_rules_are_refused() {
+        for (rules, error) in [
+            (serde_json::json!([{ "rule": "max_attestation_age", "days": 0 }]), "max_attestation_age: days must be positive"),
+            (serde_json::json!([{ "rule": "allowed_countries", "countries": [] }]), "allowed_countries: at least one country is required"),
+            (serde_json::json!([{ "rule": "allowed_countries", "countries": ["DEU"] }]), "allowed_countries: DEU is not an ISO 3166-1 alpha-2 code"),
+            (serde_json::json!([{ "rule": "allowed_countries", "countries": ["D1"] }]), "allowed_countries: D1 is not an ISO 3166-1 alpha-2 code"),
+            (serde_json::json!([{ "rule": "min_enrollment_liveness", "score": 1.5 }]), "min_enrollment_liveness: score must be between 0 and 1"),
+        ] {
+            assert_eq!(policy(rules).validate().unwrap_err(), error);
+        }
+        let yaml: Policy = serde_yaml::from_str("rules:\n  - rule: min_tier\n    tier: biometric\n").unwrap();
+        assert!(yaml.validate().is_ok());
+    }
+}
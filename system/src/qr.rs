@@ -0,0 +1,297 @@
+//! Compact QR encoding for presentation proofs, modelled on the EU digital
+//! COVID certificate: the proof is packed as a CBOR array, signed as a
+//! COSE_Sign1 message (ES256K over secp256k1), zlib-compressed and
+//! Base45-encoded behind an `HH1:` prefix. Base45 keeps the string inside the
+//! QR alphanumeric mode, which packs far denser than byte mode.
+
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use secp256k1::ecdsa::Signature;
+use secp256k1::{Message, PublicKey, Secp256k1, SecretKey, Signing, Verification};
+use serde_cbor::Value;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+
+use crate::challenge::{Challenge, PublicInputs};
+
+pub const PREFIX: &str = "HH1:";
+
+/// Proofs are `mock_proof_<hex payload>_<hex binding digest>`.
+const PROOF_PREFIX: &str = "mock_proof_";
+
+/// Environment variable holding the hex-encoded secp256k1 signing key.
+pub const SIGNING_KEY_ENV: &str = "QR_SIGNING_KEY";
+
+/// COSE algorithm identifier for ECDSA over secp256k1 with SHA-256 (RFC 8812).
+pub const COSE_ALG_ES256K: i128 = -47;
+
+const COSE_SIGN1_TAG: u64 = 18;
+const HEADER_ALG: i128 = 1;
+const HEADER_KID: i128 = 4;
+const BASE45_ALPHABET: &[u8; 45] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ $%*+-./:";
+/// Upper bound on the decompressed payload, so a small QR cannot expand
+/// into an arbitrarily large allocation.
+const MAX_PAYLOAD_BYTES: u64 = 64 * 1024;
+
+#[derive(Debug, PartialEq)]
+pub enum QrError {
+    MissingPrefix,
+    InvalidBase45,
+    InvalidCompression,
+    InvalidCose,
+    UnsupportedAlgorithm,
+    UnknownKey,
+    InvalidSignature,
+    InvalidPayload(&'static str),
+}
+
+impl std::fmt::Display for QrError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            QrError::MissingPrefix => write!(f, "missing {} prefix", PREFIX),
+            QrError::InvalidBase45 => write!(f, "invalid base45"),
+            QrError::InvalidCompression => write!(f, "invalid zlib stream"),
+            QrError::InvalidCose => write!(f, "not a COSE_Sign1 message"),
+            QrError::UnsupportedAlgorithm => write!(f, "unsupported COSE algorithm"),
+            QrError::UnknownKey => write!(f, "signed with an unknown key"),
+            QrError::InvalidSignature => write!(f, "invalid signature"),
+            QrError::InvalidPayload(field) => write!(f, "invalid payload field {}", field),
+        }
+    }
+}
+
+impl std::error::Error for QrError {}
+
+/// A proof as carried in the QR payload.
+#[derive(Clone)]
+pub struct QrProof {
+    pub proof: String,
+    pub public_inputs: PublicInputs,
+}
+
+/// The signing key and the `kid` verifier apps use to look up its public key.
+pub struct QrSigner {
+    secret_key: SecretKey,
+    pub public_key: PublicKey,
+    pub kid: [u8; 8],
+}
+
+impl QrSigner {
+    pub fn new<C: Signing>(secp: &Secp256k1<C>, secret_key: SecretKey) -> Self {
+        let public_key = PublicKey::from_secret_key(secp, &secret_key);
+        QrSigner { secret_key, public_key, kid: key_id(&public_key) }
+    }
+
+    pub fn from_env<C: Signing>(secp: &Secp256k1<C>) -> Result<Self, String> {
+        let secret = std::env::var(SIGNING_KEY_ENV).map_err(|_| format!("{} must be set to a hex secp256k1 key", SIGNING_KEY_ENV))?;
+        let secret_key = hex::decode(secret)
+            .ok()
+            .and_then(|bytes| SecretKey::from_slice(&bytes).ok())
+            .ok_or_else(|| format!("{} is not a valid secp256k1 key", SIGNING_KEY_ENV))?;
+        Ok(Self::new(secp, secret_key))
+    }
+}
+
+pub fn encode<C: Signing>(secp: &Secp256k1<C>, signer: &QrSigner, proof: &QrProof) -> Result<String, QrError> {
+    let payload = to_cbor(&payload_value(proof)?);
+    let protected = to_cbor(&Value::Map(BTreeMap::from([
+        (Value::Integer(HEADER_ALG), Value::Integer(COSE_ALG_ES256K)),
+        (Value::Integer(HEADER_KID), Value::Bytes(signer.kid.to_vec())),
+    ])));
+    let digest = Message::from_digest(Sha256::digest(sig_structure(&protected, &payload)).into());
+    let signature = secp.sign_ecdsa(&digest, &signer.secret_key).serialize_compact();
+
+    let cose = Value::Tag(
+        COSE_SIGN1_TAG,
+        Box::new(Value::Array(vec![
+            Value::Bytes(protected),
+            Value::Map(BTreeMap::new()),
+            Value::Bytes(payload),
+            Value::Bytes(signature.to_vec()),
+        ])),
+    );
+
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(&to_cbor(&cose)).expect("writing to a Vec cannot fail");
+    let compressed = encoder.finish().expect("writing to a Vec cannot fail");
+    Ok(format!("{}{}", PREFIX, base45_encode(&compressed)))
+}
+
+/// Decodes a scanned string and checks its COSE signature against
+/// `public_key`.
+pub fn decode<C: Verification>(secp: &Secp256k1<C>, public_key: &PublicKey, scanned: &str) -> Result<QrProof, QrError> {
+    let encoded = scanned.trim().strip_prefix(PREFIX).ok_or(QrError::MissingPrefix)?;
+    let compressed = base45_decode(encoded)?;
+    let mut cose_bytes = Vec::new();
+    ZlibDecoder::new(compressed.as_slice())
+        .take(MAX_PAYLOAD_BYTES)
+        .read_to_end(&mut cose_bytes)
+        .map_err(|_| QrError::InvalidCompression)?;
+
+    let cose: Value = serde_cbor::from_slice(&cose_bytes).map_err(|_| QrError::InvalidCose)?;
+    // The tag is optional on the wire when the context says COSE_Sign1.
+    let cose = match cose {
+        Value::Tag(COSE_SIGN1_TAG, inner) => *inner,
+        Value::Tag(_, _) => return Err(QrError::InvalidCose),
+        other => other,
+    };
+    let [protected, _unprotected, payload, signature] = match cose {
+        Value::Array(items) => <[Value; 4]>::try_from(items).map_err(|_| QrError::InvalidCose)?,
+        _ => return Err(QrError::InvalidCose),
+    };
+    let (Value::Bytes(protected), Value::Bytes(payload), Value::Bytes(signature)) = (protected, payload, signature) else {
+        return Err(QrError::InvalidCose);
+    };
+
+    let headers = match serde_cbor::from_slice(&protected).map_err(|_| QrError::InvalidCose)? {
+        Value::Map(headers) => headers,
+        _ => return Err(QrError::InvalidCose),
+    };
+    if headers.get(&Value::Integer(HEADER_ALG)) != Some(&Value::Integer(COSE_ALG_ES256K)) {
+        return Err(QrError::UnsupportedAlgorithm);
+    }
+    if let Some(kid) = headers.get(&Value::Integer(HEADER_KID)) {
+        if kid != &Value::Bytes(key_id(public_key).to_vec()) {
+            return Err(QrError::UnknownKey);
+        }
+    }
+
+    let signature = Signature::from_compact(&signature).map_err(|_| QrError::InvalidSignature)?;
+    let digest = Message::from_digest(Sha256::digest(sig_structure(&protected, &payload)).into());
+    secp.verify_ecdsa(&digest, &signature, public_key).map_err(|_| QrError::InvalidSignature)?;
+
+    let payload: Value = serde_cbor::from_slice(&payload).map_err(|_| QrError::InvalidPayload("payload"))?;
+    proof_from_value(payload)
+}
+
+/// `kid` as in the DCC: the first 8 bytes of the SHA-256 of the public key.
+pub fn key_id(public_key: &PublicKey) -> [u8; 8] {
+    let digest = Sha256::digest(public_key.serialize());
+    let mut kid = [0u8; 8];
+    kid.copy_from_slice(&digest[..8]);
+    kid
+}
+
+/// Payload layout: `[proof, binding, human_hash_id, commitment, challenge,
+/// verifier_id, expires_at]`. The proof is split into its payload and binding
+/// digest, and every hex field is carried as raw bytes, which halves its size.
+fn payload_value(proof: &QrProof) -> Result<Value, QrError> {
+    let inputs = &proof.public_inputs;
+    let (payload, binding) = proof
+        .proof
+        .strip_prefix(PROOF_PREFIX)
+        .and_then(|rest| rest.rsplit_once('_'))
+        .ok_or(QrError::InvalidPayload("proof"))?;
+    let payload = hex_bytes(payload, "proof")?;
+    let binding = hex_bytes(binding, "proof")?;
+    let commitment = hex::decode(&inputs.commitment).map_err(|_| QrError::InvalidPayload("commitment"))?;
+    let challenge = hex::decode(&inputs.binding.challenge).map_err(|_| QrError::InvalidPayload("challenge"))?;
+    Ok(Value::Array(vec![
+        Value::Bytes(payload),
+        Value::Bytes(binding),
+        Value::Text(inputs.human_hash_id.clone()),
+        Value::Bytes(commitment),
+        Value::Bytes(challenge),
+        Value::Text(inputs.binding.verifier_id.clone()),
+        Value::Integer(inputs.binding.expires_at as i128),
+    ]))
+}
+
+fn proof_from_value(value: Value) -> Result<QrProof, QrError> {
+    let fields = match value {
+        Value::Array(fields) => <[Value; 7]>::try_from(fields).map_err(|_| QrError::InvalidPayload("payload"))?,
+        _ => return Err(QrError::InvalidPayload("payload")),
+    };
+    match fields {
+        [Value::Bytes(payload), Value::Bytes(binding), Value::Text(human_hash_id), Value::Bytes(commitment), Value::Bytes(challenge), Value::Text(verifier_id), Value::Integer(expires_at)] => {
+            Ok(QrProof {
+                proof: format!("{}{}_{}", PROOF_PREFIX, hex::encode(payload), hex::encode(binding)),
+                public_inputs: PublicInputs {
+                    human_hash_id,
+                    commitment: hex::encode(commitment),
+                    binding: Challenge {
+                        challenge: hex::encode(challenge),
+                        verifier_id,
+                        expires_at: i64::try_from(expires_at).map_err(|_| QrError::InvalidPayload("expires_at"))?,
+                    },
+                },
+            })
+        }
+        _ => Err(QrError::InvalidPayload("payload")),
+    }
+}
+
+/// Decodes lowercase hex, the only form that survives the round trip: the
+/// binding digest covers the proof's text.
+fn hex_bytes(field: &str, name: &'static str) -> Result<Vec<u8>, QrError> {
+    match hex::decode(field) {
+        Ok(bytes) if hex::encode(&bytes) == field => Ok(bytes),
+        _ => Err(QrError::InvalidPayload(name)),
+    }
+}
+
+/// COSE `Sig_structure` for a COSE_Sign1 with no external AAD.
+fn sig_structure(protected: &[u8], payload: &[u8]) -> Vec<u8> {
+    to_cbor(&Value::Array(vec![
+        Value::Text("Signature1".to_string()),
+        Value::Bytes(protected.to_vec()),
+        Value::Bytes(Vec::new()),
+        Value::Bytes(payload.to_vec()),
+    ]))
+}
+
+fn to_cbor(value: &Value) -> Vec<u8> {
+    serde_cbor::to_vec(value).expect("CBOR values always serialize")
+}
+
+/// Base45 per RFC 9285: two bytes become three characters, a trailing byte
+/// becomes two.
+pub fn base45_encode(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(2) * 3);
+    for chunk in bytes.chunks(2) {
+        let mut n = chunk.iter().fold(0usize, |n, &b| n * 256 + b as usize);
+        for _ in 0..chunk.len() + 1 {
+            out.push(BASE45_ALPHABET[n % 45] as char);
+            n /= 45;
+        }
+    }
+    out
+}
+
+pub fn base45_decode(encoded: &str) -> Result<Vec<u8>, QrError> {
+    let digits = encoded
+        .bytes()
+        .map(|c| BASE45_ALPHABET.iter().position(|&a| a == c).ok_or(QrError::InvalidBase45))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut out = Vec::with_capacity(digits.len() / 3 * 2 + 1);
+    for chunk in digits.chunks(3) {
+        let n = chunk.iter().rev().fold(0usize, |n, &d| n * 45 + d);
+        match chunk.len() {
+            3 if n <= 0xffff => out.extend_from_slice(&[(n >> 8) as u8, n as u8]),
+            2 if n <= 0xff => out.push(n as u8),
+            _ => return Err(QrError::InvalidBase45),
+        }
+    }
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base45_matches_the_rfc_9285_examples() {
+        for (bytes, encoded) in [(&b"AB"[..], "BB8"), (b"Hello!!", "%69 VD92EX0"), (b"base-45", "UJCLQE7W581"), (b"ietf!", "QED8WEX0")] {
+            assert_eq!(base45_encode(bytes), encoded);
+            assert_eq!(base45_decode(encoded).unwrap(), bytes);
+        }
+        assert_eq!(base45_encode(b""), "");
+        // A triple above 0xffff, a pair above 0xff, and a lone character.
+        assert_eq!(base45_decode("GGW"), Err(QrError::InvalidBase45));
+        assert_eq!(base45_decode("ZZ"), Err(QrError::InvalidBase45));
+        assert_eq!(base45_decode("B"), Err(QrError::InvalidBase45));
+        assert_eq!(base45_decode("bb8"), Err(QrError::InvalidBase45));
+    }
+}
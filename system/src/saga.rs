@@ -0,0 +1,909 @@
+//! Enrollment as a saga: biometric processing, proof generation, the PoPChain
+//! write and the oracle KYC check run in order, each with retries and a
+//! timeout. When a step fails for good, the steps that already took effect
+//! are compensated in reverse, so a rejected enrollment leaves no live
+//! attestation behind. A ledger write waits for its Lightning invoice to be
+//! paid with the saga parked, showing the invoice, rather than retrying.
+//! Sagas are persisted after every transition and resumed on restart.
+
+use reqwest::{Client, StatusCode};
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{error, info};
+
+use sequence_code::Action;
+
+use crate::credentials::CredentialIssuer;
+use crate::events::Recorder;
+use crate::ledger::{self, LedgerError, PaidWriteStatus, PaymentRequired};
+
+#[derive(Clone, Deserialize)]
+pub struct SagaConfig {
+    pub store_path: String,
+    pub max_attempts: u32,
+    pub step_timeout_secs: u64,
+    pub retry_backoff_ms: u64,
+    /// How often sagas waiting on a ledger write invoice check on it.
+    pub payment_poll_secs: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Step {
+    BiometricProcessing,
+    ProofGeneration,
+    LedgerWrite,
+    KycCheck,
+}
+
+const STEPS: [Step; 4] = [Step::BiometricProcessing, Step::ProofGeneration, Step::LedgerWrite, Step::KycCheck];
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StepStatus {
+    Pending,
+    Succeeded,
+    Failed,
+    Compensated,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SagaStatus {
+    Running,
+    /// Waiting for the ledger write's invoice to be paid.
+    AwaitingPayment,
+    Compensating,
+    Completed,
+    Compensated,
+    CompensationFailed,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct StepRecord {
+    pub step: Step,
+    pub status: StepStatus,
+    pub attempts: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    pub updated_at: i64,
+}
+
+/// Enrollment data the steps need; dropped once the saga has finished.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct EnrollmentInput {
+    /// The raw scan, held in memory only until biometric processing is
+    /// done; a saga resumed before then has lost it and is rolled back.
+    #[serde(skip)]
+    pub face_scan: Option<Vec<u8>>,
+    /// Hash of the scan as popchain records it, for the ledger write and to
+    /// recognise our own attestation.
+    pub biometric_hash: String,
+    pub identity_commitment: String,
+}
+
+impl EnrollmentInput {
+    pub fn new(face_scan: Vec<u8>, identity_commitment: String) -> Self {
+        let biometric_hash = format!("{:x}", Sha256::digest(hex::encode(&face_scan)));
+        EnrollmentInput { face_scan: Some(face_scan), biometric_hash, identity_commitment }
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct EnrollmentSaga {
+    pub sequence_code: String,
+    pub session_id: String,
+    /// Relying party that started the enrollment.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant_id: Option<String>,
+    pub status: SagaStatus,
+    pub steps: Vec<StepRecord>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub human_hash_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub proof: Option<String>,
+    /// Liveness score the biometric service reported for the scan.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub liveness_score: Option<f64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub zkp_proof: Option<String>,
+    /// Invoice for the ledger write, once popchain has issued one.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub invoice: Option<PaymentRequired>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attestation_id: Option<String>,
+    /// VC-JWT issued once the enrollment completes.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub credential: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub failure: Option<String>,
+    pub created_at: i64,
+    pub updated_at: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub input: Option<EnrollmentInput>,
+}
+
+impl EnrollmentSaga {
+    pub fn new(sequence_code: String, session_id: String, tenant_id: String, input: EnrollmentInput, now: i64) -> Self {
+        EnrollmentSaga {
+            sequence_code,
+            session_id,
+            tenant_id: Some(tenant_id),
+            status: SagaStatus::Running,
+            steps: STEPS
+                .iter()
+                .map(|&step| StepRecord { step, status: StepStatus::Pending, attempts: 0, last_error: None, updated_at: now })
+                .collect(),
+            human_hash_id: None,
+            proof: None,
+            liveness_score: None,
+            zkp_proof: None,
+            invoice: None,
+            attestation_id: None,
+            credential: None,
+            failure: None,
+            created_at: now,
+            updated_at: now,
+            input: Some(input),
+        }
+    }
+
+    /// The saga as shown to API callers, without the enrollment input.
+    pub fn public_view(&self) -> Self {
+        EnrollmentSaga { input: None, ..self.clone() }
+    }
+
+    fn step_mut(&mut self, step: Step) -> &mut StepRecord {
+        self.steps.iter_mut().find(|record| record.step == step).expect("every saga records every step")
+    }
+
+    fn step_status(&self, step: Step) -> StepStatus {
+        self.steps.iter().find(|record| record.step == step).map_or(StepStatus::Pending, |record| record.status)
+    }
+
+    fn mark(&mut self, step: Step, status: StepStatus, last_error: Option<String>) {
+        let now = chrono::Utc::now().timestamp();
+        let record = self.step_mut(step);
+        record.status = status;
+        if last_error.is_some() {
+            record.last_error = last_error;
+        }
+        record.updated_at = now;
+        self.updated_at = now;
+    }
+}
+
+/// Sagas keyed by sequence code, written through to a JSON file.
+pub struct SagaStore {
+    path: PathBuf,
+    sagas: HashMap<String, EnrollmentSaga>,
+}
+
+impl SagaStore {
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let sagas = match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e),
+        };
+        Ok(SagaStore { path, sagas })
+    }
+
+    pub fn get(&self, sequence_code: &str) -> Option<&EnrollmentSaga> {
+        self.sagas.get(sequence_code)
+    }
+
+    /// The enrollment `human_hash_id` finished, oracle KYC included.
+    pub fn completed_for(&self, human_hash_id: &str) -> Option<&EnrollmentSaga> {
+        self.sagas
+            .values()
+            .filter(|saga| saga.status == SagaStatus::Completed && saga.human_hash_id.as_deref() == Some(human_hash_id))
+            .max_by_key(|saga| saga.updated_at)
+    }
+
+    /// Sagas interrupted mid-flight, e.g. by a restart.
+    pub fn unfinished(&self) -> Vec<EnrollmentSaga> {
+        self.sagas
+            .values()
+            .filter(|saga| matches!(saga.status, SagaStatus::Running | SagaStatus::Compensating))
+            .cloned()
+            .collect()
+    }
+
+    pub fn awaiting_payment(&self) -> Vec<EnrollmentSaga> {
+        self.sagas.values().filter(|saga| saga.status == SagaStatus::AwaitingPayment).cloned().collect()
+    }
+
+    /// Records `saga` and rewrites the store file; the rename keeps a crash
+    /// from leaving a half-written file behind.
+    pub fn save(&mut self, saga: &EnrollmentSaga) -> io::Result<()> {
+        self.sagas.insert(saga.sequence_code.clone(), saga.clone());
+        let contents = serde_json::to_string_pretty(&self.sagas).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, contents)?;
+        fs::rename(&tmp, &self.path)
+    }
+}
+
+#[derive(Debug)]
+enum StepError {
+    /// Worth another attempt: timeouts, unreachable services, 5xx.
+    Retryable(String),
+    /// The ledger write's invoice is unpaid; not a failed attempt.
+    AwaitingPayment,
+    Fatal(String),
+}
+
+impl std::fmt::Display for StepError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            StepError::Retryable(e) | StepError::Fatal(e) => write!(f, "{}", e),
+            StepError::AwaitingPayment => write!(f, "ledger_payment_required"),
+        }
+    }
+}
+
+impl From<LedgerError> for StepError {
+    fn from(e: LedgerError) -> Self {
+        match e {
+            LedgerError::Unavailable(_) => StepError::Retryable(e.to_string()),
+            LedgerError::PaymentRequired(_) => StepError::AwaitingPayment,
+            _ => StepError::Fatal(e.to_string()),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct BiometricRequest<'a> {
+    face_scan: &'a [u8],
+    session_id: &'a str,
+}
+
+#[derive(Deserialize)]
+struct BiometricResponse {
+    human_hash_id: String,
+    proof: String,
+    #[serde(default)]
+    liveness_score: Option<f64>,
+}
+
+#[derive(Serialize)]
+struct OracleRequest<'a> {
+    #[serde(rename = "humanHashId")]
+    human_hash_id: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    proof: Option<&'a str>,
+}
+
+#[derive(Deserialize)]
+struct ZkpResponse {
+    proof: String,
+}
+
+#[derive(Deserialize)]
+struct KycResponse {
+    verification_result: bool,
+}
+
+/// Runs enrollment sagas against the biometric, oracle and PoPChain services.
+#[derive(Clone)]
+pub struct Orchestrator {
+    pub http: Client,
+    pub biometric_url: String,
+    pub oracle_url: String,
+    pub popchain_url: String,
+    /// Bearer token for popchain's service endpoints.
+    pub popchain_token: Arc<str>,
+    pub config: SagaConfig,
+    pub store: Arc<Mutex<SagaStore>>,
+    pub events: Recorder,
+    pub credentials: Arc<CredentialIssuer>,
+}
+
+impl Orchestrator {
+    pub fn status(&self, sequence_code: &str) -> Option<EnrollmentSaga> {
+        self.store.lock().unwrap().get(sequence_code).map(EnrollmentSaga::public_view)
+    }
+
+    pub fn persist(&self, saga: &EnrollmentSaga) -> io::Result<()> {
+        self.store.lock().unwrap().save(saga)
+    }
+
+    /// Drives `saga` forward from wherever it stopped until it completes,
+    /// has been compensated, or waits on a ledger write invoice.
+    pub async fn drive(self, mut saga: EnrollmentSaga) {
+        if saga.status == SagaStatus::AwaitingPayment {
+            saga.status = SagaStatus::Running;
+        }
+        if saga.status == SagaStatus::Running {
+            for step in STEPS {
+                if saga.step_status(step) == StepStatus::Succeeded {
+                    continue;
+                }
+                match self.run_step(&mut saga, step).await {
+                    Ok(()) => {
+                        saga.mark(step, StepStatus::Succeeded, None);
+                        info!("Enrollment {}: {:?} succeeded", saga.sequence_code, step);
+                    }
+                    Err(StepError::AwaitingPayment) => {
+                        let invoice = saga.invoice.as_ref().map_or("", |invoice| invoice.payment_request.as_str());
+                        info!("Enrollment {}: {:?} waiting for payment of {}", saga.sequence_code, step, invoice);
+                        saga.status = SagaStatus::AwaitingPayment;
+                    }
+                    Err(e) => {
+                        error!("Enrollment {}: {:?} failed: {}", saga.sequence_code, step, e);
+                        saga.mark(step, StepStatus::Failed, Some(e.to_string()));
+                        saga.failure = Some(format!("{:?}: {}", step, e));
+                        saga.status = SagaStatus::Compensating;
+                    }
+                }
+                self.save(&saga);
+                if saga.status != SagaStatus::Running {
+                    break;
+                }
+            }
+            if saga.status == SagaStatus::Running {
+                // Every step passed, KYC included, so the credential states the KYC tier.
+                let human_hash_id = saga.human_hash_id.clone().unwrap_or_default();
+                match self.credentials.issue(&human_hash_id, &saga.sequence_code, "kyc", chrono::Utc::now().timestamp()) {
+                    Ok(credential) => saga.credential = Some(credential),
+                    Err(e) => error!("Enrollment {}: failed to issue credential: {}", saga.sequence_code, e),
+                }
+                // Enrolling an identity again replaces what relying parties
+                // verified, so they hear about it as an update.
+                let reenrolled = self.store.lock().unwrap().completed_for(&human_hash_id).is_some();
+                saga.status = SagaStatus::Completed;
+                saga.input = None;
+                self.save(&saga);
+                info!("Enrollment {} completed for {}", saga.sequence_code, human_hash_id);
+                if reenrolled {
+                    let sequence_code = self.events.issue(Action::Update, saga.tenant_id.as_deref(), Some(&human_hash_id), None, None);
+                    info!("Enrollment {} updated {}, sequence_code: {}", saga.sequence_code, human_hash_id, sequence_code);
+                }
+            }
+        }
+
+        if saga.status == SagaStatus::Compensating {
+            let mut compensated = true;
+            for step in STEPS.iter().rev().copied() {
+                // A failed ledger write may still have landed (e.g. a timeout
+                // after popchain committed), so it is compensated as well.
+                let status = saga.step_status(step);
+                if status != StepStatus::Succeeded && !(step == Step::LedgerWrite && status == StepStatus::Failed) {
+                    continue;
+                }
+                match self.with_retries(&saga.sequence_code, step, || self.compensate(&saga, step)).await {
+                    Ok(()) => saga.mark(step, StepStatus::Compensated, None),
+                    Err(e) => {
+                        error!("Enrollment {}: compensating {:?} failed: {}", saga.sequence_code, step, e);
+                        saga.mark(step, status, Some(format!("compensation failed: {}", e)));
+                        compensated = false;
+                    }
+                }
+                self.save(&saga);
+            }
+            saga.status = if compensated { SagaStatus::Compensated } else { SagaStatus::CompensationFailed };
+            saga.input = None;
+            self.save(&saga);
+            info!("Enrollment {} rolled back: {:?}", saga.sequence_code, saga.status);
+        }
+    }
+
+    /// Re-drives sagas parked on a ledger write invoice every
+    /// `payment_poll_secs`, so each picks up where it stopped once paid.
+    /// Each saga runs in its own task, so one slow service call does not
+    /// hold up the others; a saga still running from an earlier tick is
+    /// left to finish.
+    pub async fn watch_payments(self) {
+        let driving: Arc<Mutex<HashSet<String>>> = Arc::default();
+        let mut interval = tokio::time::interval(Duration::from_secs(self.config.payment_poll_secs));
+        loop {
+            interval.tick().await;
+            let awaiting = self.store.lock().unwrap().awaiting_payment();
+            for saga in awaiting {
+                if !driving.lock().unwrap().insert(saga.sequence_code.clone()) {
+                    continue;
+                }
+                let orchestrator = self.clone();
+                let driving = driving.clone();
+                tokio::spawn(async move {
+                    let sequence_code = saga.sequence_code.clone();
+                    orchestrator.drive(saga).await;
+                    driving.lock().unwrap().remove(&sequence_code);
+                });
+            }
+        }
+    }
+
+    async fn run_step(&self, saga: &mut EnrollmentSaga, step: Step) -> Result<(), StepError> {
+        loop {
+            let attempt = {
+                let record = saga.step_mut(step);
+                if record.attempts >= self.config.max_attempts {
+                    return Err(StepError::Fatal(record.last_error.clone().unwrap_or_else(|| "retries exhausted".to_string())));
+                }
+                record.attempts += 1;
+                record.attempts
+            };
+            self.save(saga);
+
+            let result = match tokio::time::timeout(Duration::from_secs(self.config.step_timeout_secs), self.execute(saga, step)).await {
+                Ok(result) => result,
+                Err(_) => Err(StepError::Retryable(format!("timed out after {}s", self.config.step_timeout_secs))),
+            };
+            match result {
+                Ok(()) => return Ok(()),
+                Err(StepError::AwaitingPayment) => {
+                    saga.step_mut(step).attempts -= 1;
+                    return Err(StepError::AwaitingPayment);
+                }
+                Err(StepError::Retryable(e)) => {
+                    error!("Enrollment {}: {:?} attempt {} failed: {}", saga.sequence_code, step, attempt, e);
+                    saga.step_mut(step).last_error = Some(e);
+                    self.save(saga);
+                    tokio::time::sleep(self.backoff(attempt)).await;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    async fn execute(&self, saga: &mut EnrollmentSaga, step: Step) -> Result<(), StepError> {
+        let input = saga.input.clone().ok_or_else(|| StepError::Fatal("enrollment input is no longer available".to_string()))?;
+        match step {
+            Step::BiometricProcessing => {
+                let face_scan = input
+                    .face_scan
+                    .as_deref()
+                    .ok_or_else(|| StepError::Fatal("face scan is no longer available; it is not kept across restarts".to_string()))?;
+                let url = format!("{}/identity/enroll", self.biometric_url.trim_end_matches('/'));
+                let response: BiometricResponse = self.post_json(&url, &BiometricRequest { face_scan, session_id: &saga.session_id }).await?;
+                saga.human_hash_id = Some(response.human_hash_id);
+                saga.proof = Some(response.proof);
+                saga.liveness_score = response.liveness_score;
+                if let Some(input) = saga.input.as_mut() {
+                    input.face_scan = None;
+                }
+            }
+            Step::ProofGeneration => {
+                let url = format!("{}/oracle/zkp", self.oracle_url.trim_end_matches('/'));
+                let response: ZkpResponse = self.post_json(&url, &OracleRequest { human_hash_id: human_hash_id(saga)?, proof: None }).await?;
+                saga.zkp_proof = Some(response.proof);
+            }
+            Step::LedgerWrite => {
+                let human_hash_id = human_hash_id(saga)?.to_string();
+                let attestation_id = match &saga.invoice {
+                    // Once invoiced, the write commits only when paid; writing
+                    // again would not change that.
+                    Some(invoice) => match ledger::write_status(&self.http, &self.popchain_url, &invoice.payment_hash).await? {
+                        PaidWriteStatus::Committed { receipt } => receipt.attestation_id,
+                        PaidWriteStatus::AwaitingPayment => return Err(StepError::AwaitingPayment),
+                        PaidWriteStatus::Expired => return Err(StepError::Fatal("ledger_payment_expired".to_string())),
+                        PaidWriteStatus::Failed { reason } => return Err(StepError::Fatal(format!("ledger_write_failed_after_payment: {}", reason))),
+                    },
+                    None => {
+                        match ledger::write_attestation(&self.http, &self.popchain_url, &self.popchain_token, &human_hash_id, &input.biometric_hash, &input.identity_commitment).await {
+                            Ok(written) => written.attestation_id,
+                            // A retried write whose first attempt landed: the
+                            // existing attestation is ours if it carries our scan's hash.
+                            Err(LedgerError::AlreadyEnrolled) => match self.own_attestation(&human_hash_id, &input).await? {
+                                Some(attestation) => attestation.attestation_id,
+                                None => return Err(LedgerError::AlreadyEnrolled.into()),
+                            },
+                            Err(LedgerError::PaymentRequired(invoice)) => {
+                                saga.invoice = Some(invoice);
+                                return Err(StepError::AwaitingPayment);
+                            }
+                            Err(e) => return Err(e.into()),
+                        }
+                    }
+                };
+                saga.attestation_id = Some(attestation_id);
+                if let Err(e) = ledger::record_event(&self.http, &self.popchain_url, &self.popchain_token, &human_hash_id, "ENR", &saga.sequence_code).await {
+                    error!("Failed to log {} to PoPChain: {}", saga.sequence_code, e);
+                }
+            }
+            Step::KycCheck => {
+                let url = format!("{}/oracle/kyc", self.oracle_url.trim_end_matches('/'));
+                let request = OracleRequest { human_hash_id: human_hash_id(saga)?, proof: saga.zkp_proof.as_deref() };
+                let response: KycResponse = self.post_json(&url, &request).await?;
+                if !response.verification_result {
+                    return Err(StepError::Fatal("kyc_rejected".to_string()));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Undoes `step`. Only the ledger write has an effect outside this
+    /// service; the other steps produce values the saga simply discards.
+    async fn compensate(&self, saga: &EnrollmentSaga, step: Step) -> Result<(), StepError> {
+        if step != Step::LedgerWrite {
+            return Ok(());
+        }
+        let (Some(human_hash_id), Some(input)) = (saga.human_hash_id.as_deref(), saga.input.as_ref()) else {
+            return Ok(());
+        };
+        if self.own_attestation(human_hash_id, input).await?.is_none() {
+            return Ok(());
+        }
+        let reason = saga.failure.as_deref().unwrap_or("enrollment_failed");
+        let sequence_code = self.events.issue(Action::Revoke, saga.tenant_id.as_deref(), Some(human_hash_id), None, None);
+        match ledger::revoke(&self.http, &self.popchain_url, &self.popchain_token, human_hash_id, &sequence_code, reason).await {
+            Ok(()) | Err(LedgerError::UnknownIdentity) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// The attestation for `human_hash_id`, if it exists and was written from
+    /// this enrollment's scan.
+    async fn own_attestation(&self, human_hash_id: &str, input: &EnrollmentInput) -> Result<Option<ledger::Attestation>, StepError> {
+        match ledger::fetch_attestation(&self.http, &self.popchain_url, human_hash_id).await {
+            Ok(attestation) => Ok((attestation.biometric_hash == input.biometric_hash).then_some(attestation)),
+            Err(LedgerError::UnknownIdentity) => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn with_retries<F, Fut>(&self, sequence_code: &str, step: Step, mut action: F) -> Result<(), StepError>
+    where
+        F: FnMut() -> Fut,
+        Fut: std::future::Future<Output = Result<(), StepError>>,
+    {
+        let mut attempt = 1;
+        loop {
+            let result = match tokio::time::timeout(Duration::from_secs(self.config.step_timeout_secs), action()).await {
+                Ok(result) => result,
+                Err(_) => Err(StepError::Retryable(format!("timed out after {}s", self.config.step_timeout_secs))),
+            };
+            match result {
+                Err(StepError::Retryable(e)) if attempt < self.config.max_attempts => {
+                    error!("Enrollment {}: compensating {:?}, attempt {} failed: {}", sequence_code, step, attempt, e);
+                    tokio::time::sleep(self.backoff(attempt)).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn post_json<T: DeserializeOwned>(&self, url: &str, body: &impl Serialize) -> Result<T, StepError> {
+        let response = self.http.post(url).json(body).send().await.map_err(|e| StepError::Retryable(e.to_string()))?;
+        let status = response.status();
+        if status.is_server_error() || status == StatusCode::TOO_MANY_REQUESTS {
+            return Err(StepError::Retryable(format!("{} returned {}", url, status)));
+        }
+        if !status.is_success() {
+            return Err(StepError::Fatal(format!("{} returned {}", url, status)));
+        }
+        response.json().await.map_err(|e| StepError::Fatal(format!("invalid response from {}: {}", url, e)))
+    }
+
+    fn backoff(&self, attempt: u32) -> Duration {
+        Duration::from_millis(self.config.retry_backoff_ms.saturating_mul(1 << (attempt - 1).min(16)))
+    }
+
+    fn save(&self, saga: &EnrollmentSaga) {
+        if let Err(e) = self.persist(saga) {
+            error!("Failed to persist enrollment {}: {}", saga.sequence_code, e);
+        }
+    }
+}
+
+fn human_hash_id(saga: &EnrollmentSaga) -> Result<&str, StepError> {
+    saga.human_hash_id.as_deref().ok_or_else(|| StepError::Fatal("biometric processing has not produced a human_hash_id".to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::extract::{Path, State};
+    use axum::http::StatusCode as Status;
+    use axum::response::IntoResponse;
+    use axum::routing::{get, post};
+    use axum::{Json, Router};
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    use crate::credentials::CredentialConfig;
+    use crate::events::EventStore;
+    use crate::webhooks::WebhookStore;
+
+    const COMMITMENT: &str = "0x01";
+
+    /// The biometric, oracle and PoPChain services on one listener.
+    #[derive(Default)]
+    struct Services {
+        enrolled: AtomicUsize,
+        /// Proof generation answers 503 this many more times.
+        zkp_failures: AtomicUsize,
+        zkp_calls: AtomicUsize,
+        kyc_passes: AtomicBool,
+        /// Ledger writes never answer.
+        ledger_hangs: AtomicBool,
+        /// Ledger writes are invoiced rather than committed at once.
+        invoiced: AtomicBool,
+        paid: AtomicBool,
+        /// Status checks of the invoice for `hh_slow`, which never answer.
+        slow_status_calls: AtomicUsize,
+        /// Biometric hashes written, by human_hash_id.
+        written: Mutex<HashMap<String, String>>,
+        revoked: AtomicUsize,
+    }
+
+    async fn mock_services(services: Arc<Services>) -> String {
+        let app = Router::new()
+            .route(
+                "/identity/enroll",
+                post(|State(services): State<Arc<Services>>, Json(body): Json<serde_json::Value>| async move {
+                    services.enrolled.fetch_add(1, Ordering::SeqCst);
+                    Json(serde_json::json!({
+                        "human_hash_id": format!("hh_{}", body["session_id"].as_str().unwrap()),
+                        "proof": "biometric-proof",
+                        "liveness_score": 0.9,
+                    }))
+                }),
+            )
+            .route(
+                "/oracle/zkp",
+                post(|State(services): State<Arc<Services>>| async move {
+                    services.zkp_calls.fetch_add(1, Ordering::SeqCst);
+                    if services.zkp_failures.fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1)).is_ok() {
+                        return Status::SERVICE_UNAVAILABLE.into_response();
+                    }
+                    Json(serde_json::json!({ "proof": "zkp-proof" })).into_response()
+                }),
+            )
+            .route(
+                "/oracle/kyc",
+                post(|State(services): State<Arc<Services>>| async move {
+                    Json(serde_json::json!({ "verification_result": services.kyc_passes.load(Ordering::SeqCst) }))
+                }),
+            )
+            .route(
+                "/ledger/write",
+                post(|State(services): State<Arc<Services>>, headers: axum::http::HeaderMap, Json(body): Json<serde_json::Value>| async move {
+                    if headers.get(axum::http::header::AUTHORIZATION).is_none_or(|value| value != "Bearer popchain-token") {
+                        return Status::UNAUTHORIZED.into_response();
+                    }
+                    if services.ledger_hangs.load(Ordering::SeqCst) {
+                        std::future::pending::<()>().await;
+                    }
+                    let human_hash_id = body["human_hash_id"].as_str().unwrap().to_string();
+                    assert!(body.get("biometric_data").is_none());
+                    services.written.lock().unwrap().insert(human_hash_id.clone(), body["biometric_hash"].as_str().unwrap().to_string());
+                    if services.invoiced.load(Ordering::SeqCst) {
+                        let invoice = serde_json::json!({
+                            "payment_hash": format!("ph_{}", human_hash_id),
+                            "payment_request": "lnbc10n1test",
+                            "amount_sat": 10,
+                            "expires_at": chrono::Utc::now().timestamp() + 600,
+                        });
+                        return (Status::PAYMENT_REQUIRED, Json(invoice)).into_response();
+                    }
+                    Json(serde_json::json!({ "attestation_id": format!("att_{}", human_hash_id) })).into_response()
+                }),
+            )
+            .route(
+                "/ledger/write/:payment_hash",
+                get(|State(services): State<Arc<Services>>, Path(payment_hash): Path<String>| async move {
+                    if payment_hash == "ph_hh_slow" {
+                        services.slow_status_calls.fetch_add(1, Ordering::SeqCst);
+                        std::future::pending::<()>().await;
+                    }
+                    if !services.paid.load(Ordering::SeqCst) {
+                        return Json(serde_json::json!({ "status": "awaiting_payment" }));
+                    }
+                    let attestation_id = payment_hash.replacen("ph_", "att_", 1);
+                    Json(serde_json::json!({ "status": "committed", "receipt": { "attestation_id": attestation_id } }))
+                }),
+            )
+            .route(
+                "/ledger/attestation/:human_hash_id",
+                get(|State(services): State<Arc<Services>>, Path(human_hash_id): Path<String>| async move {
+                    let Some(biometric_hash) = services.written.lock().unwrap().get(&human_hash_id).cloned() else {
+                        return Status::NOT_FOUND.into_response();
+                    };
+                    Json(serde_json::json!({
+                        "attestation_id": format!("att_{}", human_hash_id),
+                        "biometric_hash": biometric_hash,
+                        "expires_at": chrono::Utc::now().timestamp() + 3600,
+                        "revoked": services.revoked.load(Ordering::SeqCst) > 0,
+                    }))
+                    .into_response()
+                }),
+            )
+            .route(
+                "/ledger/revoke",
+                post(|State(services): State<Arc<Services>>| async move {
+                    services.revoked.fetch_add(1, Ordering::SeqCst);
+                    Status::OK
+                }),
+            )
+            .route("/ledger/event", post(|| async { Status::OK }))
+            .with_state(services);
+        let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        tokio::spawn(async move { axum::serve(listener, app).await.unwrap() });
+        url
+    }
+
+    fn store_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("saga-{}-{}", name, std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// An orchestrator over the stores in `dir`, reading back whatever an
+    /// earlier one left there.
+    fn orchestrator(dir: &std::path::Path, url: &str, step_timeout_secs: u64) -> Orchestrator {
+        let path = |file: &str| dir.join(file).to_string_lossy().into_owned();
+        let config = SagaConfig { store_path: path("sagas.json"), max_attempts: 2, step_timeout_secs, retry_backoff_ms: 10, payment_poll_secs: 1 };
+        let webhooks = Arc::new(Mutex::new(WebhookStore::open(path("webhooks.json")).unwrap()));
+        let credentials = CredentialConfig { base_url: "http://localhost:8081".to_string(), store_path: path("credentials.json"), validity_days: 365, status_list_ttl_secs: 300 };
+        Orchestrator {
+            http: Client::new(),
+            biometric_url: url.to_string(),
+            oracle_url: url.to_string(),
+            popchain_url: url.to_string(),
+            popchain_token: "popchain-token".into(),
+            store: Arc::new(Mutex::new(SagaStore::open(&config.store_path).unwrap())),
+            config,
+            events: Recorder::new(EventStore::open(path("events.jsonl")).unwrap(), webhooks, Arc::from(&b"sequence-secret"[..])),
+            credentials: Arc::new(CredentialIssuer::open(credentials, p256::ecdsa::SigningKey::from_slice(&[7; 32]).unwrap()).unwrap()),
+        }
+    }
+
+    fn saga(session_id: &str) -> EnrollmentSaga {
+        let input = EnrollmentInput::new(b"raw-face-scan".to_vec(), COMMITMENT.to_string());
+        EnrollmentSaga::new(format!("ENR-{}", session_id), session_id.to_string(), "rp_test".to_string(), input, chrono::Utc::now().timestamp())
+    }
+
+    fn record(saga: &EnrollmentSaga, step: Step) -> &StepRecord {
+        saga.steps.iter().find(|record| record.step == step).unwrap()
+    }
+
+    #[tokio::test]
+    async fn failed_steps_are_retried_then_compensated() {
+        let services = Arc::new(Services::default());
+        services.zkp_failures.store(1, Ordering::SeqCst);
+        let url = mock_services(services.clone()).await;
+        let dir = store_dir("compensate");
+        let orchestrator = orchestrator(&dir, &url, 5);
+
+        let saga = saga("rejected");
+        orchestrator.persist(&saga).unwrap();
+        orchestrator.clone().drive(saga).await;
+
+        let saga = orchestrator.status("ENR-rejected").unwrap();
+        assert_eq!(saga.status, SagaStatus::Compensated);
+        assert_eq!(record(&saga, Step::ProofGeneration).attempts, 2);
+        assert_eq!(services.zkp_calls.load(Ordering::SeqCst), 2);
+        assert_eq!(record(&saga, Step::LedgerWrite).status, StepStatus::Compensated);
+        assert_eq!(record(&saga, Step::KycCheck).status, StepStatus::Failed);
+        assert_eq!(saga.failure.as_deref(), Some("KycCheck: kyc_rejected"));
+        // The attestation written from this enrollment's scan is revoked.
+        assert_eq!(services.revoked.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn steps_that_keep_timing_out_are_given_up() {
+        let services = Arc::new(Services::default());
+        services.ledger_hangs.store(true, Ordering::SeqCst);
+        let url = mock_services(services.clone()).await;
+        let dir = store_dir("timeout");
+        let orchestrator = orchestrator(&dir, &url, 1);
+
+        let saga = saga("stalled");
+        orchestrator.persist(&saga).unwrap();
+        orchestrator.clone().drive(saga).await;
+
+        let saga = orchestrator.status("ENR-stalled").unwrap();
+        assert_eq!(saga.status, SagaStatus::Compensated);
+        let ledger_write = record(&saga, Step::LedgerWrite);
+        assert_eq!(ledger_write.attempts, 2);
+        assert_eq!(ledger_write.last_error.as_deref(), Some("timed out after 1s"));
+        // Nothing landed on the ledger, so there is nothing to revoke.
+        assert_eq!(services.revoked.load(Ordering::SeqCst), 0);
+        assert_eq!(record(&saga, Step::KycCheck).attempts, 0);
+    }
+
+    #[tokio::test]
+    async fn sagas_resume_after_a_restart_without_the_scan() {
+        let services = Arc::new(Services::default());
+        services.kyc_passes.store(true, Ordering::SeqCst);
+        let url = mock_services(services.clone()).await;
+        let dir = store_dir("resume");
+
+        // One saga stopped after biometric processing, one before it.
+        let mut processed = saga("processed");
+        processed.human_hash_id = Some("hh_processed".to_string());
+        processed.proof = Some("biometric-proof".to_string());
+        processed.mark(Step::BiometricProcessing, StepStatus::Succeeded, None);
+        let unprocessed = saga("unprocessed");
+        let biometric_hash = processed.input.as_ref().unwrap().biometric_hash.clone();
+        {
+            let before = orchestrator(&dir, &url, 5);
+            before.persist(&processed).unwrap();
+            before.persist(&unprocessed).unwrap();
+        }
+        let stored = fs::read_to_string(dir.join("sagas.json")).unwrap();
+        assert!(!stored.contains("face_scan"));
+        assert!(stored.contains(&biometric_hash));
+
+        let after = orchestrator(&dir, &url, 5);
+        let mut unfinished = after.store.lock().unwrap().unfinished();
+        unfinished.sort_by(|a, b| a.session_id.cmp(&b.session_id));
+        assert_eq!(unfinished.len(), 2);
+        for saga in unfinished {
+            after.clone().drive(saga).await;
+        }
+
+        let processed = after.status("ENR-processed").unwrap();
+        assert_eq!(processed.status, SagaStatus::Completed);
+        assert!(processed.credential.is_some());
+        assert_eq!(services.written.lock().unwrap().get("hh_processed"), Some(&biometric_hash));
+
+        // The scan did not survive the restart, so that enrollment is rolled back.
+        let unprocessed = after.status("ENR-unprocessed").unwrap();
+        assert_eq!(unprocessed.status, SagaStatus::Compensated);
+        assert!(unprocessed.failure.unwrap().contains("face scan is no longer available"));
+        assert_eq!(services.enrolled.load(Ordering::SeqCst), 0);
+    }
+
+    #[tokio::test]
+    async fn the_scan_is_dropped_once_biometric_processing_is_done() {
+        let services = Arc::new(Services::default());
+        services.invoiced.store(true, Ordering::SeqCst);
+        let url = mock_services(services.clone()).await;
+        let dir = store_dir("scan");
+        let orchestrator = orchestrator(&dir, &url, 5);
+
+        let saga = saga("parked");
+        orchestrator.persist(&saga).unwrap();
+        orchestrator.clone().drive(saga).await;
+
+        let saga = orchestrator.store.lock().unwrap().get("ENR-parked").cloned().unwrap();
+        assert_eq!(saga.status, SagaStatus::AwaitingPayment);
+        assert!(saga.input.unwrap().face_scan.is_none());
+    }
+
+    #[tokio::test]
+    async fn parked_sagas_are_driven_concurrently() {
+        let services = Arc::new(Services::default());
+        services.kyc_passes.store(true, Ordering::SeqCst);
+        services.invoiced.store(true, Ordering::SeqCst);
+        let url = mock_services(services.clone()).await;
+        let dir = store_dir("watch");
+        let orchestrator = orchestrator(&dir, &url, 30);
+
+        for session_id in ["slow", "fast"] {
+            let saga = saga(session_id);
+            orchestrator.persist(&saga).unwrap();
+            orchestrator.clone().drive(saga).await;
+            assert_eq!(orchestrator.status(&format!("ENR-{}", session_id)).unwrap().status, SagaStatus::AwaitingPayment);
+        }
+        services.paid.store(true, Ordering::SeqCst);
+        tokio::spawn(orchestrator.clone().watch_payments());
+
+        // The slow saga's status check never answers; the other completes regardless.
+        tokio::time::timeout(Duration::from_secs(5), async {
+            while orchestrator.status("ENR-fast").unwrap().status != SagaStatus::Completed {
+                tokio::time::sleep(Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .expect("the fast saga completes while the slow one is stuck");
+
+        // Later ticks leave the slow saga to the task already driving it.
+        tokio::time::sleep(Duration::from_millis(1500)).await;
+        assert_eq!(services.slow_status_calls.load(Ordering::SeqCst), 1);
+    }
+}
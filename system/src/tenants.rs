@@ -0,0 +1,383 @@
+//! Relying-party accounts. Each tenant authenticates with API keys, of which
+//! only SHA-256 digests are stored, and is limited to its scopes and its own
+//! request rate. The tenant id doubles as the verifier id that challenges
+//! are bound to and events are tagged with.
+
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
+use std::collections::HashMap;
+use std::fs;
+use std::io;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+use crate::policy::Policy;
+
+/// Environment variable holding the bearer token for `/admin` endpoints.
+pub const ADMIN_KEY_ENV: &str = "ADMIN_API_KEY";
+
+const KEY_PREFIX: &str = "hhk";
+
+#[derive(Clone, Deserialize)]
+pub struct TenantConfig {
+    pub store_path: String,
+    /// How long the previous keys keep working after a rotation.
+    pub key_rotation_grace_secs: i64,
+    pub default_rate_limit: RateLimit,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Scope {
+    /// Challenges, proof verification and webhooks.
+    Verify,
+    /// Enrollment, including the oracle KYC check.
+    Kyc,
+    /// Event reports and sequence code lookups.
+    Report,
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct RateLimit {
+    pub requests_per_minute: u32,
+    pub burst: u32,
+}
+
+/// Per-tenant overrides of the service defaults.
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct TenantSettings {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub challenge_ttl_secs: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_batch_size: Option<usize>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct ApiKey {
+    pub id: String,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub key_hash: String,
+    pub created_at: i64,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_at: Option<i64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub revoked_at: Option<i64>,
+}
+
+impl ApiKey {
+    fn active(&self, now: i64) -> bool {
+        self.revoked_at.is_none() && self.expires_at.is_none_or(|expires_at| expires_at > now)
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Tenant {
+    pub id: String,
+    pub name: String,
+    pub scopes: Vec<Scope>,
+    pub rate_limit: RateLimit,
+    #[serde(default)]
+    pub settings: TenantSettings,
+    /// Rules every verification for this tenant must also pass.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub policy: Option<Policy>,
+    #[serde(default)]
+    pub disabled: bool,
+    pub keys: Vec<ApiKey>,
+    pub created_at: i64,
+}
+
+impl Tenant {
+    pub fn has_scope(&self, scope: Scope) -> bool {
+        self.scopes.contains(&scope)
+    }
+
+    /// The tenant as shown by the admin API, without key digests.
+    pub fn public_view(&self) -> Self {
+        let keys = self.keys.iter().map(|key| ApiKey { key_hash: String::new(), ..key.clone() }).collect();
+        Tenant { keys, ..self.clone() }
+    }
+}
+
+/// Changes applied by `PATCH /admin/tenants/:id`; absent fields are kept.
+#[derive(Deserialize)]
+pub struct TenantUpdate {
+    pub name: Option<String>,
+    pub scopes: Option<Vec<Scope>>,
+    pub rate_limit: Option<RateLimit>,
+    pub settings: Option<TenantSettings>,
+    pub disabled: Option<bool>,
+}
+
+/// Tenants keyed by id, written through to a JSON file.
+pub struct TenantStore {
+    path: PathBuf,
+    tenants: HashMap<String, Tenant>,
+    /// Tenant id by key id, so a key is found from its `hhk_<id>_` prefix.
+    key_owners: HashMap<String, String>,
+}
+
+impl TenantStore {
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let tenants = match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e),
+        };
+        let key_owners = tenants
+            .values()
+            .flat_map(|tenant: &Tenant| tenant.keys.iter().map(|key| (key.id.clone(), tenant.id.clone())))
+            .collect();
+        Ok(TenantStore { path, tenants, key_owners })
+    }
+
+    /// Creates a tenant with one API key; the plaintext key is returned only
+    /// here.
+    pub fn create(&mut self, name: String, scopes: Vec<Scope>, rate_limit: RateLimit, settings: TenantSettings, now: i64) -> io::Result<(Tenant, String)> {
+        let (key, plaintext) = new_key(now);
+        let tenant = Tenant {
+            id: format!("rp_{}", random_hex(8)),
+            name,
+            scopes,
+            rate_limit,
+            settings,
+            policy: None,
+            disabled: false,
+            keys: vec![key],
+            created_at: now,
+        };
+        self.key_owners.insert(tenant.keys[0].id.clone(), tenant.id.clone());
+        self.tenants.insert(tenant.id.clone(), tenant.clone());
+        self.save()?;
+        Ok((tenant, plaintext))
+    }
+
+    pub fn get(&self, id: &str) -> Option<&Tenant> {
+        self.tenants.get(id)
+    }
+
+    pub fn list(&self) -> Vec<Tenant> {
+        let mut tenants: Vec<Tenant> = self.tenants.values().map(Tenant::public_view).collect();
+        tenants.sort_by_key(|tenant| tenant.created_at);
+        tenants
+    }
+
+    pub fn update(&mut self, id: &str, update: TenantUpdate) -> io::Result<Option<Tenant>> {
+        let Some(tenant) = self.tenants.get_mut(id) else {
+            return Ok(None);
+        };
+        if let Some(name) = update.name {
+            tenant.name = name;
+        }
+        if let Some(scopes) = update.scopes {
+            tenant.scopes = scopes;
+        }
+        if let Some(rate_limit) = update.rate_limit {
+            tenant.rate_limit = rate_limit;
+        }
+        if let Some(settings) = update.settings {
+            tenant.settings = settings;
+        }
+        if let Some(disabled) = update.disabled {
+            tenant.disabled = disabled;
+        }
+        let tenant = tenant.clone();
+        self.save()?;
+        Ok(Some(tenant))
+    }
+
+    pub fn set_policy(&mut self, id: &str, policy: Option<Policy>) -> io::Result<bool> {
+        let Some(tenant) = self.tenants.get_mut(id) else {
+            return Ok(false);
+        };
+        tenant.policy = policy;
+        self.save()?;
+        Ok(true)
+    }
+
+    /// Adds an API key. With `retire_after` set, this is a rotation: the
+    /// tenant's other live keys expire that many seconds from now.
+    pub fn add_key(&mut self, id: &str, retire_after: Option<i64>, now: i64) -> io::Result<Option<(ApiKey, String)>> {
+        let Some(tenant) = self.tenants.get_mut(id) else {
+            return Ok(None);
+        };
+        if let Some(grace) = retire_after {
+            for key in tenant.keys.iter_mut().filter(|key| key.active(now)) {
+                let retire_at = now + grace;
+                key.expires_at = Some(key.expires_at.map_or(retire_at, |expires_at| expires_at.min(retire_at)));
+            }
+        }
+        let (key, plaintext) = new_key(now);
+        tenant.keys.push(key.clone());
+        self.key_owners.insert(key.id.clone(), id.to_string());
+        self.save()?;
+        Ok(Some((ApiKey { key_hash: String::new(), ..key }, plaintext)))
+    }
+
+    pub fn revoke_key(&mut self, id: &str, key_id: &str, now: i64) -> io::Result<Option<ApiKey>> {
+        let Some(key) = self.tenants.get_mut(id).and_then(|tenant| tenant.keys.iter_mut().find(|key| key.id == key_id)) else {
+            return Ok(None);
+        };
+        key.revoked_at.get_or_insert(now);
+        let key = ApiKey { key_hash: String::new(), ..key.clone() };
+        self.save()?;
+        Ok(Some(key))
+    }
+
+    /// The enabled tenant owning `plaintext`, if the key is live.
+    pub fn authenticate(&self, plaintext: &str, now: i64) -> Option<&Tenant> {
+        let key_id = key_id_of(plaintext)?;
+        let tenant = self.tenants.get(self.key_owners.get(&key_id)?).filter(|tenant| !tenant.disabled)?;
+        let key = tenant.keys.iter().find(|key| key.id == key_id)?;
+        (key.active(now) && key_matches(plaintext, &key.key_hash)).then_some(tenant)
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let contents = serde_json::to_string_pretty(&self.tenants).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, contents)?;
+        fs::rename(&tmp, &self.path)
+    }
+}
+
+struct Bucket {
+    tokens: f64,
+    updated: Instant,
+}
+
+/// Token buckets per tenant: `burst` requests at once, refilled at
+/// `requests_per_minute`.
+#[derive(Default)]
+pub struct RateLimiter {
+    buckets: HashMap<String, Bucket>,
+}
+
+impl RateLimiter {
+    /// Takes a token for `tenant_id`, or says how long until one is free.
+    pub fn check(&mut self, tenant_id: &str, limit: RateLimit, now: Instant) -> Result<(), Duration> {
+        let capacity = f64::from(limit.burst.max(1));
+        let per_sec = f64::from(limit.requests_per_minute) / 60.0;
+        let bucket = self.buckets.entry(tenant_id.to_string()).or_insert(Bucket { tokens: capacity, updated: now });
+        bucket.tokens = (bucket.tokens + now.duration_since(bucket.updated).as_secs_f64() * per_sec).min(capacity);
+        bucket.updated = now;
+        if bucket.tokens >= 1.0 {
+            bucket.tokens -= 1.0;
+            return Ok(());
+        }
+        if per_sec == 0.0 {
+            return Err(Duration::from_secs(60));
+        }
+        Err(Duration::from_secs_f64((1.0 - bucket.tokens) / per_sec))
+    }
+}
+
+/// API keys look like `hhk_<key id>_<secret>`; the id lets the store find
+/// the digest to compare against.
+fn new_key(now: i64) -> (ApiKey, String) {
+    let id = format!("key_{}", random_hex(8));
+    let plaintext = format!("{}_{}_{}", KEY_PREFIX, id.trim_start_matches("key_"), random_hex(32));
+    let key = ApiKey { id, key_hash: hash_key(&plaintext), created_at: now, expires_at: None, revoked_at: None };
+    (key, plaintext)
+}
+
+fn key_id_of(plaintext: &str) -> Option<String> {
+    let rest = plaintext.strip_prefix(KEY_PREFIX)?.strip_prefix('_')?;
+    let (id, _) = rest.split_once('_')?;
+    Some(format!("key_{}", id))
+}
+
+pub fn hash_key(plaintext: &str) -> String {
+    hex::encode(Sha256::digest(plaintext.as_bytes()))
+}
+
+/// Whether `plaintext` hashes to `key_hash`, compared in constant time.
+pub fn key_matches(plaintext: &str, key_hash: &str) -> bool {
+    hash_key(plaintext).as_bytes().ct_eq(key_hash.as_bytes()).into()
+}
+
+fn random_hex(len: usize) -> String {
+    let mut bytes = vec![0u8; len];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    hex::encode(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const LIMIT: RateLimit = RateLimit { requests_per_minute: 60, burst: 3 };
+
+    fn store(name: &str) -> TenantStore {
+        let path = std::env::temp_dir().join(format!("tenants-{}-{}.json", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        TenantStore::open(path).unwrap()
+    }
+
+    #[test]
+    fn keys_authenticate_their_own_tenant_while_live() {
+        let mut store = store("keys");
+        let (alpha, alpha_key) = store.create("Alpha".to_string(), vec![Scope::Verify], LIMIT, TenantSettings::default(), 100).unwrap();
+        let (beta, beta_key) = store.create("Beta".to_string(), vec![Scope::Kyc], LIMIT, TenantSettings::default(), 100).unwrap();
+        assert_eq!(store.authenticate(&alpha_key, 200).unwrap().id, alpha.id);
+        assert_eq!(store.authenticate(&beta_key, 200).unwrap().id, beta.id);
+
+        // The right key id with the wrong secret, and keys not in our format.
+        let (prefix, _) = alpha_key.rsplit_once('_').unwrap();
+        assert!(store.authenticate(&format!("{}_{}", prefix, "0".repeat(64)), 200).is_none());
+        assert!(store.authenticate("hhk_unknown_secret", 200).is_none());
+        assert!(store.authenticate("not-a-key", 200).is_none());
+
+        // A rotation keeps the old key working through the grace period.
+        let (_, rotated_key) = store.add_key(&alpha.id, Some(60), 200).unwrap().unwrap();
+        assert_eq!(store.authenticate(&rotated_key, 300).unwrap().id, alpha.id);
+        assert!(store.authenticate(&alpha_key, 259).is_some());
+        assert!(store.authenticate(&alpha_key, 260).is_none());
+
+        let reopened = TenantStore::open(&store.path).unwrap();
+        assert_eq!(reopened.authenticate(&rotated_key, 300).unwrap().id, alpha.id);
+
+        let beta_key_id = store.get(&beta.id).unwrap().keys[0].id.clone();
+        store.revoke_key(&beta.id, &beta_key_id, 300).unwrap();
+        assert!(store.authenticate(&beta_key, 301).is_none());
+        store.update(&alpha.id, TenantUpdate { name: None, scopes: None, rate_limit: None, settings: None, disabled: Some(true) }).unwrap();
+        assert!(store.authenticate(&rotated_key, 301).is_none());
+    }
+
+    #[test]
+    fn tenants_have_only_their_scopes() {
+        let mut store = store("scopes");
+        let (tenant, _) = store.create("Verifier".to_string(), vec![Scope::Verify], LIMIT, TenantSettings::default(), 100).unwrap();
+        assert!(tenant.has_scope(Scope::Verify));
+        assert!(!tenant.has_scope(Scope::Kyc));
+        assert!(!tenant.has_scope(Scope::Report));
+        assert!(tenant.public_view().keys.iter().all(|key| key.key_hash.is_empty()));
+    }
+
+    #[test]
+    fn rate_limits_allow_a_burst_then_refill() {
+        let mut limiter = RateLimiter::default();
+        let start = Instant::now();
+        for _ in 0..3 {
+            assert!(limiter.check("rp_a", LIMIT, start).is_ok());
+        }
+        assert_eq!(limiter.check("rp_a", LIMIT, start), Err(Duration::from_secs(1)));
+        // Other tenants have their own buckets.
+        assert!(limiter.check("rp_b", LIMIT, start).is_ok());
+
+        // One request a second comes back, and no more than the burst is saved up.
+        assert!(limiter.check("rp_a", LIMIT, start + Duration::from_millis(1000)).is_ok());
+        assert!(limiter.check("rp_a", LIMIT, start + Duration::from_millis(1500)).is_err());
+        let later = start + Duration::from_secs(600);
+        for _ in 0..3 {
+            assert!(limiter.check("rp_a", LIMIT, later).is_ok());
+        }
+        assert!(limiter.check("rp_a", LIMIT, later).is_err());
+
+        let closed = RateLimit { requests_per_minute: 0, burst: 1 };
+        assert!(limiter.check("rp_c", closed, start).is_ok());
+        assert_eq!(limiter.check("rp_c", closed, later), Err(Duration::from_secs(60)));
+    }
+}
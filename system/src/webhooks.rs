@@ -0,0 +1,638 @@
+//! Webhook subscriptions for relying parties. Every recorded event that a
+//! subscription cares about becomes a delivery in a durable outbox; a
+//! background dispatcher POSTs due deliveries, retries failures with
+//! exponential backoff and dead-letters them after `max_attempts`.
+//! Subscribers are delivered to concurrently, each over a client pinned to
+//! the addresses its host was checked against. Delivered items are kept for
+//! `delivered_retention_secs`, so they can be replayed, then pruned.
+//!
+//! Each delivery carries `X-Humanhash-Signature: t=<unix secs>,v1=<hex>`, an
+//! HMAC-SHA256 under the subscription secret over `"<t>.<body>"`, so the
+//! receiver can authenticate it and reject stale replays.
+
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use reqwest::Client;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use futures::stream::{self, StreamExt};
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tracing::{error, info};
+
+use crate::events::EventRecord;
+
+pub const SIGNATURE_HEADER: &str = "x-humanhash-signature";
+pub const EVENT_HEADER: &str = "x-humanhash-event";
+pub const DELIVERY_HEADER: &str = "x-humanhash-delivery";
+
+#[derive(Clone, Deserialize)]
+pub struct WebhookConfig {
+    pub store_path: String,
+    pub max_attempts: u32,
+    pub retry_backoff_ms: u64,
+    pub max_backoff_secs: u64,
+    pub timeout_secs: u64,
+    /// How long delivered items stay in the outbox for replays.
+    pub delivered_retention_secs: i64,
+}
+
+/// Subscribers delivered to at once by the dispatcher.
+const DISPATCH_CONCURRENCY: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EventType {
+    Verified,
+    Revoked,
+    Updated,
+    /// The attestation expires soon unless the holder renews it.
+    ExpiringSoon,
+    Expired,
+}
+
+impl EventType {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            EventType::Verified => "verified",
+            EventType::Revoked => "revoked",
+            EventType::Updated => "updated",
+            EventType::ExpiringSoon => "expiring_soon",
+            EventType::Expired => "expired",
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DeliveryStatus {
+    Pending,
+    Delivered,
+    DeadLettered,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Subscription {
+    pub id: String,
+    pub relying_party: String,
+    pub url: String,
+    pub event_types: Vec<EventType>,
+    #[serde(skip_serializing_if = "String::is_empty")]
+    pub secret: String,
+    pub created_at: i64,
+}
+
+impl Subscription {
+    /// The subscription as shown after creation; the secret is only
+    /// returned once.
+    pub fn public_view(&self) -> Self {
+        Subscription { secret: String::new(), ..self.clone() }
+    }
+}
+
+/// Body POSTed to the subscriber.
+#[derive(Clone, Serialize, Deserialize)]
+pub struct WebhookPayload {
+    pub event_type: EventType,
+    pub sequence_code: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub human_hash_id: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub verifier_id: Option<String>,
+    pub occurred_at: i64,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Delivery {
+    pub id: u64,
+    pub subscription_id: String,
+    pub payload: WebhookPayload,
+    pub status: DeliveryStatus,
+    pub attempts: u32,
+    /// Unix milliseconds.
+    pub next_attempt_at: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub last_error: Option<String>,
+    pub created_at: i64,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub delivered_at: Option<i64>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct Persisted {
+    subscriptions: HashMap<String, Subscription>,
+    deliveries: BTreeMap<u64, Delivery>,
+}
+
+/// Subscriptions and the delivery outbox, written through to a JSON file.
+pub struct WebhookStore {
+    path: PathBuf,
+    state: Persisted,
+}
+
+impl WebhookStore {
+    pub fn open(path: impl Into<PathBuf>) -> io::Result<Self> {
+        let path = path.into();
+        let state = match fs::read_to_string(&path) {
+            Ok(contents) => serde_json::from_str(&contents).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Persisted::default(),
+            Err(e) => return Err(e),
+        };
+        Ok(WebhookStore { path, state })
+    }
+
+    pub fn subscribe(&mut self, relying_party: String, url: String, event_types: Vec<EventType>, now: i64) -> io::Result<Subscription> {
+        let mut secret = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut secret);
+        let subscription = Subscription {
+            id: subscription_id(),
+            relying_party,
+            url,
+            event_types,
+            secret: format!("whsec_{}", hex::encode(secret)),
+            created_at: now,
+        };
+        self.state.subscriptions.insert(subscription.id.clone(), subscription.clone());
+        self.save()?;
+        Ok(subscription)
+    }
+
+    pub fn subscription(&self, id: &str) -> Option<&Subscription> {
+        self.state.subscriptions.get(id)
+    }
+
+    pub fn delivery(&self, id: u64) -> Option<&Delivery> {
+        self.state.deliveries.get(&id)
+    }
+
+    pub fn unsubscribe(&mut self, id: &str) -> io::Result<bool> {
+        if self.state.subscriptions.remove(id).is_none() {
+            return Ok(false);
+        }
+        self.state.deliveries.retain(|_, delivery| delivery.subscription_id != id);
+        self.save()?;
+        Ok(true)
+    }
+
+    pub fn deliveries(&self, subscription_id: &str, status: Option<DeliveryStatus>) -> Vec<Delivery> {
+        self.state
+            .deliveries
+            .values()
+            .filter(|delivery| delivery.subscription_id == subscription_id && status.is_none_or(|status| delivery.status == status))
+            .cloned()
+            .collect()
+    }
+
+    /// Queues `payload` for every subscription to its type whose relying
+    /// party is in `recipients`.
+    pub fn enqueue(&mut self, payload: &WebhookPayload, recipients: &[String], now_ms: i64) -> io::Result<usize> {
+        let subscription_ids: Vec<String> = self
+            .state
+            .subscriptions
+            .values()
+            .filter(|subscription| subscription.event_types.contains(&payload.event_type) && recipients.contains(&subscription.relying_party))
+            .map(|subscription| subscription.id.clone())
+            .collect();
+        for subscription_id in &subscription_ids {
+            let id = self.next_id();
+            self.state.deliveries.insert(id, Delivery {
+                id,
+                subscription_id: subscription_id.clone(),
+                payload: payload.clone(),
+                status: DeliveryStatus::Pending,
+                attempts: 0,
+                next_attempt_at: now_ms,
+                last_error: None,
+                created_at: now_ms / 1000,
+                delivered_at: None,
+            });
+        }
+        if !subscription_ids.is_empty() {
+            self.save()?;
+        }
+        Ok(subscription_ids.len())
+    }
+
+    /// Puts a delivery back in the queue with a fresh attempt budget.
+    pub fn replay(&mut self, delivery_id: u64, now_ms: i64) -> io::Result<Option<Delivery>> {
+        let Some(delivery) = self.state.deliveries.get_mut(&delivery_id) else {
+            return Ok(None);
+        };
+        requeue(delivery, now_ms);
+        let delivery = delivery.clone();
+        self.save()?;
+        Ok(Some(delivery))
+    }
+
+    /// Re-sends every delivery made to `subscription_id` since `since`, e.g.
+    /// after the receiver lost data; delivered items pruned after the
+    /// retention period are gone. Returns the number re-queued.
+    pub fn replay_since(&mut self, subscription_id: &str, since: i64, now_ms: i64) -> io::Result<usize> {
+        let ids: Vec<u64> = self
+            .state
+            .deliveries
+            .values()
+            .filter(|delivery| delivery.subscription_id == subscription_id && delivery.created_at >= since)
+            .map(|delivery| delivery.id)
+            .collect();
+        for id in &ids {
+            requeue(self.state.deliveries.get_mut(id).expect("id collected above"), now_ms);
+        }
+        if !ids.is_empty() {
+            self.save()?;
+        }
+        Ok(ids.len())
+    }
+
+    /// Deliveries due by `now_ms`, grouped by subscription.
+    fn due(&self, now_ms: i64) -> Vec<(Subscription, Vec<Delivery>)> {
+        let mut due: HashMap<&str, Vec<Delivery>> = HashMap::new();
+        for delivery in self.state.deliveries.values().filter(|delivery| delivery.status == DeliveryStatus::Pending && delivery.next_attempt_at <= now_ms) {
+            due.entry(delivery.subscription_id.as_str()).or_default().push(delivery.clone());
+        }
+        due.into_iter()
+            .filter_map(|(subscription_id, deliveries)| Some((self.state.subscriptions.get(subscription_id)?.clone(), deliveries)))
+            .collect()
+    }
+
+    /// Records the outcome of one dispatch round, rewriting the file once.
+    fn record_attempts(&mut self, results: Vec<(u64, Result<(), String>)>, config: &WebhookConfig, now_ms: i64) -> io::Result<()> {
+        for (id, result) in results {
+            let Some(delivery) = self.state.deliveries.get_mut(&id) else {
+                continue;
+            };
+            delivery.attempts += 1;
+            match result {
+                Ok(()) => {
+                    delivery.status = DeliveryStatus::Delivered;
+                    delivery.delivered_at = Some(now_ms / 1000);
+                    delivery.last_error = None;
+                }
+                Err(e) if delivery.attempts >= config.max_attempts => {
+                    error!("Dead-lettering webhook delivery {} after {} attempts: {}", id, delivery.attempts, e);
+                    delivery.status = DeliveryStatus::DeadLettered;
+                    delivery.last_error = Some(e);
+                }
+                Err(e) => {
+                    delivery.next_attempt_at = now_ms + backoff(config, delivery.attempts).as_millis() as i64;
+                    delivery.last_error = Some(e);
+                }
+            }
+        }
+        self.save()
+    }
+
+    /// Drops deliveries made more than `retention_secs` ago; pending and
+    /// dead-lettered ones stay until they are delivered or replayed.
+    fn prune(&mut self, retention_secs: i64, now: i64) -> io::Result<usize> {
+        let before = self.state.deliveries.len();
+        self.state
+            .deliveries
+            .retain(|_, delivery| delivery.status != DeliveryStatus::Delivered || delivery.delivered_at.is_none_or(|at| at > now - retention_secs));
+        let pruned = before - self.state.deliveries.len();
+        if pruned > 0 {
+            self.save()?;
+        }
+        Ok(pruned)
+    }
+
+    fn next_id(&self) -> u64 {
+        self.state.deliveries.keys().next_back().map_or(1, |last| last + 1)
+    }
+
+    fn save(&self) -> io::Result<()> {
+        let contents = serde_json::to_string_pretty(&self.state).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+        let tmp = self.path.with_extension("tmp");
+        fs::write(&tmp, contents)?;
+        fs::rename(&tmp, &self.path)
+    }
+}
+
+/// Polls the outbox and delivers whatever is due; runs for the lifetime of
+/// the service.
+pub async fn run_dispatcher(store: Arc<Mutex<WebhookStore>>, config: WebhookConfig) {
+    loop {
+        let due = store.lock().unwrap().due(chrono::Utc::now().timestamp_millis());
+        if !due.is_empty() {
+            let results: Vec<Vec<(u64, Result<(), String>)>> = stream::iter(due)
+                .map(|(subscription, deliveries)| deliver_to(config.clone(), subscription, deliveries))
+                .buffer_unordered(DISPATCH_CONCURRENCY)
+                .collect()
+                .await;
+            let results = results.into_iter().flatten().collect();
+            if let Err(e) = store.lock().unwrap().record_attempts(results, &config, chrono::Utc::now().timestamp_millis()) {
+                error!("Failed to persist webhook deliveries: {}", e);
+            }
+        }
+        if let Err(e) = store.lock().unwrap().prune(config.delivered_retention_secs, chrono::Utc::now().timestamp()) {
+            error!("Failed to prune webhook deliveries: {}", e);
+        }
+        tokio::time::sleep(Duration::from_secs(1)).await;
+    }
+}
+
+/// Sends one subscriber its due deliveries in order, over one client
+/// pinned to the addresses checked for it.
+async fn deliver_to(config: WebhookConfig, subscription: Subscription, deliveries: Vec<Delivery>) -> Vec<(u64, Result<(), String>)> {
+    let http = match pinned_client(&config, &subscription.url).await {
+        Ok(http) => http,
+        Err(e) => return deliveries.iter().map(|delivery| (delivery.id, Err(e.clone()))).collect(),
+    };
+    let mut results = Vec::with_capacity(deliveries.len());
+    for delivery in deliveries {
+        let result = deliver(&http, &delivery, &subscription).await;
+        if result.is_ok() {
+            info!("Delivered webhook {} to {}", delivery.id, subscription.url);
+        }
+        results.push((delivery.id, result));
+    }
+    results
+}
+
+/// A client for `url` that connects only to the public addresses its host
+/// resolved to just now, so a DNS answer changed after the check cannot
+/// point the delivery inside the network. Redirects are not followed,
+/// since they would bypass the checks too.
+async fn pinned_client(config: &WebhookConfig, url: &str) -> Result<Client, String> {
+    let url = subscriber_url(url)?;
+    let addrs = resolve_public(&url).await?;
+    let mut builder = Client::builder().redirect(reqwest::redirect::Policy::none()).timeout(Duration::from_secs(config.timeout_secs));
+    if let Some(url::Host::Domain(domain)) = url.host() {
+        builder = builder.resolve_to_addrs(domain, &addrs);
+    }
+    builder.build().map_err(|e| e.to_string())
+}
+
+async fn deliver(http: &Client, delivery: &Delivery, subscription: &Subscription) -> Result<(), String> {
+    let body = serde_json::to_string(&delivery.payload).map_err(|e| e.to_string())?;
+    let timestamp = chrono::Utc::now().timestamp();
+    let response = http
+        .post(&subscription.url)
+        .header(reqwest::header::CONTENT_TYPE, "application/json")
+        .header(SIGNATURE_HEADER, format!("t={},v1={}", timestamp, sign(&subscription.secret, timestamp, &body)))
+        .header(EVENT_HEADER, delivery.payload.event_type.as_str())
+        .header(DELIVERY_HEADER, delivery.id.to_string())
+        .body(body)
+        .send()
+        .await
+        .map_err(|e| e.to_string())?;
+    if response.status().is_success() {
+        Ok(())
+    } else {
+        Err(format!("subscriber returned {}", response.status()))
+    }
+}
+
+/// Parses a subscriber URL: `https` only, and no host that names this
+/// machine or an internal network, so a subscription cannot be used to
+/// reach services behind the firewall.
+pub fn subscriber_url(url: &str) -> Result<reqwest::Url, String> {
+    let parsed = reqwest::Url::parse(url).map_err(|e| format!("invalid URL: {}", e))?;
+    if parsed.scheme() != "https" {
+        return Err("subscriber URLs must use https".to_string());
+    }
+    let internal = match parsed.host() {
+        Some(url::Host::Domain(domain)) => {
+            let domain = domain.trim_end_matches('.').to_ascii_lowercase();
+            domain == "localhost" || domain.ends_with(".localhost") || domain.ends_with(".internal") || domain.ends_with(".local")
+        }
+        Some(url::Host::Ipv4(ip)) => !is_public(IpAddr::V4(ip)),
+        Some(url::Host::Ipv6(ip)) => !is_public(IpAddr::V6(ip)),
+        None => true,
+    };
+    if internal {
+        return Err(format!("{} is not a public host", parsed.host_str().unwrap_or_default()));
+    }
+    Ok(parsed)
+}
+
+/// Resolves the subscriber's host and fails unless every address is
+/// public; checked again before each dispatch round, since DNS can change.
+pub async fn resolve_public(url: &reqwest::Url) -> Result<Vec<SocketAddr>, String> {
+    let host = url.host_str().ok_or("URL has no host")?;
+    let port = url.port_or_known_default().unwrap_or(443);
+    let addrs: Vec<_> = tokio::net::lookup_host((host.trim_start_matches('[').trim_end_matches(']'), port))
+        .await
+        .map_err(|e| format!("cannot resolve {}: {}", host, e))?
+        .collect();
+    match addrs.iter().find(|addr| !is_public(addr.ip())) {
+        _ if addrs.is_empty() => Err(format!("{} has no addresses", host)),
+        Some(addr) => Err(format!("{} resolves to internal address {}", host, addr.ip())),
+        None => Ok(addrs),
+    }
+}
+
+fn is_public(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => {
+            let [a, b, ..] = ip.octets();
+            !(ip.is_unspecified()
+                || ip.is_loopback()
+                || ip.is_private()
+                || ip.is_link_local()
+                || ip.is_broadcast()
+                || ip.is_multicast()
+                || ip.is_documentation()
+                || a == 0
+                // Carrier-grade NAT, 100.64.0.0/10.
+                || (a == 100 && (64..128).contains(&b))
+                || a >= 240)
+        }
+        IpAddr::V6(ip) => match ip.to_ipv4_mapped() {
+            Some(ip) => is_public(IpAddr::V4(ip)),
+            None => {
+                let first = ip.segments()[0];
+                // Unique local fc00::/7 and link-local fe80::/10.
+                !(ip.is_unspecified() || ip.is_loopback() || ip.is_multicast() || (first & 0xfe00) == 0xfc00 || (first & 0xffc0) == 0xfe80)
+            }
+        },
+    }
+}
+
+/// `hex(HMAC-SHA256(secret, "<timestamp>.<body>"))`.
+pub fn sign(secret: &str, timestamp: i64, body: &str) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes()).expect("HMAC accepts keys of any length");
+    mac.update(timestamp.to_string().as_bytes());
+    mac.update(b".");
+    mac.update(body.as_bytes());
+    hex::encode(mac.finalize().into_bytes())
+}
+
+fn requeue(delivery: &mut Delivery, now_ms: i64) {
+    delivery.status = DeliveryStatus::Pending;
+    delivery.attempts = 0;
+    delivery.next_attempt_at = now_ms;
+    delivery.last_error = None;
+    delivery.delivered_at = None;
+}
+
+fn backoff(config: &WebhookConfig, attempts: u32) -> Duration {
+    let delay = config.retry_backoff_ms.saturating_mul(1 << (attempts.saturating_sub(1)).min(20));
+    Duration::from_millis(delay).min(Duration::from_secs(config.max_backoff_secs))
+}
+
+/// Webhook payload for an event, if the event is one relying parties can
+/// subscribe to.
+pub fn payload_for(record: &EventRecord) -> Option<WebhookPayload> {
+    let event_type = match record.event.action.as_str() {
+        "VER" if record.event.verified == Some(true) => EventType::Verified,
+        "REV" => EventType::Revoked,
+        // A renewal moves the attestation's expiry.
+        "UPDATE" | "RNW" => EventType::Updated,
+        "RMD" => EventType::ExpiringSoon,
+        "EXP" => EventType::Expired,
+        _ => return None,
+    };
+    Some(WebhookPayload {
+        event_type,
+        sequence_code: record.event.sequence_code.clone(),
+        human_hash_id: record.event.human_hash_id.clone(),
+        verifier_id: record.event.verifier_id.clone(),
+        occurred_at: record.recorded_at,
+    })
+}
+
+fn subscription_id() -> String {
+    let mut bytes = [0u8; 16];
+    rand::thread_rng().fill_bytes(&mut bytes);
+    format!("whsub_{}", hex::encode(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> WebhookConfig {
+        WebhookConfig {
+            store_path: String::new(),
+            max_attempts: 3,
+            retry_backoff_ms: 1000,
+            max_backoff_secs: 5,
+            timeout_secs: 10,
+            delivered_retention_secs: 3600,
+        }
+    }
+
+    fn store(name: &str) -> WebhookStore {
+        let path = std::env::temp_dir().join(format!("webhooks-{}-{}.json", name, std::process::id()));
+        let _ = fs::remove_file(&path);
+        WebhookStore::open(path).unwrap()
+    }
+
+    fn payload(sequence_code: &str) -> WebhookPayload {
+        WebhookPayload {
+            event_type: EventType::Revoked,
+            sequence_code: sequence_code.to_string(),
+            human_hash_id: Some("hh_1".to_string()),
+            verifier_id: None,
+            occurred_at: 1_700_000_000,
+        }
+    }
+
+    #[test]
+    fn signatures_are_hmac_over_timestamp_and_body() {
+        assert_eq!(sign("whsec_test", 1_700_000_000, r#"{"a":1}"#), "38877139021993b830af32feea6e18a8da83eb2f6e49ee50bd9e4cf4ca4d3789");
+        assert_ne!(sign("whsec_test", 1_700_000_001, r#"{"a":1}"#), sign("whsec_test", 1_700_000_000, r#"{"a":1}"#));
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_the_cap() {
+        let delays: Vec<u64> = (1..=5).map(|attempts| backoff(&config(), attempts).as_millis() as u64).collect();
+        assert_eq!(delays, [1000, 2000, 4000, 5000, 5000]);
+        assert_eq!(backoff(&config(), 64), Duration::from_secs(5));
+    }
+
+    #[test]
+    fn internal_addresses_are_not_public() {
+        for ip in [
+            "127.0.0.1",
+            "10.1.2.3",
+            "172.16.0.1",
+            "192.168.1.1",
+            "169.254.169.254",
+            "100.64.0.1",
+            "0.0.0.0",
+            "255.255.255.255",
+            "224.0.0.1",
+            "::1",
+            "::",
+            "fe80::1",
+            "fd00::1",
+            "::ffff:127.0.0.1",
+            "::ffff:10.0.0.1",
+        ] {
+            assert!(!is_public(ip.parse().unwrap()), "{} is internal", ip);
+        }
+        for ip in ["93.184.216.34", "8.8.8.8", "2606:4700:4700::1111"] {
+            assert!(is_public(ip.parse().unwrap()), "{} is public", ip);
+        }
+    }
+
+    #[test]
+    fn subscriber_urls_must_be_public_https() {
+        assert!(subscriber_url("https://example.com/hooks").is_ok());
+        for url in [
+            "http://example.com/hooks",
+            "https://localhost/hooks",
+            "https://api.localhost/hooks",
+            "https://db.internal/hooks",
+            "https://127.0.0.1/hooks",
+            "https://[::1]/hooks",
+            "https://169.254.169.254/latest/meta-data",
+        ] {
+            assert!(subscriber_url(url).is_err(), "{} is refused", url);
+        }
+    }
+
+    #[tokio::test]
+    async fn hosts_resolving_internally_are_refused() {
+        let url = reqwest::Url::parse("https://127.0.0.1:8443/hooks").unwrap();
+        assert!(resolve_public(&url).await.unwrap_err().contains("internal address"));
+        assert!(pinned_client(&config(), "https://localhost/hooks").await.is_err());
+    }
+
+    #[test]
+    fn outbox_retries_dead_letters_and_prunes_delivered_items() {
+        let mut store = store("outbox");
+        let config = config();
+        let a = store.subscribe("rp_a".to_string(), "https://a.example/hooks".to_string(), vec![EventType::Revoked], 0).unwrap();
+        let b = store.subscribe("rp_b".to_string(), "https://b.example/hooks".to_string(), vec![EventType::Revoked], 0).unwrap();
+        store.subscribe("rp_c".to_string(), "https://c.example/hooks".to_string(), vec![EventType::Verified], 0).unwrap();
+        assert_eq!(store.enqueue(&payload("REV-1"), &["rp_a".to_string(), "rp_b".to_string()], 1_000).unwrap(), 2);
+        assert_eq!(store.enqueue(&payload("REV-2"), &["rp_a".to_string()], 1_000).unwrap(), 1);
+
+        let mut due = store.due(1_000);
+        due.sort_by(|x, y| x.0.relying_party.cmp(&y.0.relying_party));
+        assert_eq!(due.len(), 2);
+        assert_eq!(due[0].0.id, a.id);
+        assert_eq!(due[0].1.len(), 2);
+        assert_eq!(due[1].0.id, b.id);
+        let (to_a, to_b) = (due[0].1[0].id, due[1].1[0].id);
+
+        // rp_a takes its first delivery; rp_b is down.
+        store.record_attempts(vec![(to_a, Ok(())), (to_b, Err("subscriber returned 503".to_string()))], &config, 1_000).unwrap();
+        assert_eq!(store.delivery(to_a).unwrap().status, DeliveryStatus::Delivered);
+        assert_eq!(store.delivery(to_b).unwrap().next_attempt_at, 2_000);
+        assert!(store.due(1_500).iter().all(|(subscription, _)| subscription.id != b.id));
+        store.record_attempts(vec![(to_b, Err("timeout".to_string()))], &config, 2_000).unwrap();
+        assert_eq!(store.delivery(to_b).unwrap().next_attempt_at, 4_000);
+        store.record_attempts(vec![(to_b, Err("timeout".to_string()))], &config, 4_000).unwrap();
+        assert_eq!(store.delivery(to_b).unwrap().status, DeliveryStatus::DeadLettered);
+
+        // Only delivered items past the retention period are pruned.
+        assert_eq!(store.prune(config.delivered_retention_secs, 3600).unwrap(), 0);
+        assert_eq!(store.prune(config.delivered_retention_secs, 3601).unwrap(), 1);
+        assert!(store.delivery(to_a).is_none());
+        assert!(store.delivery(to_b).is_some());
+        assert_eq!(store.deliveries(&a.id, Some(DeliveryStatus::Pending)).len(), 1);
+
+        let reopened = WebhookStore::open(&store.path).unwrap();
+        assert!(reopened.delivery(to_a).is_none());
+        assert_eq!(reopened.delivery(to_b).unwrap().status, DeliveryStatus::DeadLettered);
+    }
+}